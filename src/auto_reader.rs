@@ -0,0 +1,245 @@
+#[cfg(any(feature = "xz", feature = "lzip"))]
+use alloc::boxed::Box;
+
+use super::{error_invalid_data, LZMA2Reader, LZMAReader, Read, Result};
+#[cfg(feature = "lzip")]
+use super::{lzip::LZIP_MAGIC, LZIPReader};
+#[cfg(feature = "xz")]
+use super::{xz::XZ_MAGIC, XZReader};
+
+/// The size of the header of the .lzma format, which is the longest header that is sniffed.
+pub(crate) const LZMA_HEADER_SIZE: usize = 13;
+
+/// A compression format that can be detected by [`AutoReader`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The XZ file format.
+    #[cfg(feature = "xz")]
+    XZ,
+    /// The LZIP file format.
+    #[cfg(feature = "lzip")]
+    LZIP,
+    /// The .lzma file format, also called LZMA-alone.
+    LZMA,
+    /// Raw LZMA2 data without any header.
+    LZMA2,
+}
+
+/// Detects the format of the compressed data from its first bytes. Raw LZMA2 data has no header,
+/// so it's only detected if `detect_lzma2` is set and no other format matches.
+pub(crate) fn detect_format(header: &[u8], detect_lzma2: bool) -> Option<Format> {
+    #[cfg(feature = "xz")]
+    if header.starts_with(&XZ_MAGIC) {
+        return Some(Format::XZ);
+    }
+
+    #[cfg(feature = "lzip")]
+    if header.starts_with(&LZIP_MAGIC) {
+        return Some(Format::LZIP);
+    }
+
+    if is_lzma_header(header) {
+        return Some(Format::LZMA);
+    }
+
+    // A LZMA2 stream is either empty or starts with a chunk that resets the dictionary.
+    match header.first() {
+        Some(0x00..=0x01 | 0xE0..=0xFF) if detect_lzma2 => Some(Format::LZMA2),
+        _ => None,
+    }
+}
+
+/// Checks the header of the .lzma format with the same heuristics as xz-utils: the properties
+/// must be valid, the dictionary size must be 2^n, 2^n + 2^(n-1) or `u32::MAX` and the
+/// uncompressed size must be unknown or smaller than 256 GiB.
+fn is_lzma_header(header: &[u8]) -> bool {
+    let Some(header) = header.get(..LZMA_HEADER_SIZE) else {
+        return false;
+    };
+
+    if header[0] > (4 * 5 + 4) * 9 + 8 {
+        return false;
+    }
+
+    let dict_size = u32::from_le_bytes(header[1..5].try_into().unwrap());
+    let valid_dict_size = dict_size == u32::MAX
+        || dict_size.is_power_of_two()
+        || dict_size % 3 == 0 && (dict_size / 3).is_power_of_two();
+    if !valid_dict_size {
+        return false;
+    }
+
+    let uncompressed_size = u64::from_le_bytes(header[5..13].try_into().unwrap());
+    uncompressed_size == u64::MAX || uncompressed_size < 1 << 38
+}
+
+/// Reads the sniffed header first and then the rest of the inner reader.
+pub(crate) struct HeaderReader<R> {
+    header: [u8; LZMA_HEADER_SIZE],
+    header_pos: usize,
+    header_len: usize,
+    inner: R,
+}
+
+impl<R: Read> HeaderReader<R> {
+    /// Reads the header from `inner`. The header is shorter if the data ends early.
+    fn new(mut inner: R) -> Result<Self> {
+        let mut header = [0; LZMA_HEADER_SIZE];
+        let mut header_len = 0;
+
+        while header_len < header.len() {
+            match inner.read(&mut header[header_len..])? {
+                0 => break,
+                bytes_read => header_len += bytes_read,
+            }
+        }
+
+        Ok(Self {
+            header,
+            header_pos: 0,
+            header_len,
+            inner,
+        })
+    }
+
+    fn header(&self) -> &[u8] {
+        &self.header[..self.header_len]
+    }
+}
+
+impl<R> HeaderReader<R> {
+    fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for HeaderReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.header_pos < self.header_len {
+            let header = &self.header[self.header_pos..self.header_len];
+            let size = header.len().min(buf.len());
+            buf[..size].copy_from_slice(&header[..size]);
+            self.header_pos += size;
+            return Ok(size);
+        }
+
+        self.inner.read(buf)
+    }
+}
+
+enum Decoder<R> {
+    #[cfg(feature = "xz")]
+    Xz(Box<XZReader<HeaderReader<R>>>),
+    #[cfg(feature = "lzip")]
+    Lzip(Box<LZIPReader<HeaderReader<R>>>),
+    Lzma(LZMAReader<HeaderReader<R>>),
+    Lzma2(LZMA2Reader<HeaderReader<R>>),
+}
+
+/// A single-threaded decompressor that detects the compression format of the data.
+///
+/// XZ and LZIP files are detected by their magic bytes, .lzma files by checking their header
+/// with the same heuristics as xz-utils. Raw LZMA2 data has no header and is only detected if a
+/// dictionary size for it is given.
+///
+/// # Examples
+/// ```
+/// use std::io::{Read, Write};
+///
+/// use lzma_rust2::{AutoReader, Format, LZIPOptions, LZIPWriter};
+///
+/// let mut writer = LZIPWriter::new(Vec::new(), LZIPOptions::default());
+/// writer.write_all(b"Hello, world!").unwrap();
+/// let compressed = writer.finish().unwrap();
+///
+/// let mut reader = AutoReader::new(compressed.as_slice(), None).unwrap();
+/// assert_eq!(reader.format(), Format::LZIP);
+///
+/// let mut decompressed = Vec::new();
+/// reader.read_to_end(&mut decompressed).unwrap();
+/// assert_eq!(&decompressed[..], b"Hello, world!");
+/// ```
+pub struct AutoReader<R> {
+    decoder: Decoder<R>,
+}
+
+impl<R: Read> AutoReader<R> {
+    /// Creates a new reader for the format that is detected from the first bytes of `inner`.
+    ///
+    /// `lzma2_dict_size` is the dictionary size of raw LZMA2 data. If it's `None`, raw LZMA2 data
+    /// is not detected.
+    pub fn new(inner: R, lzma2_dict_size: Option<u32>) -> Result<Self> {
+        Self::new_mem_limit(inner, lzma2_dict_size, u32::MAX)
+    }
+
+    /// Creates a new reader for the format that is detected from the first bytes of `inner` with
+    /// a memory usage limit.
+    /// `mem_limit_kb` is the memory usage limit in kibibytes (KiB). `u32::MAX` means no limit.
+    pub fn new_mem_limit(
+        inner: R,
+        lzma2_dict_size: Option<u32>,
+        mem_limit_kb: u32,
+    ) -> Result<Self> {
+        let inner = HeaderReader::new(inner)?;
+
+        let format = detect_format(inner.header(), lzma2_dict_size.is_some())
+            .ok_or_else(|| error_invalid_data("unknown compression format"))?;
+
+        let decoder = match format {
+            #[cfg(feature = "xz")]
+            Format::XZ => Decoder::Xz(Box::new(XZReader::new_mem_limit(inner, true, mem_limit_kb))),
+            #[cfg(feature = "lzip")]
+            Format::LZIP => {
+                Decoder::Lzip(Box::new(LZIPReader::new_mem_limit(inner, mem_limit_kb)?))
+            }
+            Format::LZMA => Decoder::Lzma(LZMAReader::new_mem_limit(inner, mem_limit_kb, None)?),
+            Format::LZMA2 => Decoder::Lzma2(LZMA2Reader::new_mem_limit(
+                inner,
+                lzma2_dict_size.expect("LZMA2 dictionary size not set"),
+                None,
+                mem_limit_kb,
+            )?),
+        };
+
+        Ok(Self { decoder })
+    }
+}
+
+impl<R> AutoReader<R> {
+    /// Returns the detected compression format.
+    pub fn format(&self) -> Format {
+        match self.decoder {
+            #[cfg(feature = "xz")]
+            Decoder::Xz(_) => Format::XZ,
+            #[cfg(feature = "lzip")]
+            Decoder::Lzip(_) => Format::LZIP,
+            Decoder::Lzma(_) => Format::LZMA,
+            Decoder::Lzma2(_) => Format::LZMA2,
+        }
+    }
+
+    /// Unwraps the reader, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        match self.decoder {
+            #[cfg(feature = "xz")]
+            Decoder::Xz(reader) => reader.into_inner().into_inner(),
+            #[cfg(feature = "lzip")]
+            Decoder::Lzip(reader) => reader.into_inner().into_inner(),
+            Decoder::Lzma(reader) => reader.into_inner().into_inner(),
+            Decoder::Lzma2(reader) => reader.into_inner().into_inner(),
+        }
+    }
+}
+
+impl<R: Read> Read for AutoReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match &mut self.decoder {
+            #[cfg(feature = "xz")]
+            Decoder::Xz(reader) => reader.read(buf),
+            #[cfg(feature = "lzip")]
+            Decoder::Lzip(reader) => reader.read(buf),
+            Decoder::Lzma(reader) => reader.read(buf),
+            Decoder::Lzma2(reader) => reader.read(buf),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+#[cfg(feature = "lzip")]
+use super::LZIPReaderMT;
+#[cfg(feature = "xz")]
+use super::XZReaderMT;
+use super::{
+    auto_reader::{detect_format, LZMA_HEADER_SIZE},
+    error_invalid_data, error_invalid_input, Format, LZMA2ReaderMT, LZMAReader,
+};
+
+enum Decoder<R: Read + Seek> {
+    #[cfg(feature = "xz")]
+    Xz(Box<XZReaderMT<R>>),
+    #[cfg(feature = "lzip")]
+    Lzip(Box<LZIPReaderMT<R>>),
+    Lzma(Box<LZMAReader<R>>),
+    Lzma2(Box<LZMA2ReaderMT<R>>),
+}
+
+/// A multi-threaded decompressor that detects the compression format of the data.
+///
+/// The format is detected the same way as by [`crate::AutoReader`]. XZ, LZIP and raw LZMA2 data
+/// is decoded in parallel. The .lzma format consists of a single LZMA stream, which can't be
+/// split, so it's decoded by a single-threaded [`LZMAReader`].
+pub struct AutoReaderMT<R: Read + Seek> {
+    decoder: Decoder<R>,
+}
+
+impl<R: Read + Seek> AutoReaderMT<R> {
+    /// Creates a new multi-threaded reader for the format that is detected from the first bytes
+    /// of `inner`.
+    ///
+    /// - `inner`: The reader to read compressed data from. Must implement Seek.
+    /// - `lzma2_dict_size`: The dictionary size of raw LZMA2 data. If it's `None`, raw LZMA2 data
+    ///   is not detected.
+    /// - `num_workers`: The maximum number of worker threads for decompression. Currently capped at 256 threads.
+    pub fn new(inner: R, lzma2_dict_size: Option<u32>, num_workers: u32) -> io::Result<Self> {
+        Self::new_mem_limit(inner, lzma2_dict_size, num_workers, u32::MAX)
+    }
+
+    /// Creates a new multi-threaded reader for the format that is detected from the first bytes
+    /// of `inner` with a memory usage limit.
+    ///
+    /// - `inner`: The reader to read compressed data from. Must implement Seek. XZ and LZIP data
+    ///   must start at position 0 of `inner`, otherwise an `InvalidInput` error is returned.
+    /// - `lzma2_dict_size`: The dictionary size of raw LZMA2 data. If it's `None`, raw LZMA2 data
+    ///   is not detected.
+    /// - `num_workers`: The maximum number of worker threads for decompression. Currently capped at 256 threads.
+    /// - `mem_limit_kb`: The memory usage limit of all workers in kibibytes (KiB). `u32::MAX` means no limit.
+    pub fn new_mem_limit(
+        mut inner: R,
+        lzma2_dict_size: Option<u32>,
+        num_workers: u32,
+        mem_limit_kb: u32,
+    ) -> io::Result<Self> {
+        let start_pos = inner.stream_position()?;
+
+        let mut header = [0; LZMA_HEADER_SIZE];
+        let mut header_len = 0;
+        while header_len < header.len() {
+            match inner.read(&mut header[header_len..])? {
+                0 => break,
+                bytes_read => header_len += bytes_read,
+            }
+        }
+
+        inner.seek(SeekFrom::Start(start_pos))?;
+
+        let format = detect_format(&header[..header_len], lzma2_dict_size.is_some())
+            .ok_or_else(|| error_invalid_data("unknown compression format"))?;
+
+        // The XZ and LZIP readers locate their index and trailers relative to the start of
+        // `inner`.
+        if start_pos != 0 && !matches!(format, Format::LZMA | Format::LZMA2) {
+            return Err(error_invalid_input(
+                "XZ and LZIP data must start at the beginning of the reader",
+            ));
+        }
+
+        let decoder = match format {
+            #[cfg(feature = "xz")]
+            Format::XZ => Decoder::Xz(Box::new(XZReaderMT::new_mem_limit(
+                inner,
+                true,
+                num_workers,
+                mem_limit_kb,
+            )?)),
+            #[cfg(feature = "lzip")]
+            Format::LZIP => Decoder::Lzip(Box::new(LZIPReaderMT::new_mem_limit(
+                inner,
+                num_workers,
+                mem_limit_kb,
+            )?)),
+            Format::LZMA => Decoder::Lzma(Box::new(LZMAReader::new_mem_limit(
+                inner,
+                mem_limit_kb,
+                None,
+            )?)),
+            Format::LZMA2 => Decoder::Lzma2(Box::new(LZMA2ReaderMT::new_mem_limit(
+                inner,
+                lzma2_dict_size.expect("LZMA2 dictionary size not set"),
+                None,
+                num_workers,
+                mem_limit_kb,
+            )?)),
+        };
+
+        Ok(Self { decoder })
+    }
+
+    /// Returns the detected compression format.
+    pub fn format(&self) -> Format {
+        match self.decoder {
+            #[cfg(feature = "xz")]
+            Decoder::Xz(_) => Format::XZ,
+            #[cfg(feature = "lzip")]
+            Decoder::Lzip(_) => Format::LZIP,
+            Decoder::Lzma(_) => Format::LZMA,
+            Decoder::Lzma2(_) => Format::LZMA2,
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for AutoReaderMT<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.decoder {
+            #[cfg(feature = "xz")]
+            Decoder::Xz(reader) => reader.read(buf),
+            #[cfg(feature = "lzip")]
+            Decoder::Lzip(reader) => reader.read(buf),
+            Decoder::Lzma(reader) => reader.read(buf),
+            Decoder::Lzma2(reader) => reader.read(buf),
+        }
+    }
+}
@@ -250,7 +250,7 @@ impl<W: Write> LZMA2Writer<W> {
 
     fn should_start_independent_chunk(&self) -> bool {
         if let Some(chunk_size) = self.chunk_size {
-            self.uncompressed_size >= chunk_size
+            self.uncompressed_size + self.pending_size as u64 >= chunk_size
         } else {
             false
         }
@@ -375,6 +375,11 @@ impl<W: Write> LZMA2Writer<W> {
         self.inner
     }
 
+    /// Returns a mutable reference to the underlying writer.
+    pub(crate) fn inner_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
     /// Finishes the compression and returns the underlying writer.
     pub fn finish(mut self) -> crate::Result<W> {
         self.lzma.lz.set_finishing();
@@ -400,7 +405,17 @@ impl<W: Write> Write for LZMA2Writer<W> {
                 self.start_independent_chunk()?;
             }
 
-            let used = self.lzma.lz.fill_window(&buf[off..(off + len)]);
+            // Never fill the window past the end of the current chunk, so that a single big
+            // write still gets split into independent chunks.
+            let fill_len = match self.chunk_size {
+                Some(chunk_size) => {
+                    let chunk_used = self.uncompressed_size + self.pending_size as u64;
+                    (len as u64).min(chunk_size - chunk_used) as usize
+                }
+                None => len,
+            };
+
+            let used = self.lzma.lz.fill_window(&buf[off..(off + fill_len)]);
             off += used;
             len -= used;
             self.pending_size += used as u32;
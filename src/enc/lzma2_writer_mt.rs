@@ -265,8 +265,7 @@ impl<W: Write> LZMA2WriterMT<W> {
                                     // We expected more chunks, but the workers are gone and the
                                     // out-of-order buffer is empty. This is a real error.
                                     self.state = State::Error;
-                                    let err = io::Error::new(
-                                        io::ErrorKind::InvalidData,
+                                    let err = io::Error::other(
                                         format!("A compressed chunk was lost. Expected up to seq {}, but only got up to {}", last_seq, self.next_sequence_to_write.saturating_sub(1)),
                                     );
                                     set_error(err, &self.error_store, &self.shutdown_flag);
@@ -0,0 +1,220 @@
+use core::fmt;
+
+use crate::Error;
+
+/// The value of an integrity check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckValue {
+    /// A CRC32 value.
+    Crc32(u32),
+    /// A CRC64 value.
+    Crc64(u64),
+    /// A SHA-256 digest.
+    Sha256([u8; 32]),
+}
+
+impl CheckValue {
+    /// Creates a check value of the same type from its little endian encoding, as it is stored in
+    /// XZ and LZIP files. Returns `None` if `bytes` doesn't have the size of the check.
+    pub(crate) fn with_le_bytes(self, bytes: &[u8]) -> Option<Self> {
+        match self {
+            CheckValue::Crc32(_) => Some(CheckValue::Crc32(u32::from_le_bytes(
+                bytes.try_into().ok()?,
+            ))),
+            CheckValue::Crc64(_) => Some(CheckValue::Crc64(u64::from_le_bytes(
+                bytes.try_into().ok()?,
+            ))),
+            CheckValue::Sha256(_) => Some(CheckValue::Sha256(bytes.try_into().ok()?)),
+        }
+    }
+}
+
+impl fmt::Display for CheckValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckValue::Crc32(crc) => write!(f, "CRC32 {crc:08x}"),
+            CheckValue::Crc64(crc) => write!(f, "CRC64 {crc:016x}"),
+            CheckValue::Sha256(digest) => {
+                f.write_str("SHA-256 ")?;
+                digest.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+            }
+        }
+    }
+}
+
+/// Detailed error of the decoders, which allows to tell corrupt data apart from I/O errors.
+///
+/// With the `std` feature, these errors are the inner error of the returned `std::io::Error` and
+/// can be retrieved with [`LzmaError::from_io_error`]. Without the `std` feature, they are
+/// returned as [`Error::Lzma`](crate::Error).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LzmaError {
+    /// The check of an XZ block or the CRC32 of an LZIP member doesn't match the decoded data.
+    ChecksumMismatch {
+        /// The check value stored in the file.
+        expected: CheckValue,
+        /// The check value of the decoded data.
+        actual: CheckValue,
+    },
+    /// Decoding needs more memory than the memory limit allows.
+    MemLimitExceeded {
+        /// The needed memory in KiB.
+        needed: u32,
+        /// The memory limit in KiB.
+        limit: u32,
+    },
+    /// An XZ block uses a filter that is not supported.
+    UnsupportedFilter(u64),
+    /// The compressed data is corrupt.
+    CorruptData {
+        /// The offset in the compressed data at which the corruption was detected, if known.
+        /// The multithreaded XZ decoder reports the start of the block.
+        compressed_offset: Option<u64>,
+        /// The offset in the uncompressed data at which the corruption was detected, if known.
+        /// The multithreaded XZ decoder reports the start of the block.
+        uncompressed_offset: Option<u64>,
+        /// What is corrupt.
+        reason: &'static str,
+    },
+}
+
+impl LzmaError {
+    /// Returns the detailed error of an `std::io::Error` returned by this crate, if it has one.
+    #[cfg(feature = "std")]
+    pub fn from_io_error(error: &std::io::Error) -> Option<Self> {
+        error.get_ref()?.downcast_ref::<Self>().copied()
+    }
+
+    #[cfg(feature = "std")]
+    fn kind(&self) -> std::io::ErrorKind {
+        match self {
+            LzmaError::ChecksumMismatch { .. } | LzmaError::CorruptData { .. } => {
+                std::io::ErrorKind::InvalidData
+            }
+            LzmaError::MemLimitExceeded { .. } => std::io::ErrorKind::OutOfMemory,
+            LzmaError::UnsupportedFilter(_) => std::io::ErrorKind::Unsupported,
+        }
+    }
+}
+
+impl fmt::Display for LzmaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LzmaError::ChecksumMismatch { expected, actual } => {
+                write!(f, "checksum mismatch: expected {expected}, actual {actual}")
+            }
+            LzmaError::MemLimitExceeded { needed, limit } => write!(
+                f,
+                "needed memory of {needed} KiB exceeds the memory limit of {limit} KiB"
+            ),
+            LzmaError::UnsupportedFilter(id) => write!(f, "unsupported filter ID {id:#x}"),
+            LzmaError::CorruptData {
+                compressed_offset,
+                uncompressed_offset,
+                reason,
+            } => {
+                f.write_str(reason)?;
+                if let Some(offset) = compressed_offset {
+                    write!(f, " at compressed offset {offset}")?;
+                }
+                if let Some(offset) = uncompressed_offset {
+                    write!(f, " at uncompressed offset {offset}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for LzmaError {}
+
+#[cfg(feature = "std")]
+impl From<LzmaError> for std::io::Error {
+    fn from(error: LzmaError) -> Self {
+        std::io::Error::new(error.kind(), error)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl From<LzmaError> for Error {
+    fn from(error: LzmaError) -> Self {
+        Error::Lzma(error)
+    }
+}
+
+#[cfg(feature = "std")]
+fn lzma_error(error: &Error) -> Option<LzmaError> {
+    LzmaError::from_io_error(error)
+}
+
+#[cfg(not(feature = "std"))]
+fn lzma_error(error: &Error) -> Option<LzmaError> {
+    match error {
+        Error::Lzma(error) => Some(*error),
+        _ => None,
+    }
+}
+
+/// Adds the position to a corrupt data error that doesn't know its position yet. All other
+/// errors are returned unchanged.
+pub(crate) fn error_with_position(
+    error: Error,
+    compressed_offset: Option<u64>,
+    uncompressed_offset: Option<u64>,
+) -> Error {
+    match lzma_error(&error) {
+        Some(LzmaError::CorruptData {
+            compressed_offset: None,
+            uncompressed_offset: None,
+            reason,
+        }) => LzmaError::CorruptData {
+            compressed_offset,
+            uncompressed_offset,
+            reason,
+        }
+        .into(),
+        _ => error,
+    }
+}
+
+/// Moves the position of a corrupt data error, which is relative to the start of a block or
+/// member, to the position of the block or member in the whole file. Offsets that are unknown are
+/// set to the start of the block or member.
+#[cfg(feature = "std")]
+pub(crate) fn error_with_base_position(
+    error: Error,
+    compressed_base: u64,
+    uncompressed_base: u64,
+) -> Error {
+    match lzma_error(&error) {
+        Some(LzmaError::CorruptData {
+            compressed_offset,
+            uncompressed_offset,
+            reason,
+        }) => LzmaError::CorruptData {
+            compressed_offset: Some(compressed_base + compressed_offset.unwrap_or(0)),
+            uncompressed_offset: Some(uncompressed_base + uncompressed_offset.unwrap_or(0)),
+            reason,
+        }
+        .into(),
+        _ => error,
+    }
+}
+
+/// Returns a copy of the error. Detailed errors are kept, other errors are copied by kind and
+/// message.
+#[cfg(feature = "std")]
+pub(crate) fn copy_error(error: &Error) -> Error {
+    match lzma_error(error) {
+        Some(lzma_error) => lzma_error.into(),
+        None => Error::new(error.kind(), error.to_string()),
+    }
+}
+
+/// Returns a copy of the error.
+#[cfg(not(feature = "std"))]
+pub(crate) fn copy_error(error: &Error) -> Error {
+    *error
+}
@@ -35,6 +35,12 @@ const FILTER_BUF_SIZE: usize = 4096;
 /// Reader that applies BCJ (Branch/Call/Jump) filtering to compressed data.
 pub struct BCJReader<R> {
     inner: R,
+    decoder: BCJDecoder,
+}
+
+/// The state of a BCJ decoder. The reader of the filtered data is passed on every read, so that
+/// the decoder can be part of a filter chain that doesn't own its reader.
+pub(crate) struct BCJDecoder {
     filter: BCJFilter,
     state: State,
     err: Option<crate::Error>,
@@ -49,10 +55,9 @@ struct State {
     end_reached: bool,
 }
 
-impl<R> BCJReader<R> {
-    fn new(inner: R, filter: BCJFilter) -> Self {
+impl BCJDecoder {
+    fn new(filter: BCJFilter) -> Self {
         Self {
-            inner,
             filter,
             state: State {
                 filter_buf: vec![0; FILTER_BUF_SIZE],
@@ -62,6 +67,63 @@ impl<R> BCJReader<R> {
         }
     }
 
+    /// Creates a new BCJ decoder for x86 instruction filtering.
+    #[inline]
+    pub(crate) fn new_x86(start_pos: usize) -> Self {
+        Self::new(BCJFilter::new_x86(start_pos, false))
+    }
+
+    /// Creates a new BCJ decoder for ARM instruction filtering.
+    #[inline]
+    pub(crate) fn new_arm(start_pos: usize) -> Self {
+        Self::new(BCJFilter::new_arm(start_pos, false))
+    }
+
+    /// Creates a new BCJ decoder for ARM64 instruction filtering.
+    #[inline]
+    pub(crate) fn new_arm64(start_pos: usize) -> Self {
+        Self::new(BCJFilter::new_arm64(start_pos, false))
+    }
+
+    /// Creates a new BCJ decoder for ARM Thumb instruction filtering.
+    #[inline]
+    pub(crate) fn new_arm_thumb(start_pos: usize) -> Self {
+        Self::new(BCJFilter::new_arm_thumb(start_pos, false))
+    }
+
+    /// Creates a new BCJ decoder for PowerPC instruction filtering.
+    #[inline]
+    pub(crate) fn new_ppc(start_pos: usize) -> Self {
+        Self::new(BCJFilter::new_power_pc(start_pos, false))
+    }
+
+    /// Creates a new BCJ decoder for SPARC instruction filtering.
+    #[inline]
+    pub(crate) fn new_sparc(start_pos: usize) -> Self {
+        Self::new(BCJFilter::new_sparc(start_pos, false))
+    }
+
+    /// Creates a new BCJ decoder for IA-64 instruction filtering.
+    #[inline]
+    pub(crate) fn new_ia64(start_pos: usize) -> Self {
+        Self::new(BCJFilter::new_ia64(start_pos, false))
+    }
+
+    /// Creates a new BCJ decoder for RISC-V instruction filtering.
+    #[inline]
+    pub(crate) fn new_riscv(start_pos: usize) -> Self {
+        Self::new(BCJFilter::new_riscv(start_pos, false))
+    }
+}
+
+impl<R> BCJReader<R> {
+    fn new(inner: R, filter: BCJFilter) -> Self {
+        Self {
+            inner,
+            decoder: BCJDecoder::new(filter),
+        }
+    }
+
     /// Unwraps the reader, returning the underlying reader.
     pub fn into_inner(self) -> R {
         self.inner
@@ -118,6 +180,13 @@ impl<R> BCJReader<R> {
 
 impl<R: Read> Read for BCJReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> crate::Result<usize> {
+        self.decoder.read(&mut self.inner, buf)
+    }
+}
+
+impl BCJDecoder {
+    /// Filters into `buf`, reading the unfiltered data from `inner`. Errors are sticky.
+    pub(crate) fn read<R: Read>(&mut self, inner: &mut R, buf: &mut [u8]) -> crate::Result<usize> {
         if buf.is_empty() {
             return Ok(0);
         }
@@ -162,7 +231,7 @@ impl<R: Read> Read for BCJReader<R> {
             let mut in_size = FILTER_BUF_SIZE - (state.pos + state.filtered + state.unfiltered);
             let start = state.pos + state.filtered + state.unfiltered;
             let temp = &mut state.filter_buf[start..(start + in_size)];
-            in_size = match self.inner.read(temp) {
+            in_size = match inner.read(temp) {
                 Ok(s) => s,
                 Err(e) => {
                     let err = copy_error(&e);
@@ -214,6 +283,11 @@ impl<W> BCJWriter<W> {
         self.inner
     }
 
+    /// Returns a mutable reference to the underlying writer.
+    pub(crate) fn inner_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
     /// Creates a new BCJ writer for x86 instruction filtering.
     #[inline]
     pub fn new_x86(inner: W, start_pos: usize) -> Self {
@@ -54,7 +54,11 @@
 
 extern crate alloc;
 
+mod auto_reader;
+#[cfg(feature = "std")]
+mod auto_reader_mt;
 mod decoder;
+mod error;
 mod lz;
 #[cfg(feature = "lzip")]
 mod lzip;
@@ -74,6 +78,8 @@ pub mod filter;
 
 #[cfg(feature = "std")]
 mod lzma2_reader_mt;
+#[cfg(feature = "std")]
+mod lzma2_shared_reader_mt;
 #[cfg(not(feature = "std"))]
 mod no_std;
 
@@ -84,23 +90,38 @@ pub(crate) use std::io::Read;
 #[cfg(feature = "std")]
 pub(crate) use std::io::Write;
 
+pub use auto_reader::{AutoReader, Format};
+#[cfg(feature = "std")]
+pub use auto_reader_mt::AutoReaderMT;
 #[cfg(feature = "encoder")]
 pub use enc::*;
+use error::copy_error;
+#[cfg(all(any(feature = "xz", feature = "lzip"), feature = "std"))]
+pub(crate) use error::error_with_base_position;
+#[cfg(any(feature = "xz", feature = "lzip"))]
+pub(crate) use error::error_with_position;
+pub use error::{CheckValue, LzmaError};
 pub use lz::MFType;
-#[cfg(feature = "lzip")]
-pub use lzip::LZIPReader;
-#[cfg(all(feature = "lzip", feature = "std"))]
-pub use lzip::LZIPReaderMT;
 #[cfg(all(feature = "lzip", feature = "encoder", feature = "std"))]
 pub use lzip::LZIPWriterMT;
+#[cfg(all(feature = "lzip", feature = "std"))]
+pub use lzip::{
+    inspect as lzip_inspect, recover as lzip_recover, LZIPDamage, LZIPInfo, LZIPLostRange,
+    LZIPMemberInfo, LZIPReaderMT, LZIPRecovery, LZIPSeekableReader, LZIPStreamReaderMT,
+};
+#[cfg(feature = "lzip")]
+pub use lzip::{verify as lzip_verify, LZIPReader, LZIPSummary, LZIPTrailer, LZIPTrailingPolicy};
 #[cfg(all(feature = "lzip", feature = "encoder"))]
 pub use lzip::{LZIPOptions, LZIPWriter};
 pub use lzma2_reader::{get_memory_usage as lzma2_get_memory_usage, LZMA2Reader};
 #[cfg(feature = "std")]
 pub use lzma2_reader_mt::LZMA2ReaderMT;
+#[cfg(feature = "std")]
+pub use lzma2_shared_reader_mt::LZMA2SharedReaderMT;
 pub use lzma_reader::{
     get_memory_usage as lzma_get_memory_usage,
-    get_memory_usage_by_props as lzma_get_memory_usage_by_props, LZMAReader,
+    get_memory_usage_by_props as lzma_get_memory_usage_by_props, verify as lzma_verify, LZMAReader,
+    LZMASummary,
 };
 #[cfg(not(feature = "std"))]
 pub use no_std::Error;
@@ -109,10 +130,25 @@ pub use no_std::Read;
 #[cfg(not(feature = "std"))]
 pub use no_std::Write;
 use state::*;
+#[cfg(all(feature = "xz", feature = "std"))]
+pub use xz::XZReaderMT;
+#[cfg(all(feature = "xz", feature = "std"))]
+pub use xz::XZSeekableReader;
+#[cfg(all(feature = "xz", feature = "std"))]
+pub use xz::XZStreamReaderMT;
+#[cfg(all(feature = "xz", feature = "encoder", feature = "std"))]
+pub use xz::XZWriterMT;
+#[cfg(all(feature = "xz", feature = "std"))]
+pub use xz::{
+    inspect as xz_inspect, recover as xz_recover, XZBlockInfo, XZDamage, XZInfo, XZLostRange,
+    XZRecovery, XZStreamInfo,
+};
 #[cfg(feature = "xz")]
-pub use xz::{CheckType, XZReader};
+pub use xz::{
+    verify as xz_verify, CheckType, FilterConfig, FilterType, RawReader, XZReader, XZSummary,
+};
 #[cfg(all(feature = "xz", feature = "encoder"))]
-pub use xz::{XZOptions, XZWriter};
+pub use xz::{RawWriter, XZBlockOptions, XZOptions, XZWriter};
 
 /// Result type of the crate.
 #[cfg(feature = "std")]
@@ -126,7 +162,7 @@ pub type Result<T> = core::result::Result<T, Error>;
 pub const DICT_SIZE_MIN: u32 = 4096;
 
 /// The maximal size of a dictionary.
-pub const DICT_SIZE_MAX: u32 = u32::MAX & !15_u32;
+pub const DICT_SIZE_MAX: u32 = !15_u32;
 
 const LOW_SYMBOLS: usize = 1 << 3;
 const MID_SYMBOLS: usize = 1 << 3;
@@ -175,6 +211,16 @@ fn set_error(
     shutdown_flag.store(true, std::sync::atomic::Ordering::Release);
 }
 
+/// Caps the count of workers, so that all workers together stay inside the memory limit.
+#[cfg(feature = "std")]
+fn workers_for_mem_limit(max_workers: u32, worker_mem_kb: u32, mem_limit_kb: u32) -> Result<u32> {
+    if worker_mem_kb > mem_limit_kb {
+        return Err(error_mem_limit(worker_mem_kb, mem_limit_kb));
+    }
+
+    Ok(max_workers.min(mem_limit_kb / worker_mem_kb.max(1)).max(1))
+}
+
 pub(crate) struct LZMACoder {
     pub(crate) pos_mask: u32,
     pub(crate) reps: [i32; REPS],
@@ -323,6 +369,43 @@ impl LengthCoder {
     }
 }
 
+/// Counts the bytes read from the inner reader.
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    bytes_read: u64,
+}
+
+impl<R> CountingReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            bytes_read: 0,
+        }
+    }
+
+    pub(crate) fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    pub(crate) fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.bytes_read += bytes_read as u64;
+        Ok(bytes_read)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.inner.read_exact(buf)?;
+        self.bytes_read += buf.len() as u64;
+        Ok(())
+    }
+}
+
 trait ByteReader {
     fn read_u8(&mut self) -> Result<u8>;
 
@@ -433,26 +516,28 @@ fn error_invalid_input(msg: &'static str) -> Error {
 
 #[cfg(feature = "std")]
 #[inline(always)]
-fn error_invalid_data(msg: &'static str) -> Error {
-    Error::new(std::io::ErrorKind::InvalidData, msg)
+fn error_unsupported(msg: &'static str) -> Error {
+    Error::new(std::io::ErrorKind::Unsupported, msg)
 }
 
-#[cfg(feature = "std")]
 #[inline(always)]
-fn error_out_of_memory(msg: &'static str) -> Error {
-    Error::new(std::io::ErrorKind::OutOfMemory, msg)
+fn error_invalid_data(msg: &'static str) -> Error {
+    LzmaError::CorruptData {
+        compressed_offset: None,
+        uncompressed_offset: None,
+        reason: msg,
+    }
+    .into()
 }
 
-#[cfg(feature = "std")]
 #[inline(always)]
-fn error_unsupported(msg: &'static str) -> Error {
-    Error::new(std::io::ErrorKind::Unsupported, msg)
+fn error_mem_limit(needed: u32, limit: u32) -> Error {
+    LzmaError::MemLimitExceeded { needed, limit }.into()
 }
 
-#[cfg(feature = "std")]
 #[inline(always)]
-fn copy_error(error: &Error) -> Error {
-    Error::new(error.kind(), error.to_string())
+fn error_unsupported_filter(id: u64) -> Error {
+    LzmaError::UnsupportedFilter(id).into()
 }
 
 #[cfg(not(feature = "std"))]
@@ -473,26 +558,8 @@ fn error_invalid_input(msg: &'static str) -> Error {
     Error::InvalidInput(msg)
 }
 
-#[cfg(not(feature = "std"))]
-#[inline(always)]
-fn error_invalid_data(msg: &'static str) -> Error {
-    Error::InvalidData(msg)
-}
-
-#[cfg(not(feature = "std"))]
-#[inline(always)]
-fn error_out_of_memory(msg: &'static str) -> Error {
-    Error::OutOfMemory(msg)
-}
-
 #[cfg(not(feature = "std"))]
 #[inline(always)]
 fn error_unsupported(msg: &'static str) -> Error {
     Error::Unsupported(msg)
 }
-
-#[cfg(not(feature = "std"))]
-#[inline(always)]
-fn copy_error(error: &Error) -> Error {
-    *error
-}
@@ -1,6 +1,6 @@
 use alloc::{vec, vec::Vec};
 
-use crate::{error_other, Read};
+use crate::{error_invalid_data, Read};
 
 #[derive(Default)]
 pub(crate) struct LZDecoder {
@@ -80,7 +80,7 @@ impl LZDecoder {
 
     pub(crate) fn repeat(&mut self, dist: usize, len: usize) -> crate::Result<()> {
         if dist >= self.full {
-            return Err(error_other("dist overflow"));
+            return Err(error_invalid_data("dist overflow"));
         }
         let mut left = usize::min(self.limit - self.pos, len);
         self.pending_len = len - left;
@@ -155,15 +155,15 @@ impl LZDecoder {
         Ok(())
     }
 
-    pub(crate) fn flush(&mut self, out: &mut [u8], out_off: usize) -> usize {
-        let copy_size = self.pos - self.start;
+    /// Returns the data decoded since the last flush straight from the dictionary.
+    pub(crate) fn take_flushed(&mut self) -> &[u8] {
+        let start = self.start;
+        let copy_size = self.pos - start;
         if self.pos == self.buf_size {
             self.pos = 0;
         }
-        out[out_off..(out_off + copy_size)]
-            .copy_from_slice(&self.buf[self.start..(self.start + copy_size)]);
 
         self.start = self.pos;
-        copy_size
+        &self.buf[start..(start + copy_size)]
     }
 }
@@ -35,20 +35,15 @@ impl MatchFind for MatchFinders {
 }
 
 /// Match finders to use when encoding.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum MFType {
     /// Hash chain for 4 bytes entries (lower quality but faster).
+    #[default]
     HC4,
     /// Binary tree for 4 byte entries (higher quality but slower).
     BT4,
 }
 
-impl Default for MFType {
-    fn default() -> Self {
-        Self::HC4
-    }
-}
-
 impl MFType {
     #[inline]
     fn get_memory_usage(self, dict_size: u32) -> u32 {
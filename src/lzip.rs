@@ -1,30 +1,58 @@
 //! LZIP format implementation.
 
+#[cfg(feature = "std")]
+mod info;
+
 mod reader;
 
 #[cfg(feature = "std")]
 mod reader_mt;
 
+#[cfg(feature = "std")]
+mod recover;
+
+#[cfg(feature = "std")]
+mod seekable_reader;
+
+#[cfg(feature = "std")]
+mod stream_reader_mt;
+
+mod verify;
+
 #[cfg(feature = "encoder")]
 mod writer;
 
 #[cfg(all(feature = "encoder", feature = "std"))]
 mod writer_mt;
 
+#[cfg(feature = "std")]
+use std::io::{Seek, SeekFrom};
+
+#[cfg(feature = "std")]
+pub use info::{inspect, LZIPInfo, LZIPMemberInfo};
 pub use reader::LZIPReader;
 #[cfg(feature = "std")]
 pub use reader_mt::LZIPReaderMT;
+#[cfg(feature = "std")]
+pub use recover::{recover, LZIPDamage, LZIPLostRange, LZIPRecovery};
+#[cfg(feature = "std")]
+pub use seekable_reader::LZIPSeekableReader;
+#[cfg(feature = "std")]
+pub use stream_reader_mt::LZIPStreamReaderMT;
+pub use verify::{verify, LZIPSummary};
 #[cfg(feature = "encoder")]
 pub use writer::{LZIPOptions, LZIPWriter};
 #[cfg(all(feature = "encoder", feature = "std"))]
 pub use writer_mt::LZIPWriterMT;
 
 use crate::{error_invalid_data, error_invalid_input, ByteReader, Read, Result};
+#[cfg(feature = "std")]
+use crate::{error_with_base_position, error_with_position, lzma_reader::get_memory_usage};
 
 const CRC32: crc::Crc<u32, crc::Table<16>> =
     crc::Crc::<u32, crc::Table<16>>::new(&crc::CRC_32_ISO_HDLC);
 
-const LZIP_MAGIC: [u8; 4] = [b'L', b'Z', b'I', b'P'];
+pub(crate) const LZIP_MAGIC: [u8; 4] = [b'L', b'Z', b'I', b'P'];
 
 const LZIP_VERSION: u8 = 1;
 
@@ -32,6 +60,9 @@ const HEADER_SIZE: usize = 6;
 
 const TRAILER_SIZE: usize = 20;
 
+/// The trailer of version 0 members lacks the member size.
+const TRAILER_SIZE_V0: usize = 12;
+
 const MIN_DICT_SIZE: u32 = 4 * 1024;
 
 const MAX_DICT_SIZE: u32 = 512 * 1024 * 1024;
@@ -51,8 +82,9 @@ impl LZIPHeader {
             return Err(error_invalid_data("invalid LZIP magic bytes"));
         }
 
+        // Version 0 was written by lzip 1.0 to 1.3 and is still decoded.
         let version = reader.read_u8()?;
-        if version != LZIP_VERSION {
+        if version > LZIP_VERSION {
             return Err(error_invalid_data("unsupported LZIP version"));
         }
 
@@ -61,20 +93,85 @@ impl LZIPHeader {
 
         Ok(LZIPHeader { version, dict_size })
     }
+
+    /// The size of the trailer of the member.
+    fn trailer_size(&self) -> usize {
+        match self.version {
+            0 => TRAILER_SIZE_V0,
+            _ => TRAILER_SIZE,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
-pub(crate) struct LZIPTrailer {
-    crc32: u32,
-    data_size: u64,
-    member_size: u64,
+/// How the data after the last member of an LZIP file is handled.
+///
+/// Files in the wild often have padding or other data appended after the last member. Trailing
+/// data that starts with the first bytes of the magic bytes "LZIP" looks like the header of a
+/// corrupt member, so lzip reports it as an error unless `--loose-trailing` is given.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LZIPTrailingPolicy {
+    /// Any trailing data is an error, like `lzip --trailing-error`.
+    Error,
+    /// Trailing data is ignored, unless it starts like a corrupt member header. This is the
+    /// default behavior of lzip.
+    #[default]
+    Ignore,
+    /// All trailing data is ignored, like `lzip --loose-trailing`.
+    IgnoreLoose,
+    /// Decoding stops after the last member and the trailing data is not checked. It's left in
+    /// the inner reader, which `into_inner` returns. Bytes that a reader already read while
+    /// looking for the next member header are returned by `trailing_data`: at most 6 bytes for
+    /// [`LZIPReader`] and less than 64 KiB plus the size of a header for `LZIPStreamReaderMT`.
+    /// `LZIPReaderMT` reads no trailing data and seeks the inner reader to its first byte.
+    Stop,
+}
+
+impl LZIPTrailingPolicy {
+    /// Checks the first bytes of the data after the last member against the policy.
+    fn check(self, trailing_data: &[u8]) -> Result<()> {
+        if trailing_data.is_empty() {
+            return Ok(());
+        }
+
+        match self {
+            LZIPTrailingPolicy::Error => Err(error_invalid_data(
+                "trailing data after the last LZIP member",
+            )),
+            LZIPTrailingPolicy::Ignore if is_magic_prefix(trailing_data) => {
+                Err(error_invalid_data("corrupt LZIP header in trailing data"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns true if `data` starts with the first bytes of [`LZIP_MAGIC`].
+fn is_magic_prefix(data: &[u8]) -> bool {
+    let length = data.len().min(LZIP_MAGIC.len());
+    length > 0 && data[..length] == LZIP_MAGIC[..length]
+}
+
+/// The trailer of an LZIP member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LZIPTrailer {
+    /// The CRC32 of the uncompressed data of the member.
+    pub crc32: u32,
+    /// The uncompressed size of the member.
+    pub data_size: u64,
+    /// The size of the member including header and trailer.
+    pub member_size: u64,
 }
 
 impl LZIPTrailer {
-    fn parse<R: Read>(reader: &mut R) -> Result<Self> {
+    /// Parses the trailer of a member with the given header. Version 0 trailers don't store the
+    /// member size, so `member_size` is used for them instead.
+    fn parse<R: Read>(reader: &mut R, header: &LZIPHeader, member_size: u64) -> Result<Self> {
         let crc32 = reader.read_u32()?;
         let data_size = reader.read_u64()?;
-        let member_size = reader.read_u64()?;
+        let member_size = match header.version {
+            0 => member_size,
+            _ => reader.read_u64()?,
+        };
 
         Ok(LZIPTrailer {
             crc32,
@@ -84,6 +181,234 @@ impl LZIPTrailer {
     }
 }
 
+/// A member found by [`scan_members`].
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+struct LZIPMember {
+    start_pos: u64,
+    compressed_size: u64,
+    data_size: u64,
+    dict_size: u32,
+}
+
+#[cfg(feature = "std")]
+impl LZIPMember {
+    /// The position of the first byte after the member.
+    fn end_pos(&self) -> u64 {
+        self.start_pos + self.compressed_size
+    }
+
+    /// The memory in KiB a worker needs to decode the member, including the buffers for the
+    /// compressed and the decompressed data.
+    fn memory_usage(&self) -> Result<u32> {
+        let buffer_size = self
+            .compressed_size
+            .saturating_add(self.data_size)
+            .div_ceil(1024);
+        let buffer_size = u32::try_from(buffer_size).unwrap_or(u32::MAX);
+
+        Ok(get_memory_usage(self.dict_size, 3, 0)?.saturating_add(buffer_size))
+    }
+}
+
+/// The size of the blocks in which trailing data is searched backwards for the last member.
+#[cfg(feature = "std")]
+const SEARCH_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Scans all members of an LZIP file.
+///
+/// This reads from the back of the file to efficiently locate member boundaries, after skipping
+/// trailing data like lzip does. The trailers of version 0 members don't store the member size,
+/// so version 0 members and the members in front of them are located by decoding them once from
+/// the front instead.
+///
+/// Returns the members in forward order and the size of the file.
+#[cfg(feature = "std")]
+fn scan_members<R: Read + Seek>(
+    reader: &mut R,
+    mem_limit_kb: u32,
+) -> Result<(Vec<LZIPMember>, u64)> {
+    let file_size = reader.seek(SeekFrom::End(0))?;
+
+    if file_size < (HEADER_SIZE + TRAILER_SIZE_V0) as u64 {
+        return Err(error_invalid_data(
+            "File too small to contain a valid LZIP member",
+        ));
+    }
+
+    let last_member_end = find_last_member_end(reader, file_size)?;
+    let mut current_pos = last_member_end.unwrap_or(0);
+    let mut members = Vec::new();
+
+    while current_pos > 0 {
+        let Some(member) = member_before(reader, current_pos)? else {
+            break;
+        };
+
+        current_pos = member.start_pos;
+        members.push(member);
+    }
+
+    // Reverse to get members in forward order.
+    members.reverse();
+
+    if current_pos > 0 {
+        let mut prefix_members = scan_members_forward(reader, 0, current_pos, 0, mem_limit_kb)?;
+        let members_end = prefix_members.last().map_or(0, LZIPMember::end_pos);
+        if members_end != current_pos {
+            return Err(error_with_position(
+                error_invalid_data("invalid LZIP member"),
+                Some(members_end),
+                None,
+            ));
+        }
+
+        prefix_members.append(&mut members);
+        members = prefix_members;
+    }
+
+    // Version 0 members after the last version 1 member.
+    let members_end = last_member_end.unwrap_or(0);
+    let uncompressed_size = members.iter().map(|member| member.data_size).sum();
+    members.append(&mut scan_members_forward(
+        reader,
+        members_end,
+        file_size,
+        uncompressed_size,
+        mem_limit_kb,
+    )?);
+
+    if members.is_empty() {
+        return Err(error_invalid_data("No valid LZIP members found"));
+    }
+
+    Ok((members, file_size))
+}
+
+/// Searches backwards from `end_pos` for the end of the last version 1 member, skipping the
+/// trailing data after it. Returns `None` if no version 1 member was found.
+#[cfg(feature = "std")]
+fn find_last_member_end<R: Read + Seek>(reader: &mut R, end_pos: u64) -> Result<Option<u64>> {
+    if member_before(reader, end_pos)?.is_some() {
+        return Ok(Some(end_pos));
+    }
+
+    let mut block = vec![0u8; SEARCH_BLOCK_SIZE];
+    let mut pos = end_pos;
+
+    loop {
+        let block_start = pos.saturating_sub(SEARCH_BLOCK_SIZE as u64);
+        let block = &mut block[..(pos - block_start) as usize];
+        reader.seek(SeekFrom::Start(block_start))?;
+        reader.read_exact(block)?;
+
+        // Check every position at which a trailer fits into the block. Only positions with a
+        // plausible member size need to be checked against the member header.
+        for member_end in (block_start + TRAILER_SIZE as u64..=pos).rev() {
+            let offset = (member_end - block_start) as usize;
+            let member_size = u64::from_le_bytes(block[offset - 8..offset].try_into().unwrap());
+
+            if member_size >= (HEADER_SIZE + TRAILER_SIZE) as u64
+                && member_size <= member_end
+                && member_before(reader, member_end)?.is_some()
+            {
+                return Ok(Some(member_end));
+            }
+        }
+
+        if block_start == 0 {
+            return Ok(None);
+        }
+
+        // Overlap the blocks, so that trailers crossing the block start are checked.
+        pos = block_start + TRAILER_SIZE as u64 - 1;
+    }
+}
+
+/// Reads the version 1 member that ends at `end_pos`. Returns `None` if the bytes in front of
+/// `end_pos` are not the trailer of a version 1 member.
+#[cfg(feature = "std")]
+fn member_before<R: Read + Seek>(reader: &mut R, end_pos: u64) -> Result<Option<LZIPMember>> {
+    if end_pos < (HEADER_SIZE + TRAILER_SIZE) as u64 {
+        return Ok(None);
+    }
+
+    // Seek to read the trailer (last 20 bytes of current member).
+    reader.seek(SeekFrom::Start(end_pos - TRAILER_SIZE as u64))?;
+    let mut trailer_buf = [0u8; TRAILER_SIZE];
+    reader.read_exact(&mut trailer_buf)?;
+
+    // data_size is in bytes 4-11 of the trailer (little endian)
+    let data_size = u64::from_le_bytes(trailer_buf[4..12].try_into().unwrap());
+
+    // member_size is in bytes 12-19 of the trailer (little endian)
+    let member_size = u64::from_le_bytes(trailer_buf[12..20].try_into().unwrap());
+
+    if member_size < (HEADER_SIZE + TRAILER_SIZE) as u64 || member_size > end_pos {
+        return Ok(None);
+    }
+
+    let member_start = end_pos - member_size;
+
+    // Verify this looks like a valid LZIP header.
+    reader.seek(SeekFrom::Start(member_start))?;
+    let header = match LZIPHeader::parse(reader) {
+        Ok(header) if header.version == LZIP_VERSION => header,
+        _ => return Ok(None),
+    };
+
+    Ok(Some(LZIPMember {
+        start_pos: member_start,
+        compressed_size: member_size,
+        data_size,
+        dict_size: header.dict_size,
+    }))
+}
+
+/// Locates the members between `start_pos` and `end_pos` by decoding them. The scan stops at the
+/// first data that is not a member. `uncompressed_pos` is the uncompressed position of the data
+/// at `start_pos`.
+#[cfg(feature = "std")]
+fn scan_members_forward<R: Read + Seek>(
+    reader: &mut R,
+    start_pos: u64,
+    end_pos: u64,
+    uncompressed_pos: u64,
+    mem_limit_kb: u32,
+) -> Result<Vec<LZIPMember>> {
+    reader.seek(SeekFrom::Start(start_pos))?;
+    if LZIPHeader::parse(reader).is_err() {
+        return Ok(Vec::new());
+    }
+
+    reader.seek(SeekFrom::Start(start_pos))?;
+    let mut lzip_reader =
+        LZIPReader::new_mem_limit(reader.by_ref().take(end_pos - start_pos), mem_limit_kb)?;
+    lzip_reader.set_trailing_policy(LZIPTrailingPolicy::IgnoreLoose);
+    lzip_reader
+        .decode_to_end(|_| {})
+        .map_err(|error| error_with_base_position(error, start_pos, uncompressed_pos))?;
+    let trailers = lzip_reader.trailers().to_vec();
+
+    let mut members = Vec::with_capacity(trailers.len());
+    let mut member_start = start_pos;
+    for trailer in trailers {
+        reader.seek(SeekFrom::Start(member_start))?;
+        let header = LZIPHeader::parse(reader)?;
+
+        members.push(LZIPMember {
+            start_pos: member_start,
+            compressed_size: trailer.member_size,
+            data_size: trailer.data_size,
+            dict_size: header.dict_size,
+        });
+
+        member_start += trailer.member_size;
+    }
+
+    Ok(members)
+}
+
 /// Decode LZIP dictionary size from encoded byte:
 ///
 /// The dictionary size is calculated by taking a power of 2 (the base size)
@@ -0,0 +1,96 @@
+use std::io::{self, Seek, SeekFrom};
+
+use super::{scan_members, LZIPHeader, LZIPMember, LZIPTrailer};
+use crate::{lzma_reader::get_memory_usage, Read};
+
+/// Metadata of an LZIP file, as shown by `lziprecover --list`.
+///
+/// Created by [`inspect`].
+#[derive(Debug, Clone)]
+pub struct LZIPInfo {
+    /// The size of the whole file in bytes.
+    pub file_size: u64,
+    /// All members of the file in forward order.
+    pub members: Vec<LZIPMemberInfo>,
+    /// The size of the data after the last member.
+    pub trailing_size: u64,
+}
+
+/// Metadata of a single LZIP member.
+#[derive(Debug, Clone)]
+pub struct LZIPMemberInfo {
+    /// The offset of the member header in the file.
+    pub start_pos: u64,
+    /// The size of the member including header and trailer.
+    pub member_size: u64,
+    /// The offset of the first uncompressed byte of the member.
+    pub uncompressed_offset: u64,
+    /// The uncompressed size of the member, as stored in the trailer.
+    pub data_size: u64,
+    /// The version of the member format. Version 0 members have no member size in the trailer.
+    pub version: u8,
+    /// The dictionary size of the member.
+    pub dict_size: u32,
+    /// The CRC32 of the uncompressed data, as stored in the trailer.
+    pub crc32: u32,
+    /// The memory in KiB that is needed to decode the member.
+    pub memory_usage: u32,
+}
+
+impl LZIPInfo {
+    /// Returns the total uncompressed size of all members.
+    pub fn uncompressed_size(&self) -> u64 {
+        self.members.iter().map(|member| member.data_size).sum()
+    }
+
+    /// Returns the memory in KiB that is needed to decode the file with a single thread.
+    pub fn memory_usage(&self) -> u32 {
+        self.members
+            .iter()
+            .map(|member| member.memory_usage)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Reads the metadata of an LZIP file without decompressing it.
+///
+/// The members are found by walking backwards through the member sizes in the trailers, the same
+/// way as [`crate::LZIPReaderMT`] does. Version 0 members are decoded once to find their ends.
+/// After that, the header and trailer of every member are read.
+pub fn inspect<R: Read + Seek>(mut reader: R) -> io::Result<LZIPInfo> {
+    let (scanned_members, file_size) = scan_members(&mut reader, u32::MAX)?;
+
+    let mut members = Vec::with_capacity(scanned_members.len());
+    let mut uncompressed_offset = 0u64;
+
+    for member in &scanned_members {
+        reader.seek(SeekFrom::Start(member.start_pos))?;
+        let header = LZIPHeader::parse(&mut reader)?;
+
+        let trailer_pos = member.end_pos() - header.trailer_size() as u64;
+        reader.seek(SeekFrom::Start(trailer_pos))?;
+        let trailer = LZIPTrailer::parse(&mut reader, &header, member.compressed_size)?;
+
+        members.push(LZIPMemberInfo {
+            start_pos: member.start_pos,
+            member_size: member.compressed_size,
+            uncompressed_offset,
+            data_size: trailer.data_size,
+            version: header.version,
+            dict_size: header.dict_size,
+            crc32: trailer.crc32,
+            memory_usage: get_memory_usage(header.dict_size, 3, 0)?,
+        });
+
+        uncompressed_offset += trailer.data_size;
+    }
+
+    let members_end = scanned_members.last().map_or(0, LZIPMember::end_pos);
+
+    Ok(LZIPInfo {
+        file_size,
+        members,
+        trailing_size: file_size - members_end,
+    })
+}
@@ -1,39 +1,15 @@
 use alloc::vec::Vec;
 
-use super::{LZIPHeader, LZIPTrailer, CRC32, HEADER_SIZE, TRAILER_SIZE};
-use crate::{error_invalid_data, error_invalid_input, LZMAReader, Read, Result};
+use super::{LZIPHeader, LZIPSummary, LZIPTrailer, LZIPTrailingPolicy, CRC32, HEADER_SIZE};
+use crate::{
+    error_invalid_data, error_mem_limit, error_with_position, lzma_reader::get_memory_usage,
+    CheckValue, CountingReader, LZMAReader, LzmaError, Read, Result,
+};
 
-struct CountingReader<R> {
-    inner: R,
-    bytes_read: u64,
-}
-
-impl<R> CountingReader<R> {
-    fn new(inner: R) -> Self {
-        Self {
-            inner,
-            bytes_read: 0,
-        }
-    }
-
-    fn bytes_read(&self) -> u64 {
-        self.bytes_read
-    }
-}
-
-impl<R: Read> Read for CountingReader<R> {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let bytes_read = self.inner.read(buf)?;
-        self.bytes_read += bytes_read as u64;
-        Ok(bytes_read)
-    }
+pub(super) type CrcDigest = crc::Digest<'static, u32, crc::Table<16>>;
 
-    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
-        self.inner.read_exact(buf)?;
-        self.bytes_read += buf.len() as u64;
-        Ok(())
-    }
-}
+/// The maximal size of the data that is decoded at once without an output buffer.
+const DECODE_CHUNK_SIZE: usize = 64 * 1024;
 
 /// A single-threaded LZIP decompressor.
 pub struct LZIPReader<R> {
@@ -41,55 +17,118 @@ pub struct LZIPReader<R> {
     lzma_reader: Option<LZMAReader<CountingReader<R>>>,
     current_header: Option<LZIPHeader>,
     finished: bool,
-    trailer_buf: Vec<u8>,
-    crc_digest: Option<crc::Digest<'static, u32, crc::Table<16>>>,
+    crc_digest: Option<CrcDigest>,
     data_size: u64,
+    mem_limit_kb: u32,
+    member_start: u64,
+    position: u64,
+    trailers: Vec<LZIPTrailer>,
+    trailing_policy: LZIPTrailingPolicy,
+    trailing_data: Vec<u8>,
+    single_member: bool,
 }
 
 impl<R> LZIPReader<R> {
     /// Consume the LZIPReader and return the inner reader.
     pub fn into_inner(mut self) -> R {
         if let Some(lzma_reader) = self.lzma_reader.take() {
-            return lzma_reader.into_inner().inner;
+            return lzma_reader.into_inner().into_inner();
         }
 
         self.inner.take().expect("inner reader not set")
     }
+
+    /// Returns the verified trailers of all members that were decoded completely, in file order.
+    pub fn trailers(&self) -> &[LZIPTrailer] {
+        &self.trailers
+    }
+
+    /// Sets how the data after the last member is handled. The default is
+    /// [`LZIPTrailingPolicy::Ignore`].
+    pub fn set_trailing_policy(&mut self, trailing_policy: LZIPTrailingPolicy) {
+        self.trailing_policy = trailing_policy;
+    }
+
+    /// Returns the bytes after the last member that were read while looking for the next member
+    /// header, if the trailing policy is [`LZIPTrailingPolicy::Stop`]. These are at most the
+    /// first 6 bytes of the trailing data, the rest is still unread in the inner reader.
+    pub fn trailing_data(&self) -> &[u8] {
+        &self.trailing_data
+    }
 }
 
 impl<R: Read> LZIPReader<R> {
     /// Create a new LZIP reader.
     pub fn new(inner: R) -> Result<Self> {
+        Self::new_mem_limit(inner, u32::MAX)
+    }
+
+    /// Create a new LZIP reader with a memory usage limit.
+    /// `mem_limit_kb` is the memory usage limit in kibibytes (KiB). `u32::MAX` means no limit.
+    ///
+    /// Every member is checked against the limit before its decoder is created.
+    pub fn new_mem_limit(inner: R, mem_limit_kb: u32) -> Result<Self> {
         Ok(Self {
             inner: Some(inner),
             lzma_reader: None,
             current_header: None,
             finished: false,
-            trailer_buf: Vec::with_capacity(TRAILER_SIZE),
             crc_digest: None,
             data_size: 0,
+            mem_limit_kb,
+            member_start: 0,
+            position: 0,
+            trailers: Vec::new(),
+            trailing_policy: LZIPTrailingPolicy::default(),
+            trailing_data: Vec::new(),
+            single_member: false,
         })
     }
 
+    /// Stops decoding after the first member, without looking at the data after it.
+    #[cfg(feature = "std")]
+    pub(super) fn set_single_member(&mut self, single_member: bool) {
+        self.single_member = single_member;
+    }
+
     /// Start processing the next LZIP member.
     /// Returns Ok(true) if a new member was started, Ok(false) if EOF was reached.
     fn start_next_member(&mut self) -> Result<bool> {
-        let mut reader = self.inner.take().expect("inner reader not set");
+        let reader = self.inner.as_mut().expect("inner reader not set");
 
-        let header = match LZIPHeader::parse(&mut reader) {
+        let mut header_buf = [0u8; HEADER_SIZE];
+        let mut header_len = 0;
+        while header_len < HEADER_SIZE {
+            match reader.read(&mut header_buf[header_len..])? {
+                0 => break,
+                bytes_read => header_len += bytes_read,
+            }
+        }
+
+        if header_len == 0 {
+            return Ok(false);
+        }
+
+        let header_bytes = &header_buf[..header_len];
+        let header = match LZIPHeader::parse(&mut &header_bytes[..]) {
             Ok(header) => header,
+            Err(error) if self.member_start == 0 => return Err(error),
             Err(_) => {
-                // If header parsing fails, we've probably reached EOF:
-                // Put the reader back and indicate we're done:
-                self.inner = Some(reader);
+                // The data after the last member is not a member.
+                self.trailing_policy.check(header_bytes)?;
+                if self.trailing_policy == LZIPTrailingPolicy::Stop {
+                    self.trailing_data = header_bytes.to_vec();
+                }
                 return Ok(false);
             }
         };
 
-        if header.version != 1 {
-            return Err(error_invalid_input("unsupported LZIP version"));
+        let memory_usage = get_memory_usage(header.dict_size, 3, 0)?;
+        if memory_usage > self.mem_limit_kb {
+            return Err(error_mem_limit(memory_usage, self.mem_limit_kb));
         }
 
+        let reader = self.inner.take().expect("inner reader not set");
         let counting_reader = CountingReader::new(reader);
 
         // Create LZMA reader with LZMA-302eos properties:
@@ -102,7 +141,6 @@ impl<R: Read> LZIPReader<R> {
 
         self.current_header = Some(header);
         self.lzma_reader = Some(lzma_reader);
-        self.trailer_buf.clear();
         self.crc_digest = Some(CRC32.digest());
         self.data_size = 0;
 
@@ -112,17 +150,25 @@ impl<R: Read> LZIPReader<R> {
     fn finish_current_member(&mut self) -> Result<()> {
         let lzma_reader = self.lzma_reader.take().expect("lzma reader not set");
 
+        let header = self.current_header.take().expect("header not set");
+
         let counting_reader = lzma_reader.into_inner();
         let compressed_bytes = counting_reader.bytes_read();
+        let actual_member_size =
+            HEADER_SIZE as u64 + compressed_bytes + header.trailer_size() as u64;
 
-        let mut inner_reader = counting_reader.inner;
-        let trailer = LZIPTrailer::parse(&mut inner_reader)?;
+        let mut inner_reader = counting_reader.into_inner();
+        let trailer = LZIPTrailer::parse(&mut inner_reader, &header, actual_member_size)?;
 
         let computed_crc = self.crc_digest.take().expect("no CRC digest").finalize();
 
         if computed_crc != trailer.crc32 {
             self.inner = Some(inner_reader);
-            return Err(error_invalid_data("LZIP CRC32 mismatch"));
+            return Err(LzmaError::ChecksumMismatch {
+                expected: CheckValue::Crc32(trailer.crc32),
+                actual: CheckValue::Crc32(computed_crc),
+            }
+            .into());
         }
 
         if self.data_size != trailer.data_size {
@@ -130,7 +176,6 @@ impl<R: Read> LZIPReader<R> {
             return Err(error_invalid_data("LZIP data size mismatch"));
         }
 
-        let actual_member_size = HEADER_SIZE as u64 + compressed_bytes + TRAILER_SIZE as u64;
         if actual_member_size != trailer.member_size {
             self.inner = Some(inner_reader);
             return Err(error_invalid_data("LZIP member size mismatch"));
@@ -138,26 +183,88 @@ impl<R: Read> LZIPReader<R> {
 
         // Store the reader for potential next member.
         self.inner = Some(inner_reader);
+        self.member_start += actual_member_size;
+        self.trailers.push(trailer);
 
         Ok(())
     }
-}
 
-impl<R: Read> Read for LZIPReader<R> {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        if buf.is_empty() {
-            return Ok(0);
+    /// The offset in the compressed data up to which the current member was read.
+    fn compressed_position(&self) -> u64 {
+        match self.lzma_reader.as_ref() {
+            Some(lzma_reader) => {
+                self.member_start + HEADER_SIZE as u64 + lzma_reader.get_ref().bytes_read()
+            }
+            None => self.member_start,
         }
+    }
+
+    fn read_members(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.decode_members(|lzma_reader, crc_digest| {
+            let bytes_read = lzma_reader.read(buf)?;
+            crc_digest.update(&buf[..bytes_read]);
+            Ok(bytes_read)
+        })
+    }
+
+    /// Decodes all remaining members like [`Self::decode_to_end`] and returns a summary of the
+    /// members.
+    pub(super) fn decode_all<F: FnMut(&[u8])>(mut self, sink: F) -> Result<LZIPSummary> {
+        self.decode_to_end(sink)?;
+
+        Ok(LZIPSummary {
+            member_count: self.trailers.len() as u64,
+            compressed_size: self.member_start,
+            uncompressed_size: self.position,
+            checks: self
+                .trailers
+                .iter()
+                .map(|trailer| CheckValue::Crc32(trailer.crc32))
+                .collect(),
+        })
+    }
 
+    /// Decodes all remaining members and passes their data to `sink` straight from the
+    /// dictionary of the decoder.
+    pub(super) fn decode_to_end<F: FnMut(&[u8])>(&mut self, mut sink: F) -> Result<()> {
+        loop {
+            let bytes_read = self
+                .decode_members(|lzma_reader, crc_digest| {
+                    lzma_reader.decode_with(DECODE_CHUNK_SIZE, |data| {
+                        crc_digest.update(data);
+                        sink(data);
+                    })
+                })
+                .map_err(|error| {
+                    error_with_position(
+                        error,
+                        Some(self.compressed_position()),
+                        Some(self.position),
+                    )
+                })?;
+
+            if bytes_read == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Decodes the next data of the current member with `decode`, which also has to update the
+    /// CRC32 of the member.
+    fn decode_members<D>(&mut self, mut decode: D) -> Result<usize>
+    where
+        D: FnMut(&mut LZMAReader<CountingReader<R>>, &mut CrcDigest) -> Result<usize>,
+    {
         loop {
             // If we have an active LZMA reader, try to read from it.
             if let Some(ref mut lzma_reader) = self.lzma_reader {
-                match lzma_reader.read(buf) {
+                let crc_digest = self.crc_digest.as_mut().expect("no CRC digest");
+                match decode(lzma_reader, crc_digest) {
                     Ok(0) => {
                         // Current member is finished, verify trailer.
                         self.finish_current_member()?;
 
-                        if !self.start_next_member()? {
+                        if self.single_member || !self.start_next_member()? {
                             // No more members, we're done.
                             self.finished = true;
                             return Ok(0);
@@ -167,11 +274,8 @@ impl<R: Read> Read for LZIPReader<R> {
                         continue;
                     }
                     Ok(bytes_read) => {
-                        // Update CRC with the decompressed data
-                        if let Some(ref mut crc_digest) = self.crc_digest {
-                            crc_digest.update(&buf[..bytes_read]);
-                            self.data_size += bytes_read as u64;
-                        }
+                        self.data_size += bytes_read as u64;
+                        self.position += bytes_read as u64;
                         return Ok(bytes_read);
                     }
                     Err(e) => {
@@ -192,3 +296,15 @@ impl<R: Read> Read for LZIPReader<R> {
         }
     }
 }
+
+impl<R: Read> Read for LZIPReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.read_members(buf).map_err(|error| {
+            error_with_position(error, Some(self.compressed_position()), Some(self.position))
+        })
+    }
+}
@@ -9,26 +9,24 @@ use std::{
     thread,
 };
 
-use super::{HEADER_SIZE, TRAILER_SIZE};
+use super::{scan_members, LZIPMember, LZIPSummary, LZIPTrailer, LZIPTrailingPolicy, HEADER_SIZE};
 use crate::{
-    error_invalid_data, set_error,
+    error_with_base_position, error_with_position, set_error,
     work_queue::{WorkStealingQueue, WorkerHandle},
-    LZIPReader, Read,
+    workers_for_mem_limit, CheckValue, LZIPReader, Read,
 };
 
-#[derive(Debug, Clone)]
-struct LZIPMember {
-    start_pos: u64,
-    compressed_size: u64,
-}
-
 /// A work unit for a worker thread.
-/// Contains the sequence number and the raw compressed bytes for a single LZIP member.
-type WorkUnit = (u64, Vec<u8>);
+/// Contains the sequence number, the raw compressed bytes for a single LZIP member and the
+/// compressed and uncompressed position of the member.
+pub(super) type WorkUnit = (u64, Vec<u8>, (u64, u64));
+
+/// A decoded member. Contains the decompressed data and the verified CRC32 of the member.
+pub(super) type DecodedMember = (Vec<u8>, Option<CheckValue>);
 
 /// A result unit from a worker thread.
-/// Contains the sequence number and the decompressed data.
-type ResultUnit = (u64, Vec<u8>);
+/// Contains the sequence number and the decoded member.
+pub(super) type ResultUnit = (u64, DecodedMember);
 
 enum State {
     /// Dispatching members to worker threads.
@@ -45,13 +43,19 @@ enum State {
 pub struct LZIPReaderMT<R: Read + Seek> {
     inner: Option<R>,
     members: Vec<LZIPMember>,
+    file_size: u64,
+    trailing_policy: LZIPTrailingPolicy,
+    verify_only: bool,
     result_rx: Receiver<ResultUnit>,
     result_tx: Sender<ResultUnit>,
     next_sequence_to_dispatch: u64,
+    uncompressed_dispatched: u64,
     next_sequence_to_return: u64,
     last_sequence_id: Option<u64>,
-    out_of_order_chunks: BTreeMap<u64, Vec<u8>>,
+    out_of_order_chunks: BTreeMap<u64, DecodedMember>,
     current_chunk: Cursor<Vec<u8>>,
+    current_trailer: Option<LZIPTrailer>,
+    trailers: Vec<LZIPTrailer>,
     shutdown_flag: Arc<AtomicBool>,
     error_store: Arc<Mutex<Option<io::Error>>>,
     state: State,
@@ -67,6 +71,18 @@ impl<R: Read + Seek> LZIPReaderMT<R> {
     /// - `inner`: The reader to read compressed data from. Must implement Seek.
     /// - `num_workers`: The maximum number of worker threads for decompression. Currently capped at 256 threads.
     pub fn new(inner: R, num_workers: u32) -> io::Result<Self> {
+        Self::new_mem_limit(inner, num_workers, u32::MAX)
+    }
+
+    /// Creates a new multi-threaded LZIP reader with a memory usage limit.
+    ///
+    /// Every worker needs its own dictionary and holds a whole member in memory, so the count of
+    /// workers is reduced until all workers together stay inside the limit.
+    ///
+    /// - `inner`: The reader to read compressed data from. Must implement Seek.
+    /// - `num_workers`: The maximum number of worker threads for decompression. Currently capped at 256 threads.
+    /// - `mem_limit_kb`: The memory usage limit of all workers in kibibytes (KiB). `u32::MAX` means no limit.
+    pub fn new_mem_limit(inner: R, num_workers: u32, mem_limit_kb: u32) -> io::Result<Self> {
         let max_workers = num_workers.clamp(1, 256);
 
         let work_queue = WorkStealingQueue::new();
@@ -78,13 +94,19 @@ impl<R: Read + Seek> LZIPReaderMT<R> {
         let mut reader = Self {
             inner: Some(inner),
             members: Vec::new(),
+            file_size: 0,
+            trailing_policy: LZIPTrailingPolicy::default(),
+            verify_only: false,
             result_rx,
             result_tx,
             next_sequence_to_dispatch: 0,
+            uncompressed_dispatched: 0,
             next_sequence_to_return: 0,
             last_sequence_id: None,
             out_of_order_chunks: BTreeMap::new(),
             current_chunk: Cursor::new(Vec::new()),
+            current_trailer: None,
+            trailers: Vec::new(),
             shutdown_flag,
             error_store,
             state: State::Dispatching,
@@ -94,89 +116,43 @@ impl<R: Read + Seek> LZIPReaderMT<R> {
             worker_handles: Vec::new(),
         };
 
-        reader.scan_members()?;
+        let mut inner = reader.inner.take().expect("inner reader not set");
+        let scan_result = scan_members(&mut inner, mem_limit_kb);
+        reader.inner = Some(inner);
+        (reader.members, reader.file_size) = scan_result?;
 
-        Ok(reader)
-    }
-
-    /// Scan the LZIP file to collect information about all members.
-    /// This reads from the back of the file to efficiently locate member boundaries.
-    fn scan_members(&mut self) -> io::Result<()> {
-        let mut reader = self.inner.take().expect("inner reader not set");
-
-        let file_size = reader.seek(SeekFrom::End(0))?;
-
-        if file_size < (HEADER_SIZE + TRAILER_SIZE) as u64 {
-            self.inner = Some(reader);
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "File too small to contain a valid LZIP member",
-            ));
-        }
-
-        let mut current_pos = file_size;
-
-        while current_pos > 0 {
-            if current_pos < TRAILER_SIZE as u64 {
-                break;
-            }
-
-            // Seek to read the trailer (last 20 bytes of current member).
-            reader.seek(SeekFrom::Start(current_pos - TRAILER_SIZE as u64))?;
-            let mut trailer_buf = [0u8; TRAILER_SIZE];
-            reader.read_exact(&mut trailer_buf)?;
-
-            // member_size is in bytes 12-19 of the trailer (little endian)
-            let member_size = u64::from_le_bytes([
-                trailer_buf[12],
-                trailer_buf[13],
-                trailer_buf[14],
-                trailer_buf[15],
-                trailer_buf[16],
-                trailer_buf[17],
-                trailer_buf[18],
-                trailer_buf[19],
-            ]);
-
-            if member_size == 0 || member_size > current_pos {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "Invalid LZIP member size in trailer",
-                ));
-            }
-
-            let member_start = current_pos - member_size;
-
-            // Verify this looks like a valid LZIP header.
-            reader.seek(SeekFrom::Start(member_start))?;
-            let mut header_buf = [0u8; 4];
-            reader.read_exact(&mut header_buf)?;
-
-            if header_buf != [b'L', b'Z', b'I', b'P'] {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "Invalid LZIP magic bytes",
-                ));
+        if mem_limit_kb != u32::MAX {
+            let mut worker_mem_kb = 0;
+            for member in &reader.members {
+                worker_mem_kb = worker_mem_kb.max(member.memory_usage()?);
             }
 
-            self.members.push(LZIPMember {
-                start_pos: member_start,
-                compressed_size: member_size,
-            });
-
-            current_pos = member_start;
+            reader.max_workers =
+                workers_for_mem_limit(reader.max_workers, worker_mem_kb, mem_limit_kb)?;
         }
 
-        if self.members.is_empty() {
-            self.inner = Some(reader);
-            return Err(error_invalid_data("No valid LZIP members found"));
+        Ok(reader)
+    }
+
+    /// Checks the data after the last member against the trailing policy. If the policy is
+    /// [`LZIPTrailingPolicy::Stop`], the inner reader is left at the first byte of the trailing
+    /// data instead.
+    fn handle_trailing_data(&mut self) -> io::Result<()> {
+        let members_end = self.members.last().map_or(0, |member| member.end_pos());
+        let trailing_size = self.file_size - members_end;
+        let reader = self.inner.as_mut().expect("inner reader not set");
+
+        reader.seek(SeekFrom::Start(members_end))?;
+        if self.trailing_policy == LZIPTrailingPolicy::Stop {
+            return Ok(());
         }
 
-        // Reverse to get members in forward order.
-        self.members.reverse();
-        self.inner = Some(reader);
+        let mut trailing_data = vec![0u8; trailing_size.min(HEADER_SIZE as u64) as usize];
+        reader.read_exact(&mut trailing_data)?;
 
-        Ok(())
+        self.trailing_policy
+            .check(&trailing_data)
+            .map_err(|error| error_with_position(error, Some(members_end), None))
     }
 
     fn spawn_worker_thread(&mut self) {
@@ -185,6 +161,7 @@ impl<R: Read + Seek> LZIPReaderMT<R> {
         let shutdown_flag = Arc::clone(&self.shutdown_flag);
         let error_store = Arc::clone(&self.error_store);
         let active_workers = Arc::clone(&self.active_workers);
+        let verify_only = self.verify_only;
 
         let handle = thread::spawn(move || {
             worker_thread_logic(
@@ -193,6 +170,7 @@ impl<R: Read + Seek> LZIPReaderMT<R> {
                 shutdown_flag,
                 error_store,
                 active_workers,
+                verify_only,
             );
         });
 
@@ -204,6 +182,52 @@ impl<R: Read + Seek> LZIPReaderMT<R> {
         self.members.len()
     }
 
+    /// Returns the verified trailers of all members whose data was read completely, in file
+    /// order.
+    pub fn trailers(&self) -> &[LZIPTrailer] {
+        &self.trailers
+    }
+
+    /// Sets how the data after the last member is handled. The default is
+    /// [`LZIPTrailingPolicy::Ignore`]. The policy is applied after the data of the last member
+    /// was read.
+    pub fn set_trailing_policy(&mut self, trailing_policy: LZIPTrailingPolicy) {
+        self.trailing_policy = trailing_policy;
+    }
+
+    /// Unwraps the reader, returning the underlying reader.
+    ///
+    /// If the trailing policy is [`LZIPTrailingPolicy::Stop`] and the data of the last member was
+    /// read, the inner reader is positioned at the first byte after the last member.
+    pub fn into_inner(mut self) -> R {
+        self.inner.take().expect("inner reader not set")
+    }
+
+    /// Decodes all members in parallel without producing output and returns a summary, like
+    /// [`lzip_verify`](crate::lzip_verify) does with a single thread.
+    ///
+    /// The workers calculate the CRC32 of the members straight from the dictionary of their
+    /// decoder, so only the compressed members are held in memory. Members that were already read
+    /// are not part of the summary.
+    pub fn verify(mut self) -> io::Result<LZIPSummary> {
+        self.verify_only = true;
+
+        let first_member = (self.next_sequence_to_return as usize).min(self.members.len());
+        let mut checks = Vec::with_capacity(self.members.len() - first_member);
+        while let Some((_, check)) = self.get_next_uncompressed_chunk()? {
+            checks.extend(check);
+        }
+
+        let members = &self.members[first_member..];
+
+        Ok(LZIPSummary {
+            member_count: checks.len() as u64,
+            compressed_size: members.iter().map(|member| member.compressed_size).sum(),
+            uncompressed_size: members.iter().map(|member| member.data_size).sum(),
+            checks,
+        })
+    }
+
     fn dispatch_next_member(&mut self) -> io::Result<bool> {
         let member_index = self.next_sequence_to_dispatch as usize;
 
@@ -221,9 +245,12 @@ impl<R: Read + Seek> LZIPReaderMT<R> {
 
         self.inner = Some(reader);
 
+        let position = (member.start_pos, self.uncompressed_dispatched);
+        let data_size = member.data_size;
+
         if !self
             .work_queue
-            .push((self.next_sequence_to_dispatch, member_data))
+            .push((self.next_sequence_to_dispatch, member_data, position))
         {
             // Queue is closed, this indicates shutdown.
             self.state = State::Error;
@@ -250,10 +277,22 @@ impl<R: Read + Seek> LZIPReaderMT<R> {
         }
 
         self.next_sequence_to_dispatch += 1;
+        self.uncompressed_dispatched += data_size;
         Ok(true)
     }
 
-    fn get_next_uncompressed_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+    /// Finishes the stream after all members were returned.
+    fn finish(&mut self) {
+        match self.handle_trailing_data() {
+            Ok(()) => self.state = State::Finished,
+            Err(error) => {
+                set_error(error, &self.error_store, &self.shutdown_flag);
+                self.state = State::Error;
+            }
+        }
+    }
+
+    fn get_next_uncompressed_chunk(&mut self) -> io::Result<Option<DecodedMember>> {
         loop {
             // Always check for already-received chunks first.
             if let Some(result) = self
@@ -338,7 +377,7 @@ impl<R: Read + Seek> LZIPReaderMT<R> {
                 State::Draining => {
                     if let Some(last_seq) = self.last_sequence_id {
                         if self.next_sequence_to_return > last_seq {
-                            self.state = State::Finished;
+                            self.finish();
                             continue;
                         }
                     }
@@ -355,7 +394,7 @@ impl<R: Read + Seek> LZIPReaderMT<R> {
                         }
                         Err(_) => {
                             // All workers finished, and channel is empty. We are done.
-                            self.state = State::Finished;
+                            self.finish();
                         }
                     }
                 }
@@ -374,15 +413,16 @@ impl<R: Read + Seek> LZIPReaderMT<R> {
 }
 
 /// The logic for a single worker thread.
-fn worker_thread_logic(
+pub(super) fn worker_thread_logic(
     worker_handle: WorkerHandle<WorkUnit>,
     result_tx: Sender<ResultUnit>,
     shutdown_flag: Arc<AtomicBool>,
     error_store: Arc<Mutex<Option<io::Error>>>,
     active_workers: Arc<AtomicU32>,
+    verify_only: bool,
 ) {
     while !shutdown_flag.load(Ordering::Acquire) {
-        let (seq, work_unit_data) = match worker_handle.steal() {
+        let (seq, work_unit_data, position) = match worker_handle.steal() {
             Some(work) => {
                 active_workers.fetch_add(1, Ordering::Release);
                 work
@@ -393,23 +433,23 @@ fn worker_thread_logic(
             }
         };
 
-        let reader_result = LZIPReader::new(work_unit_data.as_slice());
-
-        let mut lzip_reader = match reader_result {
-            Ok(reader) => reader,
-            Err(error) => {
-                active_workers.fetch_sub(1, Ordering::Release);
-                set_error(error, &error_store, &shutdown_flag);
-                return;
-            }
-        };
-
         let mut decompressed_data = Vec::new();
-        let result = match lzip_reader.read_to_end(&mut decompressed_data) {
-            Ok(_) => decompressed_data,
+        let result = LZIPReader::new(work_unit_data.as_slice()).and_then(|lzip_reader| {
+            lzip_reader.decode_all(|data| {
+                if !verify_only {
+                    decompressed_data.extend_from_slice(data);
+                }
+            })
+        });
+
+        let result = match result {
+            Ok(summary) => (decompressed_data, summary.checks.first().copied()),
             Err(error) => {
                 active_workers.fetch_sub(1, Ordering::Release);
+                let error = error_with_base_position(error, position.0, position.1);
                 set_error(error, &error_store, &shutdown_flag);
+                // Wake up the reader, so that it picks up the stored error.
+                let _ = result_tx.send((seq, (Vec::new(), None)));
                 return;
             }
         };
@@ -435,13 +475,27 @@ impl<R: Read + Seek> Read for LZIPReaderMT<R> {
             return Ok(bytes_read);
         }
 
+        if let Some(trailer) = self.current_trailer.take() {
+            self.trailers.push(trailer);
+        }
+
         let chunk_data = self.get_next_uncompressed_chunk()?;
 
-        let Some(chunk_data) = chunk_data else {
+        let Some((chunk_data, check)) = chunk_data else {
             // This is the clean end of the stream.
             return Ok(0);
         };
 
+        // The member was verified against its trailer by the worker.
+        let member = &self.members[self.next_sequence_to_return as usize - 1];
+        if let Some(CheckValue::Crc32(crc32)) = check {
+            self.current_trailer = Some(LZIPTrailer {
+                crc32,
+                data_size: member.data_size,
+                member_size: member.compressed_size,
+            });
+        }
+
         self.current_chunk = Cursor::new(chunk_data);
 
         // Recursive call to read the new chunk data.
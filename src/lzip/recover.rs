@@ -0,0 +1,365 @@
+use std::io::{self, ErrorKind, Seek, SeekFrom, Write};
+
+use super::{
+    scan_members, LZIPHeader, LZIPMember, LZIPTrailer, CRC32, HEADER_SIZE, LZIP_MAGIC,
+    SEARCH_BLOCK_SIZE,
+};
+use crate::{error_invalid_data, CountingReader, LZMAReader, Read};
+
+/// The reason why a part of an LZIP file couldn't be recovered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LZIPDamage {
+    /// The member header has invalid magic bytes, an unsupported version or an invalid
+    /// dictionary size.
+    Header,
+    /// The LZMA data is corrupt.
+    CorruptData,
+    /// The file ends before the trailer of the member.
+    Truncated,
+    /// The data size or the member size in the trailer doesn't match the decoded member.
+    SizeMismatch,
+    /// The CRC32 in the trailer doesn't match the decoded data.
+    Checksum,
+}
+
+/// A part of an LZIP file that couldn't be recovered.
+#[derive(Debug, Clone)]
+pub struct LZIPLostRange {
+    /// The offset of the skipped data in the file.
+    pub compressed_offset: u64,
+    /// The size of the skipped data in the file.
+    pub compressed_size: u64,
+    /// The offset of the lost data in the uncompressed data. `None` if the size of an earlier
+    /// lost range is unknown.
+    pub uncompressed_offset: Option<u64>,
+    /// The size of the lost uncompressed data. `None` if the member couldn't be located with the
+    /// trailers.
+    pub uncompressed_size: Option<u64>,
+    /// The reason why the data couldn't be recovered.
+    pub damage: LZIPDamage,
+}
+
+/// Summary of [`recover`].
+#[derive(Debug, Clone)]
+pub struct LZIPRecovery {
+    /// Whether the members were located with the member sizes in their trailers. If a trailer is
+    /// damaged, the file is scanned for member headers instead.
+    pub used_trailers: bool,
+    /// The count of members that were recovered.
+    pub recovered_members: u64,
+    /// The count of uncompressed bytes that were written.
+    pub recovered_size: u64,
+    /// All ranges that couldn't be recovered, in file order.
+    pub lost: Vec<LZIPLostRange>,
+}
+
+/// A member that passed all checks.
+struct DecodedMember {
+    member_size: u64,
+    data_size: u64,
+}
+
+enum MemberError {
+    Io(io::Error),
+    Damage(LZIPDamage),
+}
+
+trait OrDamage<T> {
+    /// Maps errors caused by corrupt data to the given damage and keeps all other errors.
+    fn or_damage(self, damage: LZIPDamage) -> Result<T, MemberError>;
+}
+
+impl<T> OrDamage<T> for io::Result<T> {
+    fn or_damage(self, damage: LZIPDamage) -> Result<T, MemberError> {
+        self.map_err(|error| {
+            if is_damage(&error) {
+                MemberError::Damage(damage)
+            } else {
+                MemberError::Io(error)
+            }
+        })
+    }
+}
+
+/// Decodes all intact members of an LZIP file and writes their data to `writer`, skipping damaged
+/// members.
+///
+/// The members are located with the member sizes in their trailers, so that the uncompressed
+/// ranges of damaged members are known. If a trailer is damaged, the file is scanned for member
+/// headers instead. Every member is fully verified against the CRC32, the data size and the
+/// member size of its trailer before its data is written, so `writer` only receives data of
+/// intact members. Members of up to 64 MiB whose data size is known from their trailer are
+/// buffered for this, all other intact members are decoded twice.
+///
+/// Data after the last member that doesn't start a member is treated as trailing data and is not
+/// reported as lost.
+///
+/// Returns an error if reading or writing fails, or if no member header was found at all.
+pub fn recover<R: Read + Seek, W: Write>(mut reader: R, mut writer: W) -> io::Result<LZIPRecovery> {
+    let mut recovery = LZIPRecovery {
+        used_trailers: true,
+        recovered_members: 0,
+        recovered_size: 0,
+        lost: Vec::new(),
+    };
+
+    match scan_members(&mut reader, u32::MAX) {
+        Ok((members, _)) => {
+            let mut uncompressed_offset = 0;
+
+            for member in &members {
+                match recover_member(&mut reader, member.start_pos, Some(member), &mut writer)? {
+                    Ok(decoded) => {
+                        recovery.recovered_members += 1;
+                        recovery.recovered_size += decoded.data_size;
+                    }
+                    Err(damage) => recovery.lost.push(LZIPLostRange {
+                        compressed_offset: member.start_pos,
+                        compressed_size: member.compressed_size,
+                        uncompressed_offset: Some(uncompressed_offset),
+                        uncompressed_size: Some(member.data_size),
+                        damage,
+                    }),
+                }
+
+                uncompressed_offset += member.data_size;
+            }
+        }
+        Err(error)
+            if matches!(
+                error.kind(),
+                ErrorKind::InvalidData | ErrorKind::UnexpectedEof
+            ) =>
+        {
+            recovery.used_trailers = false;
+            recover_without_trailers(&mut reader, &mut writer, &mut recovery)?;
+        }
+        Err(error) => return Err(error),
+    }
+
+    writer.flush()?;
+
+    Ok(recovery)
+}
+
+/// Walks forward through the file, decoding every member header that is found.
+fn recover_without_trailers<R: Read + Seek, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    recovery: &mut LZIPRecovery,
+) -> io::Result<()> {
+    let file_size = reader.seek(SeekFrom::End(0))?;
+
+    let mut pos = 0;
+    let mut uncompressed_offset = Some(0);
+    let mut lost: Option<(u64, LZIPDamage)> = None;
+    let mut found_member = false;
+
+    while pos < file_size {
+        match recover_member(reader, pos, None, writer)? {
+            Ok(decoded) => {
+                close_lost_range(&mut lost, pos, &mut uncompressed_offset, recovery);
+                found_member = true;
+                recovery.recovered_members += 1;
+                recovery.recovered_size += decoded.data_size;
+                uncompressed_offset = uncompressed_offset.map(|offset| offset + decoded.data_size);
+                pos += decoded.member_size;
+            }
+            Err(damage) => {
+                let next_pos = find_header(reader, pos + 1, file_size)?;
+
+                if damage == LZIPDamage::Header && lost.is_none() && next_pos == file_size {
+                    // Trailing data after the last member.
+                    break;
+                }
+
+                found_member |= damage != LZIPDamage::Header;
+                if lost.is_none() {
+                    lost = Some((pos, damage));
+                }
+                pos = next_pos;
+            }
+        }
+    }
+
+    close_lost_range(&mut lost, file_size, &mut uncompressed_offset, recovery);
+
+    if !found_member {
+        return Err(error_invalid_data("no LZIP member header found"));
+    }
+
+    Ok(())
+}
+
+fn close_lost_range(
+    lost: &mut Option<(u64, LZIPDamage)>,
+    end_pos: u64,
+    uncompressed_offset: &mut Option<u64>,
+    recovery: &mut LZIPRecovery,
+) {
+    if let Some((start_pos, damage)) = lost.take() {
+        recovery.lost.push(LZIPLostRange {
+            compressed_offset: start_pos,
+            compressed_size: end_pos - start_pos,
+            uncompressed_offset: *uncompressed_offset,
+            uncompressed_size: None,
+            damage,
+        });
+        *uncompressed_offset = None;
+    }
+}
+
+/// Returns whether the error was caused by corrupt data.
+fn is_damage(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::InvalidData | ErrorKind::InvalidInput | ErrorKind::UnexpectedEof
+    )
+}
+
+/// Searches the next position that contains a valid member header. Returns `file_size` if
+/// nothing was found.
+fn find_header<R: Read + Seek>(reader: &mut R, from: u64, file_size: u64) -> io::Result<u64> {
+    let mut window_start = from;
+    let mut window = vec![0u8; SEARCH_BLOCK_SIZE + HEADER_SIZE];
+
+    while window_start < file_size {
+        let window_size = (file_size - window_start).min(window.len() as u64) as usize;
+        reader.seek(SeekFrom::Start(window_start))?;
+        reader.read_exact(&mut window[..window_size])?;
+        let window = &window[..window_size];
+
+        let search_size = if window_start + window_size as u64 == file_size {
+            window_size
+        } else {
+            SEARCH_BLOCK_SIZE
+        };
+
+        for offset in 0..search_size {
+            let candidate = &window[offset..];
+
+            if candidate.starts_with(&LZIP_MAGIC) && LZIPHeader::parse(&mut &candidate[..]).is_ok()
+            {
+                return Ok(window_start + offset as u64);
+            }
+        }
+
+        window_start += search_size as u64;
+    }
+
+    Ok(file_size)
+}
+
+/// Verifies the member at `start_pos` and writes its data to `writer` if it is intact.
+///
+/// If the member was found by its trailer and its data size is at most 64 MiB, the member is
+/// decoded once into a buffer. Otherwise it's decoded twice, first to verify it and then to write
+/// it, so that a big member or a corrupt data size in the trailer never ends up in memory as a
+/// whole.
+fn recover_member<R: Read + Seek, W: Write>(
+    reader: &mut R,
+    start_pos: u64,
+    scanned_member: Option<&LZIPMember>,
+    writer: &mut W,
+) -> io::Result<Result<DecodedMember, LZIPDamage>> {
+    reader.seek(SeekFrom::Start(start_pos))?;
+
+    if let Some(size) = scanned_member
+        .map(|member| member.data_size)
+        .filter(|&size| size <= 64 * 1024 * 1024)
+    {
+        let mut buffer = Vec::with_capacity(size as usize);
+        return match decode_member(reader, scanned_member, &mut buffer) {
+            Ok(decoded) => {
+                writer.write_all(&buffer)?;
+                Ok(Ok(decoded))
+            }
+            Err(MemberError::Io(error)) => Err(error),
+            Err(MemberError::Damage(damage)) => Ok(Err(damage)),
+        };
+    }
+
+    if let Err(error) = decode_member(reader, scanned_member, &mut io::sink()) {
+        return match error {
+            MemberError::Io(error) => Err(error),
+            MemberError::Damage(damage) => Ok(Err(damage)),
+        };
+    }
+
+    reader.seek(SeekFrom::Start(start_pos))?;
+    match decode_member(reader, scanned_member, writer) {
+        Ok(decoded) => Ok(Ok(decoded)),
+        Err(MemberError::Io(error)) => Err(error),
+        Err(MemberError::Damage(_)) => Err(error_invalid_data(
+            "LZIP member changed while it was recovered",
+        )),
+    }
+}
+
+/// Decodes the member at the current position of `reader` and writes its data to `writer`.
+fn decode_member<R: Read, W: Write>(
+    reader: &mut R,
+    scanned_member: Option<&LZIPMember>,
+    writer: &mut W,
+) -> Result<DecodedMember, MemberError> {
+    let header = LZIPHeader::parse(reader).or_damage(LZIPDamage::Header)?;
+
+    let mut lzma_reader = LZMAReader::new(
+        CountingReader::new(reader.by_ref()),
+        u64::MAX,
+        3,
+        0,
+        2,
+        header.dict_size,
+        None,
+    )
+    .or_damage(LZIPDamage::Header)?;
+
+    let size_limit = scanned_member.map_or(u64::MAX, |member| member.data_size);
+
+    let mut crc_digest = CRC32.digest();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut data_size = 0u64;
+
+    loop {
+        let bytes_read = lzma_reader
+            .read(&mut buf)
+            .or_damage(LZIPDamage::CorruptData)?;
+        if bytes_read == 0 {
+            break;
+        }
+
+        data_size += bytes_read as u64;
+        if data_size > size_limit {
+            return Err(MemberError::Damage(LZIPDamage::SizeMismatch));
+        }
+
+        crc_digest.update(&buf[..bytes_read]);
+        writer
+            .write_all(&buf[..bytes_read])
+            .map_err(MemberError::Io)?;
+    }
+
+    let compressed_size = lzma_reader.into_inner().bytes_read();
+    let member_size = HEADER_SIZE as u64 + compressed_size + header.trailer_size() as u64;
+    let trailer =
+        LZIPTrailer::parse(reader, &header, member_size).or_damage(LZIPDamage::Truncated)?;
+
+    if crc_digest.finalize() != trailer.crc32 {
+        return Err(MemberError::Damage(LZIPDamage::Checksum));
+    }
+
+    let size_mismatch = trailer.data_size != data_size
+        || trailer.member_size != member_size
+        || scanned_member.is_some_and(|member| {
+            member.data_size != data_size || member.compressed_size != member_size
+        });
+    if size_mismatch {
+        return Err(MemberError::Damage(LZIPDamage::SizeMismatch));
+    }
+
+    Ok(DecodedMember {
+        member_size,
+        data_size,
+    })
+}
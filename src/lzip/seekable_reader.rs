@@ -0,0 +1,298 @@
+use std::io::{self, Seek, SeekFrom};
+
+use super::{
+    reader::CrcDigest, scan_members, LZIPHeader, LZIPMember, LZIPTrailer, CRC32, HEADER_SIZE,
+};
+use crate::{
+    error_invalid_data, error_invalid_input, error_mem_limit, error_with_position,
+    lzma_reader::get_memory_usage, CheckValue, CountingReader, LZMAReader, LzmaError, Read,
+};
+
+/// The decoder of the current member.
+struct MemberDecoder<R> {
+    lzma_reader: LZMAReader<CountingReader<R>>,
+    header: LZIPHeader,
+    crc_digest: CrcDigest,
+}
+
+/// A single-threaded LZIP decompressor that supports random access.
+///
+/// The reader locates all members first, the same way as [`crate::LZIPReaderMT`] does. Seeking
+/// to an uncompressed position then only needs to decode the member that contains the position,
+/// starting from the beginning of that member. Files written with a single member (the default
+/// of [`crate::LZIPWriter`]) need to be decoded from the start for every backwards seek, so
+/// random access needs files written with [`crate::LZIPOptions::set_member_size`].
+pub struct LZIPSeekableReader<R> {
+    inner: Option<R>,
+    decoder: Option<MemberDecoder<R>>,
+    members: Vec<LZIPMember>,
+    member_starts: Vec<u64>,
+    uncompressed_size: u64,
+    current_member: usize,
+    member_pos: u64,
+    position: u64,
+    mem_limit_kb: u32,
+}
+
+impl<R> LZIPSeekableReader<R> {
+    /// Consume the LZIPSeekableReader and return the inner reader.
+    pub fn into_inner(mut self) -> R {
+        if let Some(decoder) = self.decoder.take() {
+            return decoder.lzma_reader.into_inner().into_inner();
+        }
+
+        self.inner.take().expect("inner reader not set")
+    }
+}
+
+impl<R: Read + Seek> LZIPSeekableReader<R> {
+    /// Creates a new seekable LZIP reader.
+    ///
+    /// - `inner`: The reader to read compressed data from.
+    pub fn new(inner: R) -> io::Result<Self> {
+        Self::new_mem_limit(inner, u32::MAX)
+    }
+
+    /// Creates a new seekable LZIP reader with a memory usage limit.
+    ///
+    /// - `inner`: The reader to read compressed data from.
+    /// - `mem_limit_kb`: The memory usage limit in kibibytes (KiB). `u32::MAX` means no limit.
+    ///   Every member is checked against the limit before its decoder is created.
+    pub fn new_mem_limit(mut inner: R, mem_limit_kb: u32) -> io::Result<Self> {
+        let (members, _) = scan_members(&mut inner, mem_limit_kb)?;
+
+        let mut member_starts = Vec::with_capacity(members.len());
+        let mut uncompressed_size = 0u64;
+
+        for member in &members {
+            member_starts.push(uncompressed_size);
+            uncompressed_size = uncompressed_size
+                .checked_add(member.data_size)
+                .ok_or_else(|| error_invalid_data("LZIP uncompressed size overflow"))?;
+        }
+
+        Ok(Self {
+            inner: Some(inner),
+            decoder: None,
+            members,
+            member_starts,
+            uncompressed_size,
+            current_member: 0,
+            member_pos: 0,
+            position: 0,
+            mem_limit_kb,
+        })
+    }
+
+    /// Get the count of LZIP members found in the file.
+    pub fn member_count(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Returns the total uncompressed size, as stored in the trailers.
+    pub fn uncompressed_size(&self) -> u64 {
+        self.uncompressed_size
+    }
+
+    /// Takes the inner reader back from the decoder of the current member.
+    fn take_inner(&mut self) -> R {
+        match self.decoder.take() {
+            Some(decoder) => decoder.lzma_reader.into_inner().into_inner(),
+            None => self.inner.take().expect("inner reader not set"),
+        }
+    }
+
+    /// Seeks the compressed stream to the start of the member and prepares its decoder.
+    fn start_member(&mut self, member_index: usize) -> io::Result<()> {
+        let mut inner = self.take_inner();
+        let member = &self.members[member_index];
+
+        let header = inner
+            .seek(SeekFrom::Start(member.start_pos))
+            .and_then(|_| LZIPHeader::parse(&mut inner));
+        self.inner = Some(inner);
+        let header = header?;
+
+        let memory_usage = get_memory_usage(header.dict_size, 3, 0)?;
+        if memory_usage > self.mem_limit_kb {
+            return Err(error_mem_limit(memory_usage, self.mem_limit_kb));
+        }
+
+        let inner = self.inner.take().expect("inner reader not set");
+        let lzma_reader = LZMAReader::new(
+            CountingReader::new(inner),
+            u64::MAX,
+            3,
+            0,
+            2,
+            header.dict_size,
+            None,
+        )?;
+
+        self.decoder = Some(MemberDecoder {
+            lzma_reader,
+            header,
+            crc_digest: CRC32.digest(),
+        });
+        self.current_member = member_index;
+        self.member_pos = 0;
+
+        Ok(())
+    }
+
+    /// Reads decompressed data of the current member and updates the CRC32.
+    fn read_member(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let member = &self.members[self.current_member];
+        let remaining = member.data_size - self.member_pos;
+        let read_size = (buf.len() as u64).min(remaining) as usize;
+
+        let decoder = self.decoder.as_mut().expect("member decoder not set");
+        let bytes_read = decoder.lzma_reader.read(&mut buf[..read_size])?;
+
+        if bytes_read == 0 && read_size > 0 {
+            return Err(error_invalid_data(
+                "LZIP member is smaller than stated in trailer",
+            ));
+        }
+
+        decoder.crc_digest.update(&buf[..bytes_read]);
+        self.member_pos += bytes_read as u64;
+
+        if self.member_pos == member.data_size {
+            self.finish_member()?;
+        }
+
+        Ok(bytes_read)
+    }
+
+    /// Makes sure the member ends where the trailer says and verifies its CRC32.
+    fn finish_member(&mut self) -> io::Result<()> {
+        let MemberDecoder {
+            mut lzma_reader,
+            header,
+            crc_digest,
+        } = self.decoder.take().expect("member decoder not set");
+
+        let mut probe = [0u8; 1];
+        let probe_result = lzma_reader.read(&mut probe);
+
+        let counting_reader = lzma_reader.into_inner();
+        let compressed_bytes = counting_reader.bytes_read();
+        self.inner = Some(counting_reader.into_inner());
+
+        if probe_result? != 0 {
+            return Err(error_invalid_data(
+                "LZIP member is bigger than stated in trailer",
+            ));
+        }
+
+        let member = &self.members[self.current_member];
+        let member_size = HEADER_SIZE as u64 + compressed_bytes + header.trailer_size() as u64;
+        let inner = self.inner.as_mut().expect("inner reader not set");
+        let trailer = LZIPTrailer::parse(inner, &header, member_size)?;
+
+        let computed_crc = crc_digest.finalize();
+        if computed_crc != trailer.crc32 {
+            return Err(LzmaError::ChecksumMismatch {
+                expected: CheckValue::Crc32(trailer.crc32),
+                actual: CheckValue::Crc32(computed_crc),
+            }
+            .into());
+        }
+
+        if trailer.data_size != member.data_size {
+            return Err(error_invalid_data("LZIP data size mismatch"));
+        }
+
+        if member_size != trailer.member_size || member_size != member.compressed_size {
+            return Err(error_invalid_data("LZIP member size mismatch"));
+        }
+
+        Ok(())
+    }
+
+    /// Moves the decoder to the current position, decoding from the start of the member if
+    /// needed.
+    fn seek_decoder(&mut self) -> io::Result<()> {
+        let member_index = self
+            .member_starts
+            .partition_point(|&start| start <= self.position)
+            - 1;
+        let offset_in_member = self.position - self.member_starts[member_index];
+
+        let can_continue = self.decoder.is_some()
+            && self.current_member == member_index
+            && self.member_pos <= offset_in_member;
+
+        if !can_continue {
+            self.start_member(member_index)?;
+        }
+
+        let mut buf = [0u8; 4096];
+
+        while self.member_pos < offset_in_member {
+            let skip = ((offset_in_member - self.member_pos) as usize).min(buf.len());
+            self.read_member(&mut buf[..skip])?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds the position of the decoder in the current member to a corrupt data error.
+    fn error_with_position(&self, error: io::Error) -> io::Error {
+        let member = &self.members[self.current_member];
+        let compressed_offset = match self.decoder.as_ref() {
+            Some(decoder) => {
+                member.start_pos + HEADER_SIZE as u64 + decoder.lzma_reader.get_ref().bytes_read()
+            }
+            None => member.start_pos,
+        };
+        let uncompressed_offset = self.member_starts[self.current_member] + self.member_pos;
+
+        error_with_position(error, Some(compressed_offset), Some(uncompressed_offset))
+    }
+
+    fn read_at_position(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let at_position = self.decoder.is_some()
+            && self.member_starts[self.current_member] + self.member_pos == self.position;
+
+        if !at_position {
+            self.seek_decoder()?;
+        }
+
+        self.read_member(buf)
+    }
+}
+
+impl<R: Read + Seek> Read for LZIPSeekableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.uncompressed_size {
+            return Ok(0);
+        }
+
+        let bytes_read = self
+            .read_at_position(buf)
+            .map_err(|error| self.error_with_position(error))?;
+        self.position += bytes_read as u64;
+
+        Ok(bytes_read)
+    }
+}
+
+impl<R: Read + Seek> Seek for LZIPSeekableReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.uncompressed_size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        let Some(new_position) = new_position else {
+            return Err(error_invalid_input("invalid seek to a negative position"));
+        };
+
+        self.position = new_position;
+
+        Ok(self.position)
+    }
+}
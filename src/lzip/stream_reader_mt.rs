@@ -0,0 +1,753 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Chain, Cursor},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use super::{
+    is_magic_prefix,
+    reader_mt::{worker_thread_logic, DecodedMember, ResultUnit, WorkUnit},
+    LZIPHeader, LZIPMember, LZIPReader, LZIPSummary, LZIPTrailer, LZIPTrailingPolicy, HEADER_SIZE,
+    TRAILER_SIZE,
+};
+use crate::{
+    error_invalid_data, error_unsupported, error_with_base_position, error_with_position,
+    lzma_reader::get_memory_usage, set_error, work_queue::WorkStealingQueue, workers_for_mem_limit,
+    CheckValue, Read,
+};
+
+/// The count of bytes that are read from the inner reader at once.
+const READ_SIZE: usize = 64 * 1024;
+
+/// Members are buffered up to this multiple of their dictionary size. plzip writes members of
+/// twice the dictionary size by default.
+const BUFFER_DICT_FACTOR: usize = 4;
+
+/// Members with small dictionaries are buffered up to this size. plzip writes members of 1 MiB
+/// with the 64 KiB dictionary of level 0.
+const MIN_BUFFER_SIZE: usize = 1024 * 1024;
+
+enum State {
+    /// Reading members from the inner reader and dispatching them to worker threads.
+    Reading,
+    /// All members dispatched, waiting for workers to complete.
+    Draining,
+    /// All data has been decompressed and returned. The stream is exhausted.
+    Finished,
+    /// A fatal error occurred in either the reader or a worker thread.
+    Error,
+}
+
+/// The next data to return.
+enum Chunk {
+    /// A member that was decoded by a worker.
+    Decoded(DecodedMember),
+    /// The member that doesn't fit into the memory limit, which is decoded directly from the
+    /// inner reader.
+    Direct,
+}
+
+/// The next member of the inner reader.
+enum NextMember {
+    /// A member that was read completely.
+    Buffered(Vec<u8>, LZIPMember),
+    /// A member with the given dictionary size that doesn't fit into the memory limit of a
+    /// worker. It starts at the beginning of the buffer.
+    Oversized(u32),
+    /// The data after the last member was reached.
+    End,
+}
+
+/// A member that doesn't fit into the memory limit of a worker. It's decoded by a
+/// [`LZIPReader`] that reads the buffered data first and then the inner reader.
+struct DirectMember<R> {
+    sequence: u64,
+    reader: LZIPReader<Chain<Cursor<Vec<u8>>, R>>,
+    start_pos: u64,
+    uncompressed_offset: u64,
+}
+
+/// A multi-threaded LZIP decompressor that doesn't need to seek.
+///
+/// [`crate::LZIPReaderMT`] locates the members with the trailers at the end of the file, which
+/// needs Seek. This reader instead finds the end of every member while reading: a member ends at
+/// a trailer whose member size matches the bytes read since the member header, and which is
+/// followed by the header of the next member or the end of the data. Complete members are
+/// decoded in parallel while the next members are still being read, so it can be used with pipes
+/// and network streams.
+///
+/// Every member is held in memory until it's decoded, but only up to four times its dictionary
+/// size (at least 1 MiB) or the memory limit. Bigger members are not buffered. Like in the
+/// threaded decoder of xz-utils, they are decoded by a single thread directly from the inner
+/// reader instead, once all members in front of them were returned. Files written with a single
+/// member (the default of [`crate::LZIPWriter`]) are therefore decoded by a single thread.
+/// Parallel decoding needs files written with [`crate::LZIPOptions::set_member_size`] or
+/// [`crate::LZIPWriterMT`]. The trailers of version 0 members don't store the member size, so
+/// they are not supported, the same as in plzip.
+pub struct LZIPStreamReaderMT<R: Read> {
+    inner: Option<R>,
+    buffer: Vec<u8>,
+    buffer_pos: u64,
+    eof: bool,
+    mem_limit_kb: u32,
+    worker_mem_kb: u32,
+    members: Vec<LZIPMember>,
+    direct_member: Option<DirectMember<R>>,
+    trailing_policy: LZIPTrailingPolicy,
+    trailing_data: Vec<u8>,
+    verify_only: bool,
+    result_rx: Receiver<ResultUnit>,
+    result_tx: Sender<ResultUnit>,
+    next_sequence_to_dispatch: u64,
+    uncompressed_dispatched: u64,
+    next_sequence_to_return: u64,
+    last_sequence_id: Option<u64>,
+    out_of_order_chunks: BTreeMap<u64, DecodedMember>,
+    current_chunk: Cursor<Vec<u8>>,
+    current_trailer: Option<LZIPTrailer>,
+    trailers: Vec<LZIPTrailer>,
+    shutdown_flag: Arc<AtomicBool>,
+    error_store: Arc<Mutex<Option<io::Error>>>,
+    state: State,
+    work_queue: WorkStealingQueue<WorkUnit>,
+    active_workers: Arc<AtomicU32>,
+    max_workers: u32,
+    worker_handles: Vec<thread::JoinHandle<()>>,
+}
+
+impl<R: Read> LZIPStreamReaderMT<R> {
+    /// Creates a new multi-threaded LZIP reader that reads the compressed data front to back.
+    ///
+    /// Members bigger than four times their dictionary size (at least 1 MiB) are decoded directly
+    /// from the inner reader by a single thread, so a big single member stream is never held in
+    /// memory as a whole.
+    ///
+    /// - `inner`: The reader to read compressed data from.
+    /// - `num_workers`: The maximum number of worker threads for decompression. Currently capped at 256 threads.
+    pub fn new(inner: R, num_workers: u32) -> Self {
+        Self::new_mem_limit(inner, num_workers, u32::MAX)
+    }
+
+    /// Creates a new multi-threaded LZIP reader that reads the compressed data front to back
+    /// with a memory usage limit.
+    ///
+    /// Every worker needs its own dictionary and holds a whole member in memory. The count of
+    /// workers is reduced until all workers together stay inside the limit for the biggest
+    /// member that was read so far. A member that doesn't fit into the limit on its own, or that
+    /// is bigger than four times its dictionary size (at least 1 MiB), is decoded directly from
+    /// the inner reader, which only needs the dictionary.
+    ///
+    /// - `inner`: The reader to read compressed data from.
+    /// - `num_workers`: The maximum number of worker threads for decompression. Currently capped at 256 threads.
+    /// - `mem_limit_kb`: The memory usage limit in kibibytes (KiB). `u32::MAX` means no limit.
+    pub fn new_mem_limit(inner: R, num_workers: u32, mem_limit_kb: u32) -> Self {
+        let max_workers = num_workers.clamp(1, 256);
+
+        let work_queue = WorkStealingQueue::new();
+        let (result_tx, result_rx) = mpsc::channel::<ResultUnit>();
+        let shutdown_flag = Arc::new(AtomicBool::new(false));
+        let error_store = Arc::new(Mutex::new(None));
+        let active_workers = Arc::new(AtomicU32::new(0));
+
+        Self {
+            inner: Some(inner),
+            buffer: Vec::new(),
+            buffer_pos: 0,
+            eof: false,
+            mem_limit_kb,
+            worker_mem_kb: 0,
+            members: Vec::new(),
+            direct_member: None,
+            trailing_policy: LZIPTrailingPolicy::default(),
+            trailing_data: Vec::new(),
+            verify_only: false,
+            result_rx,
+            result_tx,
+            next_sequence_to_dispatch: 0,
+            uncompressed_dispatched: 0,
+            next_sequence_to_return: 0,
+            last_sequence_id: None,
+            out_of_order_chunks: BTreeMap::new(),
+            current_chunk: Cursor::new(Vec::new()),
+            current_trailer: None,
+            trailers: Vec::new(),
+            shutdown_flag,
+            error_store,
+            state: State::Reading,
+            work_queue,
+            active_workers,
+            max_workers,
+            worker_handles: Vec::new(),
+        }
+    }
+
+    /// Get the count of LZIP members that were found so far.
+    pub fn member_count(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Returns the verified trailers of all members whose data was read completely, in file
+    /// order.
+    pub fn trailers(&self) -> &[LZIPTrailer] {
+        &self.trailers
+    }
+
+    /// Sets how the data after the last member is handled. The default is
+    /// [`LZIPTrailingPolicy::Ignore`]. The policy is applied after the data of the last member
+    /// was read.
+    pub fn set_trailing_policy(&mut self, trailing_policy: LZIPTrailingPolicy) {
+        self.trailing_policy = trailing_policy;
+    }
+
+    /// Returns the bytes after the last member that were already read from the inner reader, if
+    /// the trailing policy is [`LZIPTrailingPolicy::Stop`] and the data of the last member was
+    /// read. These are less than 64 KiB plus the size of a header, the rest is still unread in
+    /// the inner reader.
+    pub fn trailing_data(&self) -> &[u8] {
+        &self.trailing_data
+    }
+
+    /// Unwraps the reader, returning the underlying reader.
+    pub fn into_inner(mut self) -> R {
+        if let Some(direct_member) = self.direct_member.take() {
+            return direct_member.reader.into_inner().into_inner().1;
+        }
+
+        self.inner.take().expect("inner reader not set")
+    }
+
+    /// Decodes all members in parallel without producing output and returns a summary, like
+    /// [`lzip_verify`](crate::lzip_verify) does with a single thread.
+    ///
+    /// The workers calculate the CRC32 of the members straight from the dictionary of their
+    /// decoder, so only the compressed members are held in memory. Members that were already read
+    /// are not part of the summary.
+    pub fn verify(mut self) -> io::Result<LZIPSummary> {
+        self.verify_only = true;
+
+        let first_member = self.next_sequence_to_return as usize;
+        let mut checks = Vec::new();
+        while let Some(chunk) = self.get_next_uncompressed_chunk()? {
+            match chunk {
+                Chunk::Decoded((_, check)) => checks.extend(check),
+                Chunk::Direct => {
+                    let mut buf = vec![0u8; READ_SIZE];
+                    while self.read_direct_member(&mut buf)? > 0 {}
+
+                    let trailer = self.trailers.last().expect("direct member not finished");
+                    checks.push(CheckValue::Crc32(trailer.crc32));
+                }
+            }
+        }
+
+        let members = &self.members[first_member..];
+
+        Ok(LZIPSummary {
+            member_count: checks.len() as u64,
+            compressed_size: members.iter().map(|member| member.compressed_size).sum(),
+            uncompressed_size: members.iter().map(|member| member.data_size).sum(),
+            checks,
+        })
+    }
+
+    fn spawn_worker_thread(&mut self) {
+        let worker_handle = self.work_queue.worker();
+        let result_tx = self.result_tx.clone();
+        let shutdown_flag = Arc::clone(&self.shutdown_flag);
+        let error_store = Arc::clone(&self.error_store);
+        let active_workers = Arc::clone(&self.active_workers);
+        let verify_only = self.verify_only;
+
+        let handle = thread::spawn(move || {
+            worker_thread_logic(
+                worker_handle,
+                result_tx,
+                shutdown_flag,
+                error_store,
+                active_workers,
+                verify_only,
+            );
+        });
+
+        self.worker_handles.push(handle);
+    }
+
+    /// Reads more data from the inner reader into the buffer. Sets `eof` at the end of the inner
+    /// reader.
+    fn fill_buffer(&mut self) -> io::Result<()> {
+        let bytes_read = self
+            .inner
+            .as_mut()
+            .expect("inner reader not set")
+            .take(READ_SIZE as u64)
+            .read_to_end(&mut self.buffer)?;
+        self.eof = bytes_read < READ_SIZE;
+
+        Ok(())
+    }
+
+    /// Finds the end of the member at the start of the buffer and returns the member size.
+    /// Returns `None` if the buffer grew to `buffer_limit` before the end was found.
+    ///
+    /// A trailer ends the member if it's followed by a member header, by the end of the data or
+    /// by trailing data that can't be a member header. A trailer that is followed by data that
+    /// starts like a corrupt member header is only used if no other trailer is found until the
+    /// end of the data.
+    fn find_member_end(&mut self, buffer_limit: usize) -> io::Result<Option<usize>> {
+        let mut member_end = HEADER_SIZE + TRAILER_SIZE;
+        let mut last_member_end = None;
+
+        loop {
+            while member_end + HEADER_SIZE <= self.buffer.len()
+                || (self.eof && member_end <= self.buffer.len())
+            {
+                let trailer_end = &self.buffer[member_end - 8..member_end];
+                let member_size = u64::from_le_bytes(trailer_end.try_into().unwrap());
+
+                if member_size == member_end as u64 {
+                    let next = &self.buffer[member_end..];
+                    if next.is_empty()
+                        || !is_magic_prefix(next)
+                        || LZIPHeader::parse(&mut &next[..]).is_ok()
+                    {
+                        return Ok(Some(member_end));
+                    }
+
+                    last_member_end.get_or_insert(member_end);
+                }
+
+                member_end += 1;
+            }
+
+            if self.eof {
+                return last_member_end.map(Some).ok_or_else(|| {
+                    error_with_position(
+                        error_invalid_data("no trailer found for LZIP member"),
+                        Some(self.buffer_pos),
+                        None,
+                    )
+                });
+            }
+
+            if self.buffer.len() >= buffer_limit {
+                return Ok(None);
+            }
+
+            self.fill_buffer()?;
+        }
+    }
+
+    /// Reads the next member from the inner reader, if it fits into the memory limit of a worker.
+    fn read_next_member(&mut self) -> io::Result<NextMember> {
+        while self.buffer.len() < HEADER_SIZE && !self.eof {
+            self.fill_buffer()?;
+        }
+
+        let header = match LZIPHeader::parse(&mut self.buffer.as_slice()) {
+            Ok(header) => header,
+            Err(error) if self.members.is_empty() => {
+                return Err(error_with_position(error, Some(0), None));
+            }
+            // The rest is trailing data, which is checked after all members were returned.
+            Err(_) => return Ok(NextMember::End),
+        };
+
+        if header.version == 0 {
+            return Err(error_with_position(
+                error_unsupported("LZIP version 0 members can't be read from a stream"),
+                Some(self.buffer_pos),
+                None,
+            ));
+        }
+
+        // The compressed member has to fit into the memory limit next to the dictionary. Even
+        // without a limit, it's only buffered up to a few times the dictionary size, so that a
+        // big single member isn't read into memory as a whole.
+        let dict_mem_kb = get_memory_usage(header.dict_size, 3, 0)?;
+        let buffer_limit = (self.mem_limit_kb.saturating_sub(dict_mem_kb) as usize)
+            .saturating_mul(1024)
+            .min(
+                (header.dict_size as usize)
+                    .saturating_mul(BUFFER_DICT_FACTOR)
+                    .max(MIN_BUFFER_SIZE),
+            );
+
+        let Some(member_size) = self.find_member_end(buffer_limit)? else {
+            return Ok(NextMember::Oversized(header.dict_size));
+        };
+
+        let data_size = u64::from_le_bytes(
+            self.buffer[member_size - 16..member_size - 8]
+                .try_into()
+                .unwrap(),
+        );
+
+        let member = LZIPMember {
+            start_pos: self.buffer_pos,
+            compressed_size: member_size as u64,
+            data_size,
+            dict_size: header.dict_size,
+        };
+
+        if member.memory_usage()? > self.mem_limit_kb {
+            return Ok(NextMember::Oversized(header.dict_size));
+        }
+
+        let rest = self.buffer.split_off(member_size);
+        let member_data = core::mem::replace(&mut self.buffer, rest);
+        self.buffer_pos += member_size as u64;
+
+        Ok(NextMember::Buffered(member_data, member))
+    }
+
+    /// Makes the oversized member at the start of the buffer the direct member. The inner
+    /// reader belongs to the direct member until it was decoded.
+    fn start_direct_member(&mut self, dict_size: u32) -> io::Result<()> {
+        let inner = self.inner.take().expect("inner reader not set");
+        let buffer = core::mem::take(&mut self.buffer);
+
+        let mut reader =
+            LZIPReader::new_mem_limit(Cursor::new(buffer).chain(inner), self.mem_limit_kb)?;
+        reader.set_single_member(true);
+
+        // The sizes are set once the member was decoded.
+        self.members.push(LZIPMember {
+            start_pos: self.buffer_pos,
+            compressed_size: 0,
+            data_size: 0,
+            dict_size,
+        });
+
+        self.direct_member = Some(DirectMember {
+            sequence: self.next_sequence_to_dispatch,
+            reader,
+            start_pos: self.buffer_pos,
+            uncompressed_offset: self.uncompressed_dispatched,
+        });
+        self.next_sequence_to_dispatch += 1;
+
+        Ok(())
+    }
+
+    /// Decodes the next data of the direct member. Returns 0 after the member was finished.
+    fn read_direct_member(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let direct_member = self.direct_member.as_mut().expect("direct member not set");
+        let bytes_read = direct_member.reader.read(buf).map_err(|error| {
+            error_with_base_position(
+                error,
+                direct_member.start_pos,
+                direct_member.uncompressed_offset,
+            )
+        })?;
+
+        if bytes_read > 0 {
+            return Ok(bytes_read);
+        }
+
+        self.finish_direct_member();
+        Ok(0)
+    }
+
+    /// Gives the inner reader and the data after the direct member back to the reading thread.
+    fn finish_direct_member(&mut self) {
+        let DirectMember {
+            sequence, reader, ..
+        } = self.direct_member.take().expect("direct member not set");
+
+        // The reader verified the trailer of the member.
+        let trailer = *reader
+            .trailers()
+            .first()
+            .expect("direct member not decoded");
+
+        let (buffer, inner) = reader.into_inner().into_inner();
+        let buffer_pos = buffer.position() as usize;
+        let mut buffer = buffer.into_inner();
+        buffer.drain(..buffer_pos);
+        self.buffer = buffer;
+        self.inner = Some(inner);
+
+        let member = &mut self.members[sequence as usize];
+        member.compressed_size = trailer.member_size;
+        member.data_size = trailer.data_size;
+
+        self.trailers.push(trailer);
+        self.buffer_pos += trailer.member_size;
+        self.next_sequence_to_return += 1;
+        self.uncompressed_dispatched += trailer.data_size;
+    }
+
+    /// Checks the data after the last member against the trailing policy. If the policy is
+    /// [`LZIPTrailingPolicy::Stop`], the trailing data that was already read is kept instead.
+    fn handle_trailing_data(&mut self) -> io::Result<()> {
+        if self.trailing_policy == LZIPTrailingPolicy::Stop {
+            self.trailing_data = core::mem::take(&mut self.buffer);
+            return Ok(());
+        }
+
+        self.trailing_policy
+            .check(&self.buffer)
+            .map_err(|error| error_with_position(error, Some(self.buffer_pos), None))
+    }
+
+    /// Reads the next member from the inner reader and dispatches it to the workers. A member
+    /// that doesn't fit into the memory limit becomes the direct member instead.
+    ///
+    /// Returns `Ok(false)` after the last member.
+    fn dispatch_next_member(&mut self) -> io::Result<bool> {
+        let (member_data, member) = match self.read_next_member()? {
+            NextMember::Buffered(member_data, member) => (member_data, member),
+            NextMember::Oversized(dict_size) => {
+                self.start_direct_member(dict_size)?;
+                return Ok(true);
+            }
+            // No more members to dispatch.
+            NextMember::End => return Ok(false),
+        };
+
+        let position = (member.start_pos, self.uncompressed_dispatched);
+        let data_size = member.data_size;
+        self.worker_mem_kb = self.worker_mem_kb.max(member.memory_usage()?);
+        self.members.push(member);
+
+        if !self
+            .work_queue
+            .push((self.next_sequence_to_dispatch, member_data, position))
+        {
+            // Queue is closed, this indicates shutdown.
+            self.state = State::Error;
+            set_error(
+                io::Error::new(io::ErrorKind::BrokenPipe, "Worker threads have shut down"),
+                &self.error_store,
+                &self.shutdown_flag,
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Worker threads have shut down",
+            ));
+        }
+
+        // We spawn a new thread if we have work queued, no available workers, and haven't reached
+        // the maximal allowed parallelism yet.
+        let max_workers =
+            workers_for_mem_limit(self.max_workers, self.worker_mem_kb, self.mem_limit_kb)?;
+        let spawned_workers = self.worker_handles.len() as u32;
+        let active_workers = self.active_workers.load(Ordering::Acquire);
+        let queue_len = self.work_queue.len();
+
+        if queue_len > 0 && active_workers == spawned_workers && spawned_workers < max_workers {
+            self.spawn_worker_thread();
+        }
+
+        self.next_sequence_to_dispatch += 1;
+        self.uncompressed_dispatched += data_size;
+        Ok(true)
+    }
+
+    /// Finishes the stream after all members were returned.
+    fn finish(&mut self) {
+        match self.handle_trailing_data() {
+            Ok(()) => self.state = State::Finished,
+            Err(error) => {
+                set_error(error, &self.error_store, &self.shutdown_flag);
+                self.state = State::Error;
+            }
+        }
+    }
+
+    fn get_next_uncompressed_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        loop {
+            // Always check for already-received chunks first.
+            if let Some(result) = self
+                .out_of_order_chunks
+                .remove(&self.next_sequence_to_return)
+            {
+                self.next_sequence_to_return += 1;
+                return Ok(Some(Chunk::Decoded(result)));
+            }
+
+            // Check for a globally stored error.
+            if let Some(err) = self.error_store.lock().unwrap().take() {
+                self.state = State::Error;
+                return Err(err);
+            }
+
+            // The direct member is decoded once all members in front of it were returned.
+            if self
+                .direct_member
+                .as_ref()
+                .is_some_and(|member| member.sequence == self.next_sequence_to_return)
+                && !matches!(self.state, State::Error)
+            {
+                return Ok(Some(Chunk::Direct));
+            }
+
+            match self.state {
+                State::Reading => {
+                    // First, always try to receive a result without blocking.
+                    // This keeps the pipeline moving and avoids unnecessary blocking on I/O.
+                    match self.result_rx.try_recv() {
+                        Ok((seq, result)) => {
+                            if seq == self.next_sequence_to_return {
+                                self.next_sequence_to_return += 1;
+                                return Ok(Some(Chunk::Decoded(result)));
+                            } else {
+                                self.out_of_order_chunks.insert(seq, result);
+                                continue; // Loop again to check the out_of_order_chunks
+                            }
+                        }
+                        Err(mpsc::TryRecvError::Disconnected) => {
+                            // All workers are done.
+                            self.state = State::Draining;
+                            continue;
+                        }
+                        Err(mpsc::TryRecvError::Empty) => {
+                            // No results are ready. Now, we can consider reading more input.
+                        }
+                    }
+
+                    // If the work queue has capacity, try to read more from the source. The
+                    // inner reader belongs to the direct member until it was decoded.
+                    if self.direct_member.is_none() && self.work_queue.len() < 4 {
+                        match self.dispatch_next_member() {
+                            Ok(true) => {
+                                // Successfully read and dispatched a member, loop to continue.
+                                continue;
+                            }
+                            Ok(false) => {
+                                // No more members to dispatch.
+                                // Set the last sequence ID and transition to draining.
+                                self.last_sequence_id =
+                                    Some(self.next_sequence_to_dispatch.saturating_sub(1));
+                                self.state = State::Draining;
+                                continue;
+                            }
+                            Err(error) => {
+                                set_error(error, &self.error_store, &self.shutdown_flag);
+                                self.state = State::Error;
+                                continue;
+                            }
+                        }
+                    }
+
+                    // Now we MUST wait for a result to make progress.
+                    match self.result_rx.recv() {
+                        Ok((seq, result)) => {
+                            if seq == self.next_sequence_to_return {
+                                self.next_sequence_to_return += 1;
+                                return Ok(Some(Chunk::Decoded(result)));
+                            } else {
+                                self.out_of_order_chunks.insert(seq, result);
+                                // We've made progress, loop to check the out_of_order_chunks
+                                continue;
+                            }
+                        }
+                        Err(_) => {
+                            // All workers are done.
+                            self.state = State::Draining;
+                        }
+                    }
+                }
+                State::Draining => {
+                    if let Some(last_seq) = self.last_sequence_id {
+                        if self.next_sequence_to_return > last_seq {
+                            self.finish();
+                            continue;
+                        }
+                    }
+
+                    // In Draining state, we only wait for results.
+                    match self.result_rx.recv() {
+                        Ok((seq, result)) => {
+                            if seq == self.next_sequence_to_return {
+                                self.next_sequence_to_return += 1;
+                                return Ok(Some(Chunk::Decoded(result)));
+                            } else {
+                                self.out_of_order_chunks.insert(seq, result);
+                            }
+                        }
+                        Err(_) => {
+                            // All workers finished, and channel is empty. We are done.
+                            self.finish();
+                        }
+                    }
+                }
+                State::Finished => {
+                    return Ok(None);
+                }
+                State::Error => {
+                    // The error was already logged, now we just propagate it.
+                    return Err(self.error_store.lock().unwrap().take().unwrap_or_else(|| {
+                        io::Error::other("decompression failed with an unknown error")
+                    }));
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for LZIPStreamReaderMT<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let bytes_read = self.current_chunk.read(buf)?;
+
+            if bytes_read > 0 {
+                return Ok(bytes_read);
+            }
+
+            if let Some(trailer) = self.current_trailer.take() {
+                self.trailers.push(trailer);
+            }
+
+            match self.get_next_uncompressed_chunk()? {
+                None => {
+                    // This is the clean end of the stream.
+                    return Ok(0);
+                }
+                Some(Chunk::Decoded((chunk_data, check))) => {
+                    // The member was verified against its trailer by the worker.
+                    let member = &self.members[self.next_sequence_to_return as usize - 1];
+                    if let Some(CheckValue::Crc32(crc32)) = check {
+                        self.current_trailer = Some(LZIPTrailer {
+                            crc32,
+                            data_size: member.data_size,
+                            member_size: member.compressed_size,
+                        });
+                    }
+
+                    self.current_chunk = Cursor::new(chunk_data);
+                }
+                Some(Chunk::Direct) => match self.read_direct_member(buf) {
+                    Ok(0) => {
+                        // The direct member is finished, continue with the next member.
+                    }
+                    Ok(bytes_read) => return Ok(bytes_read),
+                    Err(error) => {
+                        self.direct_member = None;
+                        self.state = State::Error;
+                        self.shutdown_flag.store(true, Ordering::Release);
+                        return Err(error);
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl<R: Read> Drop for LZIPStreamReaderMT<R> {
+    fn drop(&mut self) {
+        self.shutdown_flag.store(true, Ordering::Release);
+        self.work_queue.close();
+        // Worker threads will exit when the work queue is closed.
+        // JoinHandles will be dropped, which is fine since we set the shutdown flag.
+    }
+}
@@ -0,0 +1,27 @@
+use alloc::vec::Vec;
+
+use super::LZIPReader;
+use crate::{CheckValue, Read, Result};
+
+/// Summary of an LZIP file that was decoded without producing output, as done by `lzip --test`.
+///
+/// Created by [`verify`] and [`LZIPReaderMT::verify`](crate::LZIPReaderMT::verify).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LZIPSummary {
+    /// The count of members.
+    pub member_count: u64,
+    /// The size of all members.
+    pub compressed_size: u64,
+    /// The uncompressed size of all members.
+    pub uncompressed_size: u64,
+    /// The verified CRC32 of every member in file order.
+    pub checks: Vec<CheckValue>,
+}
+
+/// Decodes all members of an LZIP file without producing output and returns a summary.
+///
+/// The CRC32 of the members is calculated straight from the dictionary of the decoder, so no
+/// output buffer is needed.
+pub fn verify<R: Read>(reader: R) -> Result<LZIPSummary> {
+    LZIPReader::new(reader)?.decode_all(|_| {})
+}
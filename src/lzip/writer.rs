@@ -6,7 +6,7 @@ use super::{
 };
 use crate::{
     enc::{LZMAOptions, LZMAWriter},
-    error_invalid_data, ByteWriter, Result, Write,
+    error_invalid_input, ByteWriter, Result, Write,
 };
 
 /// Options for LZIP compression.
@@ -209,7 +209,7 @@ impl<W: Write> LZIPWriter<W> {
 impl<W: Write> Write for LZIPWriter<W> {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         if self.finished {
-            return Err(error_invalid_data("LZIP writer already finished"));
+            return Err(error_invalid_input("LZIP writer already finished"));
         }
 
         if buf.is_empty() {
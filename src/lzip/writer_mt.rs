@@ -86,7 +86,7 @@ impl<W: Write> LZIPWriterMT<W> {
             options,
             result_rx,
             result_tx,
-            current_work_unit: Vec::with_capacity((member_size as usize).min(1024 * 1024)),
+            current_work_unit: Vec::with_capacity(member_size.min(1024 * 1024)),
             member_size,
             next_sequence_to_dispatch: 0,
             next_sequence_to_write: 0,
@@ -262,8 +262,7 @@ impl<W: Write> LZIPWriterMT<W> {
                                     // We expected more chunks, but the workers are gone and the
                                     // out-of-order buffer is empty. This is a real error.
                                     self.state = State::Error;
-                                    let err = io::Error::new(
-                                        io::ErrorKind::InvalidData,
+                                    let err = io::Error::other(
                                         format!("A compressed chunk was lost. Expected up to seq {}, but only got up to {}", last_seq, self.next_sequence_to_write.saturating_sub(1)),
                                     );
                                     set_error(err, &self.error_store, &self.shutdown_flag);
@@ -1,6 +1,7 @@
 use super::{
+    copy_error,
     decoder::LZMADecoder,
-    error_invalid_input,
+    error_invalid_data, error_mem_limit,
     lz::LZDecoder,
     range_dec::{RangeDecoder, RangeDecoderBuffer},
     Error, Read,
@@ -27,6 +28,12 @@ pub const COMPRESSED_SIZE_MAX: u32 = 1 << 16;
 /// ```
 pub struct LZMA2Reader<R> {
     inner: R,
+    decoder: LZMA2Decoder,
+}
+
+/// The state of a LZMA2 decoder. The reader of the compressed data is passed on every read, so
+/// that the decoder can be part of a filter chain that doesn't own its reader.
+pub(crate) struct LZMA2Decoder {
     lz: LZDecoder,
     rc: RangeDecoder<RangeDecoderBuffer>,
     lzma: Option<LZMADecoder>,
@@ -46,7 +53,7 @@ pub fn get_memory_usage(dict_size: u32) -> u32 {
 
 #[inline]
 fn get_dict_size(dict_size: u32) -> u32 {
-    (dict_size + 15) & !15
+    dict_size.saturating_add(15) & !15
 }
 
 impl<R> LZMA2Reader<R> {
@@ -61,11 +68,36 @@ impl<R: Read> LZMA2Reader<R> {
     /// `inner` is the reader to read compressed data from.
     /// `dict_size` is the dictionary size in bytes.
     pub fn new(inner: R, dict_size: u32, preset_dict: Option<&[u8]>) -> Self {
+        Self {
+            inner,
+            decoder: LZMA2Decoder::new(dict_size, preset_dict),
+        }
+    }
+
+    /// Create a new LZMA2 reader with a memory usage limit.
+    /// `mem_limit_kb` is the memory usage limit in kibibytes (KiB). `u32::MAX` means no limit.
+    pub fn new_mem_limit(
+        inner: R,
+        dict_size: u32,
+        preset_dict: Option<&[u8]>,
+        mem_limit_kb: u32,
+    ) -> crate::Result<Self> {
+        if get_memory_usage(dict_size) > mem_limit_kb {
+            return Err(error_mem_limit(get_memory_usage(dict_size), mem_limit_kb));
+        }
+
+        Ok(Self::new(inner, dict_size, preset_dict))
+    }
+}
+
+impl LZMA2Decoder {
+    /// Create a new LZMA2 decoder.
+    /// `dict_size` is the dictionary size in bytes.
+    pub(crate) fn new(dict_size: u32, preset_dict: Option<&[u8]>) -> Self {
         let has_preset = preset_dict.as_ref().map(|a| !a.is_empty()).unwrap_or(false);
         let lz = LZDecoder::new(get_dict_size(dict_size) as _, preset_dict);
         let rc = RangeDecoder::new_buffer(COMPRESSED_SIZE_MAX as _);
         Self {
-            inner,
             lz,
             rc,
             lzma: None,
@@ -96,8 +128,8 @@ impl<R: Read> LZMA2Reader<R> {
     //  01   | 0xA0 – 0xBF  | Reset State             | No
     //  10   | 0xC0 – 0xDF  | Reset State & Props     | No
     //  11   | 0xE0 – 0xFF  | Reset Everything        | Yes
-    fn decode_chunk_header(&mut self) -> crate::Result<()> {
-        let control = self.inner.read_u8()?;
+    fn decode_chunk_header<R: Read>(&mut self, inner: &mut R) -> crate::Result<()> {
+        let control = inner.read_u8()?;
 
         if control == 0x00 {
             self.end_reached = true;
@@ -110,20 +142,20 @@ impl<R: Read> LZMA2Reader<R> {
             // Reset dictionary
             self.lz.reset();
         } else if self.need_dict_reset {
-            return Err(error_invalid_input("Corrupted input data (LZMA2:0)"));
+            return Err(error_invalid_data("Corrupted input data (LZMA2:0)"));
         }
         if control >= 0x80 {
             self.is_lzma_chunk = true;
             self.uncompressed_size = ((control & 0x1F) as usize) << 16;
-            self.uncompressed_size += self.inner.read_u16_be()? as usize + 1;
-            let compressed_size = self.inner.read_u16_be()? as usize + 1;
+            self.uncompressed_size += inner.read_u16_be()? as usize + 1;
+            let compressed_size = inner.read_u16_be()? as usize + 1;
 
             if control >= 0xC0 {
                 // Reset props and state (by re-creating it)
                 self.need_props = false;
-                self.decode_props()?;
+                self.decode_props(&mut *inner)?;
             } else if self.need_props {
-                return Err(error_invalid_input("Corrupted input data (LZMA2:1)"));
+                return Err(error_invalid_data("Corrupted input data (LZMA2:1)"));
             } else if control >= 0xA0 {
                 // Reset state
                 if let Some(l) = self.lzma.as_mut() {
@@ -131,54 +163,62 @@ impl<R: Read> LZMA2Reader<R> {
                 }
             }
 
-            self.rc.prepare(&mut self.inner, compressed_size)?;
+            self.rc.prepare(&mut *inner, compressed_size)?;
         } else if control > 0x02 {
-            return Err(error_invalid_input("Corrupted input data (LZMA2:2)"));
+            return Err(error_invalid_data("Corrupted input data (LZMA2:2)"));
         } else {
             self.is_lzma_chunk = false;
-            self.uncompressed_size = (self.inner.read_u16_be()? + 1) as _;
+            self.uncompressed_size = (inner.read_u16_be()? + 1) as _;
         }
         Ok(())
     }
 
     /// Reads the next props and re-creates the state by creating a new decoder.
-    fn decode_props(&mut self) -> crate::Result<()> {
-        let props = self.inner.read_u8()?;
+    fn decode_props<R: Read>(&mut self, inner: &mut R) -> crate::Result<()> {
+        let props = inner.read_u8()?;
         if props > (4 * 5 + 4) * 9 + 8 {
-            return Err(error_invalid_input("Corrupted input data (LZMA2:3)"));
+            return Err(error_invalid_data("Corrupted input data (LZMA2:3)"));
         }
         let pb = props / (9 * 5);
         let props = props - pb * 9 * 5;
         let lp = props / 9;
         let lc = props - lp * 9;
         if lc + lp > 4 {
-            return Err(error_invalid_input("Corrupted input data (LZMA2:4)"));
+            return Err(error_invalid_data("Corrupted input data (LZMA2:4)"));
         }
         self.lzma = Some(LZMADecoder::new(lc as _, lp as _, pb as _));
 
         Ok(())
     }
 
-    fn read_decode(&mut self, buf: &mut [u8]) -> crate::Result<usize> {
-        if buf.is_empty() {
+    fn decode<R: Read, F: FnMut(&[u8])>(
+        &mut self,
+        inner: &mut R,
+        len_max: usize,
+        mut sink: F,
+    ) -> crate::Result<usize> {
+        if len_max == 0 {
             return Ok(0);
         }
         if let Some(error) = &self.error {
-            #[cfg(not(feature = "std"))]
-            return Err(*error);
-            #[cfg(feature = "std")]
-            return Err(Error::new(error.kind(), error.to_string()));
+            return Err(copy_error(error));
         }
 
         if self.end_reached {
             return Ok(0);
         }
         let mut size = 0;
-        let mut len = buf.len();
-        let mut off = 0;
+        let mut len = len_max;
         while len > 0 {
             if self.uncompressed_size == 0 {
-                self.decode_chunk_header()?;
+                // Return the data of the finished chunk before reading the next chunk header, so
+                // that a stream that was flushed can be decoded up to the flush point before the
+                // rest of the stream is available.
+                if size > 0 {
+                    return Ok(size);
+                }
+
+                self.decode_chunk_header(&mut *inner)?;
                 if self.end_reached {
                     return Ok(size);
                 }
@@ -186,7 +226,7 @@ impl<R: Read> LZMA2Reader<R> {
 
             let copy_size_max = self.uncompressed_size.min(len);
             if !self.is_lzma_chunk {
-                self.lz.copy_uncompressed(&mut self.inner, copy_size_max)?;
+                self.lz.copy_uncompressed(&mut *inner, copy_size_max)?;
             } else {
                 self.lz.set_limit(copy_size_max);
                 if let Some(lzma) = self.lzma.as_mut() {
@@ -195,36 +235,50 @@ impl<R: Read> LZMA2Reader<R> {
             }
 
             {
-                let copied_size = self.lz.flush(buf, off);
-                off += copied_size;
+                let data = self.lz.take_flushed();
+                let copied_size = data.len();
+                sink(data);
                 len -= copied_size;
                 size += copied_size;
                 self.uncompressed_size -= copied_size;
                 if self.uncompressed_size == 0 && (!self.rc.is_finished() || self.lz.has_pending())
                 {
-                    return Err(error_invalid_input("rc not finished or lz has pending"));
+                    return Err(error_invalid_data("rc not finished or lz has pending"));
                 }
             }
         }
         Ok(size)
     }
-}
 
-impl<R: Read> Read for LZMA2Reader<R> {
-    fn read(&mut self, buf: &mut [u8]) -> crate::Result<usize> {
-        match self.read_decode(buf) {
+    /// Decodes into `buf`, reading the compressed data from `inner`. Errors are sticky.
+    pub(crate) fn read<R: Read>(&mut self, inner: &mut R, buf: &mut [u8]) -> crate::Result<usize> {
+        let mut off = 0;
+        self.decode_with(inner, buf.len(), |data| {
+            buf[off..off + data.len()].copy_from_slice(data);
+            off += data.len();
+        })
+    }
+
+    /// Decodes up to `len_max` bytes and passes them to `sink` straight from the dictionary,
+    /// reading the compressed data from `inner`. Errors are sticky.
+    pub(crate) fn decode_with<R: Read, F: FnMut(&[u8])>(
+        &mut self,
+        inner: &mut R,
+        len_max: usize,
+        sink: F,
+    ) -> crate::Result<usize> {
+        match self.decode(inner, len_max, sink) {
             Ok(size) => Ok(size),
             Err(error) => {
-                #[cfg(not(feature = "std"))]
-                {
-                    self.error = Some(error);
-                }
-                #[cfg(feature = "std")]
-                {
-                    self.error = Some(Error::new(error.kind(), error.to_string()));
-                }
+                self.error = Some(copy_error(&error));
                 Err(error)
             }
         }
     }
 }
+
+impl<R: Read> Read for LZMA2Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> crate::Result<usize> {
+        self.decoder.read(&mut self.inner, buf)
+    }
+}
@@ -11,9 +11,11 @@ use std::{
 };
 
 use crate::{
+    error_invalid_data, error_mem_limit,
+    lzma2_reader::get_memory_usage,
     set_error,
     work_queue::{WorkStealingQueue, WorkerHandle},
-    LZMA2Reader,
+    workers_for_mem_limit, LZMA2Reader,
 };
 
 /// A work unit for a worker thread.
@@ -42,6 +44,8 @@ pub struct LZMA2ReaderMT<R: Read> {
     result_rx: Receiver<ResultUnit>,
     result_tx: Sender<ResultUnit>,
     current_work_unit: Vec<u8>,
+    current_work_unit_size: u64,
+    work_unit_mem_limit_kb: u32,
     next_sequence_to_dispatch: u64,
     next_sequence_to_return: u64,
     last_sequence_id: Option<u64>,
@@ -80,6 +84,8 @@ impl<R: Read> LZMA2ReaderMT<R> {
             result_rx,
             result_tx,
             current_work_unit: Vec::with_capacity(1024 * 1024),
+            current_work_unit_size: 0,
+            work_unit_mem_limit_kb: u32::MAX,
             next_sequence_to_dispatch: 0,
             next_sequence_to_return: 0,
             last_sequence_id: None,
@@ -101,6 +107,40 @@ impl<R: Read> LZMA2ReaderMT<R> {
         reader
     }
 
+    /// Creates a new multi-threaded LZMA2 reader with a memory usage limit.
+    ///
+    /// Every worker needs its own dictionary and the buffers for the compressed and the
+    /// decompressed work unit, so the count of workers is reduced until all workers together stay
+    /// inside the limit. The buffers are budgeted with the dictionary size each. A work unit is
+    /// allowed to use the rest of the share of its worker, a bigger work unit returns
+    /// [`LzmaError::MemLimitExceeded`](crate::LzmaError::MemLimitExceeded) while reading.
+    ///
+    /// - `inner`: The reader to read compressed data from.
+    /// - `dict_size`: The dictionary size in bytes, as specified in the stream properties.
+    /// - `preset_dict`: An optional preset dictionary.
+    /// - `num_workers`: The maximum number of worker threads for decompression. Currently capped at 256 Threads.
+    /// - `mem_limit_kb`: The memory usage limit of all workers in kibibytes (KiB). `u32::MAX` means no limit.
+    pub fn new_mem_limit(
+        inner: R,
+        dict_size: u32,
+        preset_dict: Option<&[u8]>,
+        num_workers: u32,
+        mem_limit_kb: u32,
+    ) -> io::Result<Self> {
+        let dict_mem_kb = get_memory_usage(dict_size);
+        let buffer_kb = dict_size.div_ceil(1024).saturating_mul(2);
+        let num_workers = workers_for_mem_limit(
+            num_workers.clamp(1, 256),
+            dict_mem_kb.saturating_add(buffer_kb),
+            mem_limit_kb,
+        )?;
+
+        let mut reader = Self::new(inner, dict_size, preset_dict, num_workers);
+        reader.work_unit_mem_limit_kb = (mem_limit_kb / num_workers).saturating_sub(dict_mem_kb);
+
+        Ok(reader)
+    }
+
     fn spawn_worker_thread(&mut self) {
         let worker_handle = self.work_queue.worker();
         let result_tx = self.result_tx.clone();
@@ -172,18 +212,19 @@ impl<R: Read> LZMA2ReaderMT<R> {
             self.inner.read_exact(&mut header_buf[..header_len])?;
             self.current_work_unit
                 .extend_from_slice(&header_buf[..header_len]);
+            self.current_work_unit_size += (((control & 0x1F) as u64) << 16)
+                + u16::from_be_bytes([header_buf[0], header_buf[1]]) as u64
+                + 1;
             u16::from_be_bytes([header_buf[2], header_buf[3]]) as usize + 1
         } else if control == 0x01 || control == 0x02 {
             // Uncompressed chunk.
             let mut size_buf = [0u8; 2];
             self.inner.read_exact(&mut size_buf)?;
             self.current_work_unit.extend_from_slice(&size_buf);
+            self.current_work_unit_size += u16::from_be_bytes(size_buf) as u64 + 1;
             u16::from_be_bytes(size_buf) as usize + 1
         } else {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("Invalid LZMA2 control byte: {control:X}"),
-            ));
+            return Err(error_invalid_data("Invalid LZMA2 control byte"));
         };
 
         // Read the chunk data itself.
@@ -195,6 +236,18 @@ impl<R: Read> LZMA2ReaderMT<R> {
                 .read_exact(&mut self.current_work_unit[start_len..])?;
         }
 
+        let buffer_kb = (self.current_work_unit.len() as u64)
+            .saturating_add(self.current_work_unit_size)
+            .div_ceil(1024);
+        let buffer_kb = u32::try_from(buffer_kb).unwrap_or(u32::MAX);
+        if buffer_kb > self.work_unit_mem_limit_kb {
+            let dict_mem_kb = get_memory_usage(self.dict_size);
+            return Err(error_mem_limit(
+                dict_mem_kb.saturating_add(buffer_kb),
+                dict_mem_kb.saturating_add(self.work_unit_mem_limit_kb),
+            ));
+        }
+
         Ok(true)
     }
 
@@ -206,6 +259,7 @@ impl<R: Read> LZMA2ReaderMT<R> {
 
         let work_unit =
             core::mem::replace(&mut self.current_work_unit, Vec::with_capacity(1024 * 1024));
+        self.current_work_unit_size = 0;
 
         if !self
             .work_queue
@@ -0,0 +1,536 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    io::{Cursor, Read, Seek, SeekFrom},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::{
+    error_invalid_data, error_mem_limit,
+    lzma2_reader::get_memory_usage,
+    set_error,
+    work_queue::{WorkStealingQueue, WorkerHandle},
+    workers_for_mem_limit, LZMA2Reader,
+};
+
+/// A work unit for a worker thread.
+/// Contains the sequence number, the start and end position of a series of chunks in the inner
+/// reader and whether the series is followed by an end marker.
+type WorkUnit = (u64, u64, u64, bool);
+
+/// A result unit from a worker thread.
+/// Contains the sequence number and the decompressed data.
+type ResultUnit = (u64, Vec<u8>);
+
+enum State {
+    /// Actively scanning the inner reader and sending work to threads.
+    Reading,
+    /// The inner reader has reached EOF. We are now waiting for the remaining
+    /// work to be completed by the worker threads.
+    Draining,
+    /// All data has been decompressed and returned. The stream is exhausted.
+    Finished,
+    /// A fatal error occurred in either the reader or a worker thread.
+    Error,
+}
+
+/// A multi-threaded LZMA2 decompressor for sources that can be shared between threads.
+///
+/// [`crate::LZMA2ReaderMT`] copies the compressed data of every work unit into a buffer before
+/// dispatching it. This reader only scans the chunk headers and seeks over the chunk data, then
+/// dispatches the positions of the work unit. Every worker thread reads its ranges with its own
+/// clone of the inner reader, so the compressed data is never copied and workers start decoding
+/// while the following chunks are still being scanned.
+///
+/// The clones must read the same data independently of each other. A byte slice shared between
+/// the threads can be used with a `Cursor<Arc<[u8]>>` or a `Cursor<&'static [u8]>`.
+pub struct LZMA2SharedReaderMT<R: Read + Seek + Clone + Send + 'static> {
+    inner: R,
+    position: u64,
+    unit_start_pos: Option<u64>,
+    unit_uncompressed_size: u64,
+    work_unit_mem_limit_kb: u32,
+    result_rx: Receiver<ResultUnit>,
+    result_tx: Sender<ResultUnit>,
+    next_sequence_to_dispatch: u64,
+    next_sequence_to_return: u64,
+    last_sequence_id: Option<u64>,
+    out_of_order_chunks: BTreeMap<u64, Vec<u8>>,
+    current_chunk: Cursor<Vec<u8>>,
+    shutdown_flag: Arc<AtomicBool>,
+    error_store: Arc<Mutex<Option<io::Error>>>,
+    state: State,
+    work_queue: WorkStealingQueue<WorkUnit>,
+    active_workers: Arc<AtomicU32>,
+    max_workers: u32,
+    dict_size: u32,
+    preset_dict: Option<Arc<Vec<u8>>>,
+    worker_handles: Vec<thread::JoinHandle<()>>,
+}
+
+impl<R: Read + Seek + Clone + Send + 'static> LZMA2SharedReaderMT<R> {
+    /// Creates a new multi-threaded LZMA2 reader. The compressed data starts at the current
+    /// position of `inner`.
+    ///
+    /// - `inner`: The reader to read compressed data from. Every worker thread reads with its own clone.
+    /// - `dict_size`: The dictionary size in bytes, as specified in the stream properties.
+    /// - `preset_dict`: An optional preset dictionary.
+    /// - `num_workers`: The maximum number of worker threads for decompression. Currently capped at 256 Threads.
+    pub fn new(
+        mut inner: R,
+        dict_size: u32,
+        preset_dict: Option<&[u8]>,
+        num_workers: u32,
+    ) -> io::Result<Self> {
+        let position = inner.stream_position()?;
+        let max_workers = num_workers.clamp(1, 256);
+
+        let work_queue = WorkStealingQueue::new();
+        let (result_tx, result_rx) = mpsc::channel::<ResultUnit>();
+        let shutdown_flag = Arc::new(AtomicBool::new(false));
+        let error_store = Arc::new(Mutex::new(None));
+        let active_workers = Arc::new(AtomicU32::new(0));
+        let preset_dict = preset_dict.map(|s| s.to_vec()).map(Arc::new);
+
+        let mut reader = Self {
+            inner,
+            position,
+            unit_start_pos: None,
+            unit_uncompressed_size: 0,
+            work_unit_mem_limit_kb: u32::MAX,
+            result_rx,
+            result_tx,
+            next_sequence_to_dispatch: 0,
+            next_sequence_to_return: 0,
+            last_sequence_id: None,
+            out_of_order_chunks: BTreeMap::new(),
+            current_chunk: Cursor::new(Vec::new()),
+            shutdown_flag,
+            error_store,
+            state: State::Reading,
+            work_queue,
+            active_workers,
+            max_workers,
+            dict_size,
+            preset_dict,
+            worker_handles: Vec::new(),
+        };
+
+        reader.spawn_worker_thread();
+
+        Ok(reader)
+    }
+
+    /// Creates a new multi-threaded LZMA2 reader with a memory usage limit.
+    ///
+    /// Every worker needs its own dictionary and the buffers for the compressed and the
+    /// decompressed work unit, so the count of workers is reduced until all workers together stay
+    /// inside the limit. The buffers are budgeted with the dictionary size each. A work unit is
+    /// allowed to use the rest of the share of its worker, a bigger work unit returns
+    /// [`LzmaError::MemLimitExceeded`](crate::LzmaError::MemLimitExceeded) while reading.
+    ///
+    /// - `inner`: The reader to read compressed data from. Every worker thread reads with its own clone.
+    /// - `dict_size`: The dictionary size in bytes, as specified in the stream properties.
+    /// - `preset_dict`: An optional preset dictionary.
+    /// - `num_workers`: The maximum number of worker threads for decompression. Currently capped at 256 Threads.
+    /// - `mem_limit_kb`: The memory usage limit of all workers in kibibytes (KiB). `u32::MAX` means no limit.
+    pub fn new_mem_limit(
+        inner: R,
+        dict_size: u32,
+        preset_dict: Option<&[u8]>,
+        num_workers: u32,
+        mem_limit_kb: u32,
+    ) -> io::Result<Self> {
+        let dict_mem_kb = get_memory_usage(dict_size);
+        let buffer_kb = dict_size.div_ceil(1024).saturating_mul(2);
+        let num_workers = workers_for_mem_limit(
+            num_workers.clamp(1, 256),
+            dict_mem_kb.saturating_add(buffer_kb),
+            mem_limit_kb,
+        )?;
+
+        let mut reader = Self::new(inner, dict_size, preset_dict, num_workers)?;
+        reader.work_unit_mem_limit_kb = (mem_limit_kb / num_workers).saturating_sub(dict_mem_kb);
+
+        Ok(reader)
+    }
+
+    fn spawn_worker_thread(&mut self) {
+        let worker_handle = self.work_queue.worker();
+        let inner = self.inner.clone();
+        let result_tx = self.result_tx.clone();
+        let shutdown_flag = Arc::clone(&self.shutdown_flag);
+        let error_store = Arc::clone(&self.error_store);
+        let active_workers = Arc::clone(&self.active_workers);
+        let preset_dict = self.preset_dict.clone();
+        let dict_size = self.dict_size;
+
+        let handle = thread::spawn(move || {
+            worker_thread_logic(
+                worker_handle,
+                inner,
+                result_tx,
+                dict_size,
+                preset_dict,
+                shutdown_flag,
+                error_store,
+                active_workers,
+            );
+        });
+
+        self.worker_handles.push(handle);
+    }
+
+    /// The count of independent chunks found inside the compressed file.
+    /// This is effectively tha maximum parallelization possible.
+    pub fn chunk_count(&self) -> u64 {
+        self.next_sequence_to_return
+    }
+
+    /// Scans one LZMA2 chunk header of the inner reader and seeks over the chunk data.
+    /// If the chunk is an independent block, it dispatches the current work unit.
+    ///
+    /// Returns `Ok(false)` on clean EOF, `Ok(true)` on success, and `Err` on I/O error.
+    fn scan_and_dispatch_chunk(&mut self) -> io::Result<bool> {
+        let chunk_start_pos = self.position;
+
+        let mut control_buf = [0u8; 1];
+        match self.inner.read_exact(&mut control_buf) {
+            Ok(_) => (),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                // Clean end of stream.
+                return Ok(false);
+            }
+            Err(error) => return Err(error),
+        }
+
+        let control = control_buf[0];
+
+        if control == 0x00 {
+            // End of stream marker.
+            self.position += 1;
+            self.unit_start_pos.get_or_insert(chunk_start_pos);
+            self.send_work_unit(chunk_start_pos, true);
+            return Ok(false);
+        }
+
+        let is_independent_chunk = control >= 0xE0 || control == 0x01;
+
+        // Split work units before independent chunks (but not for the very first chunk).
+        if is_independent_chunk {
+            self.send_work_unit(chunk_start_pos, true);
+        }
+
+        self.unit_start_pos.get_or_insert(chunk_start_pos);
+
+        let chunk_data_size = if control >= 0x80 {
+            // Compressed chunk. Read header to find size.
+            let header_len = if control >= 0xC0 { 5 } else { 4 };
+            let mut header_buf = [0; 5];
+            self.inner.read_exact(&mut header_buf[..header_len])?;
+            self.unit_uncompressed_size += (((control & 0x1F) as u64) << 16)
+                + u16::from_be_bytes([header_buf[0], header_buf[1]]) as u64
+                + 1;
+            u16::from_be_bytes([header_buf[2], header_buf[3]]) as i64 + 1
+        } else if control == 0x01 || control == 0x02 {
+            // Uncompressed chunk.
+            let mut size_buf = [0u8; 2];
+            self.inner.read_exact(&mut size_buf)?;
+            self.unit_uncompressed_size += u16::from_be_bytes(size_buf) as u64 + 1;
+            u16::from_be_bytes(size_buf) as i64 + 1
+        } else {
+            return Err(error_invalid_data("Invalid LZMA2 control byte"));
+        };
+
+        // Skip the chunk data, it's read by the worker. A truncated chunk is detected by the
+        // worker as well.
+        self.position = self.inner.seek(SeekFrom::Current(chunk_data_size))?;
+
+        // The worker holds the compressed and the decompressed work unit in memory.
+        let unit_start_pos = self.unit_start_pos.expect("unit start position not set");
+        let buffer_kb = (self.position - unit_start_pos)
+            .saturating_add(self.unit_uncompressed_size)
+            .div_ceil(1024);
+        let buffer_kb = u32::try_from(buffer_kb).unwrap_or(u32::MAX);
+        if buffer_kb > self.work_unit_mem_limit_kb {
+            let dict_mem_kb = get_memory_usage(self.dict_size);
+            return Err(error_mem_limit(
+                dict_mem_kb.saturating_add(buffer_kb),
+                dict_mem_kb.saturating_add(self.work_unit_mem_limit_kb),
+            ));
+        }
+
+        Ok(true)
+    }
+
+    /// Sends the positions of the current work unit to the workers. The unit ends at `end_pos`.
+    fn send_work_unit(&mut self, end_pos: u64, has_end_marker: bool) {
+        let Some(start_pos) = self.unit_start_pos.take() else {
+            return;
+        };
+        self.unit_uncompressed_size = 0;
+
+        if !self.work_queue.push((
+            self.next_sequence_to_dispatch,
+            start_pos,
+            end_pos,
+            has_end_marker,
+        )) {
+            // Queue is closed, this indicates shutdown.
+            self.state = State::Error;
+            set_error(
+                io::Error::new(io::ErrorKind::BrokenPipe, "Worker threads have shut down"),
+                &self.error_store,
+                &self.shutdown_flag,
+            );
+        }
+
+        // We spawn a new thread if we have work queued, no available workers, and haven't reached
+        // the maximal allowed parallelism yet.
+        let spawned_workers = self.worker_handles.len() as u32;
+        let active_workers = self.active_workers.load(Ordering::Acquire);
+        let queue_len = self.work_queue.len();
+
+        if queue_len > 0 && active_workers == spawned_workers && spawned_workers < self.max_workers
+        {
+            self.spawn_worker_thread();
+        }
+
+        self.next_sequence_to_dispatch += 1;
+    }
+
+    fn get_next_uncompressed_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            // Check for a globally stored error first, since a worker that failed wakes up the
+            // reader with an empty result.
+            if let Some(err) = self.error_store.lock().unwrap().take() {
+                self.state = State::Error;
+                return Err(err);
+            }
+
+            // Then check for already-received chunks.
+            if let Some(result) = self
+                .out_of_order_chunks
+                .remove(&self.next_sequence_to_return)
+            {
+                self.next_sequence_to_return += 1;
+                return Ok(Some(result));
+            }
+
+            match self.state {
+                State::Reading => {
+                    // First, always try to receive a result without blocking.
+                    // This keeps the pipeline moving and avoids unnecessary blocking on I/O.
+                    match self.result_rx.try_recv() {
+                        Ok((seq, result)) => {
+                            if seq == self.next_sequence_to_return {
+                                self.next_sequence_to_return += 1;
+                                return Ok(Some(result));
+                            } else {
+                                self.out_of_order_chunks.insert(seq, result);
+                                continue; // Loop again to check the out_of_order_chunks
+                            }
+                        }
+                        Err(mpsc::TryRecvError::Disconnected) => {
+                            // All workers are done.
+                            self.state = State::Draining;
+                            continue;
+                        }
+                        Err(mpsc::TryRecvError::Empty) => {
+                            // No results are ready. Now, we can consider scanning more input.
+                        }
+                    }
+
+                    // If the work queue has capacity, try to scan more chunks.
+                    if self.work_queue.len() < 4 {
+                        match self.scan_and_dispatch_chunk() {
+                            Ok(true) => {
+                                // Successfully scanned a chunk, loop to continue.
+                                continue;
+                            }
+                            Ok(false) => {
+                                // Clean EOF from inner reader.
+                                // Send any remaining chunks as the final work unit.
+                                self.send_work_unit(self.position, false);
+                                self.last_sequence_id =
+                                    Some(self.next_sequence_to_dispatch.saturating_sub(1));
+                                self.state = State::Draining;
+                                continue;
+                            }
+                            Err(error) => {
+                                set_error(error, &self.error_store, &self.shutdown_flag);
+                                self.state = State::Error;
+                                continue;
+                            }
+                        }
+                    }
+
+                    // Now we MUST wait for a result to make progress.
+                    match self.result_rx.recv() {
+                        Ok((seq, result)) => {
+                            if seq == self.next_sequence_to_return {
+                                self.next_sequence_to_return += 1;
+                                return Ok(Some(result));
+                            } else {
+                                self.out_of_order_chunks.insert(seq, result);
+                                // We've made progress, loop to check the out_of_order_chunks
+                                continue;
+                            }
+                        }
+                        Err(_) => {
+                            // All workers are done.
+                            self.state = State::Draining;
+                        }
+                    }
+                }
+                State::Draining => {
+                    if let Some(last_seq) = self.last_sequence_id {
+                        if self.next_sequence_to_return > last_seq {
+                            self.state = State::Finished;
+                            continue;
+                        }
+                    }
+
+                    // In Draining state, we only wait for results.
+                    match self.result_rx.recv() {
+                        Ok((seq, result)) => {
+                            if seq == self.next_sequence_to_return {
+                                self.next_sequence_to_return += 1;
+                                return Ok(Some(result));
+                            } else {
+                                self.out_of_order_chunks.insert(seq, result);
+                            }
+                        }
+                        Err(_) => {
+                            // All workers finished, and channel is empty. We are done.
+                            self.state = State::Finished;
+                        }
+                    }
+                }
+                State::Finished => {
+                    return Ok(None);
+                }
+                State::Error => {
+                    // The error was already logged, now we just propagate it.
+                    return Err(self.error_store.lock().unwrap().take().unwrap_or_else(|| {
+                        io::Error::other("decompression failed with an unknown error")
+                    }));
+                }
+            }
+        }
+    }
+}
+
+/// The logic for a single worker thread.
+#[allow(clippy::too_many_arguments)]
+fn worker_thread_logic<R: Read + Seek>(
+    worker_handle: WorkerHandle<WorkUnit>,
+    mut inner: R,
+    result_tx: Sender<ResultUnit>,
+    dict_size: u32,
+    preset_dict: Option<Arc<Vec<u8>>>,
+    shutdown_flag: Arc<AtomicBool>,
+    error_store: Arc<Mutex<Option<io::Error>>>,
+    active_workers: Arc<AtomicU32>,
+) {
+    while !shutdown_flag.load(Ordering::Acquire) {
+        let (seq, start_pos, end_pos, has_end_marker) = match worker_handle.steal() {
+            Some(work) => {
+                active_workers.fetch_add(1, Ordering::Release);
+                work
+            }
+            None => {
+                // No more work available and queue is closed
+                break;
+            }
+        };
+
+        let result = decode_work_unit(
+            &mut inner,
+            start_pos,
+            end_pos,
+            has_end_marker,
+            dict_size,
+            preset_dict.as_deref().map(|v| v.as_slice()),
+        );
+
+        let result = match result {
+            Ok(decompressed_data) => decompressed_data,
+            Err(error) => {
+                active_workers.fetch_sub(1, Ordering::Release);
+                set_error(error, &error_store, &shutdown_flag);
+                // Wake up the reader, so that it picks up the stored error.
+                let _ = result_tx.send((seq, Vec::new()));
+                return;
+            }
+        };
+
+        if result_tx.send((seq, result)).is_err() {
+            active_workers.fetch_sub(1, Ordering::Release);
+            return;
+        }
+
+        active_workers.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// Decodes the chunks between `start_pos` and `end_pos` directly from `inner`.
+fn decode_work_unit<R: Read + Seek>(
+    inner: &mut R,
+    start_pos: u64,
+    end_pos: u64,
+    has_end_marker: bool,
+    dict_size: u32,
+    preset_dict: Option<&[u8]>,
+) -> io::Result<Vec<u8>> {
+    inner.seek(SeekFrom::Start(start_pos))?;
+
+    let end_marker: &[u8] = if has_end_marker { &[0x00] } else { &[] };
+    let chunks = inner.take(end_pos - start_pos).chain(end_marker);
+    let mut reader = LZMA2Reader::new(chunks, dict_size, preset_dict);
+
+    let mut decompressed_data = Vec::with_capacity((end_pos - start_pos) as usize);
+    reader.read_to_end(&mut decompressed_data)?;
+
+    Ok(decompressed_data)
+}
+
+impl<R: Read + Seek + Clone + Send + 'static> Read for LZMA2SharedReaderMT<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let bytes_read = self.current_chunk.read(buf)?;
+
+        if bytes_read > 0 {
+            return Ok(bytes_read);
+        }
+
+        let chunk_data = self.get_next_uncompressed_chunk()?;
+
+        let Some(chunk_data) = chunk_data else {
+            // This is the clean end of the stream.
+            return Ok(0);
+        };
+
+        self.current_chunk = Cursor::new(chunk_data);
+
+        // Recursive call to read the new chunk data.
+        self.read(buf)
+    }
+}
+
+impl<R: Read + Seek + Clone + Send + 'static> Drop for LZMA2SharedReaderMT<R> {
+    fn drop(&mut self) {
+        self.shutdown_flag.store(true, Ordering::Release);
+        self.work_queue.close();
+        // Worker threads will exit when the work queue is closed.
+        // JoinHandles will be dropped, which is fine since we set the shutdown flag,
+    }
+}
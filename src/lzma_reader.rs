@@ -1,8 +1,11 @@
 use super::{
-    decoder::LZMADecoder, error_invalid_data, error_invalid_input, error_out_of_memory,
-    lz::LZDecoder, range_dec::RangeDecoder, ByteReader, Read, DICT_SIZE_MAX,
+    decoder::LZMADecoder, error_invalid_data, error_invalid_input, error_mem_limit, lz::LZDecoder,
+    range_dec::RangeDecoder, ByteReader, CountingReader, Read, DICT_SIZE_MAX,
 };
 
+/// The maximal size of the data that is decoded at once by [`verify`].
+const VERIFY_CHUNK_SIZE: usize = 64 * 1024;
+
 /// Calculates the memory usage in KiB required for LZMA decompression from properties byte.
 pub fn get_memory_usage_by_props(dict_size: u32, props_byte: u8) -> crate::Result<u32> {
     if dict_size > DICT_SIZE_MAX {
@@ -33,6 +36,40 @@ fn get_dict_size(dict_size: u32) -> crate::Result<u32> {
     Ok((dict_size + 15) & !15)
 }
 
+/// Summary of an .lzma file that was decoded without producing output.
+///
+/// Created by [`verify`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LZMASummary {
+    /// The size of the file including the header.
+    pub compressed_size: u64,
+    /// The uncompressed size.
+    pub uncompressed_size: u64,
+}
+
+/// Decodes an .lzma file without producing output and returns a summary.
+///
+/// The .lzma format has no check, so only the integrity of the compressed data and the
+/// uncompressed size of the header, if it's known, are verified. The data is decoded straight
+/// into the dictionary of the decoder, so no output buffer is needed.
+pub fn verify<R: Read>(reader: R) -> crate::Result<LZMASummary> {
+    let mut reader = LZMAReader::new_mem_limit(CountingReader::new(reader), u32::MAX, None)?;
+
+    let mut uncompressed_size = 0;
+    loop {
+        let bytes_read = reader.decode_with(VERIFY_CHUNK_SIZE, |_| {})?;
+        if bytes_read == 0 {
+            break;
+        }
+        uncompressed_size += bytes_read as u64;
+    }
+
+    Ok(LZMASummary {
+        compressed_size: reader.get_ref().bytes_read(),
+        uncompressed_size,
+    })
+}
+
 /// A single-threaded LZMA decompressor.
 ///
 /// # Examples
@@ -71,6 +108,11 @@ impl<R> LZMAReader<R> {
     pub fn into_inner(self) -> R {
         self.rc.into_inner()
     }
+
+    /// Returns a reference to the underlying reader.
+    pub(crate) fn get_ref(&self) -> &R {
+        self.rc.get_ref()
+    }
 }
 
 impl<R: Read> LZMAReader<R> {
@@ -148,13 +190,13 @@ impl<R: Read> LZMAReader<R> {
     ) -> crate::Result<Self> {
         let props = reader.read_u8()?;
         let dict_size = reader.read_u32()?;
+        // Like liblzma, accept the dictionary size `u32::MAX` that some encoders write.
+        let dict_size = dict_size.min(DICT_SIZE_MAX);
 
         let uncomp_size = reader.read_u64()?;
         let need_mem = get_memory_usage_by_props(dict_size, props)?;
         if mem_limit_kb < need_mem {
-            return Err(error_out_of_memory(
-                "needed memory too big for mem_limit_kb",
-            ));
+            return Err(error_mem_limit(need_mem, mem_limit_kb));
         }
         Self::construct1(reader, uncomp_size, props, dict_size, preset_dict)
     }
@@ -195,16 +237,20 @@ impl<R: Read> LZMAReader<R> {
         Self::construct2(reader, uncomp_size, lc, lp, pb, dict_size, preset_dict)
     }
 
-    fn read_decode(&mut self, buf: &mut [u8]) -> crate::Result<usize> {
-        if buf.is_empty() {
+    /// Decodes up to `len_max` bytes and passes them to `sink` straight from the dictionary.
+    pub(crate) fn decode_with<F: FnMut(&[u8])>(
+        &mut self,
+        len_max: usize,
+        mut sink: F,
+    ) -> crate::Result<usize> {
+        if len_max == 0 {
             return Ok(0);
         }
         if self.end_reached {
             return Ok(0);
         }
         let mut size = 0;
-        let mut len = buf.len() as u64;
-        let mut off = 0;
+        let mut len = len_max as u64;
         while len > 0 {
             let mut copy_size_max = len;
             if self.remaining_size <= u64::MAX / 2 && self.remaining_size < len {
@@ -223,8 +269,9 @@ impl<R: Read> LZMAReader<R> {
                 }
             }
 
-            let copied_size = self.lz.flush(buf, off as _) as u64;
-            off += copied_size;
+            let data = self.lz.take_flushed();
+            let copied_size = data.len() as u64;
+            sink(data);
             len -= copied_size;
             size += copied_size;
             if self.remaining_size <= u64::MAX / 2 {
@@ -249,6 +296,10 @@ impl<R: Read> LZMAReader<R> {
 
 impl<R: Read> Read for LZMAReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> crate::Result<usize> {
-        self.read_decode(buf)
+        let mut off = 0;
+        self.decode_with(buf.len(), |data| {
+            buf[off..off + data.len()].copy_from_slice(data);
+            off += data.len();
+        })
     }
 }
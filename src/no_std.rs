@@ -9,12 +9,10 @@ pub enum Error {
     EOF,
     /// Operation was interrupted.
     Interrupted,
-    /// Invalid data encountered.
-    InvalidData(&'static str),
+    /// Invalid or corrupt data encountered, or another detailed error of the decoders.
+    Lzma(crate::LzmaError),
     /// Invalid input provided.
     InvalidInput(&'static str),
-    /// Out of memory error.
-    OutOfMemory(&'static str),
     /// Other error.
     Other(&'static str),
     /// Unsupported operation.
@@ -1,7 +1,7 @@
 use alloc::{vec, vec::Vec};
 
 use crate::{
-    error_eof, error_invalid_input, ByteReader, Read, BIT_MODEL_TOTAL_BITS, MOVE_BITS,
+    error_eof, error_invalid_data, ByteReader, Read, BIT_MODEL_TOTAL_BITS, MOVE_BITS,
     RC_BIT_MODEL_OFFSET, SHIFT_BITS,
 };
 
@@ -15,6 +15,10 @@ impl<R> RangeDecoder<R> {
     pub(crate) fn into_inner(self) -> R {
         self.inner
     }
+
+    pub(crate) fn get_ref(&self) -> &R {
+        &self.inner
+    }
 }
 
 impl RangeDecoder<RangeDecoderBuffer> {
@@ -31,7 +35,7 @@ impl<R: RangeReader> RangeDecoder<R> {
     pub(crate) fn new_stream(mut inner: R) -> crate::Result<Self> {
         let b = inner.try_read_u8()?;
         if b != 0x00 {
-            return Err(error_invalid_input("range decoder first byte is not zero"));
+            return Err(error_invalid_data("range decoder first byte is not zero"));
         }
         let code = inner.read_u32_be()?;
         Ok(Self {
@@ -344,12 +348,12 @@ impl RangeDecoder<RangeDecoderBuffer> {
         len: usize,
     ) -> crate::Result<()> {
         if len < 5 {
-            return Err(error_invalid_input("buffer len must >= 5"));
+            return Err(error_invalid_data("buffer len must >= 5"));
         }
 
         let b = reader.read_u8()?;
         if b != 0x00 {
-            return Err(error_invalid_input("first byte is 0"));
+            return Err(error_invalid_data("range decoder first byte is not zero"));
         }
         self.code = reader.read_u32_be()?;
 
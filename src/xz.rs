@@ -1,28 +1,64 @@
 //! XZ format decoder and encoder implementation.
 
+#[cfg(feature = "std")]
+mod info;
+mod raw_reader;
+#[cfg(feature = "encoder")]
+mod raw_writer;
 mod reader;
+#[cfg(feature = "std")]
+mod reader_mt;
+#[cfg(feature = "std")]
+mod recover;
+#[cfg(feature = "std")]
+mod seekable_reader;
+#[cfg(feature = "std")]
+mod stream_reader_mt;
+mod verify;
 #[cfg(feature = "encoder")]
 mod writer;
+#[cfg(all(feature = "encoder", feature = "std"))]
+mod writer_mt;
+
+use alloc::{vec, vec::Vec};
 
+#[cfg(feature = "std")]
+pub use info::{inspect, XZBlockInfo, XZInfo, XZStreamInfo};
+pub use raw_reader::RawReader;
+#[cfg(feature = "encoder")]
+pub use raw_writer::RawWriter;
 pub use reader::XZReader;
+#[cfg(feature = "std")]
+pub use reader_mt::XZReaderMT;
+#[cfg(feature = "std")]
+pub use recover::{recover, XZDamage, XZLostRange, XZRecovery};
+#[cfg(feature = "std")]
+pub use seekable_reader::XZSeekableReader;
 use sha2::Digest;
+#[cfg(feature = "std")]
+pub use stream_reader_mt::XZStreamReaderMT;
+pub use verify::{verify, XZSummary};
 #[cfg(feature = "encoder")]
-pub use writer::{XZOptions, XZWriter};
+pub use writer::{XZBlockOptions, XZOptions, XZWriter};
+#[cfg(all(feature = "encoder", feature = "std"))]
+pub use writer_mt::XZWriterMT;
 
-use crate::{error_invalid_data, ByteReader, Read, Result};
+use crate::{
+    error_invalid_data, error_unsupported_filter, ByteReader, CheckValue, LzmaError, Read, Result,
+};
 
 const CRC32: crc::Crc<u32, crc::Table<16>> =
     crc::Crc::<u32, crc::Table<16>>::new(&crc::CRC_32_ISO_HDLC);
 const CRC64: crc::Crc<u64, crc::Table<16>> = crc::Crc::<u64, crc::Table<16>>::new(&crc::CRC_64_XZ);
 
 /// XZ stream magic bytes: 0xFD, '7', 'z', 'X', 'Z', 0x00
-const XZ_MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0x00];
+pub(crate) const XZ_MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0x00];
 
 /// XZ stream footer magic bytes.
 const XZ_FOOTER_MAGIC: [u8; 2] = [b'Y', b'Z'];
 
 /// XZ Index record containing block metadata.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct IndexRecord {
     unpadded_size: u64,
     uncompressed_size: u64,
@@ -31,7 +67,11 @@ pub(crate) struct IndexRecord {
 /// Configuration for a filter in the XZ filter chain.
 #[derive(Debug, Clone)]
 pub struct FilterConfig {
+    /// The type of the filter.
     pub filter_type: FilterType,
+    /// The property of the filter. The distance for the delta filter and the start offset for the
+    /// BCJ filters. Filters read from a block header store the dictionary size as the property of
+    /// the LZMA2 filter.
     pub property: u32,
 }
 
@@ -132,8 +172,19 @@ impl CheckType {
             _ => Err(error_invalid_data("unsupported XZ check type")),
         }
     }
+
+    /// Size of the check field in bytes.
+    fn checksum_size(self) -> usize {
+        match self {
+            CheckType::None => 0,
+            CheckType::Crc32 => 4,
+            CheckType::Crc64 => 8,
+            CheckType::Sha256 => 32,
+        }
+    }
 }
 
+/// Filters supported in the XZ filter chain.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum FilterType {
     /// Delta filter
@@ -178,6 +229,69 @@ impl TryFrom<u64> for FilterType {
     }
 }
 
+impl FilterType {
+    /// The alignment that the start offset of a BCJ filter must have. This is the size of the
+    /// instructions of the architecture.
+    fn start_offset_alignment(self) -> u32 {
+        match self {
+            FilterType::BcjPPC
+            | FilterType::BcjARM
+            | FilterType::BcjSPARC
+            | FilterType::BcjARM64 => 4,
+            FilterType::BcjIA64 => 16,
+            FilterType::BcjARMThumb | FilterType::BcjRISCV => 2,
+            FilterType::Delta | FilterType::BcjX86 | FilterType::LZMA2 => 1,
+        }
+    }
+}
+
+/// Checks a filter chain of filter types and their properties against the rules of the XZ
+/// specification, which are shared by the encoder and the decoder:
+///
+/// - The chain has one to four filters.
+/// - LZMA2 is the last filter and is only used once.
+/// - No filter is used twice.
+/// - The distance of the Delta filter is in `1..=256`.
+/// - The start offset of a BCJ filter is aligned to the instruction size of its architecture.
+fn validate_filter_chain<I: IntoIterator<Item = (FilterType, u32)>>(
+    filters: I,
+) -> core::result::Result<(), &'static str> {
+    let mut used = [None; 4];
+    let mut count = 0;
+
+    for (filter_type, property) in filters {
+        if count == used.len() {
+            return Err("XZ filter chain has more than 4 filters");
+        }
+        if used.contains(&Some(FilterType::LZMA2)) {
+            return Err("XZ block's last filter must be a LZMA2 filter");
+        }
+        if used.contains(&Some(filter_type)) {
+            return Err("XZ filter chain contains a filter twice");
+        }
+
+        match filter_type {
+            FilterType::Delta if !(1..=256).contains(&property) => {
+                return Err("Delta distance must be between 1 and 256");
+            }
+            FilterType::Delta | FilterType::LZMA2 => {}
+            _ if property % filter_type.start_offset_alignment() != 0 => {
+                return Err("BCJ start offset not aligned to filter requirements");
+            }
+            _ => {}
+        }
+
+        used[count] = Some(filter_type);
+        count += 1;
+    }
+
+    if count == 0 || used[count - 1] != Some(FilterType::LZMA2) {
+        return Err("XZ block's last filter must be a LZMA2 filter");
+    }
+
+    Ok(())
+}
+
 /// Parse XZ multibyte integer (variable length encoding).
 fn parse_multibyte_integer(data: &[u8]) -> Result<u64> {
     let mut result = 0u64;
@@ -268,6 +382,594 @@ fn encode_multibyte_integer(mut value: u64, buf: &mut [u8]) -> Result<usize> {
     Ok(i)
 }
 
+/// XZ Index containing all block records and metadata.
+#[derive(Debug)]
+struct Index {
+    number_of_records: u64,
+    records: Vec<IndexRecord>,
+}
+
+impl Index {
+    fn parse<R: Read>(reader: &mut R) -> Result<Self> {
+        // sic! Index indicator is already parsed (0x00) in BlockHeader::parse.
+
+        let number_of_records = parse_multibyte_integer_from_reader(reader)?;
+        // Don't trust the record count for the allocation, since the index could be corrupted.
+        let mut records = Vec::with_capacity(number_of_records.min(1024) as usize);
+
+        for _ in 0..number_of_records {
+            let unpadded_size = parse_multibyte_integer_from_reader(reader)?;
+            let uncompressed_size = parse_multibyte_integer_from_reader(reader)?;
+
+            if unpadded_size == 0 {
+                return Err(error_invalid_data("invalid index record unpadded size"));
+            }
+
+            records.push(IndexRecord {
+                unpadded_size,
+                uncompressed_size,
+            });
+        }
+
+        // Skip index padding (0-3 null bytes to make multiple of 4).
+        let mut bytes_read = 1;
+        bytes_read += count_multibyte_integer_size_for_value(number_of_records);
+        for record in &records {
+            bytes_read += count_multibyte_integer_size_for_value(record.unpadded_size);
+            bytes_read += count_multibyte_integer_size_for_value(record.uncompressed_size);
+        }
+
+        let padding_needed = (4 - (bytes_read % 4)) % 4;
+
+        if padding_needed > 0 {
+            let mut padding_buf = [0u8; 3];
+            reader.read_exact(&mut padding_buf[..padding_needed])?;
+
+            if !padding_buf[..padding_needed].iter().all(|&b| b == 0) {
+                return Err(error_invalid_data("invalid index padding"));
+            }
+        }
+
+        let expected_crc = reader.read_u32()?;
+
+        // Calculate CRC32 over index data (excluding CRC32 itself).
+        let mut crc = CRC32.digest();
+        crc.update(&[0]);
+
+        // Add number of records.
+        let mut temp_buf = [0u8; 10];
+        let size = encode_multibyte_integer(number_of_records, &mut temp_buf)?;
+        crc.update(&temp_buf[..size]);
+
+        // Add all records.
+        for record in &records {
+            let size = encode_multibyte_integer(record.unpadded_size, &mut temp_buf)?;
+            crc.update(&temp_buf[..size]);
+            let size = encode_multibyte_integer(record.uncompressed_size, &mut temp_buf)?;
+            crc.update(&temp_buf[..size]);
+        }
+
+        // Add padding.
+        match padding_needed {
+            1 => crc.update(&[0]),
+            2 => crc.update(&[0, 0]),
+            3 => crc.update(&[0, 0, 0]),
+            _ => {}
+        }
+
+        if expected_crc != crc.finalize() {
+            return Err(error_invalid_data("index CRC32 mismatch"));
+        }
+
+        Ok(Index {
+            number_of_records,
+            records,
+        })
+    }
+}
+
+/// XZ stream footer,
+#[derive(Debug)]
+struct StreamFooter {
+    pub backward_size: u32,
+    pub stream_flags: [u8; 2],
+}
+
+impl StreamFooter {
+    fn parse<R: Read>(reader: &mut R) -> Result<Self> {
+        let expected_crc = reader.read_u32()?;
+
+        let backward_size = reader.read_u32()?;
+
+        let mut stream_flags = [0u8; 2];
+        reader.read_exact(&mut stream_flags)?;
+
+        // Verify CRC32 of backward size + stream flags.
+        let mut crc = CRC32.digest();
+        crc.update(&backward_size.to_le_bytes());
+        crc.update(&stream_flags);
+
+        if expected_crc != crc.finalize() {
+            return Err(error_invalid_data("stream footer CRC32 mismatch"));
+        }
+
+        let mut footer_magic = [0u8; 2];
+        reader.read_exact(&mut footer_magic)?;
+        if footer_magic != XZ_FOOTER_MAGIC {
+            return Err(error_invalid_data("invalid XZ footer magic bytes"));
+        }
+
+        Ok(StreamFooter {
+            backward_size,
+            stream_flags,
+        })
+    }
+}
+
+/// XZ stream header (12 bytes total)
+#[derive(Debug)]
+struct StreamHeader {
+    check_type: CheckType,
+}
+
+impl StreamHeader {
+    /// Parse stream header from reader
+    fn parse<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut magic = [0u8; 6];
+        reader.read_exact(&mut magic)?;
+        if magic != XZ_MAGIC {
+            return Err(error_invalid_data("invalid XZ magic bytes"));
+        }
+
+        Self::parse_flags_and_crc(reader)
+    }
+
+    /// Parse stream flags and CRC32 after magic bytes have been read.
+    fn parse_flags_and_crc<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut flags = [0u8; 2];
+        reader.read_exact(&mut flags)?;
+
+        if flags[0] != 0 {
+            return Err(error_invalid_data("invalid XZ stream flags"));
+        }
+
+        let check_type = CheckType::from_byte(flags[1])?;
+
+        let expected_crc = reader.read_u32()?;
+
+        if expected_crc != CRC32.checksum(&flags) {
+            return Err(error_invalid_data("XZ stream header CRC32 mismatch"));
+        }
+
+        Ok(StreamHeader { check_type })
+    }
+}
+
+/// XZ block header information
+#[derive(Debug)]
+struct BlockHeader {
+    header_size: u64,
+    compressed_size: Option<u64>,
+    uncompressed_size: Option<u64>,
+    filters: [Option<FilterType>; 4],
+    properties: [u32; 4],
+}
+
+impl BlockHeader {
+    fn parse<R: Read>(reader: &mut R) -> Result<Option<Self>> {
+        let header_size_encoded = reader.read_u8()?;
+
+        if header_size_encoded == 0 {
+            // If header size is 0, this indicates end of blocks (index follows).
+            return Ok(None);
+        }
+
+        let header_size = (header_size_encoded as usize + 1) * 4;
+        if !(8..=1024).contains(&header_size) {
+            return Err(error_invalid_data("invalid XZ block header size"));
+        }
+
+        // -1 because we already read the size byte.
+        let mut header_data = vec![0u8; header_size - 1];
+        reader.read_exact(&mut header_data)?;
+
+        let block_flags = header_data[0];
+        let num_filters = ((block_flags & 0x03) + 1) as usize;
+        let has_compressed_size = (block_flags & 0x40) != 0;
+        let has_uncompressed_size = (block_flags & 0x80) != 0;
+
+        let mut offset = 1;
+        let mut compressed_size = None;
+        let mut uncompressed_size = None;
+
+        // Parse optional compressed size.
+        if has_compressed_size {
+            if offset >= header_data.len() {
+                return Err(error_invalid_data(
+                    "XZ block header too short for compressed size",
+                ));
+            }
+            let size = parse_multibyte_integer(&header_data[offset..])?;
+            if size == 0 {
+                return Err(error_invalid_data("invalid XZ block compressed size"));
+            }
+            compressed_size = Some(size);
+            offset += count_multibyte_integer_size(&header_data[offset..]);
+        }
+
+        if has_uncompressed_size {
+            if offset >= header_data.len() {
+                return Err(error_invalid_data(
+                    "XZ block header too short for uncompressed size",
+                ));
+            }
+            uncompressed_size = Some(parse_multibyte_integer(&header_data[offset..])?);
+            offset += count_multibyte_integer_size(&header_data[offset..]);
+        }
+
+        let mut filters = [None; 4];
+        let mut properties = [0; 4];
+
+        for i in 0..num_filters {
+            if offset >= header_data.len() {
+                return Err(error_invalid_data("XZ block header too short for filters"));
+            }
+
+            let filter_id = parse_multibyte_integer(&header_data[offset..])?;
+            let filter_type =
+                FilterType::try_from(filter_id).map_err(|_| error_unsupported_filter(filter_id))?;
+
+            offset += count_multibyte_integer_size(&header_data[offset..]);
+
+            let property = match filter_type {
+                FilterType::Delta => {
+                    if offset >= header_data.len() {
+                        return Err(error_invalid_data(
+                            "XZ block header too short for Delta properties",
+                        ));
+                    }
+
+                    let props_size = parse_multibyte_integer(&header_data[offset..])?;
+                    offset += count_multibyte_integer_size(&header_data[offset..]);
+
+                    if props_size != 1 {
+                        return Err(error_invalid_data("invalid Delta properties size"));
+                    }
+
+                    if offset >= header_data.len() {
+                        return Err(error_invalid_data(
+                            "XZ block header too short for Delta properties",
+                        ));
+                    }
+
+                    let distance_prop = header_data[offset];
+                    offset += 1;
+
+                    // Distance is encoded as byte value + 1, range [1, 256].
+                    (distance_prop as u32) + 1
+                }
+                FilterType::BcjX86
+                | FilterType::BcjPPC
+                | FilterType::BcjIA64
+                | FilterType::BcjARM
+                | FilterType::BcjARMThumb
+                | FilterType::BcjSPARC
+                | FilterType::BcjARM64
+                | FilterType::BcjRISCV => {
+                    if offset >= header_data.len() {
+                        return Err(error_invalid_data(
+                            "XZ block header too short for BCJ properties",
+                        ));
+                    }
+
+                    let props_size = parse_multibyte_integer(&header_data[offset..])?;
+                    offset += count_multibyte_integer_size(&header_data[offset..]);
+
+                    match props_size {
+                        0 => {
+                            // No start offset specified, use default (0).
+                            0
+                        }
+                        4 => {
+                            // 4-byte start offset specified.
+                            if offset + 4 > header_data.len() {
+                                return Err(error_invalid_data(
+                                    "XZ block header too short for BCJ start offset",
+                                ));
+                            }
+
+                            let start_offset_value = u32::from_le_bytes([
+                                header_data[offset],
+                                header_data[offset + 1],
+                                header_data[offset + 2],
+                                header_data[offset + 3],
+                            ]);
+                            offset += 4;
+
+                            start_offset_value
+                        }
+                        _ => {
+                            return Err(error_invalid_data("invalid BCJ properties size"));
+                        }
+                    }
+                }
+                FilterType::LZMA2 => {
+                    if offset >= header_data.len() {
+                        return Err(error_invalid_data(
+                            "XZ block header too short for LZMA2 properties",
+                        ));
+                    }
+
+                    let props_size = parse_multibyte_integer(&header_data[offset..])?;
+                    offset += count_multibyte_integer_size(&header_data[offset..]);
+
+                    if props_size != 1 {
+                        return Err(error_invalid_data("invalid LZMA2 properties size"));
+                    }
+
+                    if offset >= header_data.len() {
+                        return Err(error_invalid_data(
+                            "XZ block header too short for LZMA2 properties",
+                        ));
+                    }
+
+                    let dict_size_prop = header_data[offset];
+                    offset += 1;
+
+                    if dict_size_prop > 40 {
+                        return Err(error_invalid_data("invalid LZMA2 dictionary size"));
+                    }
+
+                    if dict_size_prop == 40 {
+                        0xFFFFFFFF
+                    } else {
+                        let base = 2 | ((dict_size_prop & 1) as u32);
+                        base << (dict_size_prop / 2 + 11)
+                    }
+                }
+            };
+
+            filters[i] = Some(filter_type);
+            properties[i] = property;
+        }
+
+        let filter_chain = filters
+            .iter()
+            .zip(properties)
+            .filter_map(|(filter_type, property)| filter_type.map(|ty| (ty, property)));
+        validate_filter_chain(filter_chain).map_err(error_invalid_data)?;
+
+        // Header must be padded so that the total header size matches the declared size.
+        // We need to pad until: 1 (size byte) + offset + 4 (CRC32) == header_size
+        let expected_offset = header_size - 1 - 4; // header_size - size_byte - crc32_size
+        while offset < expected_offset {
+            if offset >= header_data.len() || header_data[offset] != 0 {
+                return Err(error_invalid_data("invalid XZ block header padding"));
+            }
+            offset += 1;
+        }
+
+        // Last 4 bytes should be CRC32 of the header (excluding the CRC32 itself).
+        if offset + 4 != header_data.len() {
+            return Err(error_invalid_data("invalid XZ block header CRC32 position"));
+        }
+
+        let expected_crc = u32::from_le_bytes([
+            header_data[offset],
+            header_data[offset + 1],
+            header_data[offset + 2],
+            header_data[offset + 3],
+        ]);
+
+        // Calculate CRC32 of header size byte + header data (excluding CRC32).
+        let mut crc = CRC32.digest();
+        crc.update(&[header_size_encoded]);
+        crc.update(&header_data[..offset]);
+
+        if expected_crc != crc.finalize() {
+            return Err(error_invalid_data("XZ block header CRC32 mismatch"));
+        }
+
+        Ok(Some(BlockHeader {
+            header_size: header_size as u64,
+            compressed_size,
+            uncompressed_size,
+            filters,
+            properties,
+        }))
+    }
+
+    /// The memory in KiB that is needed to decode the block. Only the LZMA2 filter needs a
+    /// noteworthy amount of memory.
+    fn memory_usage(&self) -> u32 {
+        self.filters
+            .iter()
+            .zip(self.properties)
+            .filter(|(filter, _)| **filter == Some(FilterType::LZMA2))
+            .map(|(_, dict_size)| crate::lzma2_reader::get_memory_usage(dict_size))
+            .sum()
+    }
+
+    /// Checks the sizes of a decoded block against the optional sizes stored in the header.
+    fn verify_sizes(&self, compressed_size: u64, uncompressed_size: u64) -> Result<()> {
+        if self
+            .compressed_size
+            .is_some_and(|size| size != compressed_size)
+        {
+            return Err(error_invalid_data(
+                "XZ block compressed size doesn't match block header",
+            ));
+        }
+
+        if self
+            .uncompressed_size
+            .is_some_and(|size| size != uncompressed_size)
+        {
+            return Err(error_invalid_data(
+                "XZ block uncompressed size doesn't match block header",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Size of the stream header and the stream footer.
+#[cfg(feature = "std")]
+const STREAM_HEADER_SIZE: u64 = 12;
+
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+struct XZBlock {
+    start_pos: u64,
+    unpadded_size: u64,
+    uncompressed_size: u64,
+    check_type: CheckType,
+}
+
+/// A stream found by [`scan_streams`].
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+struct XZStream {
+    start_pos: u64,
+    check_type: CheckType,
+    index_size: u64,
+    padding: u64,
+    blocks: Vec<XZBlock>,
+}
+
+#[cfg(feature = "std")]
+impl XZStream {
+    /// The size of the stream including the stream header and footer, but without the padding.
+    fn total_size(&self) -> u64 {
+        let blocks_size: u64 = self.blocks.iter().map(XZBlock::total_size).sum();
+        2 * STREAM_HEADER_SIZE + blocks_size + self.index_size
+    }
+}
+
+#[cfg(feature = "std")]
+impl XZBlock {
+    /// The size of the block including the block padding and the check.
+    fn total_size(&self) -> u64 {
+        self.unpadded_size.div_ceil(4) * 4
+    }
+}
+
+/// Scans all streams of an XZ file from back to front, skipping stream padding.
+///
+/// Returns the streams in forward order.
+#[cfg(feature = "std")]
+fn scan_streams<R: Read + std::io::Seek>(reader: &mut R) -> Result<Vec<XZStream>> {
+    let file_size = reader.seek(std::io::SeekFrom::End(0))?;
+
+    if file_size < 2 * STREAM_HEADER_SIZE {
+        return Err(error_invalid_data(
+            "file too small to contain a valid XZ stream",
+        ));
+    }
+
+    let mut streams = Vec::new();
+    let mut current_pos = file_size;
+    let mut padding = 0;
+
+    while current_pos > 0 {
+        if current_pos % 4 != 0 {
+            return Err(error_invalid_data("XZ stream size not multiple of 4"));
+        }
+
+        // Skip stream padding.
+        reader.seek(std::io::SeekFrom::Start(current_pos - 4))?;
+        if reader.read_u32()? == 0 {
+            current_pos -= 4;
+            padding += 4;
+            if current_pos == 0 {
+                return Err(error_invalid_data("invalid data before XZ stream"));
+            }
+            continue;
+        }
+
+        if current_pos < 2 * STREAM_HEADER_SIZE {
+            return Err(error_invalid_data("invalid data before XZ stream"));
+        }
+
+        let footer_pos = current_pos - STREAM_HEADER_SIZE;
+        reader.seek(std::io::SeekFrom::Start(footer_pos))?;
+        let stream_footer = StreamFooter::parse(reader)?;
+
+        let index_size = (stream_footer.backward_size as u64 + 1) * 4;
+        let index_pos = footer_pos
+            .checked_sub(index_size)
+            .ok_or_else(|| error_invalid_data("invalid XZ backward size"))?;
+
+        reader.seek(std::io::SeekFrom::Start(index_pos))?;
+        if reader.read_u8()? != 0x00 {
+            return Err(error_invalid_data("invalid XZ index indicator"));
+        }
+        let index = Index::parse(reader)?;
+
+        if reader.stream_position()? != footer_pos {
+            return Err(error_invalid_data(
+                "XZ backward size doesn't match index size",
+            ));
+        }
+
+        let mut blocks_size = 0u64;
+        for record in &index.records {
+            blocks_size = blocks_size
+                .checked_add(record.unpadded_size.div_ceil(4) * 4)
+                .ok_or_else(|| error_invalid_data("XZ index blocks size overflow"))?;
+        }
+
+        let stream_start = index_pos
+            .checked_sub(blocks_size)
+            .and_then(|pos| pos.checked_sub(STREAM_HEADER_SIZE))
+            .ok_or_else(|| error_invalid_data("XZ index doesn't match file size"))?;
+
+        reader.seek(std::io::SeekFrom::Start(stream_start))?;
+        let stream_header = StreamHeader::parse(reader)?;
+
+        let header_flags = [0, stream_header.check_type as u8];
+        if stream_footer.stream_flags != header_flags {
+            return Err(error_invalid_data(
+                "stream header and footer flags mismatch",
+            ));
+        }
+
+        let mut block_pos = stream_start + STREAM_HEADER_SIZE;
+        let mut blocks = Vec::with_capacity(index.records.len());
+
+        for record in index.records {
+            let block = XZBlock {
+                start_pos: block_pos,
+                unpadded_size: record.unpadded_size,
+                uncompressed_size: record.uncompressed_size,
+                check_type: stream_header.check_type,
+            };
+            block_pos += block.total_size();
+            blocks.push(block);
+        }
+
+        streams.push(XZStream {
+            start_pos: stream_start,
+            check_type: stream_header.check_type,
+            index_size,
+            padding,
+            blocks,
+        });
+        current_pos = stream_start;
+        padding = 0;
+    }
+
+    if streams.is_empty() {
+        return Err(error_invalid_data("no valid XZ stream found"));
+    }
+
+    // Reverse to get streams in forward order.
+    streams.reverse();
+
+    Ok(streams)
+}
+
 /// Handles checksum calculation for different XZ check types
 enum ChecksumCalculator {
     None,
@@ -301,51 +1003,32 @@ impl ChecksumCalculator {
         }
     }
 
-    fn verify(self, expected: &[u8]) -> bool {
+    /// Finishes the calculation. Returns `None` if there is no check.
+    fn finalize(self) -> Option<CheckValue> {
         match self {
-            ChecksumCalculator::None => true,
-            ChecksumCalculator::Crc32(crc) => {
-                if expected.len() != 4 {
-                    return false;
-                }
-
-                let expected_crc =
-                    u32::from_le_bytes([expected[0], expected[1], expected[2], expected[3]]);
-
-                let final_crc = crc.finalize();
-
-                final_crc == expected_crc
-            }
-            ChecksumCalculator::Crc64(crc) => {
-                if expected.len() != 8 {
-                    return false;
-                }
-
-                let expected_crc = u64::from_le_bytes([
-                    expected[0],
-                    expected[1],
-                    expected[2],
-                    expected[3],
-                    expected[4],
-                    expected[5],
-                    expected[6],
-                    expected[7],
-                ]);
-
-                let final_crc = crc.finalize();
+            ChecksumCalculator::None => None,
+            ChecksumCalculator::Crc32(crc) => Some(CheckValue::Crc32(crc.finalize())),
+            ChecksumCalculator::Crc64(crc) => Some(CheckValue::Crc64(crc.finalize())),
+            ChecksumCalculator::Sha256(sha) => Some(CheckValue::Sha256(sha.finalize().into())),
+        }
+    }
 
-                final_crc == expected_crc
-            }
-            ChecksumCalculator::Sha256(sha) => {
-                if expected.len() != 32 {
-                    return false;
-                }
+    /// Verifies the calculated check against the check stored in the file and returns it.
+    /// Returns `None` if there is no check.
+    fn verify(self, expected: &[u8]) -> Result<Option<CheckValue>> {
+        let Some(actual) = self.finalize() else {
+            return Ok(None);
+        };
 
-                let final_sha = sha.finalize();
+        let expected = actual
+            .with_le_bytes(expected)
+            .ok_or_else(|| error_invalid_data("invalid XZ check size"))?;
 
-                &final_sha[..32] == expected
-            }
+        if expected != actual {
+            return Err(LzmaError::ChecksumMismatch { expected, actual }.into());
         }
+
+        Ok(Some(actual))
     }
 }
 
@@ -403,7 +1086,7 @@ mod tests {
 
         // CRC32 of "123456789" in little-endian format
         let expected = [0x26, 0x39, 0xF4, 0xCB];
-        assert!(calc.verify(&expected));
+        assert!(calc.verify(&expected).is_ok());
     }
 
     #[test]
@@ -413,7 +1096,7 @@ mod tests {
 
         // CRC64 of "123456789" in little-endian format.
         let expected = [250, 57, 25, 223, 187, 201, 93, 153];
-        assert!(calc.verify(&expected));
+        assert!(calc.verify(&expected).is_ok());
     }
 
     #[test]
@@ -426,6 +1109,206 @@ mod tests {
             21, 226, 176, 211, 195, 56, 145, 235, 176, 241, 239, 96, 158, 196, 25, 66, 12, 32, 227,
             32, 206, 148, 198, 95, 188, 140, 51, 18, 68, 142, 178, 37,
         ];
-        assert!(calc.verify(&expected));
+        assert!(calc.verify(&expected).is_ok());
+    }
+
+    /// Compresses the data into a single stream and splits it into the blocks part,
+    /// the index records and the check type.
+    #[cfg(all(feature = "encoder", feature = "std"))]
+    fn split_stream(data: &[u8]) -> (Vec<u8>, Vec<IndexRecord>, CheckType) {
+        use std::io::Write;
+
+        let mut options = XZOptions::with_preset(0);
+        options.set_block_size(core::num::NonZeroU64::new(4096));
+        options.lzma_options.dict_size = 4096;
+
+        let mut writer = XZWriter::new(Vec::new(), options).unwrap();
+        writer.write_all(data).unwrap();
+        let compressed = writer.finish().unwrap();
+
+        let footer_pos = compressed.len() - 12;
+        let mut footer = &compressed[footer_pos..];
+        let stream_footer = StreamFooter::parse(&mut footer).unwrap();
+        let index_pos = footer_pos - (stream_footer.backward_size as usize + 1) * 4;
+
+        let mut index = &compressed[index_pos + 1..footer_pos];
+        let index = Index::parse(&mut index).unwrap();
+
+        (
+            compressed[..index_pos].to_vec(),
+            index.records,
+            CheckType::Crc64,
+        )
+    }
+
+    #[cfg(all(feature = "encoder", feature = "std"))]
+    fn build_stream(blocks: &[u8], records: &[IndexRecord], check_type: CheckType) -> Vec<u8> {
+        let mut stream = blocks.to_vec();
+        writer::write_index(&mut stream, records).unwrap();
+        writer::write_stream_footer(&mut stream, records, check_type).unwrap();
+        stream
+    }
+
+    #[cfg(all(feature = "encoder", feature = "std"))]
+    fn decode_all(stream: &[u8]) -> [Result<Vec<u8>>; 3] {
+        use std::io::{Cursor, Read};
+
+        let mut st = Vec::new();
+        let st_result = XZReader::new(stream, false)
+            .read_to_end(&mut st)
+            .map(|_| st);
+
+        let mut mt = Vec::new();
+        let mt_result = XZReaderMT::new(Cursor::new(stream), false, 2)
+            .and_then(|mut reader| reader.read_to_end(&mut mt))
+            .map(|_| mt);
+
+        let mut seekable = Vec::new();
+        let seekable_result = XZSeekableReader::new(Cursor::new(stream), false)
+            .and_then(|mut reader| reader.read_to_end(&mut seekable))
+            .map(|_| seekable);
+
+        [st_result, mt_result, seekable_result]
+    }
+
+    #[cfg(all(feature = "encoder", feature = "std"))]
+    #[test]
+    fn test_index_matches_decoded_blocks() {
+        let data: Vec<u8> = (0..20000u32).map(|i| (i % 251) as u8).collect();
+        let (blocks, records, check_type) = split_stream(&data);
+        assert!(records.len() > 1);
+
+        let stream = build_stream(&blocks, &records, check_type);
+        for result in decode_all(&stream) {
+            assert!(result.unwrap() == data);
+        }
+
+        let mut tampered = records.clone();
+        tampered[1].uncompressed_size += 1;
+        let stream = build_stream(&blocks, &tampered, check_type);
+        for result in decode_all(&stream) {
+            assert!(result.is_err());
+        }
+
+        let mut tampered = records.clone();
+        tampered[0].uncompressed_size -= 1;
+        let stream = build_stream(&blocks, &tampered, check_type);
+        for result in decode_all(&stream) {
+            assert!(result.is_err());
+        }
+
+        let mut tampered = records.clone();
+        tampered[0].unpadded_size -= 1;
+        let stream = build_stream(&blocks, &tampered, check_type);
+        for result in decode_all(&stream) {
+            assert!(result.is_err());
+        }
+    }
+
+    #[cfg(all(feature = "encoder", feature = "std"))]
+    #[test]
+    fn test_backward_size_matches_index() {
+        let data: Vec<u8> = (0..20000u32).map(|i| (i % 251) as u8).collect();
+        let (blocks, records, check_type) = split_stream(&data);
+
+        let mut stream = build_stream(&blocks, &records, check_type);
+        let footer_pos = stream.len() - 12;
+
+        let backward_size =
+            u32::from_le_bytes(stream[footer_pos + 4..footer_pos + 8].try_into().unwrap()) - 1;
+        let stream_flags = [0u8, check_type as u8];
+
+        let mut crc = CRC32.digest();
+        crc.update(&backward_size.to_le_bytes());
+        crc.update(&stream_flags);
+
+        stream[footer_pos..footer_pos + 4].copy_from_slice(&crc.finalize().to_le_bytes());
+        stream[footer_pos + 4..footer_pos + 8].copy_from_slice(&backward_size.to_le_bytes());
+
+        for result in decode_all(&stream) {
+            assert!(result.is_err());
+        }
+    }
+
+    #[cfg(all(feature = "encoder", feature = "std"))]
+    #[test]
+    fn test_block_header_sizes() {
+        let data: Vec<u8> = (0..20000u32).map(|i| (i % 251) as u8).collect();
+        let options = XZOptions::with_preset(0);
+        let check_type = options.check_type;
+
+        let (block, record) = XZWriter::compress_block(&data, options.clone()).unwrap();
+
+        let mut stream = Vec::new();
+        writer::write_stream_header(&mut stream, check_type).unwrap();
+        let mut block_reader = block.as_slice();
+        let block_header = BlockHeader::parse(&mut block_reader).unwrap().unwrap();
+        assert_eq!(block_header.uncompressed_size, Some(data.len() as u64));
+        assert!(block_header.compressed_size.is_some());
+
+        let stream = build_stream(
+            &[stream.as_slice(), &block].concat(),
+            std::slice::from_ref(&record),
+            check_type,
+        );
+        for result in decode_all(&stream) {
+            assert!(result.unwrap() == data);
+        }
+
+        // Rewrite the block header with sizes that don't match the block.
+        let header_size = block_header.header_size as usize;
+        let compressed_size = block_header.compressed_size.unwrap();
+        let uncompressed_size = data.len() as u64;
+
+        for (compressed_size, uncompressed_size) in [
+            (compressed_size, uncompressed_size - 1),
+            (compressed_size, uncompressed_size + 1),
+            (compressed_size - 1, uncompressed_size),
+            (compressed_size + 1, uncompressed_size),
+        ] {
+            let writer = XZWriter::new(Vec::new(), options.clone()).unwrap();
+            let header = writer
+                .encode_block_header(Some(compressed_size), Some(uncompressed_size))
+                .unwrap();
+            assert_eq!(header.len(), header_size);
+
+            let mut stream = Vec::new();
+            writer::write_stream_header(&mut stream, check_type).unwrap();
+            stream.extend_from_slice(&header);
+            stream.extend_from_slice(&block[header_size..]);
+
+            let stream = build_stream(&stream, std::slice::from_ref(&record), check_type);
+            for result in decode_all(&stream) {
+                assert!(result.is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate_filter_chain() {
+        use FilterType::*;
+
+        assert!(validate_filter_chain([(LZMA2, 0)]).is_ok());
+        assert!(validate_filter_chain([(Delta, 1), (BcjX86, 3), (LZMA2, 0)]).is_ok());
+        assert!(validate_filter_chain([(Delta, 256), (BcjIA64, 32), (LZMA2, 0)]).is_ok());
+
+        assert!(validate_filter_chain([]).is_err());
+        assert!(validate_filter_chain([(Delta, 1)]).is_err());
+        assert!(validate_filter_chain([(LZMA2, 0), (Delta, 1)]).is_err());
+        assert!(validate_filter_chain([(LZMA2, 0), (LZMA2, 0)]).is_err());
+        assert!(validate_filter_chain([(Delta, 1), (Delta, 2), (LZMA2, 0)]).is_err());
+        assert!(validate_filter_chain([(Delta, 0), (LZMA2, 0)]).is_err());
+        assert!(validate_filter_chain([(Delta, 257), (LZMA2, 0)]).is_err());
+        assert!(validate_filter_chain([(BcjARM, 2), (LZMA2, 0)]).is_err());
+        assert!(validate_filter_chain([(BcjARMThumb, 1), (LZMA2, 0)]).is_err());
+        assert!(validate_filter_chain([(BcjIA64, 8), (LZMA2, 0)]).is_err());
+        assert!(validate_filter_chain([
+            (Delta, 1),
+            (BcjX86, 0),
+            (BcjARM, 0),
+            (BcjPPC, 0),
+            (LZMA2, 0)
+        ])
+        .is_err());
     }
 }
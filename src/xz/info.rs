@@ -0,0 +1,163 @@
+use std::io::{self, Seek, SeekFrom};
+
+use super::{scan_streams, BlockHeader, CheckType, FilterConfig};
+use crate::{error_invalid_data, Read};
+
+/// Metadata of an XZ file, as shown by `xz --list`.
+///
+/// Created by [`inspect`].
+#[derive(Debug, Clone)]
+pub struct XZInfo {
+    /// The size of the whole file in bytes.
+    pub file_size: u64,
+    /// All streams of the file in forward order.
+    pub streams: Vec<XZStreamInfo>,
+}
+
+/// Metadata of a single XZ stream.
+#[derive(Debug, Clone)]
+pub struct XZStreamInfo {
+    /// The offset of the stream header in the file.
+    pub start_pos: u64,
+    /// The size of the stream including stream header, index and stream footer, but without the
+    /// stream padding.
+    pub compressed_size: u64,
+    /// The uncompressed size of all blocks of the stream.
+    pub uncompressed_size: u64,
+    /// The offset of the first uncompressed byte of the stream.
+    pub uncompressed_offset: u64,
+    /// The check type of the stream.
+    pub check_type: CheckType,
+    /// The size of the index in bytes.
+    pub index_size: u64,
+    /// The size of the stream padding that follows the stream.
+    pub padding: u64,
+    /// All blocks of the stream.
+    pub blocks: Vec<XZBlockInfo>,
+}
+
+/// Metadata of a single XZ block.
+#[derive(Debug, Clone)]
+pub struct XZBlockInfo {
+    /// The offset of the block header in the file.
+    pub start_pos: u64,
+    /// The offset of the first uncompressed byte of the block.
+    pub uncompressed_offset: u64,
+    /// The size of the block header in bytes.
+    pub header_size: u64,
+    /// The size of the compressed data, without block header, block padding and check.
+    pub compressed_size: u64,
+    /// The size of the block including block header, block padding and check.
+    pub total_size: u64,
+    /// The uncompressed size of the block.
+    pub uncompressed_size: u64,
+    /// Whether the compressed and uncompressed sizes are stored in the block header.
+    pub sizes_in_header: bool,
+    /// The filter chain of the block. The property of the LZMA2 filter is the dictionary size.
+    pub filters: Vec<FilterConfig>,
+    /// The memory in KiB that is needed to decode the block.
+    pub memory_usage: u32,
+}
+
+impl XZInfo {
+    /// Returns the total count of blocks in all streams.
+    pub fn block_count(&self) -> usize {
+        self.streams.iter().map(|stream| stream.blocks.len()).sum()
+    }
+
+    /// Returns the total uncompressed size of all streams.
+    pub fn uncompressed_size(&self) -> u64 {
+        self.streams
+            .iter()
+            .map(|stream| stream.uncompressed_size)
+            .sum()
+    }
+
+    /// Returns the total size of the stream padding in the file.
+    pub fn padding(&self) -> u64 {
+        self.streams.iter().map(|stream| stream.padding).sum()
+    }
+
+    /// Returns the memory in KiB that is needed to decode the file with a single thread.
+    pub fn memory_usage(&self) -> u32 {
+        self.streams
+            .iter()
+            .flat_map(|stream| stream.blocks.iter())
+            .map(|block| block.memory_usage)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Reads the metadata of an XZ file without decompressing it.
+///
+/// The streams are found by walking backwards through the stream footers, indexes and stream
+/// headers. After that, the header of every block is parsed to read its filter chain.
+pub fn inspect<R: Read + Seek>(mut reader: R) -> io::Result<XZInfo> {
+    let file_size = reader.seek(SeekFrom::End(0))?;
+    let scanned_streams = scan_streams(&mut reader)?;
+
+    let mut streams = Vec::with_capacity(scanned_streams.len());
+    let mut uncompressed_offset = 0u64;
+
+    for stream in scanned_streams {
+        let stream_uncompressed_offset = uncompressed_offset;
+        let compressed_size = stream.total_size();
+        let mut blocks = Vec::with_capacity(stream.blocks.len());
+
+        for block in &stream.blocks {
+            reader.seek(SeekFrom::Start(block.start_pos))?;
+            let block_header = BlockHeader::parse(&mut reader)?
+                .ok_or_else(|| error_invalid_data("expected XZ block header but found index"))?;
+
+            let check_size = block.check_type.checksum_size() as u64;
+            let block_compressed_size = block
+                .unpadded_size
+                .checked_sub(block_header.header_size + check_size)
+                .ok_or_else(|| error_invalid_data("XZ block size doesn't match index"))?;
+            block_header.verify_sizes(block_compressed_size, block.uncompressed_size)?;
+
+            let filters = block_header
+                .filters
+                .iter()
+                .zip(block_header.properties)
+                .filter_map(|(filter_type, property)| {
+                    filter_type.map(|filter_type| FilterConfig {
+                        filter_type,
+                        property,
+                    })
+                })
+                .collect();
+
+            blocks.push(XZBlockInfo {
+                start_pos: block.start_pos,
+                uncompressed_offset,
+                header_size: block_header.header_size,
+                compressed_size: block_compressed_size,
+                total_size: block.total_size(),
+                uncompressed_size: block.uncompressed_size,
+                sizes_in_header: block_header.compressed_size.is_some()
+                    && block_header.uncompressed_size.is_some(),
+                filters,
+                memory_usage: block_header.memory_usage(),
+            });
+
+            uncompressed_offset = uncompressed_offset
+                .checked_add(block.uncompressed_size)
+                .ok_or_else(|| error_invalid_data("XZ uncompressed size overflow"))?;
+        }
+
+        streams.push(XZStreamInfo {
+            start_pos: stream.start_pos,
+            compressed_size,
+            uncompressed_size: uncompressed_offset - stream_uncompressed_offset,
+            uncompressed_offset: stream_uncompressed_offset,
+            check_type: stream.check_type,
+            index_size: stream.index_size,
+            padding: stream.padding,
+            blocks,
+        });
+    }
+
+    Ok(XZInfo { file_size, streams })
+}
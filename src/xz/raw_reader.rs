@@ -0,0 +1,59 @@
+use alloc::vec::Vec;
+
+use super::{reader::FilterChain, validate_filter_chain, FilterConfig, FilterType};
+use crate::{error_invalid_input, Read, Result};
+
+/// A decoder for raw LZMA2 data that was filtered with a chain of XZ pre-filters, without any
+/// container around it.
+///
+/// This is the equivalent of `lzma_raw_decoder` of liblzma. It can be used for formats that
+/// store the data of an XZ filter chain without the XZ headers, like squashfs or the coders of
+/// 7z. The data must end with the end marker of LZMA2, which [`crate::RawWriter`] writes.
+pub struct RawReader<R> {
+    inner: R,
+    filter_chain: FilterChain,
+}
+
+impl<R> RawReader<R> {
+    /// Consume the RawReader and return the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> RawReader<R> {
+    /// Creates a new raw reader.
+    ///
+    /// - `inner`: The reader to read the filtered LZMA2 data from.
+    /// - `filters`: The pre-filters in front of LZMA2 (at most 3), with the outermost filter
+    ///   first, as used for [`crate::XZOptions::filters`].
+    /// - `dict_size`: The dictionary size of LZMA2.
+    pub fn new(inner: R, filters: Vec<FilterConfig>, dict_size: u32) -> Result<Self> {
+        if filters.len() > 3 {
+            return Err(error_invalid_input(
+                "XZ allows only at most 3 pre-filters plus LZMA2",
+            ));
+        }
+
+        let filter_chain = filters
+            .iter()
+            .map(|filter| (filter.filter_type, filter.property))
+            .chain(core::iter::once((FilterType::LZMA2, dict_size)));
+        validate_filter_chain(filter_chain.clone()).map_err(error_invalid_input)?;
+
+        Ok(Self {
+            inner,
+            filter_chain: FilterChain::with_filters(filter_chain, u64::MAX)?,
+        })
+    }
+}
+
+impl<R: Read> Read for RawReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.filter_chain.read(&mut self.inner, buf)
+    }
+}
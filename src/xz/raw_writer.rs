@@ -0,0 +1,98 @@
+use alloc::vec::Vec;
+
+use super::{validate_filter_chain, writer::ChainWriter, FilterConfig, FilterType};
+use crate::{enc::LZMAOptions, error_invalid_input, Result, Write};
+
+/// An encoder that writes raw LZMA2 data filtered with a chain of XZ pre-filters, without any
+/// container around it.
+///
+/// This is the equivalent of `lzma_raw_encoder` of liblzma. The output can be decoded with
+/// [`crate::RawReader`] and the same filters and dictionary size, which need to be stored
+/// elsewhere.
+pub struct RawWriter<W> {
+    inner: W,
+    chain_writer: Option<ChainWriter>,
+}
+
+impl<W> RawWriter<W> {
+    /// Consume the RawWriter and return the inner writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> RawWriter<W> {
+    /// Creates a new raw writer.
+    ///
+    /// - `inner`: The writer to write the filtered LZMA2 data to.
+    /// - `filters`: The pre-filters in front of LZMA2 (at most 3), with the outermost filter
+    ///   first, as used for [`crate::XZOptions::filters`].
+    /// - `lzma_options`: The options of LZMA2.
+    pub fn new(inner: W, filters: Vec<FilterConfig>, lzma_options: LZMAOptions) -> Result<Self> {
+        if filters.len() > 3 {
+            return Err(error_invalid_input(
+                "XZ allows only at most 3 pre-filters plus LZMA2",
+            ));
+        }
+
+        let filter_chain = filters
+            .iter()
+            .map(|filter| (filter.filter_type, filter.property))
+            .chain(core::iter::once((
+                FilterType::LZMA2,
+                lzma_options.dict_size,
+            )));
+        validate_filter_chain(filter_chain).map_err(error_invalid_input)?;
+
+        Ok(Self {
+            inner,
+            chain_writer: Some(ChainWriter::new(&filters, &lzma_options)),
+        })
+    }
+
+    /// Writes the compressed data that the filter chain has produced so far to the inner writer.
+    fn drain_chain_writer(&mut self) -> Result<()> {
+        if let Some(chain_writer) = self.chain_writer.as_mut() {
+            let buffer = chain_writer.buffer_mut();
+            if !buffer.is_empty() {
+                self.inner.write_all(buffer)?;
+                buffer.clear();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finish writing the LZMA2 data including its end marker and return the inner writer.
+    pub fn finish(mut self) -> Result<W> {
+        if let Some(chain_writer) = self.chain_writer.take() {
+            let buffer = chain_writer.finish()?;
+            self.inner.write_all(&buffer)?;
+        }
+
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for RawWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let chain_writer = self
+            .chain_writer
+            .as_mut()
+            .ok_or_else(|| error_invalid_input("RawWriter already finished"))?;
+
+        let written = chain_writer.write(buf)?;
+        self.drain_chain_writer()?;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(chain_writer) = self.chain_writer.as_mut() {
+            chain_writer.flush()?;
+            self.drain_chain_writer()?;
+        }
+
+        self.inner.flush()
+    }
+}
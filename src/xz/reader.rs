@@ -1,606 +1,379 @@
-use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
-use core::cell::{Cell, RefCell};
+use alloc::vec::Vec;
 
 use super::{
-    count_multibyte_integer_size, count_multibyte_integer_size_for_value, encode_multibyte_integer,
-    parse_multibyte_integer, parse_multibyte_integer_from_reader, CheckType, ChecksumCalculator,
-    FilterType, IndexRecord, CRC32, XZ_FOOTER_MAGIC, XZ_MAGIC,
+    BlockHeader, CheckType, ChecksumCalculator, FilterType, Index, IndexRecord, StreamFooter,
+    StreamHeader, XZSummary, XZ_MAGIC,
 };
 use crate::{
-    error_invalid_data, error_invalid_input,
-    filter::{bcj::BCJReader, delta::DeltaReader},
-    ByteReader, LZMA2Reader, Read, Result,
+    error_invalid_data, error_mem_limit, error_with_position,
+    filter::{bcj::BCJDecoder, delta::Delta},
+    lzma2_reader::LZMA2Decoder,
+    CheckValue, CountingReader, Read, Result,
 };
 
-/// XZ Index containing all block records and metadata.
-#[derive(Debug)]
-struct Index {
-    number_of_records: u64,
-    records: Vec<IndexRecord>,
-}
+/// The maximal size of the data that is decoded at once without an output buffer.
+const DECODE_CHUNK_SIZE: usize = 64 * 1024;
 
-impl Index {
-    fn parse<R: Read>(reader: &mut R) -> Result<Self> {
-        // sic! Index indicator is already parsed (0x00) in BlockHeader::parse.
+/// A filter in front of the LZMA2 decoder.
+enum PreFilter {
+    Delta(Delta),
+    Bcj(BCJDecoder),
+}
 
-        let number_of_records = parse_multibyte_integer_from_reader(reader)?;
-        let mut records = Vec::with_capacity(number_of_records as usize);
+/// The decoding filter chain of a block.
+///
+/// The chain doesn't own the reader of the compressed data, it's passed on every read instead.
+/// This way the XZ readers always keep the ownership of their inner reader.
+pub(super) struct FilterChain {
+    /// The pre-filters, with the outermost filter first.
+    filters: Vec<PreFilter>,
+    lzma2: LZMA2Decoder,
+    compressed_size_limit: u64,
+    compressed_bytes_read: u64,
+}
 
-        for _ in 0..number_of_records {
-            let unpadded_size = parse_multibyte_integer_from_reader(reader)?;
-            let uncompressed_size = parse_multibyte_integer_from_reader(reader)?;
+impl FilterChain {
+    /// Builds the decoding filter chain of a block.
+    pub(super) fn new(block_header: &BlockHeader) -> Result<Self> {
+        let filters = block_header
+            .filters
+            .iter()
+            .copied()
+            .zip(block_header.properties)
+            .filter_map(|(filter, property)| filter.map(|filter| (filter, property)));
+
+        // Never read past the declared compressed size of the block.
+        Self::with_filters(filters, block_header.compressed_size.unwrap_or(u64::MAX))
+    }
 
-            if unpadded_size == 0 {
-                return Err(error_invalid_data("invalid index record unpadded size"));
+    /// Builds a decoding filter chain from filter types and their properties. The property of
+    /// the LZMA2 filter is the dictionary size.
+    pub(super) fn with_filters<I: IntoIterator<Item = (FilterType, u32)>>(
+        filter_chain: I,
+        compressed_size_limit: u64,
+    ) -> Result<Self> {
+        let mut filters = Vec::with_capacity(3);
+        let mut lzma2 = None;
+
+        for (filter, property) in filter_chain {
+            if lzma2.is_some() {
+                return Err(error_invalid_data(
+                    "XZ block's last filter must be a LZMA2 filter",
+                ));
             }
 
-            records.push(IndexRecord {
-                unpadded_size,
-                uncompressed_size,
-            });
-        }
+            let start_offset = property as usize;
+
+            let filter = match filter {
+                FilterType::Delta => PreFilter::Delta(Delta::new(property as usize)),
+                FilterType::BcjX86 => PreFilter::Bcj(BCJDecoder::new_x86(start_offset)),
+                FilterType::BcjPPC => PreFilter::Bcj(BCJDecoder::new_ppc(start_offset)),
+                FilterType::BcjIA64 => PreFilter::Bcj(BCJDecoder::new_ia64(start_offset)),
+                FilterType::BcjARM => PreFilter::Bcj(BCJDecoder::new_arm(start_offset)),
+                FilterType::BcjARMThumb => PreFilter::Bcj(BCJDecoder::new_arm_thumb(start_offset)),
+                FilterType::BcjSPARC => PreFilter::Bcj(BCJDecoder::new_sparc(start_offset)),
+                FilterType::BcjARM64 => PreFilter::Bcj(BCJDecoder::new_arm64(start_offset)),
+                FilterType::BcjRISCV => PreFilter::Bcj(BCJDecoder::new_riscv(start_offset)),
+                FilterType::LZMA2 => {
+                    let dict_size = property;
+                    lzma2 = Some(LZMA2Decoder::new(dict_size, None));
+                    continue;
+                }
+            };
 
-        // Skip index padding (0-3 null bytes to make multiple of 4).
-        let mut bytes_read = 1;
-        bytes_read += count_multibyte_integer_size_for_value(number_of_records);
-        for record in &records {
-            bytes_read += count_multibyte_integer_size_for_value(record.unpadded_size);
-            bytes_read += count_multibyte_integer_size_for_value(record.uncompressed_size);
+            filters.push(filter);
         }
 
-        let padding_needed = (4 - (bytes_read % 4)) % 4;
-
-        if padding_needed > 0 {
-            let mut padding_buf = [0u8; 3];
-            reader.read_exact(&mut padding_buf[..padding_needed])?;
-
-            if !padding_buf[..padding_needed].iter().all(|&b| b == 0) {
-                return Err(error_invalid_data("invalid index padding"));
-            }
-        }
+        let lzma2 = lzma2
+            .ok_or_else(|| error_invalid_data("XZ block's last filter must be a LZMA2 filter"))?;
 
-        let expected_crc = reader.read_u32()?;
+        Ok(Self {
+            filters,
+            lzma2,
+            compressed_size_limit,
+            compressed_bytes_read: 0,
+        })
+    }
 
-        // Calculate CRC32 over index data (excluding CRC32 itself).
-        let mut crc = CRC32.digest();
-        crc.update(&[0]);
+    /// The count of compressed bytes read from the inner reader so far.
+    pub(super) fn compressed_bytes_read(&self) -> u64 {
+        self.compressed_bytes_read
+    }
 
-        // Add number of records.
-        let mut temp_buf = [0u8; 10];
-        let size = encode_multibyte_integer(number_of_records, &mut temp_buf)?;
-        crc.update(&temp_buf[..size]);
+    /// Decodes into `buf`, reading the compressed data of the block from `inner`.
+    pub(super) fn read<R: Read>(&mut self, inner: &mut R, buf: &mut [u8]) -> Result<usize> {
+        let mut input = BlockInput {
+            inner,
+            bytes_read: &mut self.compressed_bytes_read,
+            limit: self.compressed_size_limit,
+        };
 
-        // Add all records.
-        for record in &records {
-            let size = encode_multibyte_integer(record.unpadded_size, &mut temp_buf)?;
-            crc.update(&temp_buf[..size]);
-            let size = encode_multibyte_integer(record.uncompressed_size, &mut temp_buf)?;
-            crc.update(&temp_buf[..size]);
+        ChainReader {
+            filters: &mut self.filters,
+            lzma2: &mut self.lzma2,
+            input: &mut input,
         }
+        .read(buf)
+    }
 
-        // Add padding.
-        match padding_needed {
-            1 => crc.update(&[0]),
-            2 => crc.update(&[0, 0]),
-            3 => crc.update(&[0, 0, 0]),
-            _ => {}
+    /// Decodes the next data of the block and passes it to `sink`. Without pre-filters, the data
+    /// is passed straight from the dictionary of the LZMA2 decoder. Pre-filters work on
+    /// `scratch` instead, which is allocated on first use.
+    pub(super) fn decode_with<R: Read, F: FnMut(&[u8])>(
+        &mut self,
+        inner: &mut R,
+        scratch: &mut Vec<u8>,
+        mut sink: F,
+    ) -> Result<usize> {
+        if self.filters.is_empty() {
+            let mut input = BlockInput {
+                inner,
+                bytes_read: &mut self.compressed_bytes_read,
+                limit: self.compressed_size_limit,
+            };
+            return self.lzma2.decode_with(&mut input, DECODE_CHUNK_SIZE, sink);
         }
 
-        if expected_crc != crc.finalize() {
-            return Err(error_invalid_data("index CRC32 mismatch"));
+        if scratch.is_empty() {
+            scratch.resize(DECODE_CHUNK_SIZE, 0);
         }
 
-        Ok(Index {
-            number_of_records,
-            records,
-        })
+        let bytes_read = self.read(inner, scratch)?;
+        sink(&scratch[..bytes_read]);
+        Ok(bytes_read)
     }
 }
 
-/// XZ stream footer,
-#[derive(Debug)]
-struct StreamFooter {
-    pub backward_size: u32,
-    pub stream_flags: [u8; 2],
+/// Reads the compressed data of a block without reading past its declared compressed size.
+struct BlockInput<'a, R> {
+    inner: &'a mut R,
+    bytes_read: &'a mut u64,
+    limit: u64,
 }
 
-impl StreamFooter {
-    fn parse<R: Read>(reader: &mut R) -> Result<Self> {
-        let expected_crc = reader.read_u32()?;
-
-        let backward_size = reader.read_u32()?;
-
-        let mut stream_flags = [0u8; 2];
-        reader.read_exact(&mut stream_flags)?;
-
-        // Verify CRC32 of backward size + stream flags.
-        let mut crc = CRC32.digest();
-        crc.update(&backward_size.to_le_bytes());
-        crc.update(&stream_flags);
-
-        if expected_crc != crc.finalize() {
-            return Err(error_invalid_data("stream footer CRC32 mismatch"));
-        }
-
-        let mut footer_magic = [0u8; 2];
-        reader.read_exact(&mut footer_magic)?;
-        if footer_magic != XZ_FOOTER_MAGIC {
-            return Err(error_invalid_data("invalid XZ footer magic bytes"));
+impl<R: Read> Read for BlockInput<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if *self.bytes_read >= self.limit {
+            return Ok(0);
         }
 
-        Ok(StreamFooter {
-            backward_size,
-            stream_flags,
-        })
+        let left = (self.limit - *self.bytes_read).min(buf.len() as u64) as usize;
+        let read_size = self.inner.read(&mut buf[..left])?;
+        *self.bytes_read += read_size as u64;
+        Ok(read_size)
     }
 }
 
-/// XZ stream header (12 bytes total)
-#[derive(Debug)]
-struct StreamHeader {
-    check_type: CheckType,
+/// Reads through the remaining filters of a chain.
+struct ChainReader<'a, R> {
+    filters: &'a mut [PreFilter],
+    lzma2: &'a mut LZMA2Decoder,
+    input: &'a mut R,
 }
 
-impl StreamHeader {
-    /// Parse stream header from reader
-    fn parse<R: Read>(reader: &mut R) -> Result<Self> {
-        let mut magic = [0u8; 6];
-        reader.read_exact(&mut magic)?;
-        if magic != XZ_MAGIC {
-            return Err(error_invalid_data("invalid XZ magic bytes"));
-        }
-
-        Self::parse_flags_and_crc(reader)
-    }
-
-    /// Parse stream flags and CRC32 after magic bytes have been read.
-    fn parse_flags_and_crc<R: Read>(reader: &mut R) -> Result<Self> {
-        let mut flags = [0u8; 2];
-        reader.read_exact(&mut flags)?;
-
-        if flags[0] != 0 {
-            return Err(error_invalid_data("invalid XZ stream flags"));
-        }
-
-        let check_type = CheckType::from_byte(flags[1])?;
+impl<R: Read> Read for ChainReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let Some((filter, filters)) = self.filters.split_first_mut() else {
+            return self.lzma2.read(self.input, buf);
+        };
 
-        let expected_crc = reader.read_u32()?;
+        let mut next = ChainReader {
+            filters,
+            lzma2: self.lzma2,
+            input: self.input,
+        };
 
-        if expected_crc != CRC32.checksum(&flags) {
-            return Err(error_invalid_data("XZ stream header CRC32 mismatch"));
+        match filter {
+            PreFilter::Delta(delta) => {
+                let bytes_read = next.read(buf)?;
+                delta.decode(&mut buf[..bytes_read]);
+                Ok(bytes_read)
+            }
+            PreFilter::Bcj(bcj) => bcj.read(&mut next, buf),
         }
-
-        Ok(StreamHeader { check_type })
     }
 }
 
-/// XZ block header information
-#[derive(Debug)]
-struct BlockHeader {
-    compressed_size: Option<u64>,
-    uncompressed_size: Option<u64>,
-    filters: [Option<FilterType>; 4],
-    properties: [u32; 4],
+/// A single-threaded XZ decompressor.
+pub struct XZReader<R> {
+    inner: CountingReader<R>,
+    filter_chain: Option<FilterChain>,
+    stream_header: Option<StreamHeader>,
+    checksum_calculator: Option<ChecksumCalculator>,
+    finished: bool,
+    allow_multiple_streams: bool,
+    block_header: Option<BlockHeader>,
+    block_start_pos: u64,
+    block_uncompressed_size: u64,
+    decoded_records: Vec<IndexRecord>,
+    mem_limit_kb: u32,
+    position: u64,
+    stream_count: u64,
+    block_checks: Vec<Option<CheckValue>>,
 }
 
-impl BlockHeader {
-    fn parse<R: Read>(reader: &mut R) -> Result<Option<Self>> {
-        let header_size_encoded = reader.read_u8()?;
-
-        if header_size_encoded == 0 {
-            // If header size is 0, this indicates end of blocks (index follows).
-            return Ok(None);
-        }
-
-        let header_size = (header_size_encoded as usize + 1) * 4;
-        if !(8..=1024).contains(&header_size) {
-            return Err(error_invalid_data("invalid XZ block header size"));
-        }
-
-        // -1 because we already read the size byte.
-        let mut header_data = vec![0u8; header_size - 1];
-        reader.read_exact(&mut header_data)?;
-
-        let block_flags = header_data[0];
-        let num_filters = ((block_flags & 0x03) + 1) as usize;
-        let has_compressed_size = (block_flags & 0x40) != 0;
-        let has_uncompressed_size = (block_flags & 0x80) != 0;
-
-        let mut offset = 1;
-        let mut compressed_size = None;
-        let mut uncompressed_size = None;
-
-        // Parse optional compressed size.
-        if has_compressed_size {
-            if offset + 8 > header_data.len() {
-                return Err(error_invalid_data(
-                    "XZ block header too short for compressed size",
-                ));
-            }
-            compressed_size = Some(parse_multibyte_integer(&header_data[offset..])?);
-            offset += count_multibyte_integer_size(&header_data[offset..]);
-        }
-
-        if has_uncompressed_size {
-            if offset >= header_data.len() {
-                return Err(error_invalid_data(
-                    "XZ block header too short for uncompressed size",
-                ));
-            }
-            uncompressed_size = Some(parse_multibyte_integer(&header_data[offset..])?);
-            offset += count_multibyte_integer_size(&header_data[offset..]);
-        }
-
-        let mut filters = [None; 4];
-        let mut properties = [0; 4];
-
-        for i in 0..num_filters {
-            if offset >= header_data.len() {
-                return Err(error_invalid_data("XZ block header too short for filters"));
-            }
-
-            let filter_type =
-                FilterType::try_from(parse_multibyte_integer(&header_data[offset..])?)
-                    .map_err(|_| error_invalid_input("unsupported filter type found"))?;
-
-            offset += count_multibyte_integer_size(&header_data[offset..]);
-
-            let property = match filter_type {
-                FilterType::Delta => {
-                    if offset >= header_data.len() {
-                        return Err(error_invalid_data(
-                            "XZ block header too short for Delta properties",
-                        ));
-                    }
-
-                    let props_size = parse_multibyte_integer(&header_data[offset..])?;
-                    offset += count_multibyte_integer_size(&header_data[offset..]);
-
-                    if props_size != 1 {
-                        return Err(error_invalid_data("invalid Delta properties size"));
-                    }
-
-                    if offset >= header_data.len() {
-                        return Err(error_invalid_data(
-                            "XZ block header too short for Delta properties",
-                        ));
-                    }
-
-                    let distance_prop = header_data[offset];
-                    offset += 1;
-
-                    // Distance is encoded as byte value + 1, range [1, 256].
-                    (distance_prop as u32) + 1
-                }
-                FilterType::BcjX86
-                | FilterType::BcjPPC
-                | FilterType::BcjIA64
-                | FilterType::BcjARM
-                | FilterType::BcjARMThumb
-                | FilterType::BcjSPARC
-                | FilterType::BcjARM64
-                | FilterType::BcjRISCV => {
-                    if offset >= header_data.len() {
-                        return Err(error_invalid_data(
-                            "XZ block header too short for BCJ properties",
-                        ));
-                    }
-
-                    let props_size = parse_multibyte_integer(&header_data[offset..])?;
-                    offset += count_multibyte_integer_size(&header_data[offset..]);
-
-                    match props_size {
-                        0 => {
-                            // No start offset specified, use default (0).
-                            0
-                        }
-                        4 => {
-                            // 4-byte start offset specified.
-                            if offset + 4 > header_data.len() {
-                                return Err(error_invalid_data(
-                                    "XZ block header too short for BCJ start offset",
-                                ));
-                            }
-
-                            let start_offset_value = u32::from_le_bytes([
-                                header_data[offset],
-                                header_data[offset + 1],
-                                header_data[offset + 2],
-                                header_data[offset + 3],
-                            ]);
-                            offset += 4;
-
-                            // Validate alignment based on filter type.
-                            let bcj_alignment = match filter_type {
-                                FilterType::BcjX86 => 1,
-                                FilterType::BcjPPC => 4,
-                                FilterType::BcjIA64 => 16,
-                                FilterType::BcjARM => 4,
-                                FilterType::BcjARMThumb => 2,
-                                FilterType::BcjSPARC => 4,
-                                FilterType::BcjARM64 => 4,
-                                FilterType::BcjRISCV => 2,
-                                _ => unreachable!(),
-                            };
-
-                            if start_offset_value % bcj_alignment != 0 {
-                                return Err(error_invalid_data(
-                                    "BCJ start offset not aligned to filter requirements",
-                                ));
-                            }
-
-                            start_offset_value
-                        }
-                        _ => {
-                            return Err(error_invalid_data("invalid BCJ properties size"));
-                        }
-                    }
-                }
-                FilterType::LZMA2 => {
-                    if offset >= header_data.len() {
-                        return Err(error_invalid_data(
-                            "XZ block header too short for LZMA2 properties",
-                        ));
-                    }
+/// Decodes a single block. `data` must contain exactly the block header, the compressed data,
+/// the block padding and the check of the block. The block must match the sizes of its index
+/// record. Returns the data and the verified check of the block.
+pub(super) fn decode_block(
+    data: &[u8],
+    check_type: CheckType,
+    index_record: &IndexRecord,
+) -> Result<(Vec<u8>, Option<CheckValue>)> {
+    let mut uncompressed =
+        Vec::with_capacity(index_record.uncompressed_size.min(64 * 1024 * 1024) as usize);
+    let check = decode_block_with(data, check_type, index_record, |data| {
+        uncompressed.extend_from_slice(data)
+    })?;
+    Ok((uncompressed, check))
+}
 
-                    let props_size = parse_multibyte_integer(&header_data[offset..])?;
-                    offset += count_multibyte_integer_size(&header_data[offset..]);
+/// Decodes a single block like [`decode_block`], but without producing output. Returns the
+/// verified check of the block.
+pub(super) fn verify_block(
+    data: &[u8],
+    check_type: CheckType,
+    index_record: &IndexRecord,
+) -> Result<Option<CheckValue>> {
+    decode_block_with(data, check_type, index_record, |_| {})
+}
 
-                    if props_size != 1 {
-                        return Err(error_invalid_data("invalid LZMA2 properties size"));
-                    }
+/// Decodes a single block and passes its data to `sink`. Returns the verified check of the block.
+fn decode_block_with<F: FnMut(&[u8])>(
+    data: &[u8],
+    check_type: CheckType,
+    index_record: &IndexRecord,
+    mut sink: F,
+) -> Result<Option<CheckValue>> {
+    let uncompressed_size = index_record.uncompressed_size;
 
-                    if offset >= header_data.len() {
-                        return Err(error_invalid_data(
-                            "XZ block header too short for LZMA2 properties",
-                        ));
-                    }
+    let mut reader = data;
+    let block_header = BlockHeader::parse(&mut reader)?
+        .ok_or_else(|| error_invalid_data("expected XZ block header but found index"))?;
+    let header_size = data.len() - reader.len();
 
-                    let dict_size_prop = header_data[offset];
-                    offset += 1;
+    let declared_uncompressed_size = block_header.uncompressed_size.unwrap_or(u64::MAX);
 
-                    if dict_size_prop > 40 {
-                        return Err(error_invalid_data("invalid LZMA2 dictionary size"));
-                    }
+    let mut filter_chain = FilterChain::new(&block_header)?;
 
-                    if dict_size_prop == 40 {
-                        0xFFFFFFFF
-                    } else {
-                        let base = 2 | ((dict_size_prop & 1) as u32);
-                        base << (dict_size_prop / 2 + 11)
-                    }
-                }
-            };
+    let mut checksum_calculator = ChecksumCalculator::new(check_type);
+    let mut scratch = Vec::new();
+    let mut decoded_size = 0u64;
 
-            filters[i] = Some(filter_type);
-            properties[i] = property;
+    loop {
+        let bytes_read = filter_chain.decode_with(&mut reader, &mut scratch, |data| {
+            checksum_calculator.update(data);
+            sink(data);
+        })?;
+        if bytes_read == 0 {
+            break;
         }
+        decoded_size += bytes_read as u64;
 
-        if filters.iter().filter_map(|x| *x).next_back() != Some(FilterType::LZMA2) {
-            return Err(error_invalid_input(
-                "XZ block's last filter must be a LZMA2 filter",
+        if decoded_size > uncompressed_size {
+            return Err(error_invalid_data(
+                "XZ block is bigger than stated in index",
             ));
         }
 
-        // Header must be padded so that the total header size matches the declared size.
-        // We need to pad until: 1 (size byte) + offset + 4 (CRC32) == header_size
-        let expected_offset = header_size - 1 - 4; // header_size - size_byte - crc32_size
-        while offset < expected_offset {
-            if offset >= header_data.len() || header_data[offset] != 0 {
-                return Err(error_invalid_data("invalid XZ block header padding"));
-            }
-            offset += 1;
-        }
-
-        // Last 4 bytes should be CRC32 of the header (excluding the CRC32 itself).
-        if offset + 4 != header_data.len() {
-            return Err(error_invalid_data("invalid XZ block header CRC32 position"));
-        }
-
-        let expected_crc = u32::from_le_bytes([
-            header_data[offset],
-            header_data[offset + 1],
-            header_data[offset + 2],
-            header_data[offset + 3],
-        ]);
-
-        // Calculate CRC32 of header size byte + header data (excluding CRC32).
-        let mut crc = CRC32.digest();
-        crc.update(&[header_size_encoded]);
-        crc.update(&header_data[..offset]);
-
-        if expected_crc != crc.finalize() {
-            return Err(error_invalid_data("XZ block header CRC32 mismatch"));
+        if decoded_size > declared_uncompressed_size {
+            return Err(error_invalid_data(
+                "XZ block is bigger than its declared uncompressed size",
+            ));
         }
-
-        Ok(Some(BlockHeader {
-            compressed_size,
-            uncompressed_size,
-            filters,
-            properties,
-        }))
     }
-}
 
-struct BoundedReader<R> {
-    inner: R,
-    position: u64,
-    limit: u64,
-}
+    let compressed_bytes_read = filter_chain.compressed_bytes_read();
 
-impl<R> BoundedReader<R> {
-    fn new(inner: R, limit: u64) -> Self {
-        Self {
-            inner,
-            position: 0,
-            limit,
-        }
+    if decoded_size < uncompressed_size {
+        return Err(error_invalid_data(
+            "XZ block is smaller than stated in index",
+        ));
     }
 
-    fn into_inner(self) -> R {
-        self.inner
-    }
-}
+    block_header.verify_sizes(compressed_bytes_read, decoded_size)?;
 
-impl<R: Read> Read for BoundedReader<R> {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        if self.position >= self.limit {
-            return Ok(0);
-        }
+    let check_size = check_type.checksum_size();
+    let data_size = header_size + compressed_bytes_read as usize;
+    let padding_size = (4 - (data_size % 4)) % 4;
 
-        let left = (self.limit - self.position).min(buf.len() as u64) as usize;
-        let read_size = self.inner.read(&mut buf[..left])?;
-        self.position += read_size as u64;
-        Ok(read_size)
+    if (data_size + check_size) as u64 != index_record.unpadded_size
+        || data.len() != data_size + padding_size + check_size
+    {
+        return Err(error_invalid_data("XZ block size doesn't match index"));
     }
-}
 
-struct SharedReader<R> {
-    inner: Rc<RefCell<R>>,
-    compressed_bytes_read: Rc<Cell<u64>>,
-}
+    let padding = &data[data_size..data_size + padding_size];
+    if !padding.iter().all(|&byte| byte == 0) {
+        return Err(error_invalid_data("invalid XZ block padding"));
+    }
 
-/// A single-threaded XZ decompressor.
-pub struct XZReader<'reader, R> {
-    reader: Box<dyn Read + 'reader>,
-    stream_header: Option<StreamHeader>,
-    checksum_calculator: Option<ChecksumCalculator>,
-    finished: bool,
-    allow_multiple_streams: bool,
-    blocks_processed: u64,
-    compressed_bytes_read: Rc<Cell<u64>>,
-    original_reader: Rc<RefCell<R>>,
+    checksum_calculator.verify(&data[data_size + padding_size..])
 }
 
-impl<R> SharedReader<R> {
-    fn new(inner: R, compressed_bytes_read: Rc<Cell<u64>>) -> Self {
-        Self {
-            inner: Rc::new(RefCell::new(inner)),
-            compressed_bytes_read,
-        }
+impl<R> XZReader<R> {
+    /// Consume the XZReader and return the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner.into_inner()
     }
-}
 
-impl<R: Read> Read for SharedReader<R> {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let mut reader = self.inner.borrow_mut();
-        let bytes_read = reader.read(buf)?;
-        self.compressed_bytes_read
-            .set(self.compressed_bytes_read.get() + bytes_read as u64);
-        Ok(bytes_read)
+    /// Returns the verified checks of all blocks that were decoded completely, in file order.
+    /// Blocks of streams with the check type [`CheckType::None`] have no check value.
+    pub fn block_checks(&self) -> &[Option<CheckValue>] {
+        &self.block_checks
     }
 }
 
-impl<'reader, R: Read + 'reader> XZReader<'reader, R> {
+impl<R: Read> XZReader<R> {
     /// Create a new [`XZReader`].
     pub fn new(inner: R, allow_multiple_streams: bool) -> Self {
-        let compressed_bytes_read = Rc::new(Cell::new(0));
-        let original_reader = Rc::new(RefCell::new(inner));
-        let reader = Box::new(SharedReader {
-            inner: Rc::clone(&original_reader),
-            compressed_bytes_read: Rc::clone(&compressed_bytes_read),
-        });
+        Self::new_mem_limit(inner, allow_multiple_streams, u32::MAX)
+    }
 
+    /// Create a new [`XZReader`] with a memory usage limit.
+    /// `mem_limit_kb` is the memory usage limit in kibibytes (KiB). `u32::MAX` means no limit.
+    ///
+    /// Every block is checked against the limit before its filter chain is created.
+    pub fn new_mem_limit(inner: R, allow_multiple_streams: bool, mem_limit_kb: u32) -> Self {
         Self {
-            reader,
+            inner: CountingReader::new(inner),
+            filter_chain: None,
             stream_header: None,
             checksum_calculator: None,
             finished: false,
             allow_multiple_streams,
-            blocks_processed: 0,
-            compressed_bytes_read,
-            original_reader,
-        }
-    }
-
-    /// Consume the XZReader and return the inner reader.
-    pub fn into_inner(self) -> R {
-        let Self {
-            reader,
-            original_reader,
-            ..
-        } = self;
-
-        drop(reader);
-
-        match Rc::try_unwrap(original_reader) {
-            Ok(refcell) => refcell.into_inner(),
-            Err(_) => {
-                panic!("failed to unwrap original reader - other references exists");
-            }
+            block_header: None,
+            block_start_pos: 0,
+            block_uncompressed_size: 0,
+            decoded_records: Vec::new(),
+            mem_limit_kb,
+            position: 0,
+            stream_count: 0,
+            block_checks: Vec::new(),
         }
     }
-}
-
-impl<'reader, R: Read + 'reader> XZReader<'reader, R> {
     fn ensure_stream_header(&mut self) -> Result<()> {
         if self.stream_header.is_none() {
-            let header = StreamHeader::parse(&mut self.reader)?;
+            let header = StreamHeader::parse(&mut self.inner)?;
             self.stream_header = Some(header);
+            self.stream_count += 1;
         }
         Ok(())
     }
 
     fn prepare_next_block(&mut self) -> Result<bool> {
-        match BlockHeader::parse(&mut self.reader)? {
+        let block_start_pos = self.inner.bytes_read();
+
+        match BlockHeader::parse(&mut self.inner)? {
             Some(block_header) => {
-                static DUMMY: &[u8] = &[];
-                let mut chain_reader: Box<dyn Read + 'reader> =
-                    core::mem::replace(&mut self.reader, Box::new(DUMMY));
-
-                for (filter, property) in block_header
-                    .filters
-                    .iter()
-                    .copied()
-                    .zip(block_header.properties)
-                    .filter_map(|(filter, property)| filter.map(|filter| (filter, property)))
-                    .rev()
-                {
-                    chain_reader = match filter {
-                        FilterType::Delta => {
-                            let distance = property as usize;
-                            Box::new(DeltaReader::new(chain_reader, distance))
-                        }
-                        FilterType::BcjX86 => {
-                            let start_offset = property as usize;
-                            Box::new(BCJReader::new_x86(chain_reader, start_offset))
-                        }
-                        FilterType::BcjPPC => {
-                            let start_offset = property as usize;
-                            Box::new(BCJReader::new_ppc(chain_reader, start_offset))
-                        }
-                        FilterType::BcjIA64 => {
-                            let start_offset = property as usize;
-                            Box::new(BCJReader::new_ia64(chain_reader, start_offset))
-                        }
-                        FilterType::BcjARM => {
-                            let start_offset = property as usize;
-                            Box::new(BCJReader::new_arm(chain_reader, start_offset))
-                        }
-                        FilterType::BcjARMThumb => {
-                            let start_offset = property as usize;
-                            Box::new(BCJReader::new_arm_thumb(chain_reader, start_offset))
-                        }
-                        FilterType::BcjSPARC => {
-                            let start_offset = property as usize;
-                            Box::new(BCJReader::new_sparc(chain_reader, start_offset))
-                        }
-                        FilterType::BcjARM64 => {
-                            let start_offset = property as usize;
-                            Box::new(BCJReader::new_arm64(chain_reader, start_offset))
-                        }
-                        FilterType::BcjRISCV => {
-                            let start_offset = property as usize;
-                            Box::new(BCJReader::new_riscv(chain_reader, start_offset))
-                        }
-                        FilterType::LZMA2 => {
-                            let dict_size = property;
-                            Box::new(LZMA2Reader::new(chain_reader, dict_size, None))
-                        }
-                    };
+                if block_header.memory_usage() > self.mem_limit_kb {
+                    return Err(error_mem_limit(
+                        block_header.memory_usage(),
+                        self.mem_limit_kb,
+                    ));
                 }
 
-                self.reader = chain_reader;
+                self.filter_chain = Some(FilterChain::new(&block_header)?);
 
                 match self.stream_header.as_ref() {
                     Some(header) => {
@@ -611,7 +384,9 @@ impl<'reader, R: Read + 'reader> XZReader<'reader, R> {
                     }
                 }
 
-                self.blocks_processed += 1;
+                self.block_header = Some(block_header);
+                self.block_start_pos = block_start_pos;
+                self.block_uncompressed_size = 0;
 
                 Ok(true)
             }
@@ -630,14 +405,14 @@ impl<'reader, R: Read + 'reader> XZReader<'reader, R> {
     }
 
     fn consume_padding(&mut self) -> Result<()> {
-        let padding_needed = match (4 - (self.compressed_bytes_read.get() % 4)) % 4 {
+        let padding_needed = match (4 - (self.inner.bytes_read() % 4)) % 4 {
             0 => return Ok(()),
             n => n as usize,
         };
 
         let mut padding_buf = [0u8; 3];
 
-        let bytes_read = self.reader.read(&mut padding_buf[..padding_needed])?;
+        let bytes_read = self.inner.read(&mut padding_buf[..padding_needed])?;
 
         if bytes_read != padding_needed {
             return Err(error_invalid_data("incomplete XZ block padding"));
@@ -656,125 +431,230 @@ impl<'reader, R: Read + 'reader> XZReader<'reader, R> {
             .take()
             .expect("checksum_calculator not set");
 
-        match checksum_calculator {
-            ChecksumCalculator::None => { /* Nothing to check */ }
-            ChecksumCalculator::Crc32(_) => {
-                let mut checksum = [0u8; 4];
-                self.reader.read_exact(&mut checksum)?;
+        let check_size = self
+            .stream_header
+            .as_ref()
+            .map(|header| header.check_type.checksum_size())
+            .unwrap_or(0);
 
-                if !checksum_calculator.verify(&checksum) {
-                    return Err(error_invalid_data("invalid block checksum"));
-                }
-            }
-            ChecksumCalculator::Crc64(_) => {
-                let mut checksum = [0u8; 8];
-                self.reader.read_exact(&mut checksum)?;
+        let mut checksum = [0u8; 32];
+        self.inner.read_exact(&mut checksum[..check_size])?;
 
-                if !checksum_calculator.verify(&checksum) {
-                    return Err(error_invalid_data("invalid block checksum"));
-                }
-            }
-            ChecksumCalculator::Sha256(_) => {
-                let mut checksum = [0u8; 32];
-                self.reader.read_exact(&mut checksum)?;
-
-                if !checksum_calculator.verify(&checksum) {
-                    return Err(error_invalid_data("invalid block checksum"));
-                }
-            }
-        }
+        let check = checksum_calculator.verify(&checksum[..check_size])?;
+        self.block_checks.push(check);
 
         Ok(())
     }
 
-    /// Look for the start of the next stream by reading bytes one at a time
-    /// and checking for the XZ magic sequence, allowing for stream padding.
+    /// Look for the start of the next stream, allowing for stream padding.
     fn try_start_next_stream(&mut self) -> Result<bool> {
-        let mut padding_bytes = 0;
-        let mut buffer = [0u8; 6];
+        let Some(stream_header) = parse_next_stream_header(&mut self.inner)? else {
+            return Ok(false);
+        };
 
-        loop {
-            let mut byte_buffer = [0u8; 1];
-            let read = self.reader.read(&mut byte_buffer)?;
-            if read == 0 {
-                // EOF reached, no more streams.
-                return Ok(false);
-            }
+        // Reset state for new stream.
+        self.stream_header = Some(stream_header);
+        self.stream_count += 1;
+        self.decoded_records.clear();
 
-            let byte = byte_buffer[0];
+        Ok(true)
+    }
 
-            if byte == 0 {
-                // Potential stream padding.
-                padding_bytes += 1;
-                continue;
-            }
+    /// Verifies the sizes of the block that was just decoded against its header and records
+    /// them for the index verification. Must be called after the compressed data of the block
+    /// was read, but before the padding.
+    fn record_decoded_block(&mut self) -> Result<()> {
+        let check_type = self
+            .stream_header
+            .as_ref()
+            .expect("stream_header not set")
+            .check_type;
+
+        let block_header = self.block_header.take().expect("block_header not set");
+
+        let block_size = self.inner.bytes_read() - self.block_start_pos;
+        block_header.verify_sizes(
+            block_size - block_header.header_size,
+            self.block_uncompressed_size,
+        )?;
+
+        self.decoded_records.push(IndexRecord {
+            unpadded_size: block_size + check_type.checksum_size() as u64,
+            uncompressed_size: self.block_uncompressed_size,
+        });
 
-            // Non-zero byte found - check if it starts XZ magic.
-            if byte == XZ_MAGIC[0] {
-                return Err(error_invalid_data("invalid data after stream"));
-            }
+        Ok(())
+    }
 
-            buffer[0] = byte;
-            let mut buffer_pos = 1;
+    fn parse_index_and_footer(&mut self) -> Result<()> {
+        let check_type = self
+            .stream_header
+            .as_ref()
+            .expect("stream_header not set")
+            .check_type;
 
-            // Read the rest of the magic bytes.
-            while buffer_pos < 6 {
-                match self.reader.read(&mut byte_buffer)? {
-                    0 => {
-                        return Err(error_invalid_data("incomplete XZ magic bytes"));
-                    }
-                    1 => {
-                        buffer[buffer_pos] = byte_buffer[0];
-                        buffer_pos += 1;
-                    }
-                    _ => unreachable!(),
-                }
-            }
+        parse_index_and_footer(&mut self.inner, &self.decoded_records, check_type)
+    }
+}
 
-            if buffer != XZ_MAGIC {
-                return Err(error_invalid_data("invalid data after stream padding"));
-            }
+/// Look for the start of the next stream by reading bytes one at a time and checking for the XZ
+/// magic sequence, allowing for stream padding. Returns `None` at the end of the data.
+pub(super) fn parse_next_stream_header<R: Read>(
+    inner: &mut CountingReader<R>,
+) -> Result<Option<StreamHeader>> {
+    let mut padding_bytes = 0;
+    let mut buffer = [0u8; 6];
+
+    loop {
+        let mut byte_buffer = [0u8; 1];
+        let read = inner.read(&mut byte_buffer)?;
+        if read == 0 {
+            // EOF reached, no more streams.
+            return Ok(None);
+        }
 
-            if padding_bytes % 4 != 0 {
-                return Err(error_invalid_data("stream padding size not multiple of 4"));
-            }
+        let byte = byte_buffer[0];
+
+        if byte == 0 {
+            // Potential stream padding.
+            padding_bytes += 1;
+            continue;
+        }
 
-            let stream_header = StreamHeader::parse_flags_and_crc(&mut self.reader)?;
+        // Non-zero byte found - check if it starts XZ magic.
+        if byte != XZ_MAGIC[0] {
+            return Err(error_invalid_data("invalid data after stream"));
+        }
 
-            // Reset state for new stream.
-            self.stream_header = Some(stream_header);
-            self.blocks_processed = 0;
+        buffer[0] = byte;
+        let mut buffer_pos = 1;
 
-            return Ok(true);
+        // Read the rest of the magic bytes.
+        while buffer_pos < 6 {
+            match inner.read(&mut byte_buffer)? {
+                0 => {
+                    return Err(error_invalid_data("incomplete XZ magic bytes"));
+                }
+                1 => {
+                    buffer[buffer_pos] = byte_buffer[0];
+                    buffer_pos += 1;
+                }
+                _ => unreachable!(),
+            }
         }
-    }
 
-    fn parse_index_and_footer(&mut self) -> Result<()> {
-        let index = Index::parse(&mut self.reader)?;
+        if buffer != XZ_MAGIC {
+            return Err(error_invalid_data("invalid data after stream padding"));
+        }
 
-        if index.number_of_records != self.blocks_processed {
-            return Err(error_invalid_data(
-                "number of blocks processed doesn't match index records",
-            ));
+        if padding_bytes % 4 != 0 {
+            return Err(error_invalid_data("stream padding size not multiple of 4"));
         }
 
-        let stream_footer = StreamFooter::parse(&mut self.reader)?;
+        return StreamHeader::parse_flags_and_crc(inner).map(Some);
+    }
+}
+
+/// Parses the index and the stream footer of a stream and verifies them against the records of
+/// the decoded blocks. The index indicator must already be read.
+pub(super) fn parse_index_and_footer<R: Read>(
+    inner: &mut CountingReader<R>,
+    decoded_records: &[IndexRecord],
+    check_type: CheckType,
+) -> Result<()> {
+    // The index indicator was already read by the block header parser.
+    let index_start_pos = inner.bytes_read() - 1;
 
-        let header = self.stream_header.as_ref().expect("stream_header not set");
+    let index = Index::parse(inner)?;
 
-        let header_flags = [0, header.check_type as u8];
-        if stream_footer.stream_flags != header_flags {
-            return Err(error_invalid_data(
-                "stream header and footer flags mismatch",
-            ));
-        }
+    if index.number_of_records != decoded_records.len() as u64 {
+        return Err(error_invalid_data(
+            "number of blocks processed doesn't match index records",
+        ));
+    }
 
-        Ok(())
+    if index.records != decoded_records {
+        return Err(error_invalid_data("XZ index doesn't match decoded blocks"));
+    }
+
+    let index_size = inner.bytes_read() - index_start_pos;
+
+    let stream_footer = StreamFooter::parse(inner)?;
+
+    if (stream_footer.backward_size as u64 + 1) * 4 != index_size {
+        return Err(error_invalid_data(
+            "XZ backward size doesn't match index size",
+        ));
+    }
+
+    let header_flags = [0, check_type as u8];
+    if stream_footer.stream_flags != header_flags {
+        return Err(error_invalid_data(
+            "stream header and footer flags mismatch",
+        ));
     }
+
+    Ok(())
 }
 
-impl<'reader, R: Read + 'reader> Read for XZReader<'reader, R> {
+impl<R: Read> Read for XZReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let bytes_read = self.read_blocks(buf).map_err(|error| {
+            error_with_position(error, Some(self.inner.bytes_read()), Some(self.position))
+        })?;
+        self.position += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl<R: Read> XZReader<R> {
+    fn read_blocks(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.decode_blocks(|filter_chain, inner, checksum_calculator| {
+            let bytes_read = filter_chain.read(inner, buf)?;
+            checksum_calculator.update(&buf[..bytes_read]);
+            Ok(bytes_read)
+        })
+    }
+
+    /// Decodes all remaining blocks without producing output and returns a summary of them.
+    pub(super) fn verify(mut self) -> Result<XZSummary> {
+        let mut scratch = Vec::new();
+
+        loop {
+            let bytes_read = self
+                .decode_blocks(|filter_chain, inner, checksum_calculator| {
+                    filter_chain
+                        .decode_with(inner, &mut scratch, |data| checksum_calculator.update(data))
+                })
+                .map_err(|error| {
+                    error_with_position(error, Some(self.inner.bytes_read()), Some(self.position))
+                })?;
+
+            if bytes_read == 0 {
+                break;
+            }
+            self.position += bytes_read as u64;
+        }
+
+        Ok(XZSummary {
+            stream_count: self.stream_count,
+            block_count: self.block_checks.len() as u64,
+            compressed_size: self.inner.bytes_read(),
+            uncompressed_size: self.position,
+            checks: self.block_checks,
+        })
+    }
+
+    /// Decodes the next data of the current block with `decode`, which also has to update the
+    /// check of the block.
+    fn decode_blocks<D>(&mut self, mut decode: D) -> Result<usize>
+    where
+        D: FnMut(
+            &mut FilterChain,
+            &mut CountingReader<R>,
+            &mut ChecksumCalculator,
+        ) -> Result<usize>,
+    {
         if self.finished {
             return Ok(0);
         }
@@ -782,24 +662,34 @@ impl<'reader, R: Read + 'reader> Read for XZReader<'reader, R> {
         self.ensure_stream_header()?;
 
         loop {
-            if self.checksum_calculator.is_some() {
-                let bytes_read = self.reader.read(buf)?;
+            if let Some(filter_chain) = self.filter_chain.as_mut() {
+                let checksum_calculator = self
+                    .checksum_calculator
+                    .as_mut()
+                    .expect("checksum_calculator not set");
+                let bytes_read = decode(filter_chain, &mut self.inner, checksum_calculator)?;
 
                 if bytes_read > 0 {
-                    if let Some(ref mut calc) = self.checksum_calculator {
-                        calc.update(&buf[..bytes_read]);
+                    self.block_uncompressed_size += bytes_read as u64;
+
+                    if let Some(uncompressed_size) = self
+                        .block_header
+                        .as_ref()
+                        .and_then(|header| header.uncompressed_size)
+                    {
+                        if self.block_uncompressed_size > uncompressed_size {
+                            return Err(error_invalid_data(
+                                "XZ block is bigger than its declared uncompressed size",
+                            ));
+                        }
                     }
 
                     return Ok(bytes_read);
                 } else {
                     // Current block is finished.
-                    let shared_reader = Box::new(SharedReader {
-                        inner: Rc::clone(&self.original_reader),
-                        compressed_bytes_read: Rc::clone(&self.compressed_bytes_read),
-                    });
-
-                    self.reader = shared_reader;
+                    self.filter_chain = None;
 
+                    self.record_decoded_block()?;
                     self.consume_padding()?;
                     self.verify_block_checksum()?;
                 }
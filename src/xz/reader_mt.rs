@@ -0,0 +1,547 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Cursor, Seek, SeekFrom},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use super::{
+    reader::{decode_block, verify_block},
+    scan_streams, BlockHeader, CheckType, IndexRecord, XZBlock, XZSummary,
+};
+use crate::{
+    error_invalid_data, error_with_base_position, set_error,
+    work_queue::{WorkStealingQueue, WorkerHandle},
+    workers_for_mem_limit, CheckValue, Read,
+};
+
+/// A work unit for a worker thread.
+/// Contains the sequence number, the raw bytes of a single XZ block, its check type, its
+/// index record and the compressed and uncompressed position of the block.
+pub(super) type WorkUnit = (u64, Vec<u8>, CheckType, IndexRecord, (u64, u64));
+
+/// A decoded block. Contains the decompressed data and the verified check of the block.
+pub(super) type DecodedBlock = (Vec<u8>, Option<CheckValue>);
+
+/// A result unit from a worker thread.
+/// Contains the sequence number and the decoded block.
+pub(super) type ResultUnit = (u64, DecodedBlock);
+
+enum State {
+    /// Dispatching blocks to worker threads.
+    Dispatching,
+    /// All blocks dispatched, waiting for workers to complete.
+    Draining,
+    /// All data has been decompressed and returned. The stream is exhausted.
+    Finished,
+    /// A fatal error occurred in either the reader or a worker thread.
+    Error,
+}
+
+/// A multi-threaded XZ decompressor.
+///
+/// The reader parses the stream footer and the index of every stream first and then decodes
+/// the independent blocks in parallel. Files written with a single block (the default of
+/// [`crate::XZWriter`]) can't be decoded in parallel.
+pub struct XZReaderMT<R: Read + Seek> {
+    inner: Option<R>,
+    blocks: Vec<XZBlock>,
+    stream_count: u64,
+    compressed_size: u64,
+    verify_only: bool,
+    result_rx: Receiver<ResultUnit>,
+    result_tx: Sender<ResultUnit>,
+    next_sequence_to_dispatch: u64,
+    uncompressed_dispatched: u64,
+    next_sequence_to_return: u64,
+    last_sequence_id: Option<u64>,
+    out_of_order_chunks: BTreeMap<u64, DecodedBlock>,
+    current_chunk: Cursor<Vec<u8>>,
+    current_check: Option<Option<CheckValue>>,
+    block_checks: Vec<Option<CheckValue>>,
+    shutdown_flag: Arc<AtomicBool>,
+    error_store: Arc<Mutex<Option<io::Error>>>,
+    state: State,
+    work_queue: WorkStealingQueue<WorkUnit>,
+    active_workers: Arc<AtomicU32>,
+    max_workers: u32,
+    worker_handles: Vec<thread::JoinHandle<()>>,
+}
+
+impl<R: Read + Seek> XZReaderMT<R> {
+    /// Creates a new multi-threaded XZ reader.
+    ///
+    /// - `inner`: The reader to read compressed data from. Must implement Seek.
+    /// - `allow_multiple_streams`: Whether to decode concatenated streams. If false, only the
+    ///   first stream is decoded.
+    /// - `num_workers`: The maximum number of worker threads for decompression. Currently capped at 256 threads.
+    pub fn new(inner: R, allow_multiple_streams: bool, num_workers: u32) -> io::Result<Self> {
+        Self::new_mem_limit(inner, allow_multiple_streams, num_workers, u32::MAX)
+    }
+
+    /// Creates a new multi-threaded XZ reader with a memory usage limit.
+    ///
+    /// Every worker needs its own dictionary and holds a whole block in memory, so the count of
+    /// workers is reduced until all workers together stay inside the limit. The block headers are
+    /// read up front to find the needed memory.
+    ///
+    /// - `inner`: The reader to read compressed data from. Must implement Seek.
+    /// - `allow_multiple_streams`: Whether to decode concatenated streams. If false, only the
+    ///   first stream is decoded.
+    /// - `num_workers`: The maximum number of worker threads for decompression. Currently capped at 256 threads.
+    /// - `mem_limit_kb`: The memory usage limit of all workers in kibibytes (KiB). `u32::MAX` means no limit.
+    pub fn new_mem_limit(
+        inner: R,
+        allow_multiple_streams: bool,
+        num_workers: u32,
+        mem_limit_kb: u32,
+    ) -> io::Result<Self> {
+        let max_workers = num_workers.clamp(1, 256);
+
+        let work_queue = WorkStealingQueue::new();
+        let (result_tx, result_rx) = mpsc::channel::<ResultUnit>();
+        let shutdown_flag = Arc::new(AtomicBool::new(false));
+        let error_store = Arc::new(Mutex::new(None));
+        let active_workers = Arc::new(AtomicU32::new(0));
+
+        let mut reader = Self {
+            inner: Some(inner),
+            blocks: Vec::new(),
+            stream_count: 0,
+            compressed_size: 0,
+            verify_only: false,
+            result_rx,
+            result_tx,
+            next_sequence_to_dispatch: 0,
+            uncompressed_dispatched: 0,
+            next_sequence_to_return: 0,
+            last_sequence_id: None,
+            out_of_order_chunks: BTreeMap::new(),
+            current_chunk: Cursor::new(Vec::new()),
+            current_check: None,
+            block_checks: Vec::new(),
+            shutdown_flag,
+            error_store,
+            state: State::Dispatching,
+            work_queue,
+            active_workers,
+            max_workers,
+            worker_handles: Vec::new(),
+        };
+
+        reader.scan_blocks(allow_multiple_streams)?;
+
+        if mem_limit_kb != u32::MAX {
+            let worker_mem_kb = reader.worker_memory_usage()?;
+            reader.max_workers =
+                workers_for_mem_limit(reader.max_workers, worker_mem_kb, mem_limit_kb)?;
+        }
+
+        if reader.blocks.is_empty() {
+            // Streams without any blocks are valid and decode to nothing.
+            reader.state = State::Finished;
+        }
+
+        Ok(reader)
+    }
+
+    /// Scan the XZ file to collect information about all blocks.
+    /// This reads the stream footer and the index of each stream from the back of the file.
+    fn scan_blocks(&mut self, allow_multiple_streams: bool) -> io::Result<()> {
+        let mut reader = self.inner.take().expect("inner reader not set");
+        let result = scan_streams(&mut reader);
+        self.inner = Some(reader);
+
+        let mut streams = result?;
+
+        if !allow_multiple_streams {
+            streams.truncate(1);
+        }
+
+        self.stream_count = streams.len() as u64;
+        self.compressed_size = streams
+            .iter()
+            .map(|stream| stream.total_size() + stream.padding)
+            .sum();
+
+        self.blocks = streams
+            .into_iter()
+            .flat_map(|stream| stream.blocks)
+            .collect();
+
+        Ok(())
+    }
+
+    /// Reads the header of every block to find the memory in KiB a single worker needs, including
+    /// the buffers for the compressed and the decompressed block.
+    fn worker_memory_usage(&mut self) -> io::Result<u32> {
+        let mut reader = self.inner.take().expect("inner reader not set");
+        let mut worker_mem_kb = 0u32;
+
+        let result = self.blocks.iter().try_for_each(|block| {
+            reader.seek(SeekFrom::Start(block.start_pos))?;
+            let block_header = BlockHeader::parse(&mut reader)?
+                .ok_or_else(|| error_invalid_data("expected XZ block header but found index"))?;
+
+            let buffer_size = block
+                .total_size()
+                .saturating_add(block.uncompressed_size)
+                .div_ceil(1024);
+            let buffer_size = u32::try_from(buffer_size).unwrap_or(u32::MAX);
+
+            worker_mem_kb =
+                worker_mem_kb.max(block_header.memory_usage().saturating_add(buffer_size));
+
+            Ok::<(), io::Error>(())
+        });
+
+        self.inner = Some(reader);
+        result?;
+
+        Ok(worker_mem_kb)
+    }
+
+    fn spawn_worker_thread(&mut self) {
+        let worker_handle = self.work_queue.worker();
+        let result_tx = self.result_tx.clone();
+        let shutdown_flag = Arc::clone(&self.shutdown_flag);
+        let error_store = Arc::clone(&self.error_store);
+        let active_workers = Arc::clone(&self.active_workers);
+        let verify_only = self.verify_only;
+
+        let handle = thread::spawn(move || {
+            worker_thread_logic(
+                worker_handle,
+                result_tx,
+                shutdown_flag,
+                error_store,
+                active_workers,
+                verify_only,
+            );
+        });
+
+        self.worker_handles.push(handle);
+    }
+
+    /// Get the count of XZ blocks found in the file.
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Returns the verified checks of all blocks whose data was read completely, in file order.
+    /// Blocks of streams with the check type [`CheckType::None`] have no check value.
+    pub fn block_checks(&self) -> &[Option<CheckValue>] {
+        &self.block_checks
+    }
+
+    /// Decodes all blocks in parallel without producing output and returns a summary, like
+    /// [`xz_verify`](crate::xz_verify) does with a single thread.
+    ///
+    /// The workers calculate the block checks straight from the dictionary of their decoder, so
+    /// only the compressed blocks are held in memory. Blocks that were already read are not part
+    /// of the summary.
+    pub fn verify(mut self) -> io::Result<XZSummary> {
+        self.verify_only = true;
+
+        let first_block = self.next_sequence_to_return as usize;
+        let mut checks = Vec::with_capacity(self.blocks.len().saturating_sub(first_block));
+        while let Some((_, check)) = self.get_next_uncompressed_chunk()? {
+            checks.push(check);
+        }
+
+        let uncompressed_size = self.blocks[first_block.min(self.blocks.len())..]
+            .iter()
+            .map(|block| block.uncompressed_size)
+            .sum();
+
+        Ok(XZSummary {
+            stream_count: self.stream_count,
+            block_count: checks.len() as u64,
+            compressed_size: self.compressed_size,
+            uncompressed_size,
+            checks,
+        })
+    }
+
+    fn dispatch_next_block(&mut self) -> io::Result<bool> {
+        let block_index = self.next_sequence_to_dispatch as usize;
+
+        if block_index >= self.blocks.len() {
+            // No more blocks to dispatch.
+            return Ok(false);
+        }
+
+        let block = &self.blocks[block_index];
+        let block_size = usize::try_from(block.total_size())
+            .map_err(|_| error_invalid_data("XZ block size bigger than usize"))?;
+        let check_type = block.check_type;
+        let index_record = IndexRecord {
+            unpadded_size: block.unpadded_size,
+            uncompressed_size: block.uncompressed_size,
+        };
+
+        let position = (block.start_pos, self.uncompressed_dispatched);
+        let uncompressed_size = block.uncompressed_size;
+
+        let mut reader = self.inner.take().expect("inner reader not set");
+
+        let result = reader.seek(SeekFrom::Start(block.start_pos)).and_then(|_| {
+            let mut block_data = vec![0u8; block_size];
+            reader.read_exact(&mut block_data)?;
+            Ok(block_data)
+        });
+
+        self.inner = Some(reader);
+
+        let block_data = result?;
+
+        if !self.work_queue.push((
+            self.next_sequence_to_dispatch,
+            block_data,
+            check_type,
+            index_record,
+            position,
+        )) {
+            // Queue is closed, this indicates shutdown.
+            self.state = State::Error;
+            set_error(
+                io::Error::new(io::ErrorKind::BrokenPipe, "Worker threads have shut down"),
+                &self.error_store,
+                &self.shutdown_flag,
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Worker threads have shut down",
+            ));
+        }
+
+        // We spawn a new thread if we have work queued, no available workers, and haven't reached
+        // the maximal allowed parallelism yet.
+        let spawned_workers = self.worker_handles.len() as u32;
+        let active_workers = self.active_workers.load(Ordering::Acquire);
+        let queue_len = self.work_queue.len();
+
+        if queue_len > 0 && active_workers == spawned_workers && spawned_workers < self.max_workers
+        {
+            self.spawn_worker_thread();
+        }
+
+        self.next_sequence_to_dispatch += 1;
+        self.uncompressed_dispatched += uncompressed_size;
+        Ok(true)
+    }
+
+    fn get_next_uncompressed_chunk(&mut self) -> io::Result<Option<DecodedBlock>> {
+        loop {
+            // Check for a globally stored error first, since the workers stop processing
+            // the remaining blocks once an error occurred.
+            if let Some(err) = self.error_store.lock().unwrap().take() {
+                self.state = State::Error;
+                return Err(err);
+            }
+
+            // Then check for already-received chunks.
+            if let Some(result) = self
+                .out_of_order_chunks
+                .remove(&self.next_sequence_to_return)
+            {
+                self.next_sequence_to_return += 1;
+                return Ok(Some(result));
+            }
+
+            match self.state {
+                State::Dispatching => {
+                    // First, always try to receive a result without blocking.
+                    // This keeps the pipeline moving and avoids unnecessary blocking.
+                    match self.result_rx.try_recv() {
+                        Ok((seq, result)) => {
+                            if seq == self.next_sequence_to_return {
+                                self.next_sequence_to_return += 1;
+                                return Ok(Some(result));
+                            } else {
+                                self.out_of_order_chunks.insert(seq, result);
+                                continue; // Loop again to check the out_of_order_chunks
+                            }
+                        }
+                        Err(mpsc::TryRecvError::Disconnected) => {
+                            // All workers are done.
+                            self.state = State::Draining;
+                            continue;
+                        }
+                        Err(mpsc::TryRecvError::Empty) => {
+                            // No results are ready. Now, we can consider dispatching more work.
+                        }
+                    }
+
+                    // If the work queue has capacity, try to read more from the source.
+                    if self.work_queue.len() < 4 {
+                        match self.dispatch_next_block() {
+                            Ok(true) => {
+                                // Successfully read and dispatched a block, loop to continue.
+                                continue;
+                            }
+                            Ok(false) => {
+                                // No more blocks to dispatch.
+                                // Set the last sequence ID and transition to draining.
+                                self.last_sequence_id =
+                                    Some(self.next_sequence_to_dispatch.saturating_sub(1));
+                                self.state = State::Draining;
+                                continue;
+                            }
+                            Err(error) => {
+                                set_error(error, &self.error_store, &self.shutdown_flag);
+                                self.state = State::Error;
+                                continue;
+                            }
+                        }
+                    }
+
+                    // Now we MUST wait for a result to make progress.
+                    match self.result_rx.recv() {
+                        Ok((seq, result)) => {
+                            if seq == self.next_sequence_to_return {
+                                self.next_sequence_to_return += 1;
+                                return Ok(Some(result));
+                            } else {
+                                self.out_of_order_chunks.insert(seq, result);
+                                // We've made progress, loop to check the out_of_order_chunks
+                                continue;
+                            }
+                        }
+                        Err(_) => {
+                            // All workers are done.
+                            self.state = State::Draining;
+                        }
+                    }
+                }
+                State::Draining => {
+                    if let Some(last_seq) = self.last_sequence_id {
+                        if self.next_sequence_to_return > last_seq {
+                            self.state = State::Finished;
+                            continue;
+                        }
+                    }
+
+                    // In Draining state, we only wait for results.
+                    match self.result_rx.recv() {
+                        Ok((seq, result)) => {
+                            if seq == self.next_sequence_to_return {
+                                self.next_sequence_to_return += 1;
+                                return Ok(Some(result));
+                            } else {
+                                self.out_of_order_chunks.insert(seq, result);
+                            }
+                        }
+                        Err(_) => {
+                            // All workers finished, and channel is empty. We are done.
+                            self.state = State::Finished;
+                        }
+                    }
+                }
+                State::Finished => {
+                    return Ok(None);
+                }
+                State::Error => {
+                    // The error was already logged, now we just propagate it.
+                    return Err(self.error_store.lock().unwrap().take().unwrap_or_else(|| {
+                        io::Error::other("decompression failed with an unknown error")
+                    }));
+                }
+            }
+        }
+    }
+}
+
+/// The logic for a single worker thread.
+pub(super) fn worker_thread_logic(
+    worker_handle: WorkerHandle<WorkUnit>,
+    result_tx: Sender<ResultUnit>,
+    shutdown_flag: Arc<AtomicBool>,
+    error_store: Arc<Mutex<Option<io::Error>>>,
+    active_workers: Arc<AtomicU32>,
+    verify_only: bool,
+) {
+    while !shutdown_flag.load(Ordering::Acquire) {
+        let (seq, work_unit_data, check_type, index_record, position) = match worker_handle.steal()
+        {
+            Some(work) => {
+                active_workers.fetch_add(1, Ordering::Release);
+                work
+            }
+            None => {
+                // No more work available and queue is closed
+                break;
+            }
+        };
+
+        let result = if verify_only {
+            verify_block(&work_unit_data, check_type, &index_record)
+                .map(|check| (Vec::new(), check))
+        } else {
+            decode_block(&work_unit_data, check_type, &index_record)
+        };
+
+        let result = match result {
+            Ok(decoded_block) => decoded_block,
+            Err(error) => {
+                active_workers.fetch_sub(1, Ordering::Release);
+                let error = error_with_base_position(error, position.0, position.1);
+                set_error(error, &error_store, &shutdown_flag);
+                // Wake up the reader, so that it picks up the stored error.
+                let _ = result_tx.send((seq, (Vec::new(), None)));
+                return;
+            }
+        };
+
+        if result_tx.send((seq, result)).is_err() {
+            active_workers.fetch_sub(1, Ordering::Release);
+            return;
+        }
+
+        active_workers.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<R: Read + Seek> Read for XZReaderMT<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let bytes_read = self.current_chunk.read(buf)?;
+
+        if bytes_read > 0 {
+            return Ok(bytes_read);
+        }
+
+        if let Some(check) = self.current_check.take() {
+            self.block_checks.push(check);
+        }
+
+        let chunk_data = self.get_next_uncompressed_chunk()?;
+
+        let Some((chunk_data, check)) = chunk_data else {
+            // This is the clean end of the stream.
+            return Ok(0);
+        };
+
+        self.current_chunk = Cursor::new(chunk_data);
+        self.current_check = Some(check);
+
+        // Recursive call to read the new chunk data.
+        self.read(buf)
+    }
+}
+
+impl<R: Read + Seek> Drop for XZReaderMT<R> {
+    fn drop(&mut self) {
+        self.shutdown_flag.store(true, Ordering::Release);
+        self.work_queue.close();
+        // Worker threads will exit when the work queue is closed.
+        // JoinHandles will be dropped, which is fine since we set the shutdown flag.
+    }
+}
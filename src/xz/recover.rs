@@ -0,0 +1,462 @@
+use std::io::{self, ErrorKind, Seek, SeekFrom, Write};
+
+use super::{
+    reader::FilterChain, scan_streams, BlockHeader, CheckType, ChecksumCalculator, Index,
+    StreamFooter, StreamHeader, XZBlock, XZ_MAGIC,
+};
+use crate::{error_invalid_data, Read};
+
+/// The size of the window that is searched at once for the next block or stream header.
+const SEARCH_WINDOW_SIZE: usize = 64 * 1024;
+
+/// The maximum size of a block header.
+const MAX_BLOCK_HEADER_SIZE: usize = 1024;
+
+/// The reason why a part of an XZ file couldn't be recovered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XZDamage {
+    /// The stream header is missing or its CRC32 doesn't match.
+    StreamHeader,
+    /// The block header is corrupt or its CRC32 doesn't match.
+    BlockHeader,
+    /// The LZMA2 data or the data of a pre-filter is corrupt.
+    CorruptData,
+    /// The sizes of the block don't match its index record or its block header.
+    SizeMismatch,
+    /// The block padding contains non-zero bytes.
+    Padding,
+    /// The check (CRC32, CRC64 or SHA-256) of the block doesn't match the decoded data.
+    Checksum,
+}
+
+/// A part of an XZ file that couldn't be recovered.
+#[derive(Debug, Clone)]
+pub struct XZLostRange {
+    /// The offset of the skipped data in the file.
+    pub compressed_offset: u64,
+    /// The size of the skipped data in the file.
+    pub compressed_size: u64,
+    /// The offset of the lost data in the uncompressed data. `None` if the size of an earlier
+    /// lost range is unknown.
+    pub uncompressed_offset: Option<u64>,
+    /// The size of the lost uncompressed data. `None` if the index couldn't be used.
+    pub uncompressed_size: Option<u64>,
+    /// The reason why the data couldn't be recovered.
+    pub damage: XZDamage,
+}
+
+/// Summary of [`recover`].
+#[derive(Debug, Clone)]
+pub struct XZRecovery {
+    /// Whether the blocks were located with the indexes of the streams. If an index or a stream
+    /// footer is damaged, the file is scanned for block headers instead.
+    pub used_index: bool,
+    /// The count of blocks that were recovered.
+    pub recovered_blocks: u64,
+    /// The count of uncompressed bytes that were written.
+    pub recovered_size: u64,
+    /// All ranges that couldn't be recovered, in file order.
+    pub lost: Vec<XZLostRange>,
+}
+
+/// A block that passed all checks.
+struct DecodedBlock {
+    total_size: u64,
+    uncompressed_size: u64,
+}
+
+enum BlockError {
+    Io(io::Error),
+    Damage(XZDamage),
+}
+
+trait OrDamage<T> {
+    /// Maps errors caused by corrupt data to the given damage and keeps all other errors.
+    fn or_damage(self, damage: XZDamage) -> Result<T, BlockError>;
+}
+
+impl<T> OrDamage<T> for io::Result<T> {
+    fn or_damage(self, damage: XZDamage) -> Result<T, BlockError> {
+        self.map_err(|error| {
+            if is_damage(&error) {
+                BlockError::Damage(damage)
+            } else {
+                BlockError::Io(error)
+            }
+        })
+    }
+}
+
+/// Decodes all intact blocks of an XZ file and writes their data to `writer`, skipping damaged
+/// blocks.
+///
+/// The blocks are located with the indexes of the streams, so that the uncompressed ranges of
+/// damaged blocks are known. If an index can't be read, the file is scanned for stream and block
+/// headers instead. Every block is fully verified before its data is written, so `writer` only
+/// receives data of intact blocks. Blocks of up to 64 MiB whose size is given by the index are
+/// buffered for this, all other intact blocks are decoded twice.
+///
+/// Returns an error if reading or writing fails, or if no stream header was found at all.
+pub fn recover<R: Read + Seek, W: Write>(mut reader: R, mut writer: W) -> io::Result<XZRecovery> {
+    let mut recovery = XZRecovery {
+        used_index: true,
+        recovered_blocks: 0,
+        recovered_size: 0,
+        lost: Vec::new(),
+    };
+
+    match scan_streams(&mut reader) {
+        Ok(streams) => {
+            let mut uncompressed_offset = 0;
+
+            for block in streams.iter().flat_map(|stream| stream.blocks.iter()) {
+                match recover_block(
+                    &mut reader,
+                    block.start_pos,
+                    block.check_type,
+                    Some(block),
+                    &mut writer,
+                )? {
+                    Ok(decoded) => {
+                        recovery.recovered_blocks += 1;
+                        recovery.recovered_size += decoded.uncompressed_size;
+                    }
+                    Err(damage) => recovery.lost.push(XZLostRange {
+                        compressed_offset: block.start_pos,
+                        compressed_size: block.total_size(),
+                        uncompressed_offset: Some(uncompressed_offset),
+                        uncompressed_size: Some(block.uncompressed_size),
+                        damage,
+                    }),
+                }
+
+                uncompressed_offset += block.uncompressed_size;
+            }
+        }
+        Err(error)
+            if matches!(
+                error.kind(),
+                ErrorKind::InvalidData | ErrorKind::UnexpectedEof
+            ) =>
+        {
+            recovery.used_index = false;
+            recover_without_index(&mut reader, &mut writer, &mut recovery)?;
+        }
+        Err(error) => return Err(error),
+    }
+
+    writer.flush()?;
+
+    Ok(recovery)
+}
+
+/// Walks forward through the file, decoding every block header that is found.
+fn recover_without_index<R: Read + Seek, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    recovery: &mut XZRecovery,
+) -> io::Result<()> {
+    let file_size = reader.seek(SeekFrom::End(0))?;
+
+    let mut pos = 0;
+    let mut check_type = None;
+    let mut uncompressed_offset = Some(0);
+    let mut lost: Option<(u64, XZDamage)> = None;
+    let mut found_stream = false;
+
+    while pos < file_size {
+        reader.seek(SeekFrom::Start(pos))?;
+
+        if let Some(stream_check_type) = read_stream_header(reader)? {
+            close_lost_range(&mut lost, pos, &mut uncompressed_offset, recovery);
+            found_stream = true;
+            check_type = Some(stream_check_type);
+            pos += 12;
+            continue;
+        }
+
+        let Some(stream_check_type) = check_type else {
+            reader.seek(SeekFrom::Start(pos))?;
+            let mut padding = [0u8; 4];
+            if reader.read_exact(&mut padding).is_ok() && padding == [0; 4] {
+                pos += 4;
+                continue;
+            }
+
+            if lost.is_none() {
+                lost = Some((pos, XZDamage::StreamHeader));
+            }
+            pos = find_header(reader, pos + 4, file_size, false)?;
+            continue;
+        };
+
+        reader.seek(SeekFrom::Start(pos))?;
+        let mut indicator = [0u8; 1];
+        reader.read_exact(&mut indicator)?;
+
+        if indicator[0] == 0x00 {
+            // End of the blocks of the stream. The blocks were already recovered, so a damaged
+            // index or stream footer only means that the next stream header has to be searched.
+            check_type = None;
+            let index_end = Index::parse(reader)
+                .and_then(|_| StreamFooter::parse(reader))
+                .and_then(|_| reader.stream_position());
+            pos = match index_end {
+                Ok(index_end) => index_end,
+                Err(error) if is_damage(&error) => find_header(reader, pos + 4, file_size, false)?,
+                Err(error) => return Err(error),
+            };
+            continue;
+        }
+
+        match recover_block(reader, pos, stream_check_type, None, writer)? {
+            Ok(decoded) => {
+                close_lost_range(&mut lost, pos, &mut uncompressed_offset, recovery);
+                recovery.recovered_blocks += 1;
+                recovery.recovered_size += decoded.uncompressed_size;
+                uncompressed_offset =
+                    uncompressed_offset.map(|offset| offset + decoded.uncompressed_size);
+                pos += decoded.total_size;
+            }
+            Err(damage) => {
+                if lost.is_none() {
+                    lost = Some((pos, damage));
+                }
+                pos = find_header(reader, pos + 4, file_size, true)?;
+            }
+        }
+    }
+
+    close_lost_range(&mut lost, file_size, &mut uncompressed_offset, recovery);
+
+    if !found_stream {
+        return Err(error_invalid_data("no XZ stream header found"));
+    }
+
+    Ok(())
+}
+
+fn close_lost_range(
+    lost: &mut Option<(u64, XZDamage)>,
+    end_pos: u64,
+    uncompressed_offset: &mut Option<u64>,
+    recovery: &mut XZRecovery,
+) {
+    if let Some((start_pos, damage)) = lost.take() {
+        recovery.lost.push(XZLostRange {
+            compressed_offset: start_pos,
+            compressed_size: end_pos - start_pos,
+            uncompressed_offset: *uncompressed_offset,
+            uncompressed_size: None,
+            damage,
+        });
+        *uncompressed_offset = None;
+    }
+}
+
+/// Returns whether the error was caused by corrupt data. A damaged header can also look like an
+/// unsupported filter.
+fn is_damage(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::InvalidData
+            | ErrorKind::InvalidInput
+            | ErrorKind::Unsupported
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+/// Reads a stream header at the current position. Returns `None` if there is none.
+fn read_stream_header<R: Read>(reader: &mut R) -> io::Result<Option<CheckType>> {
+    match StreamHeader::parse(reader) {
+        Ok(header) => Ok(Some(header.check_type)),
+        Err(error) if is_damage(&error) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Searches the next position that is aligned to four bytes and contains a stream header, or a
+/// block header if `in_stream` is set. Returns `file_size` if nothing was found.
+fn find_header<R: Read + Seek>(
+    reader: &mut R,
+    from: u64,
+    file_size: u64,
+    in_stream: bool,
+) -> io::Result<u64> {
+    let mut window_start = from.next_multiple_of(4);
+    let mut window = vec![0u8; SEARCH_WINDOW_SIZE + MAX_BLOCK_HEADER_SIZE];
+
+    while window_start < file_size {
+        let window_size = (file_size - window_start).min(window.len() as u64) as usize;
+        reader.seek(SeekFrom::Start(window_start))?;
+        reader.read_exact(&mut window[..window_size])?;
+        let window = &window[..window_size];
+
+        let search_size = if window_start + window_size as u64 == file_size {
+            window_size
+        } else {
+            SEARCH_WINDOW_SIZE
+        };
+
+        for offset in (0..search_size).step_by(4) {
+            let candidate = &window[offset..];
+
+            if candidate.starts_with(&XZ_MAGIC)
+                && read_stream_header(&mut &candidate[..])?.is_some()
+            {
+                return Ok(window_start + offset as u64);
+            }
+
+            if in_stream && is_block_header(candidate) {
+                return Ok(window_start + offset as u64);
+            }
+        }
+
+        window_start += search_size as u64;
+    }
+
+    Ok(file_size)
+}
+
+fn is_block_header(candidate: &[u8]) -> bool {
+    if candidate.len() < 2 || candidate[0] == 0x00 || candidate[1] & 0x3C != 0 {
+        return false;
+    }
+
+    let header_size = (candidate[0] as usize + 1) * 4;
+    let Some(header) = candidate.get(..header_size) else {
+        return false;
+    };
+
+    // Every block header ends with the LZMA2 filter. Looking for its filter ID and properties
+    // size first avoids parsing most candidates.
+    if !header.windows(2).any(|bytes| bytes == [0x21, 0x01]) {
+        return false;
+    }
+
+    matches!(BlockHeader::parse(&mut &header[..]), Ok(Some(_)))
+}
+
+/// Verifies the block at `start_pos` and writes its data to `writer` if it is intact.
+///
+/// If the index provides an uncompressed size of at most 64 MiB, the block is decoded once into a
+/// buffer. Otherwise it's decoded twice, first to verify it and then to write it, so that a big
+/// block or a corrupt size in the index never ends up in memory as a whole.
+fn recover_block<R: Read + Seek, W: Write>(
+    reader: &mut R,
+    start_pos: u64,
+    check_type: CheckType,
+    index_record: Option<&XZBlock>,
+    writer: &mut W,
+) -> io::Result<Result<DecodedBlock, XZDamage>> {
+    reader.seek(SeekFrom::Start(start_pos))?;
+
+    if let Some(size) = index_record
+        .map(|block| block.uncompressed_size)
+        .filter(|&size| size <= 64 * 1024 * 1024)
+    {
+        let mut buffer = Vec::with_capacity(size as usize);
+        return match decode_block(reader, check_type, index_record, &mut buffer) {
+            Ok(decoded) => {
+                writer.write_all(&buffer)?;
+                Ok(Ok(decoded))
+            }
+            Err(BlockError::Io(error)) => Err(error),
+            Err(BlockError::Damage(damage)) => Ok(Err(damage)),
+        };
+    }
+
+    if let Err(error) = decode_block(reader, check_type, index_record, &mut io::sink()) {
+        return match error {
+            BlockError::Io(error) => Err(error),
+            BlockError::Damage(damage) => Ok(Err(damage)),
+        };
+    }
+
+    reader.seek(SeekFrom::Start(start_pos))?;
+    match decode_block(reader, check_type, index_record, writer) {
+        Ok(decoded) => Ok(Ok(decoded)),
+        Err(BlockError::Io(error)) => Err(error),
+        Err(BlockError::Damage(_)) => Err(error_invalid_data(
+            "XZ block changed while it was recovered",
+        )),
+    }
+}
+
+/// Decodes the block at the current position of `reader` and writes its data to `writer`.
+fn decode_block<R: Read, W: Write>(
+    reader: &mut R,
+    check_type: CheckType,
+    index_record: Option<&XZBlock>,
+    writer: &mut W,
+) -> Result<DecodedBlock, BlockError> {
+    let block_header = BlockHeader::parse(reader)
+        .or_damage(XZDamage::BlockHeader)?
+        .ok_or(BlockError::Damage(XZDamage::BlockHeader))?;
+    let mut filter_chain = FilterChain::new(&block_header).or_damage(XZDamage::BlockHeader)?;
+
+    let size_limit = index_record
+        .map(|block| block.uncompressed_size)
+        .unwrap_or(u64::MAX)
+        .min(block_header.uncompressed_size.unwrap_or(u64::MAX));
+
+    let mut checksum_calculator = ChecksumCalculator::new(check_type);
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut uncompressed_size = 0u64;
+
+    loop {
+        let bytes_read = filter_chain
+            .read(reader, &mut buf)
+            .or_damage(XZDamage::CorruptData)?;
+        if bytes_read == 0 {
+            break;
+        }
+
+        uncompressed_size += bytes_read as u64;
+        if uncompressed_size > size_limit {
+            return Err(BlockError::Damage(XZDamage::SizeMismatch));
+        }
+
+        checksum_calculator.update(&buf[..bytes_read]);
+        writer
+            .write_all(&buf[..bytes_read])
+            .map_err(BlockError::Io)?;
+    }
+
+    let compressed_size = filter_chain.compressed_bytes_read();
+    block_header
+        .verify_sizes(compressed_size, uncompressed_size)
+        .or_damage(XZDamage::SizeMismatch)?;
+
+    let check_size = check_type.checksum_size();
+    let data_size = block_header.header_size + compressed_size;
+    let unpadded_size = data_size + check_size as u64;
+
+    if let Some(block) = index_record {
+        if block.unpadded_size != unpadded_size || block.uncompressed_size != uncompressed_size {
+            return Err(BlockError::Damage(XZDamage::SizeMismatch));
+        }
+    }
+
+    let padding_size = ((4 - data_size % 4) % 4) as usize;
+    let mut padding = [0u8; 3];
+    reader
+        .read_exact(&mut padding[..padding_size])
+        .or_damage(XZDamage::Padding)?;
+    if padding != [0; 3] {
+        return Err(BlockError::Damage(XZDamage::Padding));
+    }
+
+    let mut checksum = [0u8; 32];
+    reader
+        .read_exact(&mut checksum[..check_size])
+        .or_damage(XZDamage::Checksum)?;
+    checksum_calculator
+        .verify(&checksum[..check_size])
+        .or_damage(XZDamage::Checksum)?;
+
+    Ok(DecodedBlock {
+        total_size: unpadded_size.div_ceil(4) * 4,
+        uncompressed_size,
+    })
+}
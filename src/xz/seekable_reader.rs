@@ -0,0 +1,296 @@
+use std::io::{self, Seek, SeekFrom};
+
+use super::{reader::FilterChain, scan_streams, BlockHeader, ChecksumCalculator, XZBlock};
+use crate::{error_invalid_data, error_invalid_input, error_mem_limit, error_with_position, Read};
+
+/// A single-threaded XZ decompressor that supports random access.
+///
+/// The reader parses the stream footer and the index of every stream first. Seeking to an
+/// uncompressed position then only needs to decode the block that contains the position,
+/// starting from the beginning of that block. Files written with a single block (the default of
+/// [`crate::XZWriter`]) need to be decoded from the start for every backwards seek.
+pub struct XZSeekableReader<R> {
+    inner: R,
+    filter_chain: Option<FilterChain>,
+    block_header: Option<BlockHeader>,
+    blocks: Vec<XZBlock>,
+    block_starts: Vec<u64>,
+    uncompressed_size: u64,
+    current_block: usize,
+    block_pos: u64,
+    position: u64,
+    checksum_calculator: Option<ChecksumCalculator>,
+    mem_limit_kb: u32,
+}
+
+impl<R> XZSeekableReader<R> {
+    /// Consume the XZSeekableReader and return the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read + Seek> XZSeekableReader<R> {
+    /// Creates a new seekable XZ reader.
+    ///
+    /// - `inner`: The reader to read compressed data from.
+    /// - `allow_multiple_streams`: Whether to decode concatenated streams. If false, only the
+    ///   first stream is decoded.
+    pub fn new(inner: R, allow_multiple_streams: bool) -> io::Result<Self> {
+        Self::new_mem_limit(inner, allow_multiple_streams, u32::MAX)
+    }
+
+    /// Creates a new seekable XZ reader with a memory usage limit.
+    ///
+    /// - `inner`: The reader to read compressed data from.
+    /// - `allow_multiple_streams`: Whether to decode concatenated streams. If false, only the
+    ///   first stream is decoded.
+    /// - `mem_limit_kb`: The memory usage limit in kibibytes (KiB). `u32::MAX` means no limit.
+    ///   Every block is checked against the limit before its filter chain is created.
+    pub fn new_mem_limit(
+        mut inner: R,
+        allow_multiple_streams: bool,
+        mem_limit_kb: u32,
+    ) -> io::Result<Self> {
+        let mut streams = scan_streams(&mut inner)?;
+
+        if !allow_multiple_streams {
+            streams.truncate(1);
+        }
+
+        let blocks: Vec<XZBlock> = streams
+            .into_iter()
+            .flat_map(|stream| stream.blocks)
+            .collect();
+
+        let mut block_starts = Vec::with_capacity(blocks.len());
+        let mut uncompressed_size = 0u64;
+
+        for block in &blocks {
+            block_starts.push(uncompressed_size);
+            uncompressed_size = uncompressed_size
+                .checked_add(block.uncompressed_size)
+                .ok_or_else(|| error_invalid_data("XZ uncompressed size overflow"))?;
+        }
+
+        Ok(Self {
+            inner,
+            filter_chain: None,
+            block_header: None,
+            blocks,
+            block_starts,
+            uncompressed_size,
+            current_block: 0,
+            block_pos: 0,
+            position: 0,
+            checksum_calculator: None,
+            mem_limit_kb,
+        })
+    }
+
+    /// Get the count of XZ blocks found in the file.
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Returns the total uncompressed size, as stored in the index.
+    pub fn uncompressed_size(&self) -> u64 {
+        self.uncompressed_size
+    }
+
+    /// Seeks the compressed stream to the start of the block and prepares its filter chain.
+    fn start_block(&mut self, block_index: usize) -> io::Result<()> {
+        self.filter_chain = None;
+
+        let block = &self.blocks[block_index];
+
+        self.inner.seek(SeekFrom::Start(block.start_pos))?;
+        let block_header = BlockHeader::parse(&mut self.inner)?
+            .ok_or_else(|| error_invalid_data("expected XZ block header but found index"))?;
+
+        // The declared sizes must match the index, so we can stop early if they don't.
+        let check_size = block.check_type.checksum_size() as u64;
+        let compressed_size = block
+            .unpadded_size
+            .checked_sub(block_header.header_size + check_size)
+            .ok_or_else(|| error_invalid_data("XZ block size doesn't match index"))?;
+        block_header.verify_sizes(compressed_size, block.uncompressed_size)?;
+
+        if block_header.memory_usage() > self.mem_limit_kb {
+            return Err(error_mem_limit(
+                block_header.memory_usage(),
+                self.mem_limit_kb,
+            ));
+        }
+
+        self.filter_chain = Some(FilterChain::new(&block_header)?);
+        self.block_header = Some(block_header);
+        self.checksum_calculator = Some(ChecksumCalculator::new(block.check_type));
+        self.current_block = block_index;
+        self.block_pos = 0;
+
+        Ok(())
+    }
+
+    /// Reads decompressed data of the current block and updates the checksum.
+    fn read_block(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let block = &self.blocks[self.current_block];
+        let remaining = block.uncompressed_size - self.block_pos;
+        let read_size = (buf.len() as u64).min(remaining) as usize;
+
+        let filter_chain = self.filter_chain.as_mut().expect("block reader not set");
+        let bytes_read = filter_chain.read(&mut self.inner, &mut buf[..read_size])?;
+
+        if bytes_read == 0 && read_size > 0 {
+            return Err(error_invalid_data(
+                "XZ block is smaller than stated in index",
+            ));
+        }
+
+        if let Some(calc) = self.checksum_calculator.as_mut() {
+            calc.update(&buf[..bytes_read]);
+        }
+
+        self.block_pos += bytes_read as u64;
+
+        if self.block_pos == block.uncompressed_size {
+            self.finish_block()?;
+        }
+
+        Ok(bytes_read)
+    }
+
+    /// Makes sure the block ends where the index says and verifies its checksum.
+    fn finish_block(&mut self) -> io::Result<()> {
+        let mut filter_chain = self.filter_chain.take().expect("block reader not set");
+
+        let mut probe = [0u8; 1];
+        if filter_chain.read(&mut self.inner, &mut probe)? != 0 {
+            return Err(error_invalid_data(
+                "XZ block is bigger than stated in index",
+            ));
+        }
+
+        let compressed_size = filter_chain.compressed_bytes_read();
+
+        let block = &self.blocks[self.current_block];
+        let calculator = self
+            .checksum_calculator
+            .take()
+            .expect("checksum_calculator not set");
+
+        let check_size = block.check_type.checksum_size();
+
+        let block_header = self.block_header.take().expect("block_header not set");
+        block_header.verify_sizes(compressed_size, block.uncompressed_size)?;
+
+        let unpadded_size = block_header.header_size + compressed_size + check_size as u64;
+        if unpadded_size != block.unpadded_size {
+            return Err(error_invalid_data("XZ block size doesn't match index"));
+        }
+
+        let data_size = block_header.header_size + compressed_size;
+        let padding_size = ((4 - data_size % 4) % 4) as usize;
+
+        self.inner
+            .seek(SeekFrom::Start(block.start_pos + data_size))?;
+
+        let mut padding = [0u8; 3];
+        self.inner.read_exact(&mut padding[..padding_size])?;
+        if padding != [0; 3] {
+            return Err(error_invalid_data("invalid XZ block padding"));
+        }
+
+        let mut checksum = [0u8; 32];
+        self.inner.read_exact(&mut checksum[..check_size])?;
+
+        calculator.verify(&checksum[..check_size])?;
+        Ok(())
+    }
+
+    /// Moves the decoder to the current position, decoding from the start of the block if needed.
+    fn seek_decoder(&mut self) -> io::Result<()> {
+        let block_index = self
+            .block_starts
+            .partition_point(|&start| start <= self.position)
+            - 1;
+        let offset_in_block = self.position - self.block_starts[block_index];
+
+        let can_continue = self.filter_chain.is_some()
+            && self.current_block == block_index
+            && self.block_pos <= offset_in_block;
+
+        if !can_continue {
+            self.start_block(block_index)?;
+        }
+
+        let mut buf = [0u8; 4096];
+
+        while self.block_pos < offset_in_block {
+            let skip = ((offset_in_block - self.block_pos) as usize).min(buf.len());
+            self.read_block(&mut buf[..skip])?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds the position of the decoder in the current block to a corrupt data error.
+    fn error_with_position(&self, error: io::Error) -> io::Error {
+        let block = &self.blocks[self.current_block];
+        let compressed_offset = match (self.filter_chain.as_ref(), self.block_header.as_ref()) {
+            (Some(filter_chain), Some(block_header)) => {
+                block.start_pos + block_header.header_size + filter_chain.compressed_bytes_read()
+            }
+            _ => block.start_pos,
+        };
+        let uncompressed_offset = self.block_starts[self.current_block] + self.block_pos;
+
+        error_with_position(error, Some(compressed_offset), Some(uncompressed_offset))
+    }
+}
+
+impl<R: Read + Seek> Read for XZSeekableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.uncompressed_size {
+            return Ok(0);
+        }
+
+        let bytes_read = self
+            .read_at_position(buf)
+            .map_err(|error| self.error_with_position(error))?;
+        self.position += bytes_read as u64;
+
+        Ok(bytes_read)
+    }
+}
+
+impl<R: Read + Seek> XZSeekableReader<R> {
+    fn read_at_position(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let at_position = self.filter_chain.is_some()
+            && self.block_starts[self.current_block] + self.block_pos == self.position;
+
+        if !at_position {
+            self.seek_decoder()?;
+        }
+
+        self.read_block(buf)
+    }
+}
+
+impl<R: Read + Seek> Seek for XZSeekableReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.uncompressed_size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        let Some(new_position) = new_position else {
+            return Err(error_invalid_input("invalid seek to a negative position"));
+        };
+
+        self.position = new_position;
+
+        Ok(self.position)
+    }
+}
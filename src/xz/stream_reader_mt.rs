@@ -0,0 +1,618 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Cursor},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use super::{
+    reader::{parse_index_and_footer, parse_next_stream_header, FilterChain},
+    reader_mt::{worker_thread_logic, DecodedBlock, ResultUnit, WorkUnit},
+    BlockHeader, CheckType, ChecksumCalculator, IndexRecord, StreamHeader,
+};
+use crate::{
+    error_eof, error_invalid_data, error_mem_limit, error_with_position, set_error,
+    work_queue::WorkStealingQueue, workers_for_mem_limit, CheckValue, CountingReader, Read,
+};
+
+enum State {
+    /// Reading blocks from the inner reader and dispatching them to worker threads.
+    Reading,
+    /// All blocks dispatched, waiting for workers to complete.
+    Draining,
+    /// All data has been decompressed and returned. The stream is exhausted.
+    Finished,
+    /// A fatal error occurred in either the reader or a worker thread.
+    Error,
+}
+
+/// The next data to return.
+enum Chunk {
+    /// A block that was decoded by a worker.
+    Decoded(DecodedBlock),
+    /// The block that is decoded directly from the inner reader.
+    Direct,
+}
+
+/// A block without sizes in its header, or one that doesn't fit into the memory limit together
+/// with its buffers. The end of a block without sizes is only known after decoding it, so both
+/// are decoded directly from the inner reader.
+struct DirectBlock {
+    sequence: u64,
+    block_header: BlockHeader,
+    filter_chain: FilterChain,
+    checksum_calculator: ChecksumCalculator,
+    uncompressed_offset: u64,
+    uncompressed_size: u64,
+}
+
+/// A multi-threaded XZ decompressor that doesn't need to seek.
+///
+/// [`crate::XZReaderMT`] locates the blocks with the index at the end of every stream, which
+/// needs Seek. This reader instead reads the blocks front to back, like the threaded decoder of
+/// xz-utils: blocks that store their compressed and uncompressed size in the block header are
+/// read as a whole and decoded in parallel, while the next blocks are still being read. Blocks
+/// without these sizes are decoded by a single thread directly from the inner reader, once all
+/// blocks in front of them were returned. The index of every stream is verified against the
+/// decoded blocks.
+///
+/// [`crate::XZWriterMT`] stores both sizes in the block headers. [`crate::XZWriter`] streams its
+/// blocks and doesn't know their sizes in advance, so its files are decoded by a single thread.
+///
+/// With [`XZStreamReaderMT::new_mem_limit`], blocks that don't fit into the memory limit are
+/// decoded by a single thread as well, like the `memlimit_threading` option of xz-utils.
+pub struct XZStreamReaderMT<R: Read> {
+    inner: CountingReader<R>,
+    allow_multiple_streams: bool,
+    check_type: Option<CheckType>,
+    decoded_records: Vec<IndexRecord>,
+    stream_count: u64,
+    mem_limit_kb: u32,
+    worker_mem_kb: u32,
+    direct_block: Option<DirectBlock>,
+    result_rx: Receiver<ResultUnit>,
+    result_tx: Sender<ResultUnit>,
+    next_sequence_to_dispatch: u64,
+    uncompressed_dispatched: u64,
+    next_sequence_to_return: u64,
+    last_sequence_id: Option<u64>,
+    out_of_order_chunks: BTreeMap<u64, DecodedBlock>,
+    current_chunk: Cursor<Vec<u8>>,
+    current_check: Option<Option<CheckValue>>,
+    block_checks: Vec<Option<CheckValue>>,
+    shutdown_flag: Arc<AtomicBool>,
+    error_store: Arc<Mutex<Option<io::Error>>>,
+    state: State,
+    work_queue: WorkStealingQueue<WorkUnit>,
+    active_workers: Arc<AtomicU32>,
+    max_workers: u32,
+    worker_handles: Vec<thread::JoinHandle<()>>,
+}
+
+impl<R: Read> XZStreamReaderMT<R> {
+    /// Creates a new multi-threaded XZ reader that reads the compressed data front to back.
+    ///
+    /// - `inner`: The reader to read compressed data from.
+    /// - `allow_multiple_streams`: Whether to decode concatenated streams. If false, only the
+    ///   first stream is decoded.
+    /// - `num_workers`: The maximum number of worker threads for decompression. Currently capped at 256 threads.
+    pub fn new(inner: R, allow_multiple_streams: bool, num_workers: u32) -> Self {
+        Self::new_mem_limit(inner, allow_multiple_streams, num_workers, u32::MAX)
+    }
+
+    /// Creates a new multi-threaded XZ reader that reads the compressed data front to back with
+    /// a memory usage limit.
+    ///
+    /// Every worker needs the memory of the filter chain and holds a whole block and its
+    /// decompressed data in memory. The count of workers is reduced until all workers together
+    /// stay inside the limit for the biggest block that was read so far. A block that doesn't fit
+    /// into the limit on its own is decoded directly from the inner reader, which only needs the
+    /// memory of the filter chain.
+    ///
+    /// - `inner`: The reader to read compressed data from.
+    /// - `allow_multiple_streams`: Whether to decode concatenated streams. If false, only the
+    ///   first stream is decoded.
+    /// - `num_workers`: The maximum number of worker threads for decompression. Currently capped at 256 threads.
+    /// - `mem_limit_kb`: The memory usage limit in kibibytes (KiB). `u32::MAX` means no limit.
+    pub fn new_mem_limit(
+        inner: R,
+        allow_multiple_streams: bool,
+        num_workers: u32,
+        mem_limit_kb: u32,
+    ) -> Self {
+        let max_workers = num_workers.clamp(1, 256);
+
+        let work_queue = WorkStealingQueue::new();
+        let (result_tx, result_rx) = mpsc::channel::<ResultUnit>();
+        let shutdown_flag = Arc::new(AtomicBool::new(false));
+        let error_store = Arc::new(Mutex::new(None));
+        let active_workers = Arc::new(AtomicU32::new(0));
+
+        Self {
+            inner: CountingReader::new(inner),
+            allow_multiple_streams,
+            check_type: None,
+            decoded_records: Vec::new(),
+            stream_count: 0,
+            mem_limit_kb,
+            worker_mem_kb: 0,
+            direct_block: None,
+            result_rx,
+            result_tx,
+            next_sequence_to_dispatch: 0,
+            uncompressed_dispatched: 0,
+            next_sequence_to_return: 0,
+            last_sequence_id: None,
+            out_of_order_chunks: BTreeMap::new(),
+            current_chunk: Cursor::new(Vec::new()),
+            current_check: None,
+            block_checks: Vec::new(),
+            shutdown_flag,
+            error_store,
+            state: State::Reading,
+            work_queue,
+            active_workers,
+            max_workers,
+            worker_handles: Vec::new(),
+        }
+    }
+
+    /// Get the count of XZ blocks that were found so far.
+    pub fn block_count(&self) -> usize {
+        self.next_sequence_to_dispatch as usize
+    }
+
+    /// Returns the verified checks of all blocks whose data was read completely, in file order.
+    /// Blocks of streams with the check type [`CheckType::None`] have no check value.
+    pub fn block_checks(&self) -> &[Option<CheckValue>] {
+        &self.block_checks
+    }
+
+    fn spawn_worker_thread(&mut self) {
+        let worker_handle = self.work_queue.worker();
+        let result_tx = self.result_tx.clone();
+        let shutdown_flag = Arc::clone(&self.shutdown_flag);
+        let error_store = Arc::clone(&self.error_store);
+        let active_workers = Arc::clone(&self.active_workers);
+
+        let handle = thread::spawn(move || {
+            worker_thread_logic(
+                worker_handle,
+                result_tx,
+                shutdown_flag,
+                error_store,
+                active_workers,
+                false,
+            );
+        });
+
+        self.worker_handles.push(handle);
+    }
+
+    /// Parses the headers in front of the next block, including the index and stream footer of
+    /// finished streams. Returns the check type of the current stream, or `None` after the last
+    /// stream.
+    fn start_next_block(&mut self) -> io::Result<Option<(CheckType, u8)>> {
+        loop {
+            let check_type = match self.check_type {
+                Some(check_type) => check_type,
+                None => {
+                    let stream_header = if self.stream_count == 0 {
+                        Some(StreamHeader::parse(&mut self.inner)?)
+                    } else if self.allow_multiple_streams {
+                        parse_next_stream_header(&mut self.inner)?
+                    } else {
+                        None
+                    };
+
+                    let Some(stream_header) = stream_header else {
+                        return Ok(None);
+                    };
+
+                    self.stream_count += 1;
+                    self.decoded_records.clear();
+                    self.check_type = Some(stream_header.check_type);
+                    stream_header.check_type
+                }
+            };
+
+            let mut header_size_encoded = [0u8; 1];
+            self.inner.read_exact(&mut header_size_encoded)?;
+
+            if header_size_encoded[0] != 0 {
+                return Ok(Some((check_type, header_size_encoded[0])));
+            }
+
+            // End of the blocks of the stream, the index follows.
+            parse_index_and_footer(&mut self.inner, &self.decoded_records, check_type)?;
+            self.check_type = None;
+        }
+    }
+
+    /// Reads the next block from the inner reader and dispatches it to the workers. A block
+    /// without sizes in its header or over the memory limit becomes the direct block instead.
+    ///
+    /// Returns `Ok(false)` after the last stream.
+    fn dispatch_next_block(&mut self) -> io::Result<bool> {
+        let Some((check_type, header_size_encoded)) = self.start_next_block()? else {
+            return Ok(false);
+        };
+
+        // The header size byte was already read.
+        let start_pos = self.inner.bytes_read() - 1;
+        let header_size = (header_size_encoded as usize + 1) * 4;
+        let mut block_data = vec![0u8; header_size];
+        block_data[0] = header_size_encoded;
+        self.inner.read_exact(&mut block_data[1..])?;
+
+        let block_header =
+            BlockHeader::parse(&mut block_data.as_slice())?.expect("block header size is not zero");
+
+        let block_sizes = match (block_header.compressed_size, block_header.uncompressed_size) {
+            (Some(compressed_size), Some(uncompressed_size)) => {
+                let unpadded_size = block_header
+                    .header_size
+                    .checked_add(compressed_size)
+                    .and_then(|size| size.checked_add(check_type.checksum_size() as u64))
+                    .ok_or_else(|| error_invalid_data("XZ block size overflow"))?;
+                let total_size = unpadded_size.div_ceil(4) * 4;
+
+                let buffer_size = total_size.saturating_add(uncompressed_size).div_ceil(1024);
+                let buffer_size = u32::try_from(buffer_size).unwrap_or(u32::MAX);
+                let block_mem_kb = block_header.memory_usage().saturating_add(buffer_size);
+
+                (block_mem_kb <= self.mem_limit_kb).then_some((
+                    unpadded_size,
+                    total_size,
+                    uncompressed_size,
+                    block_mem_kb,
+                ))
+            }
+            _ => None,
+        };
+
+        let Some((unpadded_size, total_size, uncompressed_size, block_mem_kb)) = block_sizes else {
+            if block_header.memory_usage() > self.mem_limit_kb {
+                return Err(error_mem_limit(
+                    block_header.memory_usage(),
+                    self.mem_limit_kb,
+                ));
+            }
+
+            let filter_chain = FilterChain::new(&block_header)?;
+            self.direct_block = Some(DirectBlock {
+                sequence: self.next_sequence_to_dispatch,
+                block_header,
+                filter_chain,
+                checksum_calculator: ChecksumCalculator::new(check_type),
+                uncompressed_offset: self.uncompressed_dispatched,
+                uncompressed_size: 0,
+            });
+            self.next_sequence_to_dispatch += 1;
+            return Ok(true);
+        };
+
+        // The block is read up to its declared size, so that a corrupt size doesn't allocate
+        // more memory than the data that is actually there.
+        (&mut self.inner)
+            .take(total_size - header_size as u64)
+            .read_to_end(&mut block_data)?;
+        if (block_data.len() as u64) < total_size {
+            return Err(error_eof());
+        }
+
+        let index_record = IndexRecord {
+            unpadded_size,
+            uncompressed_size,
+        };
+        self.decoded_records.push(index_record.clone());
+        self.worker_mem_kb = self.worker_mem_kb.max(block_mem_kb);
+
+        let position = (start_pos, self.uncompressed_dispatched);
+
+        if !self.work_queue.push((
+            self.next_sequence_to_dispatch,
+            block_data,
+            check_type,
+            index_record,
+            position,
+        )) {
+            // Queue is closed, this indicates shutdown.
+            self.state = State::Error;
+            set_error(
+                io::Error::new(io::ErrorKind::BrokenPipe, "Worker threads have shut down"),
+                &self.error_store,
+                &self.shutdown_flag,
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Worker threads have shut down",
+            ));
+        }
+
+        // We spawn a new thread if we have work queued, no available workers, and haven't reached
+        // the maximal allowed parallelism yet.
+        let max_workers =
+            workers_for_mem_limit(self.max_workers, self.worker_mem_kb, self.mem_limit_kb)?;
+        let spawned_workers = self.worker_handles.len() as u32;
+        let active_workers = self.active_workers.load(Ordering::Acquire);
+        let queue_len = self.work_queue.len();
+
+        if queue_len > 0 && active_workers == spawned_workers && spawned_workers < max_workers {
+            self.spawn_worker_thread();
+        }
+
+        self.next_sequence_to_dispatch += 1;
+        self.uncompressed_dispatched += uncompressed_size;
+        Ok(true)
+    }
+
+    /// Decodes the next data of the direct block. Returns 0 after the block was finished.
+    fn read_direct_block(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let direct_block = self.direct_block.as_mut().expect("direct block not set");
+        let bytes_read = direct_block.filter_chain.read(&mut self.inner, buf)?;
+
+        if bytes_read > 0 {
+            direct_block.checksum_calculator.update(&buf[..bytes_read]);
+            direct_block.uncompressed_size += bytes_read as u64;
+
+            if direct_block
+                .block_header
+                .uncompressed_size
+                .is_some_and(|size| direct_block.uncompressed_size > size)
+            {
+                return Err(error_invalid_data(
+                    "XZ block is bigger than its declared uncompressed size",
+                ));
+            }
+
+            return Ok(bytes_read);
+        }
+
+        self.finish_direct_block()?;
+        Ok(0)
+    }
+
+    /// Verifies the sizes, the padding and the check of the direct block after its data was
+    /// decoded.
+    fn finish_direct_block(&mut self) -> io::Result<()> {
+        let check_type = self.check_type.expect("check type not set");
+        let DirectBlock {
+            block_header,
+            filter_chain,
+            checksum_calculator,
+            uncompressed_size,
+            ..
+        } = self.direct_block.take().expect("direct block not set");
+
+        let compressed_size = filter_chain.compressed_bytes_read();
+        block_header.verify_sizes(compressed_size, uncompressed_size)?;
+
+        let data_size = block_header.header_size + compressed_size;
+        let padding_size = ((4 - data_size % 4) % 4) as usize;
+        let mut padding = [0u8; 3];
+        self.inner.read_exact(&mut padding[..padding_size])?;
+        if padding != [0; 3] {
+            return Err(error_invalid_data("invalid XZ block padding"));
+        }
+
+        let check_size = check_type.checksum_size();
+        let mut checksum = [0u8; 32];
+        self.inner.read_exact(&mut checksum[..check_size])?;
+        let check = checksum_calculator.verify(&checksum[..check_size])?;
+
+        self.decoded_records.push(IndexRecord {
+            unpadded_size: data_size + check_size as u64,
+            uncompressed_size,
+        });
+        self.block_checks.push(check);
+        self.next_sequence_to_return += 1;
+        self.uncompressed_dispatched += uncompressed_size;
+
+        Ok(())
+    }
+
+    /// Adds the position of the inner reader to an error of the reading thread.
+    fn error_with_position(&self, error: io::Error) -> io::Error {
+        let uncompressed_offset = self
+            .direct_block
+            .as_ref()
+            .map(|block| block.uncompressed_offset + block.uncompressed_size);
+
+        error_with_position(error, Some(self.inner.bytes_read()), uncompressed_offset)
+    }
+
+    fn get_next_uncompressed_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        loop {
+            // Check for a globally stored error first, since the workers stop processing
+            // the remaining blocks once an error occurred.
+            if let Some(err) = self.error_store.lock().unwrap().take() {
+                self.state = State::Error;
+                return Err(err);
+            }
+
+            // Then check for already-received chunks.
+            if let Some(result) = self
+                .out_of_order_chunks
+                .remove(&self.next_sequence_to_return)
+            {
+                self.next_sequence_to_return += 1;
+                return Ok(Some(Chunk::Decoded(result)));
+            }
+
+            // The direct block is decoded once all blocks in front of it were returned.
+            if self
+                .direct_block
+                .as_ref()
+                .is_some_and(|block| block.sequence == self.next_sequence_to_return)
+                && !matches!(self.state, State::Error)
+            {
+                return Ok(Some(Chunk::Direct));
+            }
+
+            match self.state {
+                State::Reading => {
+                    // First, always try to receive a result without blocking.
+                    // This keeps the pipeline moving and avoids unnecessary blocking on I/O.
+                    match self.result_rx.try_recv() {
+                        Ok((seq, result)) => {
+                            if seq == self.next_sequence_to_return {
+                                self.next_sequence_to_return += 1;
+                                return Ok(Some(Chunk::Decoded(result)));
+                            } else {
+                                self.out_of_order_chunks.insert(seq, result);
+                                continue; // Loop again to check the out_of_order_chunks
+                            }
+                        }
+                        Err(mpsc::TryRecvError::Disconnected) => {
+                            // All workers are done.
+                            self.state = State::Draining;
+                            continue;
+                        }
+                        Err(mpsc::TryRecvError::Empty) => {
+                            // No results are ready. Now, we can consider reading more input.
+                        }
+                    }
+
+                    // If the work queue has capacity, try to read more from the source. The
+                    // inner reader is inside the direct block until it was decoded.
+                    if self.direct_block.is_none() && self.work_queue.len() < 4 {
+                        match self.dispatch_next_block() {
+                            Ok(true) => {
+                                // Successfully read and dispatched a block, loop to continue.
+                                continue;
+                            }
+                            Ok(false) => {
+                                // No more blocks to dispatch.
+                                // Set the last sequence ID and transition to draining.
+                                self.state = match self.next_sequence_to_dispatch {
+                                    // Streams without any blocks are valid and decode to nothing.
+                                    0 => State::Finished,
+                                    next_sequence => {
+                                        self.last_sequence_id = Some(next_sequence - 1);
+                                        State::Draining
+                                    }
+                                };
+                                continue;
+                            }
+                            Err(error) => {
+                                let error = self.error_with_position(error);
+                                set_error(error, &self.error_store, &self.shutdown_flag);
+                                self.state = State::Error;
+                                continue;
+                            }
+                        }
+                    }
+
+                    // Now we MUST wait for a result to make progress.
+                    match self.result_rx.recv() {
+                        Ok((seq, result)) => {
+                            if seq == self.next_sequence_to_return {
+                                self.next_sequence_to_return += 1;
+                                return Ok(Some(Chunk::Decoded(result)));
+                            } else {
+                                self.out_of_order_chunks.insert(seq, result);
+                                // We've made progress, loop to check the out_of_order_chunks
+                                continue;
+                            }
+                        }
+                        Err(_) => {
+                            // All workers are done.
+                            self.state = State::Draining;
+                        }
+                    }
+                }
+                State::Draining => {
+                    if let Some(last_seq) = self.last_sequence_id {
+                        if self.next_sequence_to_return > last_seq {
+                            self.state = State::Finished;
+                            continue;
+                        }
+                    }
+
+                    // In Draining state, we only wait for results.
+                    match self.result_rx.recv() {
+                        Ok((seq, result)) => {
+                            if seq == self.next_sequence_to_return {
+                                self.next_sequence_to_return += 1;
+                                return Ok(Some(Chunk::Decoded(result)));
+                            } else {
+                                self.out_of_order_chunks.insert(seq, result);
+                            }
+                        }
+                        Err(_) => {
+                            // All workers finished, and channel is empty. We are done.
+                            self.state = State::Finished;
+                        }
+                    }
+                }
+                State::Finished => {
+                    return Ok(None);
+                }
+                State::Error => {
+                    // The error was already logged, now we just propagate it.
+                    return Err(self.error_store.lock().unwrap().take().unwrap_or_else(|| {
+                        io::Error::other("decompression failed with an unknown error")
+                    }));
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for XZStreamReaderMT<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let bytes_read = self.current_chunk.read(buf)?;
+
+            if bytes_read > 0 {
+                return Ok(bytes_read);
+            }
+
+            if let Some(check) = self.current_check.take() {
+                self.block_checks.push(check);
+            }
+
+            match self.get_next_uncompressed_chunk()? {
+                None => {
+                    // This is the clean end of the stream.
+                    return Ok(0);
+                }
+                Some(Chunk::Decoded((chunk_data, check))) => {
+                    self.current_chunk = Cursor::new(chunk_data);
+                    self.current_check = Some(check);
+                }
+                Some(Chunk::Direct) => match self.read_direct_block(buf) {
+                    Ok(0) => {
+                        // The direct block is finished, continue with the next block.
+                    }
+                    Ok(bytes_read) => return Ok(bytes_read),
+                    Err(error) => {
+                        let error = self.error_with_position(error);
+                        self.direct_block = None;
+                        self.state = State::Error;
+                        self.shutdown_flag.store(true, Ordering::Release);
+                        return Err(error);
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl<R: Read> Drop for XZStreamReaderMT<R> {
+    fn drop(&mut self) {
+        self.shutdown_flag.store(true, Ordering::Release);
+        self.work_queue.close();
+        // Worker threads will exit when the work queue is closed.
+        // JoinHandles will be dropped, which is fine since we set the shutdown flag.
+    }
+}
@@ -0,0 +1,30 @@
+use alloc::vec::Vec;
+
+use super::XZReader;
+use crate::{CheckValue, Read, Result};
+
+/// Summary of an XZ file that was decoded without producing output, as done by `xz --test`.
+///
+/// Created by [`verify`] and [`XZReaderMT::verify`](crate::XZReaderMT::verify).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XZSummary {
+    /// The count of streams.
+    pub stream_count: u64,
+    /// The count of blocks in all streams.
+    pub block_count: u64,
+    /// The size of all streams including stream padding.
+    pub compressed_size: u64,
+    /// The uncompressed size of all streams.
+    pub uncompressed_size: u64,
+    /// The verified check of every block in file order. Blocks of streams with the check type
+    /// [`CheckType::None`](super::CheckType::None) have no check value.
+    pub checks: Vec<Option<CheckValue>>,
+}
+
+/// Decodes all streams of an XZ file without producing output and returns a summary.
+///
+/// The block checks are calculated straight from the dictionary of the decoder, so no output
+/// buffer is needed unless a block uses BCJ or delta filters.
+pub fn verify<R: Read>(reader: R) -> Result<XZSummary> {
+    XZReader::new(reader, true).verify()
+}
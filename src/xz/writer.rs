@@ -1,103 +1,147 @@
-use alloc::{boxed::Box, rc::Rc, vec::Vec};
-use core::{
-    cell::{Cell, RefCell},
-    num::NonZeroU64,
-};
+use alloc::{boxed::Box, vec::Vec};
+use core::num::NonZeroU64;
 
 use sha2::Digest;
 
 use super::{
-    count_multibyte_integer_size_for_value, encode_multibyte_integer, CheckType,
-    ChecksumCalculator, FilterConfig, FilterType, IndexRecord, CRC32, XZ_FOOTER_MAGIC, XZ_MAGIC,
+    count_multibyte_integer_size_for_value, encode_multibyte_integer, validate_filter_chain,
+    CheckType, ChecksumCalculator, FilterConfig, FilterType, IndexRecord, CRC32, XZ_FOOTER_MAGIC,
+    XZ_MAGIC,
 };
+#[cfg(feature = "std")]
+use super::{scan_streams, STREAM_HEADER_SIZE};
 use crate::{
     enc::{LZMA2Writer, LZMAOptions},
-    error_invalid_data, error_invalid_input,
+    error_invalid_input, error_unsupported,
     filter::{bcj::BCJWriter, delta::DeltaWriter},
     ByteWriter, LZMA2Options, Result, Write,
 };
 
-trait FinishableWriter: Write {
-    fn finish(self: Box<Self>) -> Result<()>;
+/// Counts the bytes that are written to the inner writer.
+struct CountingWriter<W> {
+    inner: W,
+    bytes_written: u64,
 }
 
-impl<W: Write> FinishableWriter for LZMA2Writer<W> {
-    fn finish(self: Box<Self>) -> Result<()> {
-        (*self).finish()?;
-        Ok(())
+impl<W> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            bytes_written: 0,
+        }
     }
-}
 
-impl<W: FinishableWriter> FinishableWriter for DeltaWriter<W> {
-    fn finish(self: Box<Self>) -> Result<()> {
-        let inner = (*self).into_inner();
-        Box::new(inner).finish()
+    fn bytes_written(&self) -> u64 {
+        self.bytes_written
     }
-}
 
-impl<W: FinishableWriter> FinishableWriter for BCJWriter<W> {
-    fn finish(self: Box<Self>) -> Result<()> {
-        let inner = (*self).into_inner();
-        Box::new(inner).finish()
+    fn into_inner(self) -> W {
+        self.inner
     }
 }
 
-struct SharedWriter<W> {
-    inner: Rc<RefCell<W>>,
-    compressed_bytes_written: Rc<Cell<u64>>,
-}
-
-impl<W> SharedWriter<W> {
-    fn new(inner: W, compressed_bytes_written: Rc<Cell<u64>>) -> Self {
-        Self {
-            inner: Rc::new(RefCell::new(inner)),
-            compressed_bytes_written,
-        }
-    }
-}
-
-impl<W: Write> Write for SharedWriter<W> {
+impl<W: Write> Write for CountingWriter<W> {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        let mut writer = self.inner.borrow_mut();
-        let bytes_written = writer.write(buf)?;
-        self.compressed_bytes_written
-            .set(self.compressed_bytes_written.get() + bytes_written as u64);
+        let bytes_written = self.inner.write(buf)?;
+        self.bytes_written += bytes_written as u64;
         Ok(bytes_written)
     }
 
     fn flush(&mut self) -> Result<()> {
-        let mut writer = self.inner.borrow_mut();
-        writer.flush()
+        self.inner.flush()
     }
 }
 
-impl<W: Write> FinishableWriter for SharedWriter<W> {
-    fn finish(mut self: Box<Self>) -> Result<()> {
-        (*self).flush()
-    }
+/// The filter chain of a single block. The compressed data is collected in a buffer at the end of
+/// the chain, so that the chain doesn't need to own the inner writer.
+pub(super) enum ChainWriter {
+    LZMA2(Box<LZMA2Writer<Vec<u8>>>),
+    Delta(Box<DeltaWriter<ChainWriter>>),
+    Bcj(Box<BCJWriter<ChainWriter>>),
 }
 
-impl<'writer> FinishableWriter for Box<dyn FinishableWriter + 'writer> {
-    fn finish(self: Box<Self>) -> Result<()> {
-        (*self).finish()
-    }
-}
+impl ChainWriter {
+    pub(super) fn new(filters: &[FilterConfig], lzma_options: &LZMAOptions) -> Self {
+        let options = LZMA2Options {
+            lzma_options: lzma_options.clone(),
+            ..Default::default()
+        };
+        let mut chain_writer = ChainWriter::LZMA2(Box::new(LZMA2Writer::new(Vec::new(), options)));
 
-struct DummyWriter;
+        for filter_config in filters.iter().rev() {
+            let start_offset = filter_config.property as usize;
+            chain_writer = match filter_config.filter_type {
+                FilterType::Delta => {
+                    let distance = filter_config.property as usize;
+                    ChainWriter::Delta(Box::new(DeltaWriter::new(chain_writer, distance)))
+                }
+                FilterType::BcjX86 => {
+                    ChainWriter::Bcj(Box::new(BCJWriter::new_x86(chain_writer, start_offset)))
+                }
+                FilterType::BcjPPC => {
+                    ChainWriter::Bcj(Box::new(BCJWriter::new_ppc(chain_writer, start_offset)))
+                }
+                FilterType::BcjIA64 => {
+                    ChainWriter::Bcj(Box::new(BCJWriter::new_ia64(chain_writer, start_offset)))
+                }
+                FilterType::BcjARM => {
+                    ChainWriter::Bcj(Box::new(BCJWriter::new_arm(chain_writer, start_offset)))
+                }
+                FilterType::BcjARMThumb => ChainWriter::Bcj(Box::new(BCJWriter::new_arm_thumb(
+                    chain_writer,
+                    start_offset,
+                ))),
+                FilterType::BcjSPARC => {
+                    ChainWriter::Bcj(Box::new(BCJWriter::new_sparc(chain_writer, start_offset)))
+                }
+                FilterType::BcjARM64 => {
+                    ChainWriter::Bcj(Box::new(BCJWriter::new_arm64(chain_writer, start_offset)))
+                }
+                FilterType::BcjRISCV => {
+                    ChainWriter::Bcj(Box::new(BCJWriter::new_riscv(chain_writer, start_offset)))
+                }
+                // The LZMA2 filter is always the last filter and already created.
+                FilterType::LZMA2 => chain_writer,
+            };
+        }
 
-impl Write for DummyWriter {
-    fn write(&mut self, _buf: &[u8]) -> Result<usize> {
-        unimplemented!()
+        chain_writer
     }
 
-    fn flush(&mut self) -> Result<()> {
-        unimplemented!()
+    /// Returns the buffer that holds the compressed data written so far.
+    pub(super) fn buffer_mut(&mut self) -> &mut Vec<u8> {
+        match self {
+            ChainWriter::LZMA2(writer) => writer.inner_mut(),
+            ChainWriter::Delta(writer) => writer.inner_mut().buffer_mut(),
+            ChainWriter::Bcj(writer) => writer.inner_mut().buffer_mut(),
+        }
+    }
+
+    /// Finishes the chain and returns the remaining compressed data.
+    pub(super) fn finish(self) -> Result<Vec<u8>> {
+        match self {
+            ChainWriter::LZMA2(writer) => writer.finish(),
+            ChainWriter::Delta(writer) => writer.into_inner().finish(),
+            ChainWriter::Bcj(writer) => writer.into_inner().finish(),
+        }
     }
 }
 
-impl FinishableWriter for DummyWriter {
-    fn finish(self: Box<Self>) -> Result<()> {
-        unimplemented!()
+impl Write for ChainWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            ChainWriter::LZMA2(writer) => writer.write(buf),
+            ChainWriter::Delta(writer) => writer.write(buf),
+            ChainWriter::Bcj(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            ChainWriter::LZMA2(writer) => writer.flush(),
+            ChainWriter::Delta(writer) => writer.flush(),
+            ChainWriter::Bcj(writer) => writer.flush(),
+        }
     }
 }
 
@@ -113,6 +157,10 @@ pub struct XZOptions {
     pub block_size: Option<NonZeroU64>,
     /// Pre-filter to use (at most 3).
     pub filters: Vec<FilterConfig>,
+    /// Blocks with their own size, filter chain and LZMA options, similar to `xz --block-list`.
+    /// The blocks of the list are written first. The remaining data is written with the
+    /// `block_size`, `filters` and `lzma_options` of these options.
+    pub block_list: Vec<XZBlockOptions>,
 }
 
 impl Default for XZOptions {
@@ -122,6 +170,7 @@ impl Default for XZOptions {
             check_type: CheckType::Crc32,
             block_size: None,
             filters: Vec::new(),
+            block_list: Vec::new(),
         }
     }
 }
@@ -134,6 +183,7 @@ impl XZOptions {
             check_type: CheckType::Crc64,
             block_size: None,
             filters: Vec::new(),
+            block_list: Vec::new(),
         }
     }
 
@@ -143,6 +193,7 @@ impl XZOptions {
     }
 
     /// Set the maximum block size (None means a single block, which is the default).
+    /// The writers raise a block size smaller than the dictionary size to the dictionary size.
     pub fn set_block_size(&mut self, block_size: Option<NonZeroU64>) {
         self.block_size = block_size;
     }
@@ -157,12 +208,102 @@ impl XZOptions {
             },
         );
     }
+
+    /// Append a block to the block list.
+    pub fn add_block(&mut self, block: XZBlockOptions) {
+        self.block_list.push(block);
+    }
+
+    /// Checks the filter chains of these options and of every block of the block list against
+    /// the rules of the XZ specification, which the decoder enforces as well: at most 3
+    /// pre-filters, no filter used twice, Delta distances between 1 and 256 and BCJ start offsets
+    /// aligned to the instruction size of their architecture. LZMA2 must not be used as a
+    /// pre-filter, since it is always added as the last filter.
+    ///
+    /// The writers call this when they are created.
+    pub fn validate(&self) -> Result<()> {
+        let filter_chains = core::iter::once(&self.filters)
+            .chain(self.block_list.iter().map(|block| &block.filters));
+
+        for filters in filter_chains {
+            if filters.len() > 3 {
+                return Err(error_invalid_input(
+                    "XZ allows only at most 3 pre-filters plus LZMA2",
+                ));
+            }
+
+            let filter_chain = filters
+                .iter()
+                .map(|filter| (filter.filter_type, filter.property))
+                .chain(core::iter::once((FilterType::LZMA2, 0)));
+            validate_filter_chain(filter_chain).map_err(error_invalid_input)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the options of the block with the given index. These are the options of the entry
+    /// in the block list, or these options once the block list ended.
+    pub(super) fn for_block(&self, block_index: usize) -> XZOptions {
+        match self.block_list.get(block_index) {
+            Some(block) => XZOptions {
+                lzma_options: block.lzma_options.clone(),
+                check_type: self.check_type,
+                block_size: Some(block.uncompressed_size),
+                filters: block.filters.clone(),
+                block_list: Vec::new(),
+            },
+            None => XZOptions {
+                lzma_options: self.lzma_options.clone(),
+                check_type: self.check_type,
+                block_size: self.block_size,
+                filters: self.filters.clone(),
+                block_list: Vec::new(),
+            },
+        }
+    }
+}
+
+/// Options of a single block in the block list of [`XZOptions`].
+#[derive(Debug, Clone)]
+pub struct XZBlockOptions {
+    /// The uncompressed size of the block.
+    pub uncompressed_size: NonZeroU64,
+    /// LZMA compression options of the block.
+    pub lzma_options: LZMAOptions,
+    /// Pre-filter of the block (at most 3).
+    pub filters: Vec<FilterConfig>,
+}
+
+impl XZBlockOptions {
+    /// Create the options of a block with the given uncompressed size and no pre-filter.
+    pub fn new(uncompressed_size: NonZeroU64, lzma_options: LZMAOptions) -> Self {
+        Self {
+            uncompressed_size,
+            lzma_options,
+            filters: Vec::new(),
+        }
+    }
+
+    /// Prepend a filter to the chain of the block. You can prepend at most 3 additional filter.
+    pub fn prepend_pre_filter(&mut self, filter_type: FilterType, property: u32) {
+        self.filters.insert(
+            0,
+            FilterConfig {
+                filter_type,
+                property,
+            },
+        );
+    }
 }
 
 /// A single-threaded XZ compressor.
-pub struct XZWriter<'writer, W: Write> {
-    writer: Box<dyn FinishableWriter + 'writer>,
+pub struct XZWriter<W> {
+    inner: CountingWriter<W>,
+    chain_writer: Option<ChainWriter>,
     options: XZOptions,
+    block_options: XZOptions,
+    block_count: usize,
     index_records: Vec<IndexRecord>,
     block_uncompressed_size: u64,
     checksum_calculator: ChecksumCalculator,
@@ -170,20 +311,21 @@ pub struct XZWriter<'writer, W: Write> {
     finished: bool,
     total_uncompressed_pos: u64,
     current_block_start_pos: u64,
-    compressed_bytes_written: Rc<Cell<u64>>,
-    original_writer: Rc<RefCell<W>>,
 }
 
-impl<'writer, W: Write + 'writer> XZWriter<'writer, W> {
+impl<W> XZWriter<W> {
+    /// Consume the XZWriter and return the inner writer.
+    pub fn into_inner(self) -> W {
+        self.inner.into_inner()
+    }
+}
+
+impl<W: Write> XZWriter<W> {
     /// Create a new XZ writer with the given options.
     pub fn new(inner: W, options: XZOptions) -> Result<Self> {
         let mut options = options;
 
-        if options.filters.len() > 3 {
-            return Err(error_invalid_input(
-                "XZ allows only at most 3 pre-filters plus LZMA2",
-            ));
-        }
+        options.validate()?;
 
         if let Some(block_size) = options.block_size.as_mut() {
             *block_size =
@@ -192,25 +334,28 @@ impl<'writer, W: Write + 'writer> XZWriter<'writer, W> {
         }
 
         // Last filter is always LZMA2.
-        options.filters.push(FilterConfig {
-            filter_type: FilterType::LZMA2,
-            property: 0,
-        });
+        let filter_chains = core::iter::once(&mut options.filters).chain(
+            options
+                .block_list
+                .iter_mut()
+                .map(|block| &mut block.filters),
+        );
+        for filters in filter_chains {
+            filters.push(FilterConfig {
+                filter_type: FilterType::LZMA2,
+                property: 0,
+            });
+        }
 
         let checksum_calculator = ChecksumCalculator::new(options.check_type);
-        let compressed_bytes_written = Rc::new(Cell::new(0));
-        let original_writer = Rc::new(RefCell::new(inner));
-
-        let writer = Box::new(SharedWriter {
-            inner: Rc::clone(&original_writer),
-            compressed_bytes_written: Rc::clone(&compressed_bytes_written),
-        });
+        let block_options = options.for_block(0);
 
         Ok(Self {
-            writer,
-            compressed_bytes_written,
-            original_writer,
+            inner: CountingWriter::new(inner),
+            chain_writer: None,
             options,
+            block_options,
+            block_count: 0,
             index_records: Vec::new(),
             block_uncompressed_size: 0,
             checksum_calculator,
@@ -221,112 +366,50 @@ impl<'writer, W: Write + 'writer> XZWriter<'writer, W> {
         })
     }
 
-    /// Consume the XZWriter and return the inner writer.
-    pub fn into_inner(self) -> W {
-        let Self {
-            writer,
-            original_writer,
-            ..
-        } = self;
-
-        drop(writer);
-
-        match Rc::try_unwrap(original_writer) {
-            Ok(refcell) => refcell.into_inner(),
-            Err(_) => {
-                panic!("failed to unwrap original writer - other references exist");
-            }
-        }
-    }
-
     /// Write the XZ stream header
     fn write_stream_header(&mut self) -> Result<()> {
         if self.header_written {
             return Ok(());
         }
 
-        self.writer.write_all(&XZ_MAGIC)?;
-
-        let stream_flags = [0u8, self.options.check_type as u8];
-        self.writer.write_all(&stream_flags)?;
-
-        let crc = CRC32.checksum(&stream_flags);
-        self.writer.write_u32(crc)?;
-
+        write_stream_header(&mut self.inner, self.options.check_type)?;
         self.header_written = true;
 
         Ok(())
     }
 
     fn prepare_next_block(&mut self) -> Result<()> {
-        self.writer = Box::new(SharedWriter {
-            inner: Rc::clone(&self.original_writer),
-            compressed_bytes_written: Rc::clone(&self.compressed_bytes_written),
-        });
+        self.current_block_start_pos = self.inner.bytes_written();
+
+        self.block_options = self.options.for_block(self.block_count);
+        self.block_count += 1;
 
         self.write_block_header()?;
 
-        self.current_block_start_pos = self.compressed_bytes_written.get();
+        self.chain_writer = Some(ChainWriter::new(
+            &self.block_options.filters,
+            &self.block_options.lzma_options,
+        ));
+        self.block_uncompressed_size = 0;
 
-        let mut chain_writer: Box<dyn FinishableWriter + 'writer> =
-            core::mem::replace(&mut self.writer, Box::new(DummyWriter));
+        Ok(())
+    }
 
-        for filter_config in self.options.filters.iter().rev() {
-            chain_writer = match filter_config.filter_type {
-                FilterType::Delta => {
-                    let distance = filter_config.property as usize;
-                    Box::new(DeltaWriter::new(chain_writer, distance))
-                }
-                FilterType::BcjX86 => {
-                    let start_offset = filter_config.property as usize;
-                    Box::new(BCJWriter::new_x86(chain_writer, start_offset))
-                }
-                FilterType::BcjPPC => {
-                    let start_offset = filter_config.property as usize;
-                    Box::new(BCJWriter::new_ppc(chain_writer, start_offset))
-                }
-                FilterType::BcjIA64 => {
-                    let start_offset = filter_config.property as usize;
-                    Box::new(BCJWriter::new_ia64(chain_writer, start_offset))
-                }
-                FilterType::BcjARM => {
-                    let start_offset = filter_config.property as usize;
-                    Box::new(BCJWriter::new_arm(chain_writer, start_offset))
-                }
-                FilterType::BcjARMThumb => {
-                    let start_offset = filter_config.property as usize;
-                    Box::new(BCJWriter::new_arm_thumb(chain_writer, start_offset))
-                }
-                FilterType::BcjSPARC => {
-                    let start_offset = filter_config.property as usize;
-                    Box::new(BCJWriter::new_sparc(chain_writer, start_offset))
-                }
-                FilterType::BcjARM64 => {
-                    let start_offset = filter_config.property as usize;
-                    Box::new(BCJWriter::new_arm64(chain_writer, start_offset))
-                }
-                FilterType::BcjRISCV => {
-                    let start_offset = filter_config.property as usize;
-                    Box::new(BCJWriter::new_riscv(chain_writer, start_offset))
-                }
-                FilterType::LZMA2 => {
-                    let options = LZMA2Options {
-                        lzma_options: self.options.lzma_options.clone(),
-                        ..Default::default()
-                    };
-                    Box::new(LZMA2Writer::new(chain_writer, options))
-                }
-            };
+    /// Writes the compressed data that the filter chain has produced so far to the inner writer.
+    fn drain_chain_writer(&mut self) -> Result<()> {
+        if let Some(chain_writer) = self.chain_writer.as_mut() {
+            let buffer = chain_writer.buffer_mut();
+            if !buffer.is_empty() {
+                self.inner.write_all(buffer)?;
+                buffer.clear();
+            }
         }
 
-        self.writer = chain_writer;
-        self.block_uncompressed_size = 0;
-
         Ok(())
     }
 
     fn should_finish_block(&self) -> bool {
-        if let Some(block_size) = self.options.block_size {
+        if let Some(block_size) = self.block_options.block_size {
             self.block_uncompressed_size >= block_size.get()
         } else {
             false
@@ -335,35 +418,29 @@ impl<'writer, W: Write + 'writer> XZWriter<'writer, W> {
 
     fn add_padding(&mut self, padding_needed: usize) -> Result<()> {
         match padding_needed {
-            1 => self.writer.write_all(&[0]),
-            2 => self.writer.write_all(&[0, 0]),
-            3 => self.writer.write_all(&[0, 0, 0]),
+            1 => self.inner.write_all(&[0]),
+            2 => self.inner.write_all(&[0, 0]),
+            3 => self.inner.write_all(&[0, 0, 0]),
             _ => Ok(()),
         }
     }
 
     fn finish_current_block(&mut self) -> Result<()> {
-        // Unwrap the filter chain and finish writing the block.
-        let writer = core::mem::replace(
-            &mut self.writer,
-            Box::new(SharedWriter {
-                inner: Rc::clone(&self.original_writer),
-                compressed_bytes_written: Rc::clone(&self.compressed_bytes_written),
-            }),
-        );
-        writer.finish()?;
+        // Finish the filter chain and write the rest of the compressed data.
+        if let Some(chain_writer) = self.chain_writer.take() {
+            let buffer = chain_writer.finish()?;
+            self.inner.write_all(&buffer)?;
+        }
 
-        // self.writer is now the inner writer.
-        let block_compressed_size =
-            self.compressed_bytes_written.get() - self.current_block_start_pos;
+        // The unpadded size includes the block header and the compressed data.
+        let block_size = self.inner.bytes_written() - self.current_block_start_pos;
 
-        let data_size = block_compressed_size;
-        let padding_needed = (4 - (data_size % 4)) % 4;
+        let padding_needed = (4 - (block_size % 4)) % 4;
 
         self.add_padding(padding_needed as usize)?;
         self.write_block_checksum()?;
 
-        let unpadded_size = block_compressed_size + self.get_checksum_size();
+        let unpadded_size = block_size + self.get_checksum_size();
         self.index_records.push(IndexRecord {
             unpadded_size,
             uncompressed_size: self.block_uncompressed_size,
@@ -375,21 +452,48 @@ impl<'writer, W: Write + 'writer> XZWriter<'writer, W> {
     }
 
     fn write_block_header(&mut self) -> Result<()> {
+        let header = self.encode_block_header(None, None)?;
+        self.inner.write_all(&header)
+    }
+
+    /// Encodes the block header. The compressed and uncompressed sizes are optional and only
+    /// known when the whole block was compressed before the header is written.
+    pub(super) fn encode_block_header(
+        &self,
+        compressed_size: Option<u64>,
+        uncompressed_size: Option<u64>,
+    ) -> Result<Vec<u8>> {
         let mut header_data = Vec::new();
 
-        let num_filters = self.options.filters.len();
+        let num_filters = self.block_options.filters.len();
 
         if num_filters > 4 {
             return Err(error_invalid_input("too many filters in chain (maximum 4)"));
         }
 
-        // Block flags: no compressed size, no uncompressed size, filter count
-        let block_flags = (num_filters - 1) as u8; // -1 because 0 means 1 filter, 3 means 4 filters
+        // Block flags: filter count and the presence of the optional sizes.
+        let mut block_flags = (num_filters - 1) as u8; // -1 because 0 means 1 filter, 3 means 4 filters
+        if compressed_size.is_some() {
+            block_flags |= 0x40;
+        }
+        if uncompressed_size.is_some() {
+            block_flags |= 0x80;
+        }
         header_data.push(block_flags);
 
         let mut temp_buf = [0u8; 10];
 
-        for filter_config in &self.options.filters {
+        if let Some(compressed_size) = compressed_size {
+            let size = encode_multibyte_integer(compressed_size, &mut temp_buf)?;
+            header_data.extend_from_slice(&temp_buf[..size]);
+        }
+
+        if let Some(uncompressed_size) = uncompressed_size {
+            let size = encode_multibyte_integer(uncompressed_size, &mut temp_buf)?;
+            header_data.extend_from_slice(&temp_buf[..size]);
+        }
+
+        for filter_config in &self.block_options.filters {
             // Write filter ID.
             let filter_id = match filter_config.filter_type {
                 FilterType::Delta => 0x03,
@@ -439,7 +543,7 @@ impl<'writer, W: Write + 'writer> XZWriter<'writer, W> {
                     let size = encode_multibyte_integer(1, &mut temp_buf)?;
                     header_data.extend_from_slice(&temp_buf[..size]);
 
-                    let dict_size = self.options.lzma_options.dict_size;
+                    let dict_size = self.block_options.lzma_options.dict_size;
                     let dict_size_prop = self.encode_lzma2_dict_size(dict_size)?;
                     header_data.push(dict_size_prop);
                 }
@@ -451,34 +555,18 @@ impl<'writer, W: Write + 'writer> XZWriter<'writer, W> {
         let header_size = total_size_needed.div_ceil(4) * 4;
         let header_size_encoded = ((header_size / 4) - 1) as u8;
 
-        self.writer.write_u8(header_size_encoded)?;
-        self.writer.write_all(&header_data)?;
-
         let padding_needed = header_size - 1 - header_data.len() - 4;
-        self.add_padding(padding_needed)?;
 
-        // Calculate and write CRC32 of header size byte + header data + padding
-        let mut crc = CRC32.digest();
-        crc.update(&[header_size_encoded]);
-        crc.update(&header_data);
+        let mut header = Vec::with_capacity(header_size);
+        header.push(header_size_encoded);
+        header.extend_from_slice(&header_data);
+        header.extend_from_slice(&[0u8; 3][..padding_needed]);
 
-        match padding_needed {
-            1 => crc.update(&[0]),
-            2 => crc.update(&[0, 0]),
-            3 => crc.update(&[0, 0, 0]),
-            _ => {}
-        }
+        // CRC32 of header size byte + header data + padding
+        let crc = CRC32.checksum(&header);
+        header.extend_from_slice(&crc.to_le_bytes());
 
-        self.writer.write_u32(crc.finalize())?;
-
-        Ok(())
-    }
-
-    fn get_block_header_size(&self, _compressed_size: u64, _uncompressed_size: u64) -> u64 {
-        // Block header: size_byte(1) + flags(1) + filter_id(1) + props_size(1)
-        // + dict_prop(1) + padding + crc32(4)
-        let base_size: u64 = 9;
-        base_size.div_ceil(4) * 4
+        Ok(header)
     }
 
     fn get_checksum_size(&self) -> u64 {
@@ -514,7 +602,7 @@ impl<'writer, W: Write + 'writer> XZWriter<'writer, W> {
 
     fn write_block_checksum(&mut self) -> Result<()> {
         let checksum = self.take_checksum();
-        self.writer.write_all(&checksum)?;
+        self.inner.write_all(&checksum)?;
 
         // Reset checksum calculator for next block.
         self.checksum_calculator = ChecksumCalculator::new(self.options.check_type);
@@ -537,75 +625,59 @@ impl<'writer, W: Write + 'writer> XZWriter<'writer, W> {
     }
 
     fn write_index(&mut self) -> Result<()> {
-        // Index indicator (0x00).
-        self.writer.write_u8(0x00)?;
-
-        let mut index_data = Vec::new();
-
-        let mut temp_buf = [0u8; 10];
-        let size = encode_multibyte_integer(self.index_records.len() as u64, &mut temp_buf)?;
-        index_data.extend_from_slice(&temp_buf[..size]);
-
-        for record in &self.index_records {
-            let size = encode_multibyte_integer(record.unpadded_size, &mut temp_buf)?;
-            index_data.extend_from_slice(&temp_buf[..size]);
+        write_index(&mut self.inner, &self.index_records)
+    }
 
-            let size = encode_multibyte_integer(record.uncompressed_size, &mut temp_buf)?;
-            index_data.extend_from_slice(&temp_buf[..size]);
+    fn write_stream_footer(&mut self) -> Result<()> {
+        write_stream_footer(
+            &mut self.inner,
+            &self.index_records,
+            self.options.check_type,
+        )
+    }
+
+    /// Finishes the current block and flushes the inner writer. The next write starts a new
+    /// block. Everything written so far can be decoded from the output written so far.
+    ///
+    /// This is the equivalent of `LZMA_FULL_FLUSH` of liblzma.
+    pub fn full_flush(&mut self) -> Result<()> {
+        if self.finished {
+            return Err(error_invalid_input("XZWriter already finished"));
         }
 
-        self.writer.write_all(&index_data)?;
-
-        let bytes_written = 1 + index_data.len(); // indicator + index data
-        let padding_needed = (4 - (bytes_written % 4)) % 4;
-        self.add_padding(padding_needed)?;
-
-        let mut crc = CRC32.digest();
-        crc.update(&[0x00]);
-        crc.update(&index_data);
+        self.write_stream_header()?;
 
-        match padding_needed {
-            1 => crc.update(&[0]),
-            2 => crc.update(&[0, 0]),
-            3 => crc.update(&[0, 0, 0]),
-            _ => {}
+        if self.chain_writer.is_some() {
+            self.finish_current_block()?;
         }
 
-        self.writer.write_u32(crc.finalize())?;
-
-        Ok(())
+        self.inner.flush()
     }
 
-    fn write_stream_footer(&mut self) -> Result<()> {
-        // Calculate backward size (index size in 4-byte blocks).
-        let mut index_size = 1; // indicator
-        index_size += count_multibyte_integer_size_for_value(self.index_records.len() as u64);
-
-        for record in &self.index_records {
-            index_size += count_multibyte_integer_size_for_value(record.unpadded_size);
-            index_size += count_multibyte_integer_size_for_value(record.uncompressed_size);
+    /// Ends the current LZMA2 chunk without ending the block and flushes the inner writer.
+    /// Everything written so far can be decoded from the output written so far. Costs less
+    /// compression ratio than [`XZWriter::full_flush`].
+    ///
+    /// This is the equivalent of `LZMA_SYNC_FLUSH` of liblzma. BCJ filters hold back the end of
+    /// their input, so a sync flush is not supported if the filter chain contains one.
+    pub fn sync_flush(&mut self) -> Result<()> {
+        if self.finished {
+            return Err(error_invalid_input("XZWriter already finished"));
         }
 
-        let padding_needed = (4 - (index_size % 4)) % 4;
-        index_size += padding_needed;
-        index_size += 4; // CRC32
-
-        let backward_size = ((index_size / 4) - 1) as u32;
-
-        // Stream flags (same as header).
-        let stream_flags = [0u8, self.options.check_type as u8];
-
-        // Calculate CRC32 of backward size + stream flags
-        let mut crc = CRC32.digest();
-        crc.update(&backward_size.to_le_bytes());
-        crc.update(&stream_flags);
-
-        self.writer.write_u32(crc.finalize())?;
-        self.writer.write_u32(backward_size)?;
-        self.writer.write_all(&stream_flags)?;
-        self.writer.write_all(&XZ_FOOTER_MAGIC)?;
+        if self
+            .block_options
+            .filters
+            .iter()
+            .any(|filter| !matches!(filter.filter_type, FilterType::Delta | FilterType::LZMA2))
+        {
+            return Err(error_unsupported(
+                "sync flush is not supported with BCJ filters",
+            ));
+        }
 
-        Ok(())
+        self.write_stream_header()?;
+        self.flush()
     }
 
     /// Finish writing the XZ stream and return the inner writer.
@@ -615,7 +687,12 @@ impl<'writer, W: Write + 'writer> XZWriter<'writer, W> {
         }
 
         self.write_stream_header()?;
-        self.finish_current_block()?;
+
+        // A block is only started once data is written, so an empty stream has no blocks.
+        if self.chain_writer.is_some() {
+            self.finish_current_block()?;
+        }
+
         self.write_index()?;
         self.write_stream_footer()?;
 
@@ -623,10 +700,110 @@ impl<'writer, W: Write + 'writer> XZWriter<'writer, W> {
     }
 }
 
-impl<'writer, W: Write + 'writer> Write for XZWriter<'writer, W> {
+#[cfg(feature = "std")]
+impl<W: std::io::Read + Write + std::io::Seek> XZWriter<W> {
+    /// Opens an existing XZ file to append a new stream to it.
+    ///
+    /// The footer and index of every stream are verified first. The new stream is written after
+    /// the end of the file, so that the file can be decoded with multiple streams allowed, for
+    /// example with `XZReader::new(inner, true)`. Existing stream padding is kept. If the writer is
+    /// dropped without calling [`XZWriter::finish`], the file ends with an incomplete stream.
+    pub fn append_stream(mut inner: W, options: XZOptions) -> Result<Self> {
+        scan_streams(&mut inner)?;
+        inner.seek(std::io::SeekFrom::End(0))?;
+
+        Self::new(inner, options)
+    }
+
+    /// Opens an existing XZ file to append new blocks to its last stream.
+    ///
+    /// The footer and index of every stream are verified first. The index and footer of the last
+    /// stream are overwritten by the new blocks and rewritten by [`XZWriter::finish`], so the
+    /// file is corrupt until the writer is finished. The check type of the last stream is used
+    /// instead of the check type of the options.
+    pub fn append_blocks(mut inner: W, mut options: XZOptions) -> Result<Self> {
+        let mut streams = scan_streams(&mut inner)?;
+        let stream = streams.pop().expect("no XZ stream found");
+
+        let blocks_size: u64 = stream.blocks.iter().map(|block| block.total_size()).sum();
+        let index_pos = stream.start_pos + STREAM_HEADER_SIZE + blocks_size;
+        inner.seek(std::io::SeekFrom::Start(index_pos))?;
+
+        options.check_type = stream.check_type;
+
+        let mut writer = Self::new(inner, options)?;
+        writer.header_written = true;
+        writer.index_records = stream
+            .blocks
+            .iter()
+            .map(|block| IndexRecord {
+                unpadded_size: block.unpadded_size,
+                uncompressed_size: block.uncompressed_size,
+            })
+            .collect();
+
+        Ok(writer)
+    }
+}
+
+#[cfg(feature = "std")]
+impl XZWriter<Vec<u8>> {
+    /// Compresses the data into a single block, without the stream header, index and footer.
+    /// Since the whole block is known in advance, the block header contains both the compressed
+    /// and the uncompressed size.
+    ///
+    /// Returns the encoded block and its index record. Used by the multi-threaded encoder.
+    pub(super) fn compress_block(
+        data: &[u8],
+        mut options: XZOptions,
+    ) -> Result<(Vec<u8>, IndexRecord)> {
+        options.block_size = None;
+
+        let mut writer = XZWriter::new(Vec::new(), options)?;
+        writer.header_written = true;
+        writer.write_all(data)?;
+        writer.finish_current_block()?;
+
+        let index_record = writer
+            .index_records
+            .pop()
+            .expect("block has no index record");
+
+        // Replace the block header written while streaming with one that contains the sizes.
+        let check_size = writer.get_checksum_size() as usize;
+        let unpadded_size = index_record.unpadded_size as usize;
+        let uncompressed_size = index_record.uncompressed_size;
+
+        let block = &writer.inner.inner;
+        let header_size = (block[0] as usize + 1) * 4;
+        let compressed_data = &block[header_size..unpadded_size - check_size];
+        let check = &block[block.len() - check_size..];
+
+        let header = writer
+            .encode_block_header(Some(compressed_data.len() as u64), Some(uncompressed_size))?;
+
+        let data_size = header.len() + compressed_data.len();
+        let padding_needed = (4 - (data_size % 4)) % 4;
+
+        let mut sized_block = Vec::with_capacity(data_size + padding_needed + check_size);
+        sized_block.extend_from_slice(&header);
+        sized_block.extend_from_slice(compressed_data);
+        sized_block.extend_from_slice(&[0u8; 3][..padding_needed]);
+        sized_block.extend_from_slice(check);
+
+        let index_record = IndexRecord {
+            unpadded_size: (data_size + check_size) as u64,
+            uncompressed_size,
+        };
+
+        Ok((sized_block, index_record))
+    }
+}
+
+impl<W: Write> Write for XZWriter<W> {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         if self.finished {
-            return Err(error_invalid_data("XZWriter already finished"));
+            return Err(error_invalid_input("XZWriter already finished"));
         }
 
         self.write_stream_header()?;
@@ -641,11 +818,20 @@ impl<'writer, W: Write + 'writer> Write for XZWriter<'writer, W> {
             }
 
             // Check if we need to prepare the next block (either first block or after finishing one).
-            if self.block_uncompressed_size == 0 {
+            if self.chain_writer.is_none() {
                 self.prepare_next_block()?;
             }
 
-            let written = self.writer.write(remaining)?;
+            let bytes_to_write = if let Some(block_size) = self.block_options.block_size {
+                let remaining_in_block = block_size.get() - self.block_uncompressed_size;
+                (remaining.len() as u64).min(remaining_in_block) as usize
+            } else {
+                remaining.len()
+            };
+
+            let chain_writer = self.chain_writer.as_mut().expect("block not prepared");
+            let written = chain_writer.write(&remaining[..bytes_to_write])?;
+            self.drain_chain_writer()?;
 
             self.checksum_calculator.update(&remaining[..written]);
 
@@ -659,6 +845,97 @@ impl<'writer, W: Write + 'writer> Write for XZWriter<'writer, W> {
     }
 
     fn flush(&mut self) -> Result<()> {
-        self.writer.flush()
+        if let Some(chain_writer) = self.chain_writer.as_mut() {
+            chain_writer.flush()?;
+            self.drain_chain_writer()?;
+        }
+
+        self.inner.flush()
+    }
+}
+
+/// Writes the XZ stream header.
+pub(super) fn write_stream_header<W: Write>(writer: &mut W, check_type: CheckType) -> Result<()> {
+    writer.write_all(&XZ_MAGIC)?;
+
+    let stream_flags = [0u8, check_type as u8];
+    writer.write_all(&stream_flags)?;
+
+    let crc = CRC32.checksum(&stream_flags);
+    writer.write_u32(crc)?;
+
+    Ok(())
+}
+
+/// Writes the XZ index for the given records.
+pub(super) fn write_index<W: Write>(writer: &mut W, index_records: &[IndexRecord]) -> Result<()> {
+    // Index indicator (0x00).
+    writer.write_u8(0x00)?;
+
+    let mut index_data = Vec::new();
+
+    let mut temp_buf = [0u8; 10];
+    let size = encode_multibyte_integer(index_records.len() as u64, &mut temp_buf)?;
+    index_data.extend_from_slice(&temp_buf[..size]);
+
+    for record in index_records {
+        let size = encode_multibyte_integer(record.unpadded_size, &mut temp_buf)?;
+        index_data.extend_from_slice(&temp_buf[..size]);
+
+        let size = encode_multibyte_integer(record.uncompressed_size, &mut temp_buf)?;
+        index_data.extend_from_slice(&temp_buf[..size]);
+    }
+
+    writer.write_all(&index_data)?;
+
+    let bytes_written = 1 + index_data.len(); // indicator + index data
+    let padding_needed = (4 - (bytes_written % 4)) % 4;
+    let padding = &[0u8; 3][..padding_needed];
+    writer.write_all(padding)?;
+
+    let mut crc = CRC32.digest();
+    crc.update(&[0x00]);
+    crc.update(&index_data);
+    crc.update(padding);
+
+    writer.write_u32(crc.finalize())?;
+
+    Ok(())
+}
+
+/// Writes the XZ stream footer for an index with the given records.
+pub(super) fn write_stream_footer<W: Write>(
+    writer: &mut W,
+    index_records: &[IndexRecord],
+    check_type: CheckType,
+) -> Result<()> {
+    // Calculate backward size (index size in 4-byte blocks).
+    let mut index_size = 1; // indicator
+    index_size += count_multibyte_integer_size_for_value(index_records.len() as u64);
+
+    for record in index_records {
+        index_size += count_multibyte_integer_size_for_value(record.unpadded_size);
+        index_size += count_multibyte_integer_size_for_value(record.uncompressed_size);
     }
+
+    let padding_needed = (4 - (index_size % 4)) % 4;
+    index_size += padding_needed;
+    index_size += 4; // CRC32
+
+    let backward_size = ((index_size / 4) - 1) as u32;
+
+    // Stream flags (same as header).
+    let stream_flags = [0u8, check_type as u8];
+
+    // Calculate CRC32 of backward size + stream flags
+    let mut crc = CRC32.digest();
+    crc.update(&backward_size.to_le_bytes());
+    crc.update(&stream_flags);
+
+    writer.write_u32(crc.finalize())?;
+    writer.write_u32(backward_size)?;
+    writer.write_all(&stream_flags)?;
+    writer.write_all(&XZ_FOOTER_MAGIC)?;
+
+    Ok(())
 }
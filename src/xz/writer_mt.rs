@@ -0,0 +1,495 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use super::{
+    writer::{write_index, write_stream_footer, write_stream_header},
+    IndexRecord, XZOptions, XZWriter,
+};
+use crate::{
+    error_invalid_input, set_error,
+    work_queue::{WorkStealingQueue, WorkerHandle},
+};
+
+/// A work unit for a worker thread.
+/// Contains the sequence number and the raw uncompressed data for a single XZ block.
+type WorkUnit = (u64, Vec<u8>);
+
+/// A result unit from a worker thread.
+/// Contains the sequence number, the encoded XZ block and its index record.
+type ResultUnit = (u64, Vec<u8>, IndexRecord);
+
+enum State {
+    /// Actively accepting input data and dispatching work to threads.
+    Writing,
+    /// No more input data will come. We are now waiting for the remaining
+    /// work to be completed by the worker threads.
+    Finishing,
+    /// All data has been compressed and written. The stream is finished.
+    Finished,
+    /// A fatal error occurred in either the writer or a worker thread.
+    Error,
+}
+
+/// A multi-threaded XZ compressor.
+///
+/// The input is split into the blocks of the block list and then into blocks of `block_size`,
+/// which are compressed in parallel and then written in order into a single XZ stream.
+pub struct XZWriterMT<W: Write> {
+    inner: Option<W>,
+    options: XZOptions,
+    result_rx: Receiver<ResultUnit>,
+    result_tx: Sender<ResultUnit>,
+    current_work_unit: Vec<u8>,
+    block_size: usize,
+    block_sizes: Vec<usize>,
+    index_records: Vec<IndexRecord>,
+    header_written: bool,
+    next_sequence_to_dispatch: u64,
+    next_sequence_to_write: u64,
+    last_sequence_id: Option<u64>,
+    out_of_order_chunks: BTreeMap<u64, (Vec<u8>, IndexRecord)>,
+    shutdown_flag: Arc<AtomicBool>,
+    error_store: Arc<Mutex<Option<io::Error>>>,
+    state: State,
+    work_queue: WorkStealingQueue<WorkUnit>,
+    active_workers: Arc<AtomicU32>,
+    max_workers: u32,
+    worker_handles: Vec<thread::JoinHandle<()>>,
+}
+
+impl<W: Write> XZWriterMT<W> {
+    /// Creates a new multi-threaded XZ writer.
+    ///
+    /// - `inner`: The writer to write compressed data to.
+    /// - `options`: The XZ options used for compressing. Block size must be set when using the
+    ///   multi-threaded encoder. If you need just one block, then use the single-threaded encoder.
+    ///   Like [`XZWriter`], a block size smaller than the dictionary size is raised to the
+    ///   dictionary size. The sizes of the block list are used as given.
+    /// - `num_workers`: The maximum number of worker threads for compression.
+    ///   Currently capped at 256 threads.
+    pub fn new(inner: W, options: XZOptions, num_workers: u32) -> io::Result<Self> {
+        let max_workers = num_workers.clamp(1, 256);
+
+        options.validate()?;
+
+        let block_size = match options.block_size {
+            None => return Err(error_invalid_input("block size must be set")),
+            Some(block_size) => block_size.get().max(options.lzma_options.dict_size as u64),
+        };
+
+        let block_size = usize::try_from(block_size)
+            .map_err(|_| error_invalid_input("block size bigger than usize"))?;
+
+        let block_sizes = options
+            .block_list
+            .iter()
+            .map(|block| usize::try_from(block.uncompressed_size.get()))
+            .collect::<Result<Vec<usize>, _>>()
+            .map_err(|_| error_invalid_input("block size bigger than usize"))?;
+
+        let work_queue = WorkStealingQueue::new();
+        let (result_tx, result_rx) = mpsc::channel::<ResultUnit>();
+        let shutdown_flag = Arc::new(AtomicBool::new(false));
+        let error_store = Arc::new(Mutex::new(None));
+        let active_workers = Arc::new(AtomicU32::new(0));
+
+        let mut writer = Self {
+            inner: Some(inner),
+            options,
+            result_rx,
+            result_tx,
+            current_work_unit: Vec::with_capacity(block_size.min(1024 * 1024)),
+            block_size,
+            block_sizes,
+            index_records: Vec::new(),
+            header_written: false,
+            next_sequence_to_dispatch: 0,
+            next_sequence_to_write: 0,
+            last_sequence_id: None,
+            out_of_order_chunks: BTreeMap::new(),
+            shutdown_flag,
+            error_store,
+            state: State::Writing,
+            work_queue,
+            active_workers,
+            max_workers,
+            worker_handles: Vec::new(),
+        };
+
+        writer.spawn_worker_thread();
+
+        Ok(writer)
+    }
+
+    fn spawn_worker_thread(&mut self) {
+        let worker_handle = self.work_queue.worker();
+        let result_tx = self.result_tx.clone();
+        let shutdown_flag = Arc::clone(&self.shutdown_flag);
+        let error_store = Arc::clone(&self.error_store);
+        let active_workers = Arc::clone(&self.active_workers);
+        let options = self.options.clone();
+
+        let handle = thread::spawn(move || {
+            worker_thread_logic(
+                worker_handle,
+                result_tx,
+                options,
+                shutdown_flag,
+                error_store,
+                active_workers,
+            );
+        });
+
+        self.worker_handles.push(handle);
+    }
+
+    /// The uncompressed size of the block that is currently collected.
+    fn current_block_size(&self) -> usize {
+        self.block_sizes
+            .get(self.next_sequence_to_dispatch as usize)
+            .copied()
+            .unwrap_or(self.block_size)
+    }
+
+    /// Writes the stream header if it was not written yet.
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.header_written {
+            return Ok(());
+        }
+
+        let inner = self.inner.as_mut().expect("inner is empty");
+        write_stream_header(inner, self.options.check_type)?;
+        self.header_written = true;
+
+        Ok(())
+    }
+
+    /// Writes a compressed block and remembers its index record.
+    fn write_block(&mut self, block: Vec<u8>, index_record: IndexRecord) -> io::Result<()> {
+        self.write_header()?;
+        self.inner
+            .as_mut()
+            .expect("inner is empty")
+            .write_all(&block)?;
+        self.index_records.push(index_record);
+        Ok(())
+    }
+
+    /// Sends the current work unit to the workers, blocking if the queue is full.
+    fn send_work_unit(&mut self) -> io::Result<()> {
+        if self.current_work_unit.is_empty() {
+            return Ok(());
+        }
+
+        while self.work_queue.len() >= 4 {
+            if let Some((block, index_record)) = self.get_next_compressed_block(true)? {
+                self.write_block(block, index_record)?;
+            } else {
+                // If we get None, the stream is finished or errored. We can't send more work.
+                if !matches!(self.state, State::Writing) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "Stream has been closed or is in an error state.",
+                    ));
+                }
+            }
+        }
+
+        let work_unit = core::mem::take(&mut self.current_work_unit);
+
+        if !self
+            .work_queue
+            .push((self.next_sequence_to_dispatch, work_unit))
+        {
+            // Queue is closed, this indicates shutdown.
+            self.state = State::Error;
+            let err = io::Error::new(io::ErrorKind::BrokenPipe, "Worker threads have shut down");
+            set_error(err, &self.error_store, &self.shutdown_flag);
+
+            return Err(self
+                .error_store
+                .lock()
+                .unwrap()
+                .take()
+                .unwrap_or_else(|| io::Error::other("Failed to push to work queue")));
+        }
+
+        // We spawn a new thread if we have work queued, no available workers, and haven't reached
+        // the maximal allowed parallelism yet.
+        let spawned_workers = self.worker_handles.len() as u32;
+        let active_workers = self.active_workers.load(Ordering::Acquire);
+        let queue_len = self.work_queue.len();
+
+        if queue_len > 0 && active_workers == spawned_workers && spawned_workers < self.max_workers
+        {
+            self.spawn_worker_thread();
+        }
+
+        self.next_sequence_to_dispatch += 1;
+        Ok(())
+    }
+
+    /// Pulls the next available compressed block, managing state transitions.
+    ///
+    /// The `blocking` parameter controls whether to wait for a result or return immediately.
+    fn get_next_compressed_block(
+        &mut self,
+        blocking: bool,
+    ) -> io::Result<Option<(Vec<u8>, IndexRecord)>> {
+        loop {
+            // Check for a stored error first, since a worker that failed wakes up the writer with
+            // an empty result.
+            if let Some(err) = self.error_store.lock().unwrap().take() {
+                self.state = State::Error;
+                return Err(err);
+            }
+
+            if let Some(result) = self
+                .out_of_order_chunks
+                .remove(&self.next_sequence_to_write)
+            {
+                self.next_sequence_to_write += 1;
+                return Ok(Some(result));
+            }
+
+            match self.state {
+                State::Writing => {
+                    let recv_result = if blocking {
+                        self.result_rx
+                            .recv()
+                            .map_err(|_| mpsc::TryRecvError::Disconnected)
+                    } else {
+                        self.result_rx.try_recv()
+                    };
+
+                    match recv_result {
+                        Ok((seq, block, index_record)) => {
+                            if seq == self.next_sequence_to_write {
+                                self.next_sequence_to_write += 1;
+                                return Ok(Some((block, index_record)));
+                            } else {
+                                self.out_of_order_chunks.insert(seq, (block, index_record));
+                            }
+                        }
+                        Err(mpsc::TryRecvError::Empty) => {
+                            // This only happens when blocking is false.
+                            return Ok(None);
+                        }
+                        Err(mpsc::TryRecvError::Disconnected) => {
+                            // All workers are gone. Transition to Finishing to handle
+                            // any remaining out-of-order blocks.
+                            self.state = State::Finishing;
+                        }
+                    }
+                }
+                State::Finishing => {
+                    if let Some(last_seq) = self.last_sequence_id {
+                        if self.next_sequence_to_write > last_seq
+                            && self.out_of_order_chunks.is_empty()
+                        {
+                            self.state = State::Finished;
+                            continue;
+                        }
+                    }
+
+                    match self.result_rx.recv() {
+                        Ok((seq, block, index_record)) => {
+                            if seq == self.next_sequence_to_write {
+                                self.next_sequence_to_write += 1;
+                                return Ok(Some((block, index_record)));
+                            } else {
+                                self.out_of_order_chunks.insert(seq, (block, index_record));
+                            }
+                        }
+                        Err(_) => {
+                            // If we get here, it means no more results will ever arrive.
+                            // Let's check if the blocks we have are sufficient.
+                            if let Some(last_seq) = self.last_sequence_id {
+                                if self.next_sequence_to_write <= last_seq
+                                    && self.out_of_order_chunks.is_empty()
+                                {
+                                    // We expected more blocks, but the workers are gone and the
+                                    // out-of-order buffer is empty. This is a real error.
+                                    self.state = State::Error;
+                                    let err = io::Error::other(
+                                        format!("A compressed block was lost. Expected up to seq {}, but only got up to {}", last_seq, self.next_sequence_to_write.saturating_sub(1)),
+                                    );
+                                    set_error(err, &self.error_store, &self.shutdown_flag);
+                                }
+                            }
+                            // Otherwise, allow the loop to continue to drain the map.
+                        }
+                    }
+                }
+                State::Finished => {
+                    return Ok(None);
+                }
+                State::Error => {
+                    return Err(self.error_store.lock().unwrap().take().unwrap_or_else(|| {
+                        io::Error::other("Compression failed with an unknown error")
+                    }));
+                }
+            }
+        }
+    }
+
+    /// Returns a mutable reference to the underlying writer.
+    pub fn inner(&mut self) -> &mut W {
+        self.inner.as_mut().expect("inner is empty")
+    }
+
+    /// Finishes the compression and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.send_work_unit()?;
+
+        if self.next_sequence_to_dispatch > 0 {
+            self.last_sequence_id = Some(self.next_sequence_to_dispatch.saturating_sub(1));
+            self.state = State::Finishing;
+
+            while let Some((block, index_record)) = self.get_next_compressed_block(true)? {
+                self.write_block(block, index_record)?;
+            }
+        }
+
+        // If no data was provided to compress, this writes an empty XZ stream without any blocks.
+        self.write_header()?;
+
+        let mut inner = self.inner.take().expect("inner is empty");
+        write_index(&mut inner, &self.index_records)?;
+        write_stream_footer(&mut inner, &self.index_records, self.options.check_type)?;
+        inner.flush()?;
+
+        self.shutdown_flag.store(true, Ordering::Release);
+        self.work_queue.close();
+
+        Ok(inner)
+    }
+}
+
+/// The logic for a single worker thread.
+fn worker_thread_logic(
+    worker_handle: WorkerHandle<WorkUnit>,
+    result_tx: Sender<ResultUnit>,
+    options: XZOptions,
+    shutdown_flag: Arc<AtomicBool>,
+    error_store: Arc<Mutex<Option<io::Error>>>,
+    active_workers: Arc<AtomicU32>,
+) {
+    while !shutdown_flag.load(Ordering::Acquire) {
+        let (seq, work_unit_data) = match worker_handle.steal() {
+            Some(work) => {
+                active_workers.fetch_add(1, Ordering::Release);
+                work
+            }
+            None => {
+                // No more work available and queue is closed
+                break;
+            }
+        };
+
+        let block_options = options.for_block(seq as usize);
+        let (block, index_record) = match XZWriter::compress_block(&work_unit_data, block_options) {
+            Ok(result) => result,
+            Err(error) => {
+                active_workers.fetch_sub(1, Ordering::Release);
+                set_error(error, &error_store, &shutdown_flag);
+                // Wake up the writer, so that it picks up the stored error.
+                let empty_record = IndexRecord {
+                    unpadded_size: 0,
+                    uncompressed_size: 0,
+                };
+                let _ = result_tx.send((seq, Vec::new(), empty_record));
+                return;
+            }
+        };
+
+        if result_tx.send((seq, block, index_record)).is_err() {
+            active_workers.fetch_sub(1, Ordering::Release);
+            return;
+        }
+
+        active_workers.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<W: Write> Write for XZWriterMT<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if !matches!(self.state, State::Writing) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot write after finishing",
+            ));
+        }
+
+        let mut total_written = 0;
+        let mut remaining_buf = buf;
+
+        while !remaining_buf.is_empty() {
+            let block_size = self.current_block_size();
+            let block_remaining = block_size.saturating_sub(self.current_work_unit.len());
+            let to_write = remaining_buf.len().min(block_remaining);
+
+            if to_write > 0 {
+                self.current_work_unit
+                    .extend_from_slice(&remaining_buf[..to_write]);
+                total_written += to_write;
+                remaining_buf = &remaining_buf[to_write..];
+            }
+
+            if self.current_work_unit.len() >= block_size {
+                self.send_work_unit()?;
+            }
+
+            while let Some((block, index_record)) = self.get_next_compressed_block(false)? {
+                self.write_block(block, index_record)?;
+            }
+        }
+
+        Ok(total_written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.current_work_unit.is_empty() {
+            self.send_work_unit()?;
+        }
+
+        let sequence_to_wait = self.next_sequence_to_dispatch;
+
+        while self.next_sequence_to_write < sequence_to_wait {
+            match self.get_next_compressed_block(true)? {
+                Some((block, index_record)) => {
+                    self.write_block(block, index_record)?;
+                }
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "Compression stream ended unexpectedly during flush",
+                    ));
+                }
+            }
+        }
+
+        self.inner.as_mut().expect("inner is empty").flush()
+    }
+}
+
+impl<W: Write> Drop for XZWriterMT<W> {
+    fn drop(&mut self) {
+        self.shutdown_flag.store(true, Ordering::Release);
+        self.work_queue.close();
+        // Worker threads will exit when the work queue is closed.
+        // JoinHandles will be dropped, which is fine since we set the shutdown flag.
+    }
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use std::{
+    io::{Cursor, ErrorKind, Read, Write},
+    num::NonZeroU64,
+};
+
+use common::{compress_lzip, compress_xz, xz_options};
+use lzma_rust2::{
+    AutoReader, AutoReaderMT, Format, LZMA2Options, LZMA2Writer, LZMAOptions, LZMAWriter,
+};
+
+static PG6800: &str = "tests/data/pg6800.txt";
+
+const DICT_SIZE: u32 = 1024 * 1024;
+
+fn compress_lzma(data: &[u8]) -> Vec<u8> {
+    let mut options = LZMAOptions::with_preset(0);
+    options.dict_size = DICT_SIZE;
+
+    let mut writer =
+        LZMAWriter::new_use_header(Vec::new(), &options, Some(data.len() as u64)).unwrap();
+    writer.write_all(data).unwrap();
+    writer.finish().unwrap()
+}
+
+fn compress_lzma2(data: &[u8]) -> Vec<u8> {
+    let mut options = LZMA2Options::with_preset(0);
+    options.lzma_options.dict_size = DICT_SIZE;
+    options.set_chunk_size(NonZeroU64::new(DICT_SIZE as u64));
+
+    let mut writer = LZMA2Writer::new(Vec::new(), options);
+    writer.write_all(data).unwrap();
+    writer.finish().unwrap()
+}
+
+fn check_format(compressed: &[u8], lzma2_dict_size: Option<u32>, format: Format, data: &[u8]) {
+    let mut reader = AutoReader::new(compressed, lzma2_dict_size).unwrap();
+    assert_eq!(reader.format(), format);
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+    assert!(uncompressed == data);
+
+    let mut reader = AutoReaderMT::new(Cursor::new(compressed), lzma2_dict_size, 4).unwrap();
+    assert_eq!(reader.format(), format);
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+    assert!(uncompressed == data);
+}
+
+#[test]
+fn detect_xz() {
+    let data = std::fs::read(PG6800).unwrap();
+    check_format(
+        &compress_xz(&data, xz_options(DICT_SIZE as u64)),
+        None,
+        Format::XZ,
+        &data,
+    );
+}
+
+#[test]
+fn detect_lzip() {
+    let data = std::fs::read(PG6800).unwrap();
+    check_format(
+        &compress_lzip(&data, DICT_SIZE as u64),
+        None,
+        Format::LZIP,
+        &data,
+    );
+}
+
+#[test]
+fn detect_lzma() {
+    let data = std::fs::read(PG6800).unwrap();
+    check_format(&compress_lzma(&data), None, Format::LZMA, &data);
+}
+
+#[test]
+fn detect_lzma_max_dict_size() {
+    let data = std::fs::read(PG6800).unwrap();
+    let mut compressed = compress_lzma(&data);
+    compressed[1..5].copy_from_slice(&u32::MAX.to_le_bytes());
+
+    check_format(&compressed, None, Format::LZMA, &data);
+}
+
+#[test]
+fn start_position() {
+    let data = std::fs::read(PG6800).unwrap();
+
+    let mut compressed = vec![0xAA; 100];
+    compressed.extend_from_slice(&compress_lzma(&data));
+    let mut inner = Cursor::new(&compressed);
+    inner.set_position(100);
+
+    let mut reader = AutoReaderMT::new(inner, None, 4).unwrap();
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+    assert!(uncompressed == data);
+
+    for compressed in [
+        compress_xz(&data, xz_options(DICT_SIZE as u64)),
+        compress_lzip(&data, DICT_SIZE as u64),
+    ] {
+        let mut prefixed = vec![0xAA; 100];
+        prefixed.extend_from_slice(&compressed);
+        let mut inner = Cursor::new(&prefixed);
+        inner.set_position(100);
+
+        let error = AutoReaderMT::new(inner, None, 4).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn detect_lzma2() {
+    let data = std::fs::read(PG6800).unwrap();
+    let compressed = compress_lzma2(&data);
+    check_format(&compressed, Some(DICT_SIZE), Format::LZMA2, &data);
+
+    // Raw LZMA2 data has no header, so it's only detected with a dictionary size.
+    let error = AutoReader::new(compressed.as_slice(), None).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn unknown_format() {
+    let data = b"This is not compressed at all.";
+
+    let error = AutoReader::new(data.as_slice(), Some(DICT_SIZE))
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+    let error = AutoReaderMT::new(Cursor::new(data), Some(DICT_SIZE), 4)
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+    let error = AutoReader::new([].as_slice(), None).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
@@ -0,0 +1,66 @@
+//! Helpers that are shared by the integration tests.
+
+#![allow(dead_code)]
+
+use std::{
+    io::{Read, Seek, SeekFrom, Write},
+    num::NonZeroU64,
+};
+
+use lzma_rust2::{CheckType, LZIPOptions, LZIPWriter, XZOptions, XZWriter};
+
+/// A reader that can't seek and returns at most `chunk_size` bytes per read, like a pipe.
+pub struct Pipe<'a> {
+    pub data: &'a [u8],
+    pub chunk_size: usize,
+}
+
+impl Read for Pipe<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let size = buf.len().min(self.chunk_size);
+        self.data.read(&mut buf[..size])
+    }
+}
+
+pub fn pipe(data: &[u8]) -> Pipe<'_> {
+    Pipe {
+        data,
+        chunk_size: usize::MAX,
+    }
+}
+
+/// Options for XZ files with blocks of `block_size` bytes, which use the block size as
+/// dictionary size.
+pub fn xz_options(block_size: u64) -> XZOptions {
+    let mut options = XZOptions::with_preset(1);
+    options.lzma_options.dict_size = block_size as u32;
+    options.check_type = CheckType::Crc64;
+    options.set_block_size(NonZeroU64::new(block_size));
+    options
+}
+
+/// Compresses with [`XZWriter`], which doesn't store the sizes in the block headers.
+pub fn compress_xz(data: &[u8], options: XZOptions) -> Vec<u8> {
+    let mut writer = XZWriter::new(Vec::new(), options).unwrap();
+    writer.write_all(data).unwrap();
+    writer.finish().unwrap()
+}
+
+/// Compresses into LZIP members of `member_size` bytes, which use the member size as
+/// dictionary size.
+pub fn compress_lzip(data: &[u8], member_size: u64) -> Vec<u8> {
+    let mut options = LZIPOptions::with_preset(1);
+    options.lzma_options.dict_size = member_size as u32;
+    options.set_member_size(NonZeroU64::new(member_size));
+
+    let mut writer = LZIPWriter::new(Vec::new(), options);
+    writer.write_all(data).unwrap();
+    writer.finish().unwrap()
+}
+
+pub fn read_at<R: Read + Seek>(reader: &mut R, position: u64, len: usize) -> Vec<u8> {
+    reader.seek(SeekFrom::Start(position)).unwrap();
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf).unwrap();
+    buf
+}
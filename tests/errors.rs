@@ -0,0 +1,203 @@
+mod common;
+
+use std::io::{Cursor, ErrorKind, Read};
+
+use common::{compress_lzip, compress_xz, xz_options};
+use lzma_rust2::{
+    xz_inspect, CheckType, CheckValue, LZIPReader, LZIPReaderMT, LzmaError, XZReader, XZReaderMT,
+    XZSeekableReader,
+};
+
+const BLOCK_SIZE: usize = 64 * 1024;
+
+const CRC32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+fn test_data(len: usize, seed: u32) -> Vec<u8> {
+    let words: [&[u8]; 6] = [
+        b"lorem ",
+        b"ipsum ",
+        b"dolor ",
+        b"sit ",
+        b"amet\n",
+        b"consectetur ",
+    ];
+    let mut state = seed;
+    let mut data = Vec::with_capacity(len + 16);
+    while data.len() < len {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        data.extend_from_slice(words[(state >> 16) as usize % words.len()]);
+    }
+    data.truncate(len);
+    data
+}
+
+/// Compresses with a CRC32 check, which is easy to recompute in the tests.
+fn compress_crc32(data: &[u8]) -> Vec<u8> {
+    let mut options = xz_options(BLOCK_SIZE as u64);
+    options.check_type = CheckType::Crc32;
+    compress_xz(data, options)
+}
+
+fn read_error<R: Read>(mut reader: R) -> LzmaError {
+    let mut uncompressed = Vec::new();
+    let error = reader.read_to_end(&mut uncompressed).unwrap_err();
+    LzmaError::from_io_error(&error).expect("error has no LzmaError")
+}
+
+#[test]
+fn xz_checksum_mismatch() {
+    let data = test_data(3 * BLOCK_SIZE, 1);
+    let mut compressed = compress_crc32(&data);
+    let info = xz_inspect(Cursor::new(&compressed)).unwrap();
+
+    let block = &info.streams[0].blocks[1];
+    let check_pos = (block.start_pos + block.total_size - 4) as usize;
+    let expected = u32::from_le_bytes(compressed[check_pos..check_pos + 4].try_into().unwrap());
+    compressed[check_pos] ^= 0xFF;
+
+    let error = read_error(XZReader::new(compressed.as_slice(), false));
+    assert_eq!(
+        error,
+        LzmaError::ChecksumMismatch {
+            expected: CheckValue::Crc32(expected ^ 0xFF),
+            actual: CheckValue::Crc32(expected),
+        }
+    );
+
+    let seekable = XZSeekableReader::new(Cursor::new(compressed.as_slice()), false).unwrap();
+    assert_eq!(read_error(seekable), error);
+
+    let reader_mt = XZReaderMT::new(Cursor::new(compressed.as_slice()), false, 2).unwrap();
+    assert_eq!(read_error(reader_mt), error);
+}
+
+#[test]
+fn xz_corrupt_data_position() {
+    let data = test_data(3 * BLOCK_SIZE, 2);
+    let mut compressed = compress_crc32(&data);
+    let info = xz_inspect(Cursor::new(&compressed)).unwrap();
+
+    // Replace the LZMA2 control byte of the second block with an invalid one.
+    let block = &info.streams[0].blocks[1];
+    compressed[(block.start_pos + block.header_size) as usize] = 0x03;
+
+    let check_error = |error: LzmaError, exact_position: bool| {
+        let LzmaError::CorruptData {
+            compressed_offset: Some(compressed_offset),
+            uncompressed_offset: Some(uncompressed_offset),
+            ..
+        } = error
+        else {
+            panic!("unexpected error: {error}");
+        };
+
+        assert_eq!(uncompressed_offset, block.uncompressed_offset);
+        if exact_position {
+            assert!(compressed_offset > block.start_pos + block.header_size);
+            assert!(compressed_offset < block.start_pos + block.total_size);
+        } else {
+            assert_eq!(compressed_offset, block.start_pos);
+        }
+    };
+
+    check_error(
+        read_error(XZReader::new(compressed.as_slice(), false)),
+        true,
+    );
+
+    let seekable = XZSeekableReader::new(Cursor::new(compressed.as_slice()), false).unwrap();
+    check_error(read_error(seekable), true);
+
+    let reader_mt = XZReaderMT::new(Cursor::new(compressed.as_slice()), false, 2).unwrap();
+    check_error(read_error(reader_mt), false);
+}
+
+#[test]
+fn xz_unsupported_filter() {
+    let data = test_data(BLOCK_SIZE, 3);
+    let mut compressed = compress_crc32(&data);
+    let info = xz_inspect(Cursor::new(&compressed)).unwrap();
+
+    // The block header only contains the LZMA2 filter. Replace its ID and fix the header CRC32.
+    let block = &info.streams[0].blocks[0];
+    let header_start = block.start_pos as usize;
+    let header_end = header_start + block.header_size as usize;
+    let filter_pos = compressed[header_start + 2..]
+        .iter()
+        .position(|&byte| byte == 0x21)
+        .unwrap();
+    compressed[header_start + 2 + filter_pos] = 0x7F;
+    let crc = CRC32.checksum(&compressed[header_start..header_end - 4]);
+    compressed[header_end - 4..header_end].copy_from_slice(&crc.to_le_bytes());
+
+    let mut reader = XZReader::new(compressed.as_slice(), false);
+    let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+    assert_eq!(
+        LzmaError::from_io_error(&error),
+        Some(LzmaError::UnsupportedFilter(0x7F))
+    );
+}
+
+#[test]
+fn xz_mem_limit_exceeded() {
+    let data = test_data(BLOCK_SIZE, 4);
+    let compressed = compress_crc32(&data);
+    let info = xz_inspect(Cursor::new(&compressed)).unwrap();
+    let needed = info.streams[0].blocks[0].memory_usage;
+
+    let mut reader = XZReader::new_mem_limit(compressed.as_slice(), false, needed - 1);
+    let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+    assert_eq!(
+        LzmaError::from_io_error(&error),
+        Some(LzmaError::MemLimitExceeded {
+            needed,
+            limit: needed - 1,
+        })
+    );
+}
+
+#[test]
+fn lzip_checksum_mismatch() {
+    let data = test_data(BLOCK_SIZE, 5);
+    let mut compressed = compress_lzip(&data, BLOCK_SIZE as u64);
+
+    let crc_pos = compressed.len() - 20;
+    let expected = u32::from_le_bytes(compressed[crc_pos..crc_pos + 4].try_into().unwrap());
+    compressed[crc_pos] ^= 0xFF;
+
+    let error = read_error(LZIPReader::new(compressed.as_slice()).unwrap());
+    assert_eq!(
+        error,
+        LzmaError::ChecksumMismatch {
+            expected: CheckValue::Crc32(expected ^ 0xFF),
+            actual: CheckValue::Crc32(expected),
+        }
+    );
+}
+
+#[test]
+fn lzip_corrupt_data_position() {
+    let first = test_data(BLOCK_SIZE, 6);
+    let second = test_data(BLOCK_SIZE, 7);
+
+    let mut compressed = compress_lzip(&first, BLOCK_SIZE as u64);
+    let member_start = compressed.len();
+    compressed.extend_from_slice(&compress_lzip(&second, BLOCK_SIZE as u64));
+
+    // The first byte of the range coder must be zero.
+    compressed[member_start + 6] = 0x01;
+
+    let expected = LzmaError::CorruptData {
+        compressed_offset: Some(member_start as u64),
+        uncompressed_offset: Some(first.len() as u64),
+        reason: "range decoder first byte is not zero",
+    };
+
+    let error = read_error(LZIPReader::new(compressed.as_slice()).unwrap());
+    assert_eq!(error, expected);
+
+    let reader_mt = LZIPReaderMT::new(Cursor::new(compressed.as_slice()), 2).unwrap();
+    assert_eq!(read_error(reader_mt), expected);
+}
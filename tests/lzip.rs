@@ -1,11 +1,19 @@
-use std::io::{Read, Write};
+mod common;
 
-use lzma_rust2::{LZIPOptions, LZIPReader, LZIPWriter};
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+
+use common::{compress_lzip, read_at};
+use lzma_rust2::{
+    lzip_inspect, lzip_recover, LZIPDamage, LZIPInfo, LZIPOptions, LZIPReader, LZIPReaderMT,
+    LZIPSeekableReader, LZIPTrailingPolicy, LZIPWriter, LzmaError,
+};
 
 static EXECUTABLE: &str = "tests/data/executable.exe";
 static PG100: &str = "tests/data/pg100.txt";
 static PG6800: &str = "tests/data/pg6800.txt";
 
+const MEMBER_SIZE: usize = 64 * 1024;
+
 fn test_round_trip(path: &str, level: u32) {
     let data = std::fs::read(path).unwrap();
 
@@ -179,3 +187,560 @@ fn round_trip_pg6800_8() {
 fn round_trip_pg6800_9() {
     test_round_trip(PG6800, 9);
 }
+
+fn with_trailing(compressed: &[u8], trailing: &[u8]) -> Vec<u8> {
+    let mut file = compressed.to_vec();
+    file.extend_from_slice(trailing);
+    file
+}
+
+/// The decoded data and the trailing data.
+type Decoded = std::io::Result<(Vec<u8>, Vec<u8>)>;
+
+/// Decodes `file` with both readers.
+fn decode_trailing(file: &[u8], policy: LZIPTrailingPolicy) -> (Decoded, Decoded) {
+    let mut reader = LZIPReader::new(file).unwrap();
+    reader.set_trailing_policy(policy);
+    let mut uncompressed = Vec::new();
+    let result = reader
+        .read_to_end(&mut uncompressed)
+        .map(|_| (uncompressed, reader.trailing_data().to_vec()));
+
+    let result_mt = LZIPReaderMT::new(Cursor::new(file), 4).and_then(|mut reader| {
+        reader.set_trailing_policy(policy);
+        let mut uncompressed = Vec::new();
+        reader.read_to_end(&mut uncompressed)?;
+        assert_eq!(reader.member_count(), 5);
+
+        let mut trailing_data = Vec::new();
+        reader.into_inner().read_to_end(&mut trailing_data)?;
+        Ok((uncompressed, trailing_data))
+    });
+
+    (result, result_mt)
+}
+
+fn assert_trailing_ok(file: &[u8], policy: LZIPTrailingPolicy, data: &[u8]) {
+    let (result, result_mt) = decode_trailing(file, policy);
+    assert!(result.unwrap().0 == data);
+    assert!(result_mt.unwrap().0 == data);
+}
+
+fn assert_trailing_invalid(file: &[u8], policy: LZIPTrailingPolicy) {
+    let (result, result_mt) = decode_trailing(file, policy);
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(result_mt.unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+fn trailing_test_data() -> Vec<u8> {
+    let data = std::fs::read(PG6800).unwrap();
+    data[..4 * MEMBER_SIZE + 1000].to_vec()
+}
+
+#[test]
+fn trailing_none() {
+    let data = trailing_test_data();
+    let compressed = compress_lzip(&data, MEMBER_SIZE as u64);
+
+    for policy in [
+        LZIPTrailingPolicy::Error,
+        LZIPTrailingPolicy::Ignore,
+        LZIPTrailingPolicy::IgnoreLoose,
+        LZIPTrailingPolicy::Stop,
+    ] {
+        assert_trailing_ok(&compressed, policy, &data);
+    }
+}
+
+#[test]
+fn trailing_garbage() {
+    let data = trailing_test_data();
+    let compressed = compress_lzip(&data, MEMBER_SIZE as u64);
+
+    for trailing in [
+        &[0u8; 1][..],
+        &[0u8; 512][..],
+        b"This is some trailing text.",
+        b"ZIP",
+    ] {
+        let file = with_trailing(&compressed, trailing);
+        assert_trailing_invalid(&file, LZIPTrailingPolicy::Error);
+        assert_trailing_ok(&file, LZIPTrailingPolicy::Ignore, &data);
+        assert_trailing_ok(&file, LZIPTrailingPolicy::IgnoreLoose, &data);
+    }
+}
+
+#[test]
+fn trailing_corrupt_header() {
+    let data = trailing_test_data();
+    let compressed = compress_lzip(&data, MEMBER_SIZE as u64);
+
+    for trailing in [&b"L"[..], b"LZ", b"LZI", b"LZIP", b"LZIP\x05\x0C and more"] {
+        let file = with_trailing(&compressed, trailing);
+        assert_trailing_invalid(&file, LZIPTrailingPolicy::Error);
+        assert_trailing_invalid(&file, LZIPTrailingPolicy::Ignore);
+        assert_trailing_ok(&file, LZIPTrailingPolicy::IgnoreLoose, &data);
+    }
+}
+
+#[test]
+fn trailing_stop() {
+    let data = trailing_test_data();
+    let compressed = compress_lzip(&data, MEMBER_SIZE as u64);
+    let trailing = b"LZIP\x05 followed by another container";
+    let file = with_trailing(&compressed, trailing);
+
+    let (result, result_mt) = decode_trailing(&file, LZIPTrailingPolicy::Stop);
+
+    let (uncompressed, trailing_data) = result.unwrap();
+    assert!(uncompressed == data);
+    assert_eq!(trailing_data, &trailing[..6]);
+
+    // The MT reader leaves all trailing data in the inner reader.
+    let (uncompressed, trailing_data) = result_mt.unwrap();
+    assert!(uncompressed == data);
+    assert_eq!(trailing_data, trailing);
+
+    // The rest of the trailing data stays in the inner reader.
+    let mut reader = LZIPReader::new(file.as_slice()).unwrap();
+    reader.set_trailing_policy(LZIPTrailingPolicy::Stop);
+    std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+    assert_eq!(reader.into_inner(), &trailing[6..]);
+}
+
+#[test]
+fn trailing_large() {
+    let data = trailing_test_data();
+    let compressed = compress_lzip(&data, MEMBER_SIZE as u64);
+
+    // The trailing data is larger than the blocks in which the last member is searched and
+    // contains a member size that points to a position without a header.
+    let mut trailing = vec![0xAA; 200 * 1024];
+    let len = trailing.len();
+    trailing[len - 8..].copy_from_slice(&100u64.to_le_bytes());
+    let file = with_trailing(&compressed, &trailing);
+
+    assert_trailing_ok(&file, LZIPTrailingPolicy::Ignore, &data);
+
+    let (_, result_mt) = decode_trailing(&file, LZIPTrailingPolicy::Stop);
+    assert!(result_mt.unwrap().1 == trailing);
+}
+
+#[test]
+fn trailing_not_lzip() {
+    let file = [0xAAu8; 1024];
+
+    for policy in [LZIPTrailingPolicy::Ignore, LZIPTrailingPolicy::IgnoreLoose] {
+        let mut reader = LZIPReader::new(file.as_slice()).unwrap();
+        reader.set_trailing_policy(policy);
+        let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        let error = LZIPReaderMT::new(Cursor::new(file), 4).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn seekable_read_to_end() {
+    let data = std::fs::read(PG100).unwrap();
+    let compressed = compress_lzip(&data, 256 * 1024);
+
+    let mut reader = LZIPSeekableReader::new(Cursor::new(compressed)).unwrap();
+    assert!(reader.member_count() > 1);
+    assert_eq!(reader.uncompressed_size(), data.len() as u64);
+
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+
+    // We don't use assert_eq since the debug output would be too big.
+    assert!(uncompressed == data);
+}
+
+#[test]
+fn seekable_random_access() {
+    let data = std::fs::read(PG100).unwrap();
+    let compressed = compress_lzip(&data, 256 * 1024);
+
+    let mut reader = LZIPSeekableReader::new(Cursor::new(compressed)).unwrap();
+
+    let len = data.len() as u64;
+    let positions = [
+        len / 2,
+        0,
+        len - 100,
+        256 * 1024 - 50,
+        256 * 1024,
+        1000,
+        len / 3,
+        len / 3 + 10,
+    ];
+
+    for position in positions {
+        let chunk = read_at(&mut reader, position, 100);
+        let start = position as usize;
+        assert!(chunk.as_slice() == &data[start..start + 100]);
+    }
+}
+
+#[test]
+fn seekable_relative_seeks() {
+    let data = std::fs::read(PG6800).unwrap();
+    let compressed = compress_lzip(&data, 64 * 1024);
+
+    let mut reader = LZIPSeekableReader::new(Cursor::new(compressed)).unwrap();
+
+    let position = reader.seek(SeekFrom::End(-10)).unwrap();
+    assert_eq!(position, data.len() as u64 - 10);
+
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf).unwrap();
+    assert!(buf.as_slice() == &data[data.len() - 10..]);
+
+    let position = reader.seek(SeekFrom::Current(-1000)).unwrap();
+    assert_eq!(position, data.len() as u64 - 1000);
+
+    let mut buf = [0u8; 10];
+    reader.read_exact(&mut buf).unwrap();
+    assert!(buf.as_slice() == &data[data.len() - 1000..data.len() - 990]);
+
+    assert!(reader
+        .seek(SeekFrom::Current(-(data.len() as i64)))
+        .is_err());
+
+    reader
+        .seek(SeekFrom::Start(data.len() as u64 + 10))
+        .unwrap();
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn seekable_trailing_data() {
+    let data = std::fs::read(PG6800).unwrap();
+    let mut compressed = compress_lzip(&data, 64 * 1024);
+    compressed.extend_from_slice(&[0u8; 100]);
+
+    let mut reader = LZIPSeekableReader::new(Cursor::new(compressed)).unwrap();
+    assert_eq!(reader.uncompressed_size(), data.len() as u64);
+
+    let position = data.len() as u64 - 50;
+    let chunk = read_at(&mut reader, position, 50);
+    assert!(chunk.as_slice() == &data[position as usize..]);
+}
+
+#[test]
+fn seekable_corrupt_crc() {
+    let data = std::fs::read(PG6800).unwrap();
+    let mut compressed = compress_lzip(&data, 64 * 1024);
+
+    // The CRC32 of the last member.
+    let crc_pos = compressed.len() - 20;
+    compressed[crc_pos] ^= 0xFF;
+
+    let mut reader = LZIPSeekableReader::new(Cursor::new(compressed)).unwrap();
+
+    // Members in front of the damaged one can still be read.
+    let chunk = read_at(&mut reader, 1000, 100);
+    assert!(chunk.as_slice() == &data[1000..1100]);
+
+    reader.seek(SeekFrom::End(-10)).unwrap();
+    let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(matches!(
+        LzmaError::from_io_error(&error),
+        Some(LzmaError::ChecksumMismatch { .. })
+    ));
+}
+
+#[test]
+fn inspect_multiple_members() {
+    let data = std::fs::read(PG6800).unwrap();
+    let compressed = compress_lzip(&data, MEMBER_SIZE as u64);
+
+    let info = lzip_inspect(Cursor::new(&compressed)).unwrap();
+
+    assert_eq!(info.file_size, compressed.len() as u64);
+    assert_eq!(info.trailing_size, 0);
+    assert_eq!(info.uncompressed_size(), data.len() as u64);
+    assert_eq!(info.members.len(), data.len().div_ceil(MEMBER_SIZE));
+
+    let mut reader = LZIPReader::new(compressed.as_slice()).unwrap();
+    std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+
+    let mut uncompressed_offset = 0;
+    let mut start_pos = 0;
+
+    for (member, trailer) in info.members.iter().zip(reader.trailers()) {
+        assert_eq!(member.start_pos, start_pos);
+        assert_eq!(member.uncompressed_offset, uncompressed_offset);
+        assert_eq!(member.member_size, trailer.member_size);
+        assert_eq!(member.data_size, trailer.data_size);
+        assert_eq!(member.crc32, trailer.crc32);
+        assert_eq!(member.version, 1);
+        assert_eq!(member.dict_size, MEMBER_SIZE as u32);
+        assert_eq!(
+            member.memory_usage,
+            lzma_rust2::lzma_get_memory_usage(MEMBER_SIZE as u32, 3, 0).unwrap()
+        );
+
+        start_pos += member.member_size;
+        uncompressed_offset += member.data_size;
+    }
+
+    assert_eq!(start_pos, compressed.len() as u64);
+    assert_eq!(info.memory_usage(), info.members[0].memory_usage);
+}
+
+#[test]
+fn inspect_trailing_data() {
+    let data = std::fs::read(PG6800).unwrap();
+    let mut compressed = compress_lzip(&data, MEMBER_SIZE as u64);
+    let members_size = compressed.len() as u64;
+    compressed.extend_from_slice(b"some trailing data");
+
+    let info = lzip_inspect(Cursor::new(&compressed)).unwrap();
+
+    assert_eq!(info.file_size, compressed.len() as u64);
+    assert_eq!(info.trailing_size, 18);
+    assert_eq!(info.uncompressed_size(), data.len() as u64);
+
+    let last = info.members.last().unwrap();
+    assert_eq!(last.start_pos + last.member_size, members_size);
+}
+
+#[test]
+fn inspect_version_0() {
+    let data = std::fs::read(PG6800).unwrap();
+    let data = &data[..MEMBER_SIZE / 2];
+    let mut compressed = compress_lzip(data, MEMBER_SIZE as u64);
+
+    // Version 0 has the same header and a trailer without the member size.
+    compressed[4] = 0;
+    compressed.truncate(compressed.len() - 8);
+
+    let info = lzip_inspect(Cursor::new(&compressed)).unwrap();
+
+    assert_eq!(info.members.len(), 1);
+    assert_eq!(info.members[0].version, 0);
+    assert_eq!(info.members[0].member_size, compressed.len() as u64);
+    assert_eq!(info.members[0].data_size, data.len() as u64);
+}
+
+#[test]
+fn inspect_not_lzip() {
+    assert!(lzip_inspect(Cursor::new(vec![0x42; 1000])).is_err());
+}
+
+fn compress_and_inspect(data: &[u8]) -> (Vec<u8>, LZIPInfo) {
+    let compressed = compress_lzip(data, MEMBER_SIZE as u64);
+    let info = lzip_inspect(Cursor::new(&compressed)).unwrap();
+
+    (compressed, info)
+}
+
+fn recover_test_data() -> Vec<u8> {
+    let data = std::fs::read(PG100).unwrap();
+    data[..16 * MEMBER_SIZE + 1000].to_vec()
+}
+
+/// Returns the data without the given member.
+fn without_member(data: &[u8], member: usize) -> Vec<u8> {
+    let mut expected = data[..member * MEMBER_SIZE].to_vec();
+    expected.extend_from_slice(&data[(member + 1) * MEMBER_SIZE..]);
+    expected
+}
+
+#[test]
+fn recover_intact_file() {
+    let data = recover_test_data();
+    let (compressed, info) = compress_and_inspect(&data);
+
+    let mut recovered = Vec::new();
+    let recovery = lzip_recover(Cursor::new(&compressed), &mut recovered).unwrap();
+
+    assert!(recovery.used_trailers);
+    assert!(recovery.lost.is_empty());
+    assert_eq!(recovery.recovered_members, info.members.len() as u64);
+    assert_eq!(recovery.recovered_size, data.len() as u64);
+    assert!(recovered == data);
+}
+
+#[test]
+fn recover_corrupt_data() {
+    let data = recover_test_data();
+    let (mut compressed, info) = compress_and_inspect(&data);
+
+    let member = &info.members[3];
+    compressed[(member.start_pos + member.member_size / 2) as usize] ^= 0x55;
+
+    let mut recovered = Vec::new();
+    let recovery = lzip_recover(Cursor::new(&compressed), &mut recovered).unwrap();
+
+    assert!(recovery.used_trailers);
+    assert_eq!(recovery.recovered_members, info.members.len() as u64 - 1);
+    assert_eq!(recovery.lost.len(), 1);
+
+    let lost = &recovery.lost[0];
+    assert_eq!(lost.compressed_offset, member.start_pos);
+    assert_eq!(lost.compressed_size, member.member_size);
+    assert_eq!(lost.uncompressed_offset, Some(3 * MEMBER_SIZE as u64));
+    assert_eq!(lost.uncompressed_size, Some(MEMBER_SIZE as u64));
+    assert!(matches!(
+        lost.damage,
+        LZIPDamage::CorruptData | LZIPDamage::SizeMismatch | LZIPDamage::Checksum
+    ));
+
+    assert!(recovered == without_member(&data, 3));
+}
+
+#[test]
+fn recover_bad_checksum() {
+    let data = recover_test_data();
+    let (mut compressed, info) = compress_and_inspect(&data);
+
+    // The CRC32 is the first field of the trailer.
+    let member = &info.members[5];
+    compressed[(member.start_pos + member.member_size - 20) as usize] ^= 0x01;
+
+    let mut recovered = Vec::new();
+    let recovery = lzip_recover(Cursor::new(&compressed), &mut recovered).unwrap();
+
+    assert!(recovery.used_trailers);
+    assert_eq!(recovery.lost.len(), 1);
+    assert_eq!(recovery.lost[0].damage, LZIPDamage::Checksum);
+    assert_eq!(
+        recovery.lost[0].uncompressed_offset,
+        Some(5 * MEMBER_SIZE as u64)
+    );
+    assert!(recovered == without_member(&data, 5));
+}
+
+#[test]
+fn recover_bad_member_size() {
+    let data = recover_test_data();
+    let (mut compressed, info) = compress_and_inspect(&data);
+
+    // The member size is the last field of the trailer.
+    let member = &info.members[7];
+    compressed[(member.start_pos + member.member_size - 8) as usize] ^= 0x01;
+
+    let mut recovered = Vec::new();
+    let recovery = lzip_recover(Cursor::new(&compressed), &mut recovered).unwrap();
+
+    assert!(!recovery.used_trailers);
+    assert_eq!(recovery.recovered_members, info.members.len() as u64 - 1);
+    assert_eq!(recovery.lost.len(), 1);
+
+    let lost = &recovery.lost[0];
+    assert_eq!(lost.compressed_offset, member.start_pos);
+    assert_eq!(lost.compressed_size, member.member_size);
+    assert_eq!(lost.uncompressed_offset, Some(7 * MEMBER_SIZE as u64));
+    assert_eq!(lost.uncompressed_size, None);
+    assert_eq!(lost.damage, LZIPDamage::SizeMismatch);
+
+    assert!(recovered == without_member(&data, 7));
+}
+
+#[test]
+fn recover_huge_data_size() {
+    let data = recover_test_data();
+    let (mut compressed, info) = compress_and_inspect(&data);
+
+    // The data size is the second field of the trailer. It's far too big to be buffered.
+    let member = &info.members[7];
+    let pos = (member.start_pos + member.member_size - 16) as usize;
+    compressed[pos..pos + 8].copy_from_slice(&(1u64 << 50).to_le_bytes());
+
+    let mut recovered = Vec::new();
+    let recovery = lzip_recover(Cursor::new(&compressed), &mut recovered).unwrap();
+
+    assert_eq!(recovery.lost.len(), 1);
+    assert_eq!(recovery.lost[0].compressed_offset, member.start_pos);
+    assert_eq!(recovery.lost[0].damage, LZIPDamage::SizeMismatch);
+    assert!(recovered == without_member(&data, 7));
+}
+
+#[test]
+fn recover_bad_header() {
+    let data = recover_test_data();
+    let (mut compressed, info) = compress_and_inspect(&data);
+
+    let member = &info.members[9];
+    compressed[member.start_pos as usize + 1] ^= 0x01;
+
+    let mut recovered = Vec::new();
+    let recovery = lzip_recover(Cursor::new(&compressed), &mut recovered).unwrap();
+
+    assert!(!recovery.used_trailers);
+    assert_eq!(recovery.lost.len(), 1);
+    assert_eq!(recovery.lost[0].compressed_offset, member.start_pos);
+    assert_eq!(recovery.lost[0].compressed_size, member.member_size);
+    assert_eq!(recovery.lost[0].damage, LZIPDamage::Header);
+    assert!(recovered == without_member(&data, 9));
+}
+
+#[test]
+fn recover_damaged_last_member() {
+    let data = recover_test_data();
+    let (mut compressed, info) = compress_and_inspect(&data);
+    compressed.extend_from_slice(&[0u8; 100]);
+
+    // A damaged member size breaks the backward scan, so the members are located by their headers.
+    let member = info.members.last().unwrap();
+    compressed[(member.start_pos + member.member_size - 1) as usize] ^= 0x01;
+
+    let mut recovered = Vec::new();
+    let recovery = lzip_recover(Cursor::new(&compressed), &mut recovered).unwrap();
+
+    assert!(!recovery.used_trailers);
+    assert_eq!(recovery.recovered_members, info.members.len() as u64 - 1);
+    assert_eq!(recovery.lost.len(), 1);
+    assert_eq!(recovery.lost[0].compressed_offset, member.start_pos);
+    assert_eq!(recovery.lost[0].damage, LZIPDamage::SizeMismatch);
+    assert_eq!(
+        recovery.lost[0].uncompressed_offset,
+        Some(member.uncompressed_offset)
+    );
+    assert!(recovered == data[..member.uncompressed_offset as usize]);
+}
+
+#[test]
+fn recover_truncated_file() {
+    let data = recover_test_data();
+    let (compressed, info) = compress_and_inspect(&data);
+
+    let member = &info.members[10];
+    let truncated = &compressed[..(member.start_pos + member.member_size / 2) as usize];
+
+    let mut recovered = Vec::new();
+    let recovery = lzip_recover(Cursor::new(truncated), &mut recovered).unwrap();
+
+    assert_eq!(recovery.recovered_members, 10);
+    assert_eq!(recovery.lost.len(), 1);
+    assert_eq!(recovery.lost[0].compressed_offset, member.start_pos);
+    assert_eq!(
+        recovery.lost[0].compressed_offset + recovery.lost[0].compressed_size,
+        truncated.len() as u64
+    );
+    assert!(recovered == data[..10 * MEMBER_SIZE]);
+}
+
+#[test]
+fn recover_trailing_data() {
+    let data = recover_test_data();
+    let (mut compressed, info) = compress_and_inspect(&data);
+    compressed.extend_from_slice(b"LZIP\x05 but not a member");
+
+    let mut recovered = Vec::new();
+    let recovery = lzip_recover(Cursor::new(&compressed), &mut recovered).unwrap();
+
+    assert!(recovery.lost.is_empty());
+    assert_eq!(recovery.recovered_members, info.members.len() as u64);
+    assert!(recovered == data);
+}
+
+#[test]
+fn recover_no_member() {
+    let mut recovered = Vec::new();
+    assert!(lzip_recover(Cursor::new(vec![0x42; 1000]), &mut recovered).is_err());
+}
@@ -1,14 +1,22 @@
+mod common;
+
 use std::{
-    io::{Cursor, Read, Write},
+    io::{Cursor, ErrorKind, Read, Write},
     num::{NonZero, NonZeroU64},
 };
 
-use lzma_rust2::{LZIPOptions, LZIPReaderMT, LZIPWriterMT};
+use common::{compress_lzip, pipe, Pipe};
+use lzma_rust2::{
+    lzip_verify, LZIPOptions, LZIPReaderMT, LZIPStreamReaderMT, LZIPTrailingPolicy, LZIPWriter,
+    LZIPWriterMT,
+};
 
 static EXECUTABLE: &str = "tests/data/executable.exe";
 static PG100: &str = "tests/data/pg100.txt";
 static PG6800: &str = "tests/data/pg6800.txt";
 
+const MEMBER_SIZE: u32 = 64 * 1024;
+
 fn test_round_trip(path: &str, level: u32) {
     let data = std::fs::read(path).unwrap();
     let data_len = data.len() as u32;
@@ -196,3 +204,251 @@ fn round_trip_pg6800_8() {
 fn round_trip_pg6800_9() {
     test_round_trip(PG6800, 9);
 }
+
+fn decode_stream(reader: Pipe, policy: LZIPTrailingPolicy) -> std::io::Result<(Vec<u8>, Vec<u8>)> {
+    let mut reader = LZIPStreamReaderMT::new(reader, 4);
+    reader.set_trailing_policy(policy);
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed)?;
+
+    // The trailing data that was not read yet is still in the inner reader.
+    let mut trailing_data = reader.trailing_data().to_vec();
+    reader.into_inner().read_to_end(&mut trailing_data)?;
+    Ok((uncompressed, trailing_data))
+}
+
+#[test]
+fn stream_multiple_members() {
+    let data = std::fs::read(PG100).unwrap();
+    let compressed = compress_lzip(&data, MEMBER_SIZE as u64);
+
+    let mut reader = LZIPStreamReaderMT::new(pipe(&compressed), 4);
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+
+    // We don't use assert_eq since the debug output would be too big.
+    assert!(uncompressed == data);
+    assert_eq!(
+        reader.member_count(),
+        data.len().div_ceil(MEMBER_SIZE as usize)
+    );
+    assert_eq!(reader.trailers().len(), reader.member_count());
+
+    let member_sizes: u64 = reader
+        .trailers()
+        .iter()
+        .map(|trailer| trailer.member_size)
+        .sum();
+    assert_eq!(member_sizes, compressed.len() as u64);
+}
+
+#[test]
+fn stream_small_reads() {
+    let data = std::fs::read(PG6800).unwrap();
+    let compressed = compress_lzip(&data, MEMBER_SIZE as u64);
+
+    for chunk_size in [1, 7, 1000] {
+        let reader = Pipe {
+            data: &compressed,
+            chunk_size,
+        };
+        let (uncompressed, _) = decode_stream(reader, LZIPTrailingPolicy::Ignore).unwrap();
+        assert!(uncompressed == data);
+    }
+}
+
+#[test]
+fn stream_single_member() {
+    let data = std::fs::read(PG6800).unwrap();
+
+    let mut writer = LZIPWriter::new(Vec::new(), LZIPOptions::with_preset(1));
+    writer.write_all(&data).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    let (uncompressed, _) = decode_stream(pipe(&compressed), LZIPTrailingPolicy::Ignore).unwrap();
+    assert!(uncompressed == data);
+}
+
+#[test]
+fn stream_writer_mt() {
+    let data = std::fs::read(PG100).unwrap();
+
+    let mut options = LZIPOptions::with_preset(1);
+    options.set_member_size(NonZeroU64::new(256 * 1024));
+    let mut writer = LZIPWriterMT::new(Vec::new(), options, 4).unwrap();
+    writer.write_all(&data).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    let (uncompressed, _) = decode_stream(pipe(&compressed), LZIPTrailingPolicy::Ignore).unwrap();
+    assert!(uncompressed == data);
+}
+
+#[test]
+fn stream_trailing_data() {
+    let data = std::fs::read(PG6800).unwrap();
+    let compressed = compress_lzip(&data, MEMBER_SIZE as u64);
+
+    for trailing in [&b"x"[..], b"some trailing data", &[0u8; 100_000]] {
+        let mut file = compressed.clone();
+        file.extend_from_slice(trailing);
+
+        let (uncompressed, _) = decode_stream(pipe(&file), LZIPTrailingPolicy::Ignore).unwrap();
+        assert!(uncompressed == data);
+
+        let (uncompressed, trailing_data) =
+            decode_stream(pipe(&file), LZIPTrailingPolicy::Stop).unwrap();
+        assert!(uncompressed == data);
+        assert!(trailing_data == trailing);
+
+        let error = decode_stream(pipe(&file), LZIPTrailingPolicy::Error).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    let mut file = compressed.clone();
+    file.extend_from_slice(b"LZIP\x05 corrupt header");
+
+    let error = decode_stream(pipe(&file), LZIPTrailingPolicy::Ignore).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+    let (uncompressed, _) = decode_stream(pipe(&file), LZIPTrailingPolicy::IgnoreLoose).unwrap();
+    assert!(uncompressed == data);
+}
+
+#[test]
+fn stream_corrupt_member() {
+    let data = std::fs::read(PG6800).unwrap();
+    let mut compressed = compress_lzip(&data, MEMBER_SIZE as u64);
+    let length = compressed.len();
+    compressed[length / 2] ^= 0x55;
+
+    let error = decode_stream(pipe(&compressed), LZIPTrailingPolicy::Ignore).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn stream_truncated() {
+    let data = std::fs::read(PG6800).unwrap();
+    let compressed = compress_lzip(&data, MEMBER_SIZE as u64);
+    let truncated = &compressed[..compressed.len() - 10];
+
+    let error = decode_stream(pipe(truncated), LZIPTrailingPolicy::Ignore).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn stream_version_0() {
+    let data = std::fs::read(PG6800).unwrap();
+    let data = &data[..MEMBER_SIZE as usize / 2];
+    let mut compressed = compress_lzip(data, MEMBER_SIZE as u64);
+
+    // Version 0 has the same header and a trailer without the member size.
+    compressed[4] = 0;
+    compressed.truncate(compressed.len() - 8);
+
+    let error = decode_stream(pipe(&compressed), LZIPTrailingPolicy::Ignore).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn stream_not_lzip() {
+    for file in [&[][..], &[0xAA; 1024][..]] {
+        assert!(decode_stream(pipe(file), LZIPTrailingPolicy::IgnoreLoose).is_err());
+    }
+}
+
+#[test]
+fn stream_verify() {
+    let data = std::fs::read(PG6800).unwrap();
+    let compressed = compress_lzip(&data, MEMBER_SIZE as u64);
+
+    let summary = LZIPStreamReaderMT::new(pipe(&compressed), 4)
+        .verify()
+        .unwrap();
+    assert_eq!(summary, lzip_verify(compressed.as_slice()).unwrap());
+    assert_eq!(summary.uncompressed_size, data.len() as u64);
+    assert_eq!(summary.compressed_size, compressed.len() as u64);
+}
+
+#[test]
+fn stream_trailing_data_not_buffered() {
+    let data = std::fs::read(PG6800).unwrap();
+    let mut file = compress_lzip(&data, MEMBER_SIZE as u64);
+    file.extend_from_slice(&vec![0xAA; 1024 * 1024]);
+
+    // The last trailer is followed by data that can't be a member header, so the reader stops
+    // there instead of buffering the trailing data to its end.
+    let mut reader = LZIPStreamReaderMT::new(pipe(&file), 4);
+    reader.set_trailing_policy(LZIPTrailingPolicy::Stop);
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+
+    assert!(uncompressed == data);
+    assert!(reader.trailing_data().len() <= 128 * 1024);
+}
+
+#[test]
+fn stream_single_member_not_buffered() {
+    let data = std::fs::read(PG100).unwrap().repeat(3);
+
+    let mut options = LZIPOptions::with_preset(1);
+    options.lzma_options.dict_size = MEMBER_SIZE;
+    let mut writer = LZIPWriter::new(Vec::new(), options);
+    writer.write_all(&data).unwrap();
+    let file = writer.finish().unwrap();
+    assert!(file.len() > 1024 * 1024 + 256 * 1024);
+
+    // The member is far bigger than its dictionary, so it's decoded directly instead of being
+    // buffered until its trailer was found.
+    let mut reader = LZIPStreamReaderMT::new(pipe(&file), 4);
+    let mut buf = [0u8; 1000];
+    reader.read_exact(&mut buf).unwrap();
+    assert!(buf == data[..1000]);
+    let consumed = file.len() - reader.into_inner().data.len();
+    assert!(consumed < 1024 * 1024 + 256 * 1024);
+
+    let (uncompressed, _) = decode_stream(pipe(&file), LZIPTrailingPolicy::Ignore).unwrap();
+    assert!(uncompressed == data);
+}
+
+#[test]
+fn stream_mem_limit() {
+    let data = std::fs::read(PG100).unwrap();
+    let (first, rest) = data.split_at(500_000);
+    let (second, third) = rest.split_at(1_000_000);
+
+    // The member in the middle doesn't fit into the memory limit and is decoded directly.
+    let mut options = LZIPOptions::with_preset(3);
+    options.lzma_options.dict_size = MEMBER_SIZE;
+    let mut writer = LZIPWriter::new(Vec::new(), options);
+    writer.write_all(second).unwrap();
+
+    let mut file = compress_lzip(first, MEMBER_SIZE as u64);
+    file.extend_from_slice(&writer.finish().unwrap());
+    file.extend_from_slice(&compress_lzip(third, MEMBER_SIZE as u64));
+
+    let mut reader = LZIPStreamReaderMT::new_mem_limit(
+        Pipe {
+            data: &file,
+            chunk_size: 1000,
+        },
+        4,
+        1024,
+    );
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+
+    assert!(uncompressed == data);
+    let summary = lzip_verify(file.as_slice()).unwrap();
+    assert_eq!(reader.member_count() as u64, summary.member_count);
+    assert_eq!(reader.trailers().len() as u64, summary.member_count);
+
+    let verified = LZIPStreamReaderMT::new_mem_limit(pipe(&file), 4, 1024)
+        .verify()
+        .unwrap();
+    assert_eq!(verified, summary);
+
+    // Not even the dictionary fits.
+    let mut reader = LZIPStreamReaderMT::new_mem_limit(pipe(&file), 4, 16);
+    let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+}
@@ -0,0 +1,135 @@
+use std::{
+    io::{Cursor, Read, Write},
+    num::NonZeroU64,
+};
+
+use lzma_rust2::{LZIPOptions, LZIPReader, LZIPReaderMT, LZIPWriter};
+
+static PG6800: &str = "tests/data/pg6800.txt";
+
+const MEMBER_SIZE: u32 = 64 * 1024;
+
+/// Compresses `data` into version 1 members of `MEMBER_SIZE` bytes and converts the members for
+/// which `version_0` returns true into version 0 members.
+fn compress(data: &[u8], version_0: impl Fn(usize) -> bool) -> Vec<u8> {
+    let mut options = LZIPOptions::with_preset(3);
+    options.lzma_options.dict_size = MEMBER_SIZE;
+    options.set_member_size(NonZeroU64::new(MEMBER_SIZE as u64));
+
+    let mut writer = LZIPWriter::new(Vec::new(), options);
+    writer.write_all(data).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    let mut reader = LZIPReader::new(compressed.as_slice()).unwrap();
+    std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+
+    let mut converted = Vec::new();
+    let mut start = 0;
+    for (index, trailer) in reader.trailers().iter().enumerate() {
+        let member = &compressed[start..start + trailer.member_size as usize];
+        start += trailer.member_size as usize;
+
+        if version_0(index) {
+            // Version 0 has the same header and a trailer without the member size.
+            converted.extend_from_slice(&member[..4]);
+            converted.push(0);
+            converted.extend_from_slice(&member[5..member.len() - 8]);
+        } else {
+            converted.extend_from_slice(member);
+        }
+    }
+
+    assert_eq!(start, compressed.len());
+    converted
+}
+
+fn check(data: &[u8], compressed: &[u8], member_count: usize) {
+    let mut reader = LZIPReader::new(compressed).unwrap();
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+    assert!(uncompressed == data);
+    assert_eq!(reader.trailers().len(), member_count);
+    let member_sizes: u64 = reader
+        .trailers()
+        .iter()
+        .map(|trailer| trailer.member_size)
+        .sum();
+    assert_eq!(member_sizes, compressed.len() as u64);
+
+    let mut reader = LZIPReaderMT::new(Cursor::new(compressed), 4).unwrap();
+    assert_eq!(reader.member_count(), member_count);
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+    assert!(uncompressed == data);
+}
+
+fn member_count(data: &[u8]) -> usize {
+    data.len().div_ceil(MEMBER_SIZE as usize)
+}
+
+#[test]
+fn version_0() {
+    let data = std::fs::read(PG6800).unwrap();
+    let compressed = compress(&data, |_| true);
+    check(&data, &compressed, member_count(&data));
+}
+
+#[test]
+fn version_0_single_member() {
+    let data = std::fs::read(PG6800).unwrap();
+    let data = &data[..MEMBER_SIZE as usize / 2];
+    let compressed = compress(data, |_| true);
+    check(data, &compressed, 1);
+}
+
+#[test]
+fn mixed_versions() {
+    let data = std::fs::read(PG6800).unwrap();
+    let count = member_count(&data);
+
+    let compressed = compress(&data, |index| index < count / 2);
+    check(&data, &compressed, count);
+
+    let compressed = compress(&data, |index| index >= count / 2);
+    check(&data, &compressed, count);
+
+    let compressed = compress(&data, |index| index % 2 == 0);
+    check(&data, &compressed, count);
+}
+
+#[test]
+fn version_0_corrupt() {
+    let data = std::fs::read(PG6800).unwrap();
+    let mut compressed = compress(&data, |_| true);
+    let length = compressed.len();
+    compressed[length / 2] ^= 0x55;
+
+    let mut reader = LZIPReader::new(compressed.as_slice()).unwrap();
+    assert!(reader.read_to_end(&mut Vec::new()).is_err());
+
+    assert!(LZIPReaderMT::new(Cursor::new(compressed), 4).is_err());
+}
+
+#[test]
+fn version_0_trailing_data() {
+    let data = std::fs::read(PG6800).unwrap();
+    let count = member_count(&data);
+
+    for mut file in [
+        compress(&data, |_| true),
+        compress(&data, |index| index >= count / 2),
+    ] {
+        file.extend_from_slice(b"trailing data");
+
+        let mut reader = LZIPReader::new(file.as_slice()).unwrap();
+        let mut uncompressed = Vec::new();
+        reader.read_to_end(&mut uncompressed).unwrap();
+        assert!(uncompressed == data);
+
+        let mut reader = LZIPReaderMT::new(Cursor::new(file), 4).unwrap();
+        assert_eq!(reader.member_count(), count);
+        let mut uncompressed = Vec::new();
+        reader.read_to_end(&mut uncompressed).unwrap();
+        assert!(uncompressed == data);
+    }
+}
@@ -1,14 +1,17 @@
 use std::{
-    io::{Cursor, Read, Write},
+    io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write},
     num::{NonZero, NonZeroU64},
+    sync::Arc,
 };
 
-use lzma_rust2::{LZMA2Options, LZMA2ReaderMT, LZMA2WriterMT};
+use lzma_rust2::{LZMA2Options, LZMA2ReaderMT, LZMA2SharedReaderMT, LZMA2Writer, LZMA2WriterMT};
 
 static EXECUTABLE: &str = "tests/data/executable.exe";
 static PG100: &str = "tests/data/pg100.txt";
 static PG6800: &str = "tests/data/pg6800.txt";
 
+const CHUNK_SIZE: u32 = 64 * 1024;
+
 fn test_round_trip(path: &str, level: u32) {
     let data = std::fs::read(path).unwrap();
     let data_len = data.len() as u32;
@@ -200,3 +203,164 @@ fn round_trip_pg6800_8() {
 fn round_trip_pg6800_9() {
     test_round_trip(PG6800, 9);
 }
+
+/// Returns the uncompressed sizes of the series of chunks that start with an independent chunk.
+fn independent_chunk_sizes(mut compressed: &[u8]) -> Vec<u64> {
+    let mut sizes = Vec::new();
+
+    loop {
+        let control = compressed[0];
+        let (uncompressed_size, chunk_size) = match control {
+            0x00 => return sizes,
+            0x01 | 0x02 => {
+                let size = u16::from_be_bytes([compressed[1], compressed[2]]) as u64 + 1;
+                (size, 3 + size as usize)
+            }
+            _ => {
+                let size = (((control & 0x1F) as u64) << 16)
+                    + u16::from_be_bytes([compressed[1], compressed[2]]) as u64
+                    + 1;
+                let compressed_size =
+                    u16::from_be_bytes([compressed[3], compressed[4]]) as usize + 1;
+                let header_size = if control >= 0xC0 { 6 } else { 5 };
+                (size, header_size + compressed_size)
+            }
+        };
+
+        if control >= 0xE0 || control == 0x01 {
+            sizes.push(0);
+        }
+        *sizes.last_mut().unwrap() += uncompressed_size;
+        compressed = &compressed[chunk_size..];
+    }
+}
+
+#[test]
+fn single_write_is_split_into_chunks() {
+    let data = std::fs::read(PG100).unwrap();
+
+    let mut option = LZMA2Options::with_preset(1);
+    let dict_size = option.lzma_options.dict_size;
+    option.set_chunk_size(NonZeroU64::new(dict_size as u64));
+
+    let mut writer = LZMA2Writer::new(Vec::new(), option);
+    writer.write_all(&data).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    let sizes = independent_chunk_sizes(&compressed);
+    assert_eq!(sizes.len(), data.len().div_ceil(dict_size as usize));
+    assert!(sizes[..sizes.len() - 1]
+        .iter()
+        .all(|&size| size == dict_size as u64));
+
+    let mut reader = LZMA2ReaderMT::new(compressed.as_slice(), dict_size, None, 4);
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+    assert!(uncompressed == data);
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut options = LZMA2Options::with_preset(1);
+    options.lzma_options.dict_size = CHUNK_SIZE;
+    options.set_chunk_size(NonZeroU64::new(CHUNK_SIZE as u64));
+
+    let mut writer = LZMA2WriterMT::new(Vec::new(), options, 4).unwrap();
+    writer.write_all(data).unwrap();
+    writer.finish().unwrap()
+}
+
+fn decode_shared<R: Read + Seek + Clone + Send + 'static>(inner: R) -> std::io::Result<Vec<u8>> {
+    let mut reader = LZMA2SharedReaderMT::new(inner, CHUNK_SIZE, None, 4)?;
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed)?;
+    Ok(uncompressed)
+}
+
+#[test]
+fn shared_arc_slice() {
+    let data = std::fs::read(PG100).unwrap();
+    let compressed: Arc<[u8]> = compress(&data).into();
+
+    let mut reader =
+        LZMA2SharedReaderMT::new(Cursor::new(compressed), CHUNK_SIZE, None, 4).unwrap();
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+
+    // We don't use assert_eq since the debug output would be too big.
+    assert!(uncompressed == data);
+    assert_eq!(
+        reader.chunk_count(),
+        data.len().div_ceil(CHUNK_SIZE as usize) as u64
+    );
+}
+
+#[test]
+fn shared_static_slice() {
+    let data = std::fs::read(PG6800).unwrap();
+    let compressed: &'static [u8] = compress(&data).leak();
+
+    let uncompressed = decode_shared(Cursor::new(compressed)).unwrap();
+    assert!(uncompressed == data);
+}
+
+#[test]
+fn shared_start_position() {
+    let data = std::fs::read(PG6800).unwrap();
+
+    let mut compressed = vec![0xAA; 1000];
+    compressed.extend_from_slice(&compress(&data));
+
+    let mut inner = Cursor::new(Arc::<[u8]>::from(compressed));
+    inner.seek(SeekFrom::Start(1000)).unwrap();
+
+    let uncompressed = decode_shared(inner).unwrap();
+    assert!(uncompressed == data);
+}
+
+#[test]
+fn shared_single_chunk() {
+    let data = std::fs::read(PG6800).unwrap();
+
+    let mut writer = LZMA2Writer::new(Vec::new(), LZMA2Options::with_preset(1));
+    writer.write_all(&data).unwrap();
+    let compressed: Arc<[u8]> = writer.finish().unwrap().into();
+
+    let dict_size = LZMA2Options::with_preset(1).lzma_options.dict_size;
+    let mut reader = LZMA2SharedReaderMT::new(Cursor::new(compressed), dict_size, None, 4).unwrap();
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+
+    assert!(uncompressed == data);
+    assert_eq!(reader.chunk_count(), 1);
+}
+
+#[test]
+fn shared_empty() {
+    let compressed = compress(&[]);
+
+    let uncompressed = decode_shared(Cursor::new(Arc::<[u8]>::from(compressed))).unwrap();
+    assert!(uncompressed.is_empty());
+}
+
+#[test]
+fn shared_corrupt_data() {
+    let data = std::fs::read(PG100).unwrap();
+    let mut compressed = compress(&data);
+
+    let position = compressed.len() / 2;
+    compressed[position] ^= 0x55;
+
+    let error = decode_shared(Cursor::new(Arc::<[u8]>::from(compressed))).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn shared_truncated() {
+    let data = std::fs::read(PG6800).unwrap();
+    let compressed = compress(&data);
+
+    for cut in [compressed.len() / 2, compressed.len() - 1] {
+        let truncated = Arc::<[u8]>::from(&compressed[..cut]);
+        assert!(decode_shared(Cursor::new(truncated)).is_err());
+    }
+}
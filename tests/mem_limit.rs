@@ -0,0 +1,159 @@
+mod common;
+
+use std::{
+    io::{Cursor, ErrorKind, Read, Write},
+    num::NonZeroU64,
+    sync::Arc,
+};
+
+use common::{compress_lzip, compress_xz, xz_options};
+use lzma_rust2::{
+    lzma2_get_memory_usage, lzma_get_memory_usage, LZIPReader, LZIPReaderMT, LZIPSeekableReader,
+    LZMA2Options, LZMA2Reader, LZMA2ReaderMT, LZMA2SharedReaderMT, LZMA2Writer, LZMA2WriterMT,
+    XZReader, XZReaderMT, XZSeekableReader,
+};
+
+static PG100: &str = "tests/data/pg100.txt";
+static PG6800: &str = "tests/data/pg6800.txt";
+
+const DICT_SIZE: u32 = 1024 * 1024;
+
+fn read_all<R: Read>(mut reader: R) -> std::io::Result<Vec<u8>> {
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed)?;
+    Ok(uncompressed)
+}
+
+#[test]
+fn lzma2_mem_limit() {
+    let data = std::fs::read(PG100).unwrap();
+
+    let mut options = LZMA2Options::with_preset(0);
+    options.lzma_options.dict_size = DICT_SIZE;
+    let mut writer = LZMA2Writer::new(Vec::new(), options);
+    writer.write_all(&data).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    let need = lzma2_get_memory_usage(DICT_SIZE);
+
+    let error = LZMA2Reader::new_mem_limit(compressed.as_slice(), DICT_SIZE, None, need - 1)
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+
+    let reader = LZMA2Reader::new_mem_limit(compressed.as_slice(), DICT_SIZE, None, need).unwrap();
+    assert!(read_all(reader).unwrap() == data);
+
+    // The MT reader also needs to hold the work units in memory.
+    let need_mt = need + 2 * DICT_SIZE / 1024;
+
+    let error =
+        LZMA2ReaderMT::new_mem_limit(Cursor::new(&compressed), DICT_SIZE, None, 4, need_mt - 1)
+            .err()
+            .unwrap();
+    assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+
+    // A single chunk holds all data, which is bigger than the budget of the work unit.
+    let reader =
+        LZMA2ReaderMT::new_mem_limit(Cursor::new(&compressed), DICT_SIZE, None, 4, need_mt)
+            .unwrap();
+    assert_eq!(read_all(reader).unwrap_err().kind(), ErrorKind::OutOfMemory);
+
+    let shared = Arc::<[u8]>::from(compressed);
+    let error = LZMA2SharedReaderMT::new_mem_limit(
+        Cursor::new(Arc::clone(&shared)),
+        DICT_SIZE,
+        None,
+        4,
+        need_mt - 1,
+    )
+    .err()
+    .unwrap();
+    assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+
+    let reader =
+        LZMA2SharedReaderMT::new_mem_limit(Cursor::new(shared), DICT_SIZE, None, 4, need_mt)
+            .unwrap();
+    assert_eq!(read_all(reader).unwrap_err().kind(), ErrorKind::OutOfMemory);
+
+    let mut options = LZMA2Options::with_preset(0);
+    options.lzma_options.dict_size = DICT_SIZE;
+    options.set_chunk_size(NonZeroU64::new(DICT_SIZE as u64));
+    let mut writer = LZMA2WriterMT::new(Vec::new(), options, 4).unwrap();
+    writer.write_all(&data).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    let reader =
+        LZMA2ReaderMT::new_mem_limit(Cursor::new(&compressed), DICT_SIZE, None, 4, need_mt)
+            .unwrap();
+    assert!(read_all(reader).unwrap() == data);
+
+    let reader = LZMA2SharedReaderMT::new_mem_limit(
+        Cursor::new(Arc::<[u8]>::from(compressed)),
+        DICT_SIZE,
+        None,
+        4,
+        need_mt,
+    )
+    .unwrap();
+    assert!(read_all(reader).unwrap() == data);
+}
+
+#[test]
+fn lzip_mem_limit() {
+    let data = std::fs::read(PG6800).unwrap();
+    let compressed = compress_lzip(&data, DICT_SIZE as u64);
+
+    let need = lzma_get_memory_usage(DICT_SIZE, 3, 0).unwrap();
+
+    let reader = LZIPReader::new_mem_limit(compressed.as_slice(), need - 1).unwrap();
+    assert_eq!(read_all(reader).unwrap_err().kind(), ErrorKind::OutOfMemory);
+
+    let reader = LZIPReader::new_mem_limit(compressed.as_slice(), need).unwrap();
+    assert!(read_all(reader).unwrap() == data);
+
+    let reader = LZIPSeekableReader::new_mem_limit(Cursor::new(&compressed), need - 1).unwrap();
+    assert_eq!(read_all(reader).unwrap_err().kind(), ErrorKind::OutOfMemory);
+
+    let reader = LZIPSeekableReader::new_mem_limit(Cursor::new(&compressed), need).unwrap();
+    assert!(read_all(reader).unwrap() == data);
+
+    // The MT reader also needs to hold the member in memory.
+    let error = LZIPReaderMT::new_mem_limit(Cursor::new(&compressed), 4, need)
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+
+    let reader = LZIPReaderMT::new_mem_limit(Cursor::new(&compressed), 4, 16 * 1024).unwrap();
+    assert!(read_all(reader).unwrap() == data);
+}
+
+#[test]
+fn xz_mem_limit() {
+    let data = std::fs::read(PG6800).unwrap();
+    let compressed = compress_xz(&data, xz_options(DICT_SIZE as u64));
+
+    let need = lzma2_get_memory_usage(DICT_SIZE);
+
+    let reader = XZReader::new_mem_limit(compressed.as_slice(), false, need - 1);
+    assert_eq!(read_all(reader).unwrap_err().kind(), ErrorKind::OutOfMemory);
+
+    let reader = XZReader::new_mem_limit(compressed.as_slice(), false, need);
+    assert!(read_all(reader).unwrap() == data);
+
+    let reader =
+        XZSeekableReader::new_mem_limit(Cursor::new(&compressed), false, need - 1).unwrap();
+    assert_eq!(read_all(reader).unwrap_err().kind(), ErrorKind::OutOfMemory);
+
+    let reader = XZSeekableReader::new_mem_limit(Cursor::new(&compressed), false, need).unwrap();
+    assert!(read_all(reader).unwrap() == data);
+
+    // The MT reader also needs to hold the blocks in memory.
+    let error = XZReaderMT::new_mem_limit(Cursor::new(&compressed), false, 4, need)
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+
+    let reader = XZReaderMT::new_mem_limit(Cursor::new(&compressed), false, 4, 8 * 1024).unwrap();
+    assert!(read_all(reader).unwrap() == data);
+}
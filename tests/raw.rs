@@ -0,0 +1,101 @@
+use std::io::{ErrorKind, Read, Write};
+
+use liblzma::{
+    bufread::{XzDecoder, XzEncoder},
+    stream::{Filters, LzmaOptions, Stream},
+};
+use lzma_rust2::{FilterConfig, FilterType, LZMAOptions, RawReader, RawWriter};
+
+static EXECUTABLE: &str = "tests/data/executable.exe";
+
+const DICT_SIZE: u32 = 1 << 20;
+
+fn filters() -> Vec<FilterConfig> {
+    vec![
+        FilterConfig {
+            filter_type: FilterType::Delta,
+            property: 4,
+        },
+        FilterConfig {
+            filter_type: FilterType::BcjX86,
+            property: 0,
+        },
+    ]
+}
+
+fn liblzma_filters() -> Filters {
+    let mut options = LzmaOptions::new_preset(3).unwrap();
+    options.dict_size(DICT_SIZE);
+
+    let mut filters = Filters::new();
+    filters.delta_properties(&[3]).unwrap();
+    filters.x86();
+    filters.lzma2(&options);
+    filters
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut lzma_options = LZMAOptions::with_preset(3);
+    lzma_options.dict_size = DICT_SIZE;
+
+    let mut writer = RawWriter::new(Vec::new(), filters(), lzma_options).unwrap();
+    writer.write_all(data).unwrap();
+    writer.finish().unwrap()
+}
+
+#[test]
+fn round_trip() {
+    let data = std::fs::read(EXECUTABLE).unwrap();
+    let compressed = compress(&data);
+
+    let mut reader = RawReader::new(compressed.as_slice(), filters(), DICT_SIZE).unwrap();
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+    assert!(uncompressed == data);
+}
+
+#[test]
+fn decode_with_liblzma() {
+    let data = std::fs::read(EXECUTABLE).unwrap();
+    let compressed = compress(&data);
+
+    let stream = Stream::new_raw_decoder(&liblzma_filters()).unwrap();
+    let mut decoder = XzDecoder::new_stream(compressed.as_slice(), stream);
+    let mut uncompressed = Vec::new();
+    decoder.read_to_end(&mut uncompressed).unwrap();
+    assert!(uncompressed == data);
+}
+
+#[test]
+fn decode_from_liblzma() {
+    let data = std::fs::read(EXECUTABLE).unwrap();
+
+    let stream = Stream::new_raw_encoder(&liblzma_filters()).unwrap();
+    let mut encoder = XzEncoder::new_stream(data.as_slice(), stream);
+    let mut compressed = Vec::new();
+    encoder.read_to_end(&mut compressed).unwrap();
+
+    let mut reader = RawReader::new(compressed.as_slice(), filters(), DICT_SIZE).unwrap();
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+    assert!(uncompressed == data);
+}
+
+#[test]
+fn invalid_filter_chain() {
+    let mut filters = filters();
+    filters.push(FilterConfig {
+        filter_type: FilterType::Delta,
+        property: 1,
+    });
+
+    let error = RawWriter::new(Vec::new(), filters.clone(), LZMAOptions::default())
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+    let error = RawReader::new([].as_slice(), filters, DICT_SIZE)
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
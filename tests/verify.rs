@@ -0,0 +1,240 @@
+mod common;
+
+use std::io::{Cursor, Read, Write};
+
+use common::{compress_lzip, compress_xz, xz_options};
+use lzma_rust2::{
+    lzip_verify, lzma_verify, xz_verify, CheckType, CheckValue, FilterType, LZIPReader,
+    LZIPReaderMT, LZMAOptions, LZMAWriter, LzmaError, XZReader, XZReaderMT,
+};
+use sha2::Digest;
+
+static EXECUTABLE: &str = "tests/data/executable.exe";
+static PG100: &str = "tests/data/pg100.txt";
+
+const CRC32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+const CRC64: crc::Crc<u64> = crc::Crc::<u64>::new(&crc::CRC_64_XZ);
+
+const BLOCK_SIZE: usize = 256 * 1024;
+
+fn compress(data: &[u8], check_type: CheckType, pre_filter: Option<(FilterType, u32)>) -> Vec<u8> {
+    let mut options = xz_options(BLOCK_SIZE as u64);
+    options.check_type = check_type;
+    if let Some((filter_type, property)) = pre_filter {
+        options.prepend_pre_filter(filter_type, property);
+    }
+
+    compress_xz(data, options)
+}
+
+fn check_value(check_type: CheckType, data: &[u8]) -> Option<CheckValue> {
+    match check_type {
+        CheckType::None => None,
+        CheckType::Crc32 => Some(CheckValue::Crc32(CRC32.checksum(data))),
+        CheckType::Crc64 => Some(CheckValue::Crc64(CRC64.checksum(data))),
+        CheckType::Sha256 => Some(CheckValue::Sha256(sha2::Sha256::digest(data).into())),
+    }
+}
+
+#[test]
+fn xz_summary() {
+    let data = std::fs::read(PG100).unwrap();
+
+    for check_type in [
+        CheckType::None,
+        CheckType::Crc32,
+        CheckType::Crc64,
+        CheckType::Sha256,
+    ] {
+        let compressed = compress(&data, check_type, None);
+        let summary = xz_verify(compressed.as_slice()).unwrap();
+
+        let expected_checks: Vec<_> = data
+            .chunks(BLOCK_SIZE)
+            .map(|block| check_value(check_type, block))
+            .collect();
+
+        assert_eq!(summary.stream_count, 1);
+        assert_eq!(summary.block_count, expected_checks.len() as u64);
+        assert_eq!(summary.compressed_size, compressed.len() as u64);
+        assert_eq!(summary.uncompressed_size, data.len() as u64);
+        assert_eq!(summary.checks, expected_checks);
+
+        let reader_mt = XZReaderMT::new(Cursor::new(compressed.as_slice()), true, 4).unwrap();
+        assert_eq!(reader_mt.verify().unwrap(), summary);
+    }
+}
+
+#[test]
+fn xz_summary_with_pre_filter() {
+    let data = std::fs::read(EXECUTABLE).unwrap();
+
+    for pre_filter in [(FilterType::BcjX86, 0), (FilterType::Delta, 4)] {
+        let compressed = compress(&data, CheckType::Crc64, Some(pre_filter));
+        let summary = xz_verify(compressed.as_slice()).unwrap();
+
+        let expected_checks: Vec<_> = data
+            .chunks(BLOCK_SIZE)
+            .map(|block| check_value(CheckType::Crc64, block))
+            .collect();
+
+        assert_eq!(summary.uncompressed_size, data.len() as u64);
+        assert_eq!(summary.checks, expected_checks);
+
+        let reader_mt = XZReaderMT::new(Cursor::new(compressed.as_slice()), true, 4).unwrap();
+        assert_eq!(reader_mt.verify().unwrap(), summary);
+    }
+}
+
+#[test]
+fn xz_multiple_streams() {
+    let data = std::fs::read(PG100).unwrap();
+    let (first, second) = data.split_at(data.len() / 3);
+
+    let mut compressed = compress(first, CheckType::Crc32, None);
+    compressed.extend_from_slice(&[0; 8]);
+    compressed.extend_from_slice(&compress(second, CheckType::Sha256, None));
+
+    let summary = xz_verify(compressed.as_slice()).unwrap();
+    assert_eq!(summary.stream_count, 2);
+    assert_eq!(
+        summary.block_count,
+        (first.len().div_ceil(BLOCK_SIZE) + second.len().div_ceil(BLOCK_SIZE)) as u64
+    );
+    assert_eq!(summary.compressed_size, compressed.len() as u64);
+    assert_eq!(summary.uncompressed_size, data.len() as u64);
+
+    let reader_mt = XZReaderMT::new(Cursor::new(compressed.as_slice()), true, 4).unwrap();
+    assert_eq!(reader_mt.verify().unwrap(), summary);
+}
+
+#[test]
+fn xz_corrupt() {
+    let data = std::fs::read(PG100).unwrap();
+    let mut compressed = compress(&data, CheckType::Crc64, None);
+    let len = compressed.len();
+    compressed[len / 2] ^= 0x55;
+
+    let mut reader = XZReader::new(compressed.as_slice(), true);
+    let expected = reader.read_to_end(&mut Vec::new()).unwrap_err();
+
+    // The uncompressed offset depends on how much data was decoded at once.
+    let compressed_offset = |error: &std::io::Error| match LzmaError::from_io_error(error) {
+        Some(LzmaError::CorruptData {
+            compressed_offset, ..
+        }) => compressed_offset,
+        _ => panic!("unexpected error: {error}"),
+    };
+
+    let error = xz_verify(compressed.as_slice()).unwrap_err();
+    assert_eq!(compressed_offset(&error), compressed_offset(&expected));
+
+    let reader_mt = XZReaderMT::new(Cursor::new(compressed.as_slice()), true, 4).unwrap();
+    assert!(reader_mt.verify().is_err());
+}
+
+#[test]
+fn lzip_summary() {
+    let data = std::fs::read(PG100).unwrap();
+
+    let compressed = compress_lzip(&data, BLOCK_SIZE as u64);
+
+    let summary = lzip_verify(compressed.as_slice()).unwrap();
+
+    let expected_checks: Vec<_> = data
+        .chunks(BLOCK_SIZE)
+        .map(|member| CheckValue::Crc32(CRC32.checksum(member)))
+        .collect();
+
+    assert_eq!(summary.member_count, expected_checks.len() as u64);
+    assert_eq!(summary.compressed_size, compressed.len() as u64);
+    assert_eq!(summary.uncompressed_size, data.len() as u64);
+    assert_eq!(summary.checks, expected_checks);
+
+    let reader_mt = LZIPReaderMT::new(Cursor::new(compressed.as_slice()), 4).unwrap();
+    assert_eq!(reader_mt.verify().unwrap(), summary);
+}
+
+#[test]
+fn xz_block_checks() {
+    let data = std::fs::read(PG100).unwrap();
+    let compressed = compress(&data, CheckType::Sha256, None);
+
+    let expected_checks: Vec<_> = data
+        .chunks(BLOCK_SIZE)
+        .map(|block| check_value(CheckType::Sha256, block))
+        .collect();
+
+    let mut reader = XZReader::new(compressed.as_slice(), true);
+    assert!(reader.block_checks().is_empty());
+    reader.read_exact(&mut vec![0; BLOCK_SIZE + 1]).unwrap();
+    assert_eq!(reader.block_checks(), &expected_checks[..1]);
+    reader.read_to_end(&mut Vec::new()).unwrap();
+    assert_eq!(reader.block_checks(), expected_checks);
+
+    let mut reader_mt = XZReaderMT::new(Cursor::new(compressed.as_slice()), true, 4).unwrap();
+    assert!(reader_mt.block_checks().is_empty());
+    reader_mt.read_exact(&mut vec![0; BLOCK_SIZE + 1]).unwrap();
+    assert_eq!(reader_mt.block_checks(), &expected_checks[..1]);
+    reader_mt.read_to_end(&mut Vec::new()).unwrap();
+    assert_eq!(reader_mt.block_checks(), expected_checks);
+}
+
+#[test]
+fn lzip_trailers() {
+    let data = std::fs::read(PG100).unwrap();
+
+    let compressed = compress_lzip(&data, BLOCK_SIZE as u64);
+
+    let mut reader = LZIPReader::new(compressed.as_slice()).unwrap();
+    reader.read_to_end(&mut Vec::new()).unwrap();
+    let trailers = reader.trailers().to_vec();
+
+    let member_sizes: u64 = trailers.iter().map(|trailer| trailer.member_size).sum();
+    assert_eq!(member_sizes, compressed.len() as u64);
+    for (trailer, member) in trailers.iter().zip(data.chunks(BLOCK_SIZE)) {
+        assert_eq!(trailer.crc32, CRC32.checksum(member));
+        assert_eq!(trailer.data_size, member.len() as u64);
+    }
+    assert_eq!(trailers.len(), data.len().div_ceil(BLOCK_SIZE));
+
+    let mut reader_mt = LZIPReaderMT::new(Cursor::new(compressed.as_slice()), 4).unwrap();
+    assert!(reader_mt.trailers().is_empty());
+    reader_mt.read_to_end(&mut Vec::new()).unwrap();
+    assert_eq!(reader_mt.trailers(), trailers);
+}
+
+#[test]
+fn lzip_corrupt() {
+    let data = std::fs::read(PG100).unwrap();
+
+    let mut compressed = compress_lzip(&data, BLOCK_SIZE as u64);
+    let len = compressed.len();
+    compressed[len / 2] ^= 0x55;
+
+    assert!(lzip_verify(compressed.as_slice()).is_err());
+
+    let reader_mt = LZIPReaderMT::new(Cursor::new(compressed.as_slice()), 4).unwrap();
+    assert!(reader_mt.verify().is_err());
+}
+
+#[test]
+fn lzma_summary() {
+    let data = std::fs::read(PG100).unwrap();
+    let options = LZMAOptions::with_preset(1);
+
+    for input_size in [Some(data.len() as u64), None] {
+        let mut compressed = Vec::new();
+        let mut writer = LZMAWriter::new_use_header(&mut compressed, &options, input_size).unwrap();
+        writer.write_all(&data).unwrap();
+        writer.finish().unwrap();
+
+        let summary = lzma_verify(compressed.as_slice()).unwrap();
+        assert_eq!(summary.compressed_size, compressed.len() as u64);
+        assert_eq!(summary.uncompressed_size, data.len() as u64);
+
+        let len = compressed.len();
+        compressed[len / 2] ^= 0x55;
+        assert!(lzma_verify(compressed.as_slice()).is_err());
+    }
+}
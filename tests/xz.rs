@@ -1,11 +1,26 @@
-use std::io::{Read, Write};
+mod common;
 
-use lzma_rust2::{XZOptions, XZReader, XZWriter};
+use std::{
+    cell::RefCell,
+    io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write},
+    num::NonZeroU64,
+    rc::Rc,
+};
+
+use common::{compress_xz, read_at, xz_options};
+use lzma_rust2::{
+    xz_inspect, xz_recover, CheckType, FilterType, LZMAOptions, XZBlockOptions, XZDamage, XZInfo,
+    XZOptions, XZReader, XZSeekableReader, XZWriter, XZWriterMT,
+};
 
 static EXECUTABLE: &str = "tests/data/executable.exe";
 static PG100: &str = "tests/data/pg100.txt";
 static PG6800: &str = "tests/data/pg6800.txt";
 
+const BLOCK_SIZE: usize = 64 * 1024;
+
+const CRC32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
 fn test_round_trip(path: &str, level: u32) {
     let data = std::fs::read(path).unwrap();
 
@@ -179,3 +194,778 @@ fn round_trip_pg6800_8() {
 fn round_trip_pg6800_9() {
     test_round_trip(PG6800, 9);
 }
+
+#[test]
+fn round_trip_on_other_thread() {
+    let data = std::fs::read(PG100).unwrap();
+
+    let mut options = XZOptions::with_preset(3);
+    options.prepend_pre_filter(FilterType::Delta, 2);
+    options.set_block_size(NonZeroU64::new(1 << 20));
+
+    let mut writer = XZWriter::new(Vec::new(), options).unwrap();
+    writer.write_all(&data[..data.len() / 2]).unwrap();
+
+    // The writer can be moved to another thread in the middle of a stream.
+    let compressed = std::thread::spawn({
+        let data = data.clone();
+        move || {
+            writer.write_all(&data[data.len() / 2..]).unwrap();
+            writer.finish().unwrap()
+        }
+    })
+    .join()
+    .unwrap();
+
+    let mut reader = XZReader::new(Cursor::new(compressed.clone()), false);
+    let mut uncompressed = vec![0; data.len() / 2];
+    reader.read_exact(&mut uncompressed).unwrap();
+
+    let uncompressed = std::thread::spawn(move || {
+        reader.read_to_end(&mut uncompressed).unwrap();
+        uncompressed
+    })
+    .join()
+    .unwrap();
+    assert!(uncompressed == data);
+
+    let mut reader = XZSeekableReader::new(Cursor::new(compressed), false).unwrap();
+    reader
+        .seek(SeekFrom::Start(data.len() as u64 - 100))
+        .unwrap();
+    let tail = std::thread::spawn(move || {
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        tail
+    })
+    .join()
+    .unwrap();
+    assert!(tail == data[data.len() - 100..]);
+}
+
+#[test]
+fn into_inner_after_partial_read() {
+    let data = std::fs::read(PG100).unwrap();
+
+    let mut writer = XZWriter::new(Vec::new(), XZOptions::with_preset(1)).unwrap();
+    writer.write_all(&data).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    let mut reader = XZReader::new(compressed.as_slice(), false);
+    let mut buf = [0; 1000];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, data[..1000]);
+
+    let rest = reader.into_inner();
+    assert!(rest.len() < compressed.len());
+
+    let mut writer = XZWriter::new(Vec::new(), XZOptions::with_preset(1)).unwrap();
+    writer.write_all(&data[..1000]).unwrap();
+    writer.into_inner();
+}
+
+/// A writer whose output can be inspected while the XZWriter still owns it.
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn test_flush(options: XZOptions, flush: fn(&mut XZWriter<SharedBuffer>) -> std::io::Result<()>) {
+    let data = std::fs::read(PG100).unwrap();
+    let (first, second) = data.split_at(300_000);
+
+    let buffer = SharedBuffer::default();
+    let mut writer = XZWriter::new(buffer.clone(), options).unwrap();
+    writer.write_all(first).unwrap();
+    flush(&mut writer).unwrap();
+
+    // Everything written before the flush can be decoded from the output written so far.
+    let prefix = buffer.0.borrow().clone();
+    let mut reader = XZReader::new(prefix.as_slice(), false);
+    let mut uncompressed = vec![0; first.len()];
+    reader.read_exact(&mut uncompressed).unwrap();
+    assert!(uncompressed == first);
+
+    writer.write_all(second).unwrap();
+    writer.finish().unwrap();
+
+    let compressed = buffer.0.borrow().clone();
+    let mut reader = XZReader::new(compressed.as_slice(), false);
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+    assert!(uncompressed == data);
+}
+
+#[test]
+fn full_flush() {
+    test_flush(XZOptions::with_preset(3), XZWriter::full_flush);
+
+    let mut writer = XZWriter::new(Vec::new(), XZOptions::with_preset(3)).unwrap();
+    writer.write_all(b"first block").unwrap();
+    writer.full_flush().unwrap();
+    writer.full_flush().unwrap();
+    writer.write_all(b"second block").unwrap();
+    let compressed = writer.finish().unwrap();
+
+    let info = xz_inspect(Cursor::new(compressed)).unwrap();
+    assert_eq!(info.block_count(), 2);
+    assert_eq!(info.uncompressed_size(), 23);
+}
+
+#[test]
+fn sync_flush() {
+    test_flush(XZOptions::with_preset(3), XZWriter::sync_flush);
+
+    let mut options = XZOptions::with_preset(3);
+    options.prepend_pre_filter(FilterType::Delta, 1);
+    test_flush(options, XZWriter::sync_flush);
+
+    let mut writer = XZWriter::new(Vec::new(), XZOptions::with_preset(3)).unwrap();
+    writer.write_all(b"first chunk").unwrap();
+    writer.sync_flush().unwrap();
+    writer.write_all(b"second chunk").unwrap();
+    let compressed = writer.finish().unwrap();
+
+    let info = xz_inspect(Cursor::new(compressed)).unwrap();
+    assert_eq!(info.block_count(), 1);
+}
+
+#[test]
+fn sync_flush_bcj_unsupported() {
+    let mut options = XZOptions::with_preset(3);
+    options.prepend_pre_filter(FilterType::BcjX86, 0);
+
+    let mut writer = XZWriter::new(Vec::new(), options).unwrap();
+    writer.write_all(b"data").unwrap();
+    let error = writer.sync_flush().unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+}
+
+fn decode_all_streams(compressed: &[u8]) -> Vec<u8> {
+    let mut reader = XZReader::new(compressed, true);
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+    uncompressed
+}
+
+#[test]
+fn append_stream() {
+    let data = std::fs::read(PG100).unwrap();
+    let (first, second) = data.split_at(300_000);
+
+    let mut writer = XZWriter::new(Vec::new(), XZOptions::with_preset(1)).unwrap();
+    writer.write_all(first).unwrap();
+    let mut compressed = writer.finish().unwrap();
+    compressed.extend_from_slice(&[0; 8]);
+
+    let mut options = XZOptions::with_preset(1);
+    options.set_check_sum_type(CheckType::Sha256);
+    let mut writer = XZWriter::append_stream(Cursor::new(compressed), options).unwrap();
+    writer.write_all(second).unwrap();
+    let compressed = writer.finish().unwrap().into_inner();
+
+    assert!(decode_all_streams(&compressed) == data);
+
+    let info = xz_inspect(Cursor::new(&compressed)).unwrap();
+    assert_eq!(info.streams.len(), 2);
+    assert_eq!(info.streams[0].padding, 8);
+    assert_eq!(info.streams[1].check_type, CheckType::Sha256);
+}
+
+#[test]
+fn append_blocks() {
+    let data = std::fs::read(PG100).unwrap();
+    let (first, second) = data.split_at(300_000);
+
+    let mut options = XZOptions::with_preset(1);
+    options.set_check_sum_type(CheckType::Crc32);
+    let mut writer = XZWriter::new(Vec::new(), options).unwrap();
+    writer.write_all(first).unwrap();
+    let mut compressed = writer.finish().unwrap();
+    compressed.extend_from_slice(&[0; 8]);
+
+    let mut writer =
+        XZWriter::append_blocks(Cursor::new(compressed), XZOptions::with_preset(1)).unwrap();
+    writer.write_all(second).unwrap();
+    let compressed = writer.finish().unwrap().into_inner();
+
+    assert!(decode_all_streams(&compressed) == data);
+
+    let info = xz_inspect(Cursor::new(&compressed)).unwrap();
+    assert_eq!(info.streams.len(), 1);
+    assert_eq!(info.block_count(), 2);
+    assert_eq!(info.streams[0].check_type, CheckType::Crc32);
+    assert_eq!(info.uncompressed_size(), data.len() as u64);
+
+    // Appending nothing rewrites the same index and footer.
+    let writer =
+        XZWriter::append_blocks(Cursor::new(compressed.clone()), XZOptions::default()).unwrap();
+    assert!(writer.finish().unwrap().into_inner() == compressed);
+}
+
+#[test]
+fn append_to_damaged_file() {
+    let mut writer = XZWriter::new(Vec::new(), XZOptions::with_preset(1)).unwrap();
+    writer.write_all(b"some data").unwrap();
+    let mut compressed = writer.finish().unwrap();
+
+    let footer_pos = compressed.len() - 12;
+    compressed[footer_pos + 4] ^= 0x01;
+
+    let error = XZWriter::append_stream(Cursor::new(compressed.clone()), XZOptions::default())
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+    let error = XZWriter::append_blocks(Cursor::new(compressed), XZOptions::default())
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+/// Returns options with a block list of an x86 block, a delta block and a plain block,
+/// followed by blocks of the default block size.
+fn block_list_options() -> XZOptions {
+    let mut options = XZOptions::with_preset(1);
+    options.set_block_size(NonZeroU64::new(1 << 20));
+
+    let mut x86_block = XZBlockOptions::new(
+        NonZeroU64::new(100_000).unwrap(),
+        LZMAOptions::with_preset(6),
+    );
+    x86_block.prepend_pre_filter(FilterType::BcjX86, 0);
+    options.add_block(x86_block);
+
+    let mut delta_block = XZBlockOptions::new(
+        NonZeroU64::new(50_000).unwrap(),
+        LZMAOptions::with_preset(2),
+    );
+    delta_block.prepend_pre_filter(FilterType::Delta, 4);
+    options.add_block(delta_block);
+
+    options.add_block(XZBlockOptions::new(
+        NonZeroU64::new(10_000).unwrap(),
+        LZMAOptions::with_preset(0),
+    ));
+
+    options
+}
+
+#[test]
+fn block_list() {
+    let data = std::fs::read(PG100).unwrap();
+
+    let mut writer = XZWriter::new(Vec::new(), block_list_options()).unwrap();
+    writer.write_all(&data).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    let mut reader = XZReader::new(compressed.as_slice(), false);
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+    assert!(uncompressed == data);
+
+    let info = xz_inspect(Cursor::new(compressed)).unwrap();
+    let blocks = &info.streams[0].blocks;
+    let sizes: Vec<u64> = blocks.iter().map(|block| block.uncompressed_size).collect();
+    let rest = data.len() as u64 - 160_000;
+    assert_eq!(sizes[..3], [100_000, 50_000, 10_000]);
+    assert_eq!(sizes[3..].iter().sum::<u64>(), rest);
+    assert_eq!(sizes[3], rest.min(1 << 20));
+
+    let filter_types = |index: usize| -> Vec<FilterType> {
+        blocks[index]
+            .filters
+            .iter()
+            .map(|filter| filter.filter_type)
+            .collect()
+    };
+    assert_eq!(filter_types(0), [FilterType::BcjX86, FilterType::LZMA2]);
+    assert_eq!(filter_types(1), [FilterType::Delta, FilterType::LZMA2]);
+    assert_eq!(filter_types(2), [FilterType::LZMA2]);
+    assert_eq!(filter_types(3), [FilterType::LZMA2]);
+}
+
+#[test]
+fn invalid_filter_chain() {
+    let invalid_chains: [&[(FilterType, u32)]; 5] = [
+        &[(FilterType::Delta, 0)],
+        &[(FilterType::Delta, 257)],
+        &[(FilterType::Delta, 1), (FilterType::Delta, 2)],
+        &[(FilterType::BcjARM64, 2)],
+        &[(FilterType::LZMA2, 0)],
+    ];
+
+    for chain in invalid_chains {
+        let mut options = XZOptions::with_preset(1);
+        for &(filter_type, property) in chain.iter().rev() {
+            options.prepend_pre_filter(filter_type, property);
+        }
+
+        assert_eq!(
+            options.validate().unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        let error = XZWriter::new(Vec::new(), options).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    let mut options = XZOptions::with_preset(1);
+    let mut block = XZBlockOptions::new(NonZeroU64::new(1000).unwrap(), LZMAOptions::default());
+    block.prepend_pre_filter(FilterType::BcjIA64, 8);
+    options.add_block(block);
+    assert!(XZWriter::new(Vec::new(), options).is_err());
+
+    let mut options = XZOptions::with_preset(1);
+    options.prepend_pre_filter(FilterType::BcjIA64, 16);
+    options.prepend_pre_filter(FilterType::Delta, 256);
+    assert!(options.validate().is_ok());
+}
+
+#[test]
+fn seekable_read_to_end() {
+    let data = std::fs::read(PG100).unwrap();
+    let compressed = compress_xz(&data, xz_options(256 * 1024));
+
+    let mut reader = XZSeekableReader::new(Cursor::new(compressed), false).unwrap();
+    assert!(reader.block_count() > 1);
+    assert_eq!(reader.uncompressed_size(), data.len() as u64);
+
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+
+    // We don't use assert_eq since the debug output would be too big.
+    assert!(uncompressed == data);
+}
+
+#[test]
+fn seekable_random_access() {
+    let data = std::fs::read(PG100).unwrap();
+    let compressed = compress_xz(&data, xz_options(256 * 1024));
+
+    let mut reader = XZSeekableReader::new(Cursor::new(compressed), false).unwrap();
+
+    let len = data.len() as u64;
+    let positions = [
+        len / 2,
+        0,
+        len - 100,
+        256 * 1024 - 50,
+        256 * 1024,
+        1000,
+        len / 3,
+        len / 3 + 10,
+    ];
+
+    for position in positions {
+        let chunk = read_at(&mut reader, position, 100);
+        let start = position as usize;
+        assert!(chunk.as_slice() == &data[start..start + 100]);
+    }
+}
+
+#[test]
+fn seekable_relative_seeks() {
+    let data = std::fs::read(PG6800).unwrap();
+    let compressed = compress_xz(&data, xz_options(64 * 1024));
+
+    let mut reader = XZSeekableReader::new(Cursor::new(compressed), false).unwrap();
+
+    let position = reader.seek(SeekFrom::End(-10)).unwrap();
+    assert_eq!(position, data.len() as u64 - 10);
+
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf).unwrap();
+    assert!(buf.as_slice() == &data[data.len() - 10..]);
+
+    let position = reader.seek(SeekFrom::Current(-1000)).unwrap();
+    assert_eq!(position, data.len() as u64 - 1000);
+
+    let mut buf = [0u8; 10];
+    reader.read_exact(&mut buf).unwrap();
+    assert!(buf.as_slice() == &data[data.len() - 1000..data.len() - 990]);
+
+    assert!(reader
+        .seek(SeekFrom::Current(-(data.len() as i64)))
+        .is_err());
+
+    reader
+        .seek(SeekFrom::Start(data.len() as u64 + 10))
+        .unwrap();
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn seekable_multiple_streams() {
+    let data = std::fs::read(PG6800).unwrap();
+    let (first, second) = data.split_at(data.len() / 2);
+
+    let mut compressed = compress_xz(first, xz_options(64 * 1024));
+    // Stream padding between the streams.
+    compressed.extend_from_slice(&[0u8; 8]);
+    compressed.extend_from_slice(&compress_xz(second, xz_options(64 * 1024)));
+    compressed.extend_from_slice(&[0u8; 4]);
+
+    let mut reader = XZSeekableReader::new(Cursor::new(compressed.clone()), true).unwrap();
+    assert_eq!(reader.uncompressed_size(), data.len() as u64);
+
+    let position = first.len() as u64 - 20;
+    let chunk = read_at(&mut reader, position, 40);
+    assert!(chunk.as_slice() == &data[position as usize..position as usize + 40]);
+
+    let mut uncompressed = Vec::new();
+    reader.seek(SeekFrom::Start(0)).unwrap();
+    reader.read_to_end(&mut uncompressed).unwrap();
+    assert!(uncompressed == data);
+
+    let mut reader = XZSeekableReader::new(Cursor::new(compressed), false).unwrap();
+    assert_eq!(reader.uncompressed_size(), first.len() as u64);
+
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+    assert!(uncompressed.as_slice() == first);
+}
+
+#[test]
+fn seekable_bad_padding() {
+    let data = std::fs::read(PG100).unwrap();
+    let mut compressed = compress_xz(&data, xz_options(64 * 1024));
+
+    let info = xz_inspect(Cursor::new(&compressed)).unwrap();
+    let block = info.streams[0]
+        .blocks
+        .iter()
+        .find(|block| (block.header_size + block.compressed_size) % 4 != 0)
+        .expect("no block with padding");
+    compressed[(block.start_pos + block.header_size + block.compressed_size) as usize] = 0x01;
+
+    let mut reader = XZSeekableReader::new(Cursor::new(compressed), false).unwrap();
+    let mut uncompressed = Vec::new();
+    let error = reader.read_to_end(&mut uncompressed).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn inspect_multiple_blocks() {
+    let data = std::fs::read(PG6800).unwrap();
+
+    let mut options = xz_options(64 * 1024);
+    options.prepend_pre_filter(FilterType::Delta, 4);
+
+    let compressed = compress_xz(&data, options);
+    let info = xz_inspect(Cursor::new(&compressed)).unwrap();
+
+    assert_eq!(info.file_size, compressed.len() as u64);
+    assert_eq!(info.streams.len(), 1);
+    assert_eq!(info.padding(), 0);
+    assert_eq!(info.uncompressed_size(), data.len() as u64);
+    assert_eq!(info.block_count(), data.len().div_ceil(64 * 1024));
+
+    let stream = &info.streams[0];
+    assert_eq!(stream.start_pos, 0);
+    assert_eq!(stream.compressed_size, compressed.len() as u64);
+    assert_eq!(stream.check_type, CheckType::Crc64);
+
+    let mut uncompressed_offset = 0;
+    let mut start_pos = 12;
+
+    for block in &stream.blocks {
+        assert_eq!(block.start_pos, start_pos);
+        assert_eq!(block.uncompressed_offset, uncompressed_offset);
+        assert!(!block.sizes_in_header);
+        assert_eq!(block.filters.len(), 2);
+        assert_eq!(block.filters[0].filter_type, FilterType::Delta);
+        assert_eq!(block.filters[0].property, 4);
+        assert_eq!(block.filters[1].filter_type, FilterType::LZMA2);
+        assert_eq!(block.filters[1].property, 64 * 1024);
+        assert_eq!(
+            block.memory_usage,
+            lzma_rust2::lzma2_get_memory_usage(64 * 1024)
+        );
+
+        start_pos += block.total_size;
+        uncompressed_offset += block.uncompressed_size;
+    }
+
+    assert_eq!(info.memory_usage(), stream.blocks[0].memory_usage);
+    assert_eq!(start_pos + stream.index_size + 12, compressed.len() as u64);
+}
+
+#[test]
+fn inspect_multiple_streams() {
+    let data = std::fs::read(PG6800).unwrap();
+    let (first, second) = data.split_at(data.len() / 2);
+
+    let mut compressed = compress_xz(first, XZOptions::with_preset(0));
+    let first_size = compressed.len() as u64;
+    compressed.extend_from_slice(&[0u8; 8]);
+
+    let mut options = XZOptions::with_preset(0);
+    options.set_block_size(NonZeroU64::new(1024 * 1024));
+    let mut writer = XZWriterMT::new(Vec::new(), options, 2).unwrap();
+    writer.write_all(second).unwrap();
+    compressed.extend_from_slice(&writer.finish().unwrap());
+    compressed.extend_from_slice(&[0u8; 4]);
+
+    let info = xz_inspect(Cursor::new(&compressed)).unwrap();
+
+    assert_eq!(info.streams.len(), 2);
+    assert_eq!(info.padding(), 12);
+    assert_eq!(info.uncompressed_size(), data.len() as u64);
+
+    let (stream_0, stream_1) = (&info.streams[0], &info.streams[1]);
+    assert_eq!(stream_0.compressed_size, first_size);
+    assert_eq!(stream_0.padding, 8);
+    assert_eq!(stream_0.uncompressed_size, first.len() as u64);
+    assert!(!stream_0.blocks[0].sizes_in_header);
+
+    assert_eq!(stream_1.start_pos, first_size + 8);
+    assert_eq!(stream_1.padding, 4);
+    assert_eq!(stream_1.uncompressed_offset, first.len() as u64);
+    assert_eq!(stream_1.blocks[0].uncompressed_offset, first.len() as u64);
+    assert!(stream_1.blocks[0].sizes_in_header);
+}
+
+#[test]
+fn inspect_invalid_file() {
+    let data = std::fs::read(PG6800).unwrap();
+    let mut compressed = compress_xz(&data[..10000], XZOptions::with_preset(0));
+
+    assert!(xz_inspect(Cursor::new(&compressed[..compressed.len() - 4])).is_err());
+
+    let len = compressed.len();
+    compressed[len - 9] ^= 0x01;
+    assert!(xz_inspect(Cursor::new(&compressed)).is_err());
+}
+
+fn compress_and_inspect(data: &[u8]) -> (Vec<u8>, XZInfo) {
+    let compressed = compress_xz(data, xz_options(BLOCK_SIZE as u64));
+    let info = xz_inspect(Cursor::new(&compressed)).unwrap();
+
+    (compressed, info)
+}
+
+fn recover_test_data() -> Vec<u8> {
+    let data = std::fs::read(PG100).unwrap();
+    data[..16 * BLOCK_SIZE + 1000].to_vec()
+}
+
+/// Returns the data without the given block.
+fn without_block(data: &[u8], block: usize) -> Vec<u8> {
+    let mut expected = data[..block * BLOCK_SIZE].to_vec();
+    expected.extend_from_slice(&data[(block + 1) * BLOCK_SIZE..]);
+    expected
+}
+
+#[test]
+fn recover_intact_file() {
+    let data = recover_test_data();
+    let (compressed, info) = compress_and_inspect(&data);
+
+    let mut recovered = Vec::new();
+    let recovery = xz_recover(Cursor::new(&compressed), &mut recovered).unwrap();
+
+    assert!(recovery.used_index);
+    assert!(recovery.lost.is_empty());
+    assert_eq!(recovery.recovered_blocks, info.block_count() as u64);
+    assert_eq!(recovery.recovered_size, data.len() as u64);
+    assert!(recovered == data);
+}
+
+#[test]
+fn recover_corrupt_data() {
+    let data = recover_test_data();
+    let (mut compressed, info) = compress_and_inspect(&data);
+
+    let block = &info.streams[0].blocks[3];
+    compressed[(block.start_pos + block.header_size + block.compressed_size / 2) as usize] ^= 0x55;
+
+    let mut recovered = Vec::new();
+    let recovery = xz_recover(Cursor::new(&compressed), &mut recovered).unwrap();
+
+    assert!(recovery.used_index);
+    assert_eq!(recovery.recovered_blocks, info.block_count() as u64 - 1);
+    assert_eq!(recovery.lost.len(), 1);
+
+    let lost = &recovery.lost[0];
+    assert_eq!(lost.compressed_offset, block.start_pos);
+    assert_eq!(lost.compressed_size, block.total_size);
+    assert_eq!(lost.uncompressed_offset, Some(3 * BLOCK_SIZE as u64));
+    assert_eq!(lost.uncompressed_size, Some(BLOCK_SIZE as u64));
+    assert!(matches!(
+        lost.damage,
+        XZDamage::CorruptData | XZDamage::SizeMismatch | XZDamage::Checksum
+    ));
+
+    assert!(recovered == without_block(&data, 3));
+}
+
+#[test]
+fn recover_bad_checksum() {
+    let data = recover_test_data();
+    let (mut compressed, info) = compress_and_inspect(&data);
+
+    let block = &info.streams[0].blocks[5];
+    compressed[(block.start_pos + block.total_size - 1) as usize] ^= 0x01;
+
+    let mut recovered = Vec::new();
+    let recovery = xz_recover(Cursor::new(&compressed), &mut recovered).unwrap();
+
+    assert_eq!(recovery.lost.len(), 1);
+    assert_eq!(recovery.lost[0].damage, XZDamage::Checksum);
+    assert_eq!(
+        recovery.lost[0].uncompressed_offset,
+        Some(5 * BLOCK_SIZE as u64)
+    );
+    assert!(recovered == without_block(&data, 5));
+}
+
+#[test]
+fn recover_bad_padding() {
+    let data = recover_test_data();
+    let (mut compressed, info) = compress_and_inspect(&data);
+
+    let (index, block) = info.streams[0]
+        .blocks
+        .iter()
+        .enumerate()
+        .find(|(_, block)| (block.header_size + block.compressed_size) % 4 != 0)
+        .expect("no block with padding");
+    compressed[(block.start_pos + block.header_size + block.compressed_size) as usize] = 0x01;
+
+    let mut recovered = Vec::new();
+    let recovery = xz_recover(Cursor::new(&compressed), &mut recovered).unwrap();
+
+    assert_eq!(recovery.lost.len(), 1);
+    assert_eq!(recovery.lost[0].damage, XZDamage::Padding);
+    assert!(recovered == without_block(&data, index));
+}
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Replaces the index and the stream footer of a single stream file with a valid index that has
+/// the given uncompressed block sizes.
+fn with_index(compressed: &[u8], info: &XZInfo, uncompressed_sizes: &[u64]) -> Vec<u8> {
+    let stream = &info.streams[0];
+
+    let mut index = vec![0x00];
+    encode_varint(&mut index, stream.blocks.len() as u64);
+    for (block, &uncompressed_size) in stream.blocks.iter().zip(uncompressed_sizes) {
+        // The files use CRC64 checks.
+        let check_size = 8;
+        encode_varint(
+            &mut index,
+            block.header_size + block.compressed_size + check_size,
+        );
+        encode_varint(&mut index, uncompressed_size);
+    }
+    index.resize(index.len().div_ceil(4) * 4, 0);
+    index.extend_from_slice(&CRC32.checksum(&index).to_le_bytes());
+
+    let index_start = compressed.len() - 12 - stream.index_size as usize;
+    let mut file = compressed[..index_start].to_vec();
+    file.extend_from_slice(&index);
+
+    let backward_size = (index.len() / 4 - 1) as u32;
+    let mut footer = backward_size.to_le_bytes().to_vec();
+    footer.extend_from_slice(&compressed[6..8]);
+    file.extend_from_slice(&CRC32.checksum(&footer).to_le_bytes());
+    file.extend_from_slice(&footer);
+    file.extend_from_slice(b"YZ");
+    file
+}
+
+#[test]
+fn recover_huge_index_record() {
+    let data = recover_test_data();
+    let (compressed, info) = compress_and_inspect(&data);
+
+    let mut uncompressed_sizes: Vec<u64> = info.streams[0]
+        .blocks
+        .iter()
+        .map(|block| block.uncompressed_size)
+        .collect();
+    assert!(with_index(&compressed, &info, &uncompressed_sizes) == compressed);
+
+    // The index is intact, but the size of a block is far too big to be buffered.
+    uncompressed_sizes[4] = 1 << 50;
+    let compressed = with_index(&compressed, &info, &uncompressed_sizes);
+
+    let mut recovered = Vec::new();
+    let recovery = xz_recover(Cursor::new(&compressed), &mut recovered).unwrap();
+
+    assert_eq!(recovery.lost.len(), 1);
+    assert_eq!(recovery.lost[0].damage, XZDamage::SizeMismatch);
+    assert!(recovered == without_block(&data, 4));
+}
+
+#[test]
+fn recover_without_index() {
+    let data = recover_test_data();
+    let (mut compressed, info) = compress_and_inspect(&data);
+
+    // Damage the index and the header of a block.
+    let stream = &info.streams[0];
+    let index_pos = stream.compressed_size - 12 - stream.index_size;
+    compressed[index_pos as usize + 2] ^= 0x01;
+
+    let block = &stream.blocks[7];
+    compressed[block.start_pos as usize + 1] ^= 0x01;
+
+    let mut recovered = Vec::new();
+    let recovery = xz_recover(Cursor::new(&compressed), &mut recovered).unwrap();
+
+    assert!(!recovery.used_index);
+    assert_eq!(recovery.recovered_blocks, info.block_count() as u64 - 1);
+    assert_eq!(recovery.lost.len(), 1);
+
+    let lost = &recovery.lost[0];
+    assert_eq!(lost.compressed_offset, block.start_pos);
+    assert_eq!(lost.compressed_size, block.total_size);
+    assert_eq!(lost.uncompressed_offset, Some(7 * BLOCK_SIZE as u64));
+    assert_eq!(lost.uncompressed_size, None);
+    assert_eq!(lost.damage, XZDamage::BlockHeader);
+
+    assert!(recovered == without_block(&data, 7));
+}
+
+#[test]
+fn recover_truncated_file() {
+    let data = recover_test_data();
+    let (compressed, info) = compress_and_inspect(&data);
+
+    let block = &info.streams[0].blocks[10];
+    let truncated = &compressed[..(block.start_pos + block.total_size / 2) as usize];
+
+    let mut recovered = Vec::new();
+    let recovery = xz_recover(Cursor::new(truncated), &mut recovered).unwrap();
+
+    assert!(!recovery.used_index);
+    assert_eq!(recovery.recovered_blocks, 10);
+    assert_eq!(recovery.lost.len(), 1);
+    assert_eq!(recovery.lost[0].compressed_offset, block.start_pos);
+    assert_eq!(
+        recovery.lost[0].compressed_offset + recovery.lost[0].compressed_size,
+        truncated.len() as u64
+    );
+    assert!(recovered == data[..10 * BLOCK_SIZE]);
+}
+
+#[test]
+fn recover_no_stream() {
+    let mut recovered = Vec::new();
+    assert!(xz_recover(Cursor::new(vec![0x42; 1000]), &mut recovered).is_err());
+}
@@ -0,0 +1,465 @@
+mod common;
+
+use std::{
+    io::{Cursor, ErrorKind, Read, Write},
+    num::{NonZero, NonZeroU64},
+};
+
+use common::{compress_xz, pipe, xz_options, Pipe};
+use lzma_rust2::{
+    xz_inspect, FilterType, LZMAOptions, LzmaError, XZBlockOptions, XZOptions, XZReaderMT,
+    XZStreamReaderMT, XZWriterMT,
+};
+
+static EXECUTABLE: &str = "tests/data/executable.exe";
+static PG100: &str = "tests/data/pg100.txt";
+static PG6800: &str = "tests/data/pg6800.txt";
+
+const BLOCK_SIZE: u64 = 64 * 1024;
+
+fn test_round_trip(path: &str, level: u32) {
+    let data = std::fs::read(path).unwrap();
+    let data_len = data.len() as u32;
+
+    let available_parallelism = std::thread::available_parallelism()
+        .unwrap_or(NonZero::new(1).unwrap())
+        .get()
+        .min(256) as u32;
+
+    let mut options = XZOptions::with_preset(level);
+    let dict_size = options.lzma_options.dict_size;
+    options.set_block_size(NonZeroU64::new(dict_size as u64));
+
+    let mut compressed = Vec::new();
+
+    {
+        let mut writer = XZWriterMT::new(&mut compressed, options, available_parallelism).unwrap();
+        writer.write_all(&data).unwrap();
+        writer.finish().unwrap();
+    }
+
+    let mut uncompressed = Vec::new();
+
+    {
+        let cursor = Cursor::new(compressed);
+        let mut reader = XZReaderMT::new(cursor, false, available_parallelism).unwrap();
+        reader.read_to_end(&mut uncompressed).unwrap();
+
+        if dict_size < data_len {
+            assert!(reader.block_count() > 1);
+        }
+    }
+
+    // We don't use assert_eq since the debug output would be too big.
+    assert!(uncompressed.as_slice() == data);
+}
+
+#[test]
+fn round_trip_executable_0() {
+    test_round_trip(EXECUTABLE, 0);
+}
+
+#[test]
+fn round_trip_executable_1() {
+    test_round_trip(EXECUTABLE, 1);
+}
+
+#[test]
+fn round_trip_executable_2() {
+    test_round_trip(EXECUTABLE, 2);
+}
+
+#[test]
+fn round_trip_executable_3() {
+    test_round_trip(EXECUTABLE, 3);
+}
+
+#[test]
+fn round_trip_executable_4() {
+    test_round_trip(EXECUTABLE, 4);
+}
+
+#[test]
+fn round_trip_executable_5() {
+    test_round_trip(EXECUTABLE, 5);
+}
+
+#[test]
+fn round_trip_executable_6() {
+    test_round_trip(EXECUTABLE, 6);
+}
+
+#[test]
+fn round_trip_executable_7() {
+    test_round_trip(EXECUTABLE, 7);
+}
+
+#[test]
+fn round_trip_executable_8() {
+    test_round_trip(EXECUTABLE, 8);
+}
+
+#[test]
+fn round_trip_executable_9() {
+    test_round_trip(EXECUTABLE, 9);
+}
+
+#[test]
+fn round_trip_pg100_0() {
+    test_round_trip(PG100, 0);
+}
+
+#[test]
+fn round_trip_pg100_1() {
+    test_round_trip(PG100, 1);
+}
+
+#[test]
+fn round_trip_pg100_2() {
+    test_round_trip(PG100, 2);
+}
+
+#[test]
+fn round_trip_pg100_3() {
+    test_round_trip(PG100, 3);
+}
+
+#[test]
+fn round_trip_pg100_4() {
+    test_round_trip(PG100, 4);
+}
+
+#[test]
+fn round_trip_pg100_5() {
+    test_round_trip(PG100, 5);
+}
+
+#[test]
+fn round_trip_pg100_6() {
+    test_round_trip(PG100, 6);
+}
+
+#[test]
+fn round_trip_pg100_7() {
+    test_round_trip(PG100, 7);
+}
+
+#[test]
+fn round_trip_pg100_8() {
+    test_round_trip(PG100, 8);
+}
+
+#[test]
+fn round_trip_pg100_9() {
+    test_round_trip(PG100, 9);
+}
+
+#[test]
+fn round_trip_pg6800_0() {
+    test_round_trip(PG6800, 0);
+}
+
+#[test]
+fn round_trip_pg6800_1() {
+    test_round_trip(PG6800, 1);
+}
+
+#[test]
+fn round_trip_pg6800_2() {
+    test_round_trip(PG6800, 2);
+}
+
+#[test]
+fn round_trip_pg6800_3() {
+    test_round_trip(PG6800, 3);
+}
+
+#[test]
+fn round_trip_pg6800_4() {
+    test_round_trip(PG6800, 4);
+}
+
+#[test]
+fn round_trip_pg6800_5() {
+    test_round_trip(PG6800, 5);
+}
+
+#[test]
+fn round_trip_pg6800_6() {
+    test_round_trip(PG6800, 6);
+}
+
+#[test]
+fn round_trip_pg6800_7() {
+    test_round_trip(PG6800, 7);
+}
+
+#[test]
+fn round_trip_pg6800_8() {
+    test_round_trip(PG6800, 8);
+}
+
+#[test]
+fn round_trip_pg6800_9() {
+    test_round_trip(PG6800, 9);
+}
+
+#[test]
+fn block_list() {
+    let data = std::fs::read(PG100).unwrap();
+
+    let mut options = XZOptions::with_preset(1);
+    options.set_block_size(NonZeroU64::new(1 << 20));
+
+    let mut x86_block = XZBlockOptions::new(
+        NonZeroU64::new(100_000).unwrap(),
+        LZMAOptions::with_preset(6),
+    );
+    x86_block.prepend_pre_filter(FilterType::BcjX86, 0);
+    options.add_block(x86_block);
+
+    let mut delta_block = XZBlockOptions::new(
+        NonZeroU64::new(50_000).unwrap(),
+        LZMAOptions::with_preset(2),
+    );
+    delta_block.prepend_pre_filter(FilterType::Delta, 4);
+    options.add_block(delta_block);
+
+    let mut writer = XZWriterMT::new(Vec::new(), options, 4).unwrap();
+    writer.write_all(&data).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    let mut reader = XZReaderMT::new(Cursor::new(compressed.as_slice()), false, 4).unwrap();
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+    assert!(uncompressed == data);
+
+    let info = xz_inspect(Cursor::new(&compressed)).unwrap();
+    let blocks = &info.streams[0].blocks;
+    assert_eq!(blocks[0].uncompressed_size, 100_000);
+    assert_eq!(blocks[1].uncompressed_size, 50_000);
+    assert_eq!(blocks[0].filters[0].filter_type, FilterType::BcjX86);
+    assert_eq!(blocks[1].filters[0].filter_type, FilterType::Delta);
+    assert_eq!(blocks[2].filters[0].filter_type, FilterType::LZMA2);
+}
+
+/// Compresses with sizes in the block headers.
+fn compress_mt(data: &[u8]) -> Vec<u8> {
+    let mut writer = XZWriterMT::new(Vec::new(), xz_options(BLOCK_SIZE), 4).unwrap();
+    writer.write_all(data).unwrap();
+    writer.finish().unwrap()
+}
+
+fn decode_stream(reader: Pipe, allow_multiple_streams: bool) -> std::io::Result<Vec<u8>> {
+    let mut reader = XZStreamReaderMT::new(reader, allow_multiple_streams, 4);
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed)?;
+    Ok(uncompressed)
+}
+
+#[test]
+fn stream_sized_blocks() {
+    let data = std::fs::read(PG100).unwrap();
+    let compressed = compress_mt(&data);
+
+    let mut reader = XZStreamReaderMT::new(pipe(&compressed), false, 4);
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+
+    // We don't use assert_eq since the debug output would be too big.
+    assert!(uncompressed == data);
+    assert_eq!(
+        reader.block_count() as u64,
+        (data.len() as u64).div_ceil(BLOCK_SIZE)
+    );
+    assert_eq!(reader.block_checks().len(), reader.block_count());
+    assert!(reader.block_checks().iter().all(Option::is_some));
+}
+
+#[test]
+fn stream_unsized_blocks() {
+    let data = std::fs::read(PG6800).unwrap();
+    let compressed = compress_xz(&data, xz_options(BLOCK_SIZE));
+
+    let mut reader = XZStreamReaderMT::new(pipe(&compressed), false, 4);
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+
+    assert!(uncompressed == data);
+    assert!(reader.block_count() > 1);
+    assert_eq!(reader.block_checks().len(), reader.block_count());
+}
+
+#[test]
+fn stream_single_block() {
+    let data = std::fs::read(PG6800).unwrap();
+    let compressed = compress_xz(&data, XZOptions::with_preset(1));
+
+    let uncompressed = decode_stream(pipe(&compressed), false).unwrap();
+    assert!(uncompressed == data);
+}
+
+#[test]
+fn stream_multiple_streams() {
+    let data = std::fs::read(PG6800).unwrap();
+
+    let mut compressed = compress_mt(&data);
+    compressed.extend_from_slice(&[0u8; 8]);
+    compressed.extend_from_slice(&compress_xz(&data, xz_options(BLOCK_SIZE)));
+    compressed.extend_from_slice(&compress_mt(&data));
+
+    let mut expected = data.clone();
+    expected.extend_from_slice(&data);
+    expected.extend_from_slice(&data);
+
+    let uncompressed = decode_stream(pipe(&compressed), true).unwrap();
+    assert!(uncompressed == expected);
+
+    let uncompressed = decode_stream(pipe(&compressed), false).unwrap();
+    assert!(uncompressed == data);
+}
+
+#[test]
+fn stream_small_reads() {
+    let data = std::fs::read(PG6800).unwrap();
+
+    let mut compressed = compress_mt(&data);
+    compressed.extend_from_slice(&compress_xz(&data, xz_options(BLOCK_SIZE)));
+
+    let mut reader = XZStreamReaderMT::new(
+        Pipe {
+            data: &compressed,
+            chunk_size: 7,
+        },
+        true,
+        4,
+    );
+
+    let mut uncompressed = Vec::new();
+    let mut buf = [0u8; 1000];
+    loop {
+        let bytes_read = reader.read(&mut buf).unwrap();
+        if bytes_read == 0 {
+            break;
+        }
+        uncompressed.extend_from_slice(&buf[..bytes_read]);
+    }
+
+    assert_eq!(uncompressed.len(), 2 * data.len());
+    assert!(uncompressed[..data.len()] == data);
+    assert!(uncompressed[data.len()..] == data);
+}
+
+#[test]
+fn stream_empty() {
+    let compressed = compress_xz(&[], xz_options(BLOCK_SIZE));
+
+    let mut reader = XZStreamReaderMT::new(pipe(&compressed), false, 4);
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+
+    assert!(uncompressed.is_empty());
+    assert_eq!(reader.block_count(), 0);
+}
+
+#[test]
+fn stream_corrupt_data() {
+    let data = std::fs::read(PG100).unwrap();
+
+    for mut compressed in [
+        compress_mt(&data),
+        compress_xz(&data, xz_options(BLOCK_SIZE)),
+    ] {
+        let info = xz_inspect(Cursor::new(&compressed)).unwrap();
+        let block = &info.streams[0].blocks[3];
+        compressed[(block.start_pos + block.header_size + block.compressed_size / 2) as usize] ^=
+            0x55;
+
+        let error = decode_stream(pipe(&compressed), false).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn stream_corrupt_check() {
+    let data = std::fs::read(PG100).unwrap();
+
+    for mut compressed in [
+        compress_mt(&data),
+        compress_xz(&data, xz_options(BLOCK_SIZE)),
+    ] {
+        let info = xz_inspect(Cursor::new(&compressed)).unwrap();
+        let block = &info.streams[0].blocks[5];
+        compressed[(block.start_pos + block.total_size - 1) as usize] ^= 0x01;
+
+        let mut reader = XZStreamReaderMT::new(pipe(&compressed), false, 4);
+        let mut uncompressed = Vec::new();
+        let error = reader.read_to_end(&mut uncompressed).unwrap_err();
+
+        assert!(matches!(
+            LzmaError::from_io_error(&error),
+            Some(LzmaError::ChecksumMismatch { .. })
+        ));
+        assert!(uncompressed.len() <= 6 * BLOCK_SIZE as usize);
+        assert!(uncompressed == data[..uncompressed.len()]);
+    }
+}
+
+#[test]
+fn stream_truncated() {
+    let data = std::fs::read(PG6800).unwrap();
+
+    for compressed in [
+        compress_mt(&data),
+        compress_xz(&data, xz_options(BLOCK_SIZE)),
+    ] {
+        for cut in [compressed.len() / 2, compressed.len() - 20] {
+            let error = decode_stream(pipe(&compressed[..cut]), false).unwrap_err();
+            assert!(matches!(
+                error.kind(),
+                ErrorKind::UnexpectedEof | ErrorKind::InvalidData
+            ));
+        }
+    }
+}
+
+#[test]
+fn stream_mem_limit() {
+    let data = std::fs::read(PG100).unwrap();
+    let (first, rest) = data.split_at(500_000);
+    let (second, third) = rest.split_at(1_000_000);
+
+    // The block of the stream in the middle doesn't fit into the memory limit together with its
+    // buffers and is decoded directly.
+    let mut options = xz_options(BLOCK_SIZE);
+    options.lzma_options.dict_size = 512 * 1024;
+    options.set_block_size(NonZeroU64::new(1_000_000));
+    let mut writer = XZWriterMT::new(Vec::new(), options, 4).unwrap();
+    writer.write_all(second).unwrap();
+
+    let mut compressed = compress_mt(first);
+    compressed.extend_from_slice(&writer.finish().unwrap());
+    compressed.extend_from_slice(&compress_mt(third));
+
+    let mut reader = XZStreamReaderMT::new_mem_limit(
+        Pipe {
+            data: &compressed,
+            chunk_size: 1000,
+        },
+        true,
+        4,
+        1024,
+    );
+    let mut uncompressed = Vec::new();
+    reader.read_to_end(&mut uncompressed).unwrap();
+
+    assert!(uncompressed == data);
+    let info = xz_inspect(Cursor::new(&compressed)).unwrap();
+    assert_eq!(reader.block_count(), info.block_count());
+    assert_eq!(reader.block_checks().len(), reader.block_count());
+
+    // Not even the filter chain fits.
+    let mut reader = XZStreamReaderMT::new_mem_limit(pipe(&compressed), true, 4, 16);
+    let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+}
@@ -1,6 +1,9 @@
-use std::io::Read;
+use std::{
+    io::{Read, Write},
+    num::NonZeroU64,
+};
 
-use lzma_rust2::XZReader;
+use lzma_rust2::{XZOptions, XZReader, XZWriter};
 
 fn reference_test(compressed: &[u8], original: &[u8]) {
     let mut reader = XZReader::new(compressed, false);
@@ -70,3 +73,66 @@ fn executable_bcj_x84() {
     let original = std::fs::read("tests/data/wget-x86").unwrap();
     reference_test(compressed.as_slice(), original.as_slice());
 }
+
+fn liblzma_decode(compressed: &[u8]) -> Vec<u8> {
+    let mut decoder = liblzma::read::XzDecoder::new(compressed);
+    let mut uncompressed = Vec::new();
+    decoder.read_to_end(&mut uncompressed).unwrap();
+    uncompressed
+}
+
+#[test]
+fn liblzma_decodes_multiple_blocks() {
+    let data = std::fs::read("tests/data/pg6800.txt").unwrap();
+
+    let mut options = XZOptions::with_preset(1);
+    options.lzma_options.dict_size = 64 * 1024;
+    options.set_block_size(NonZeroU64::new(64 * 1024));
+
+    let mut writer = XZWriter::new(Vec::new(), options).unwrap();
+    for chunk in data.chunks(64 * 1024) {
+        writer.write_all(chunk).unwrap();
+    }
+    let compressed = writer.finish().unwrap();
+
+    // liblzma verifies the unpadded sizes of the index against the blocks.
+    assert!(liblzma_decode(&compressed) == data);
+}
+
+#[test]
+fn liblzma_decodes_empty_stream() {
+    let writer = XZWriter::new(Vec::new(), XZOptions::with_preset(1)).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    assert!(liblzma_decode(&compressed).is_empty());
+}
+
+#[test]
+fn single_write_is_split_into_blocks() {
+    let data = std::fs::read("tests/data/pg6800.txt").unwrap();
+    let block_size = 64 * 1024;
+
+    let mut options = XZOptions::with_preset(1);
+    options.lzma_options.dict_size = block_size as u32;
+    options.set_block_size(NonZeroU64::new(block_size as u64));
+
+    let mut writer = XZWriter::new(Vec::new(), options).unwrap();
+    writer.write_all(&data).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    assert!(liblzma_decode(&compressed) == data);
+
+    // The index follows the blocks and starts with the count of records.
+    let footer = &compressed[compressed.len() - 12..];
+    let backward_size = u32::from_le_bytes(footer[4..8].try_into().unwrap()) as usize;
+    let index_start = compressed.len() - 12 - (backward_size + 1) * 4;
+    let mut record_count = 0;
+    for (i, byte) in compressed[index_start + 1..].iter().enumerate() {
+        record_count |= ((byte & 0x7F) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    assert_eq!(record_count, data.len().div_ceil(block_size));
+}